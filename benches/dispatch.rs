@@ -0,0 +1,47 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Times routing and dispatching a request against route tables of various
+//! sizes. Each request has a path not seen before, so none are answered
+//! from the route cache. Run with `cargo bench --bench dispatch`.
+
+use std::time::Instant;
+
+use canteen::{Canteen, Method, Request};
+use canteen::utils;
+
+const REQUESTS: usize = 20_000;
+
+fn table(size: usize) -> Canteen {
+    let mut cnt = Canteen::new();
+
+    for i in 0..size {
+        cnt.add_route(&format!("/api/v1/resource{}/<int:id>", i), Method::Get, |_: &Request| utils::text("ok"));
+        cnt.add_route(&format!("/api/v1/resource{}/<int:id>/<name>", i), Method::Post, utils::err_404);
+    }
+
+    cnt
+}
+
+fn main() {
+    for &size in &[10, 100, 1_000] {
+        let mut cnt = table(size);
+        let reqs: Vec<Request> = (0..REQUESTS).map(|n| {
+            format!("GET /api/v1/resource{}/{} HTTP/1.1\r\n\r\n", size - 1, n).parse().unwrap()
+        }).collect();
+
+        let start = Instant::now();
+
+        for req in reqs {
+            assert_eq!(200, cnt.dispatch(req).get_status());
+        }
+
+        let per = start.elapsed() / REQUESTS as u32;
+
+        println!("{:>5} routes: {:>8.2?} per request", size * 2, per);
+    }
+}
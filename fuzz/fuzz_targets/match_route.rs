@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+const PATTERNS: &[&str] = &[
+    "/",
+    "/api/v1/foo/<int:foo_id>",
+    "/api/v1/foo/<uint:foo_id>/bar/<str:bar>",
+    "/price/<float:amount>",
+    "/static/<path:path>",
+    "/literal.with(meta)[chars]",
+];
+
+fuzz_target!(|data: &[u8]| {
+    for pattern in PATTERNS {
+        let _ = canteen::parse::match_route(pattern, data);
+    }
+});
@@ -0,0 +1,459 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Access logs in the common or combined log format, or one of your own,
+//! written to a `LogSink`, usually a `RotatingFile`. Give the `AccessLog`
+//! to `Canteen::log_requests` to log every request the server answers,
+//! with how long it took, or use it as middleware to log only some.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use chrono::Utc;
+
+use crate::middleware::Middleware;
+use crate::request::Request;
+use crate::response::Response;
+
+/// Somewhere log lines are written.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::accesslog::LogSink;
+///
+/// let sink = |line: &str| println!("{}", line);
+/// sink.write_line("GET /");
+/// ```
+pub trait LogSink: Send + Sync {
+    /// Write one line, without its trailing newline.
+    fn write_line(&self, line: &str);
+}
+
+impl<F: Fn(&str) + Send + Sync> LogSink for F {
+    fn write_line(&self, line: &str) {
+        self(line)
+    }
+}
+
+/// When a `RotatingFile` starts a new file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rotation {
+    /// Never; the file grows forever.
+    Never,
+    /// Once the file would grow past this many bytes.
+    Size(u64),
+    /// At the start of each hour, UTC.
+    Hourly,
+    /// At the start of each day, UTC.
+    Daily,
+}
+
+impl Rotation {
+    // the hour or day that `time` falls in, for time-based rotation.
+    fn period(self, time: SystemTime) -> u64 {
+        let secs = time.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        match self {
+            Rotation::Hourly => secs / 3600,
+            Rotation::Daily  => secs / 86400,
+            _                => 0,
+        }
+    }
+}
+
+// the file being written to, and what's needed to know when to rotate it.
+struct Active {
+    file:   File,
+    size:   u64,
+    period: u64,
+}
+
+/// A log file that's rotated by size or time. Old files are renamed with a
+/// numbered suffix, `access.log.1` being the most recent, and only the
+/// newest few are kept. Lines are appended, so nothing is lost when the
+/// server restarts.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use canteen::accesslog::{LogSink, RotatingFile, Rotation};
+///
+/// let file = RotatingFile::new("/var/log/canteen/access.log")
+///                         .rotate(Rotation::Size(64 << 20))
+///                         .keep(10);
+///
+/// file.write_line("hello");
+/// ```
+pub struct RotatingFile {
+    path:     PathBuf,
+    rotation: Rotation,
+    keep:     usize,
+    active:   Mutex<Option<Active>>,
+}
+
+impl RotatingFile {
+    /// Log to the file at `path`, rotating it daily and keeping seven old
+    /// files. The file is opened when the first line is written.
+    pub fn new<P: AsRef<Path>>(path: P) -> RotatingFile {
+        RotatingFile {
+            path:     path.as_ref().to_path_buf(),
+            rotation: Rotation::Daily,
+            keep:     7,
+            active:   Mutex::new(None),
+        }
+    }
+
+    /// Set when the file is rotated.
+    pub fn rotate(mut self, rotation: Rotation) -> RotatingFile {
+        self.rotation = rotation;
+
+        self
+    }
+
+    /// Set how many old files are kept. Older ones are deleted.
+    pub fn keep(mut self, count: usize) -> RotatingFile {
+        self.keep = count;
+
+        self
+    }
+
+    // the name of the `n`th old file.
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn open(&self) -> io::Result<Active> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let md = file.metadata()?;
+        let period = self.rotation.period(md.modified().unwrap_or_else(|_| SystemTime::now()));
+
+        Ok(Active { file, size: md.len(), period })
+    }
+
+    // shift the old files up by one, dropping the oldest, and move the
+    // current file into the first slot.
+    fn shift(&self) -> io::Result<()> {
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+
+        let _ = fs::remove_file(self.numbered(self.keep));
+
+        for n in (1..self.keep).rev() {
+            let _ = fs::rename(self.numbered(n), self.numbered(n + 1));
+        }
+
+        fs::rename(&self.path, self.numbered(1))
+    }
+
+    fn write(&self, line: &str) -> io::Result<()> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        let len = line.len() as u64 + 1;
+
+        if active.is_none() {
+            *active = Some(self.open()?);
+        }
+
+        let due = match (self.rotation, active.as_ref()) {
+            (Rotation::Never, _)           => false,
+            (Rotation::Size(max), Some(a)) => a.size > 0 && a.size + len > max,
+            (rotation, Some(a))            => rotation.period(SystemTime::now()) != a.period,
+            (_, None)                      => false,
+        };
+
+        if due {
+            *active = None;
+            self.shift()?;
+            *active = Some(self.open()?);
+        }
+
+        if let Some(ref mut a) = *active {
+            a.file.write_all(format!("{}\n", line).as_bytes())?;
+            a.size += len;
+        }
+
+        Ok(())
+    }
+}
+
+impl LogSink for RotatingFile {
+    fn write_line(&self, line: &str) {
+        if let Err(e) = self.write(line) {
+            log::warn!("unable to write to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// A request that's been answered, as it's handed to a log format.
+#[derive(Debug)]
+pub struct LogEntry<'a> {
+    /// The request.
+    pub req:     &'a Request,
+    /// The response's status code.
+    pub status:  u16,
+    /// The size of the response's body, in bytes.
+    pub size:    usize,
+    /// How long it took from the request being read to its response being
+    /// ready to send.
+    pub latency: Duration,
+}
+
+impl<'a> LogEntry<'a> {
+    /// Describe `res`, the answer to `req`, sent `latency` after `req` was
+    /// read.
+    pub fn new(req: &'a Request, res: &Response, latency: Duration) -> LogEntry<'a> {
+        LogEntry {
+            req,
+            status: res.get_status().as_u16(),
+            size:   res.get_payload().len(),
+            latency,
+        }
+    }
+
+    // the parts of a line the common and combined formats share.
+    fn common(&self) -> String {
+        let req = self.req;
+        let size = match self.size {
+            0    => String::from("-"),
+            size => size.to_string(),
+        };
+
+        format!("{} - {} [{}] \"{} {} {}\" {} {}",
+                req.peer_ip().map(|ip| ip.to_string()).unwrap_or_else(|| String::from("-")),
+                req.identity().unwrap_or("-"),
+                Utc::now().format("%d/%b/%Y:%H:%M:%S %z"),
+                req.method.as_str(), req.path.replace('"', "%22"), req.version,
+                self.status, size)
+    }
+
+    // a header's value, quoted for the combined format.
+    fn quoted(&self, name: &str) -> String {
+        match self.req.get_header(name) {
+            Some(value) => value.replace('\\', "\\\\").replace('"', "\\\""),
+            None        => String::from("-"),
+        }
+    }
+}
+
+// turns a request into a line of someone else's design.
+type Formatter = Arc<dyn Fn(&LogEntry) -> String + Send + Sync>;
+
+/// How each request is written to the log.
+#[derive(Clone, Default)]
+pub enum LogFormat {
+    /// The common log format: the client's address, who they are if a
+    /// middleware such as `BasicAuth` has said, the time, the request line,
+    /// the status and the body's size.
+    Common,
+    /// The common log format followed by the `Referer` and `User-Agent`
+    /// headers.
+    #[default]
+    Combined,
+    /// Lines made by a function; see `LogFormat::custom`.
+    Custom(Formatter),
+}
+
+impl LogFormat {
+    /// Lines made by `format`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Request;
+    /// use canteen::accesslog::{LogEntry, LogFormat};
+    /// use canteen::utils;
+    ///
+    /// let format = LogFormat::custom(|e: &LogEntry| {
+    ///     format!("{} {} {} {}B {}us", e.req.method.as_str(), e.req.path, e.status, e.size, e.latency.as_micros())
+    /// });
+    /// let req: Request = "GET /a HTTP/1.1\r\n\r\n".parse().unwrap();
+    /// let line = format.format(&LogEntry::new(&req, &utils::text("hi"), Default::default()));
+    ///
+    /// assert_eq!("GET /a 200 2B 0us", line);
+    /// ```
+    pub fn custom<F>(format: F) -> LogFormat
+            where F: Fn(&LogEntry) -> String + Send + Sync + 'static {
+        LogFormat::Custom(Arc::new(format))
+    }
+
+    /// The line for `entry`.
+    pub fn format(&self, entry: &LogEntry) -> String {
+        match *self {
+            LogFormat::Common             => entry.common(),
+            LogFormat::Combined           => format!("{} \"{}\" \"{}\"", entry.common(), entry.quoted("Referer"), entry.quoted("User-Agent")),
+            LogFormat::Custom(ref format) => format(entry),
+        }
+    }
+}
+
+impl fmt::Debug for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogFormat::Common    => write!(f, "Common"),
+            LogFormat::Combined  => write!(f, "Combined"),
+            LogFormat::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Writes a line for every request, in the combined log format unless told
+/// otherwise.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use canteen::Canteen;
+/// use canteen::accesslog::{AccessLog, LogFormat, RotatingFile};
+///
+/// let mut cnt = Canteen::new();
+/// cnt.log_requests(AccessLog::new(RotatingFile::new("logs/access.log")));
+///
+/// // or just the requests to some routes, as middleware
+/// cnt.add_middleware(AccessLog::new(RotatingFile::new("logs/api.log")).format(LogFormat::Common));
+/// ```
+#[derive(Clone)]
+pub struct AccessLog {
+    sink:   Arc<dyn LogSink>,
+    format: LogFormat,
+}
+
+impl AccessLog {
+    /// Log requests to `sink`.
+    pub fn new<S: LogSink + 'static>(sink: S) -> AccessLog {
+        AccessLog { sink: Arc::new(sink), format: LogFormat::Combined }
+    }
+
+    /// Write lines in `format`.
+    pub fn format(mut self, format: LogFormat) -> AccessLog {
+        self.format = format;
+
+        self
+    }
+
+    /// Format a request and its response as they will be logged in the
+    /// combined log format.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Request;
+    /// use canteen::accesslog::AccessLog;
+    /// use canteen::utils;
+    ///
+    /// let req: Request = "GET /a HTTP/1.1\r\nUser-Agent: curl\r\n\r\n".parse().unwrap();
+    /// let line = AccessLog::format_line(&req, &utils::text("hi"));
+    ///
+    /// assert!(line.ends_with(r#""GET /a HTTP/1.1" 200 2 "-" "curl""#));
+    /// ```
+    pub fn format_line(req: &Request, res: &Response) -> String {
+        LogFormat::Combined.format(&LogEntry::new(req, res, req.elapsed()))
+    }
+
+    /// Write the line for `entry`.
+    pub fn log(&self, entry: &LogEntry) {
+        self.sink.write_line(&self.format.format(entry));
+    }
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AccessLog({:?})", self.format)
+    }
+}
+
+impl Middleware for AccessLog {
+    fn after(&self, req: &Request, res: &mut Response) {
+        self.log(&LogEntry::new(req, res, req.elapsed()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn test_size_rotation() {
+        let dir = std::env::temp_dir().join("canteen-accesslog-size");
+        let _ = fs::remove_dir_all(&dir);
+
+        let file = RotatingFile::new(dir.join("access.log")).rotate(Rotation::Size(10)).keep(2);
+
+        for line in &["one", "two", "three", "four", "five"] {
+            file.write_line(line);
+        }
+
+        assert_eq!("four\nfive\n", fs::read_to_string(dir.join("access.log")).unwrap());
+        assert_eq!("three\n", fs::read_to_string(dir.join("access.log.1")).unwrap());
+        assert_eq!("one\ntwo\n", fs::read_to_string(dir.join("access.log.2")).unwrap());
+        assert!(!dir.join("access.log.3").exists());
+
+        // a new writer appends to what's there
+        RotatingFile::new(dir.join("access.log")).rotate(Rotation::Never).write_line("six");
+        assert_eq!("four\nfive\nsix\n", fs::read_to_string(dir.join("access.log")).unwrap());
+    }
+
+    #[test]
+    fn test_time_rotation() {
+        let dir = std::env::temp_dir().join("canteen-accesslog-time");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("access.log"), "old\n").unwrap();
+
+        let yesterday = SystemTime::now() - std::time::Duration::from_secs(86400);
+        File::options().write(true).open(dir.join("access.log")).unwrap().set_modified(yesterday).unwrap();
+
+        RotatingFile::new(dir.join("access.log")).write_line("new");
+
+        assert_eq!("new\n", fs::read_to_string(dir.join("access.log")).unwrap());
+        assert_eq!("old\n", fs::read_to_string(dir.join("access.log.1")).unwrap());
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut req: Request = "GET /a HTTP/1.1\r\nUser-Agent: curl\r\n\r\n".parse().unwrap();
+
+        req.peer = "10.0.0.1".parse().ok();
+        req.extensions_mut().insert(crate::extensions::Identity(String::from("alice")));
+
+        let entry = LogEntry::new(&req, &Response::new(), Duration::from_millis(12));
+        let common = LogFormat::Common.format(&entry);
+
+        assert!(common.starts_with("10.0.0.1 - alice ["));
+        assert!(common.ends_with(r#"] "GET /a HTTP/1.1" 200 -"#));
+        assert_eq!(format!(r#"{} "-" "curl""#, common), LogFormat::Combined.format(&entry));
+        assert_eq!("12", LogFormat::custom(|e: &LogEntry| e.latency.as_millis().to_string()).format(&entry));
+    }
+
+    #[test]
+    fn test_access_log_middleware() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let seen = lines.clone();
+        let log = AccessLog::new(move |line: &str| seen.lock().unwrap().push(String::from(line)));
+        let mut req: Request = "POST /x?q=\"a\" HTTP/1.0\r\nReferer: http://a/\r\n\r\n".parse().unwrap();
+
+        crate::middleware::run(&[Arc::new(log)], &mut req, |_| utils::err_404(&Request::new()));
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(1, lines.len());
+        assert!(lines[0].starts_with("- - - ["));
+        assert!(lines[0].contains(r#""POST /x?q=%22a%22 HTTP/1.0" 404 "#));
+        assert!(lines[0].ends_with(r#" "http://a/" "-""#));
+    }
+}
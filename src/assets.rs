@@ -0,0 +1,178 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Fingerprinted static assets, for cache busting. A mount made with
+//! `StaticOptions::fingerprint` also serves each file under a name that
+//! includes a hash of its contents, e.g. `app.3f9a1b2c.css`, and tells
+//! clients they may cache it forever. `utils::asset_url` finds the name to
+//! link to.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use regex::{Captures, Regex};
+
+// the manifests of every fingerprinted mount, for `asset_url`.
+static MANIFESTS: RwLock<Vec<Arc<AssetManifest>>> = RwLock::new(Vec::new());
+
+/// The fingerprinted names of the files under a directory, as served at a
+/// URL prefix.
+#[derive(Debug, Clone, Default)]
+pub struct AssetManifest {
+    prefix: String,
+    names:  HashMap<String, String>,
+    files:  HashMap<String, String>,
+}
+
+impl AssetManifest {
+    /// Hash every file under `root`, to be served at `prefix`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::path::Path;
+    /// use canteen::assets::AssetManifest;
+    ///
+    /// let manifest = AssetManifest::build("/static", Path::new("public")).unwrap();
+    ///
+    /// println!("{:?}", manifest.url("css/app.css"));
+    /// ```
+    pub fn build(prefix: &str, root: &Path) -> io::Result<AssetManifest> {
+        let mut manifest = AssetManifest {
+            prefix: String::from(prefix.trim_end_matches('/')),
+            ..AssetManifest::default()
+        };
+
+        manifest.walk(root, "")?;
+
+        Ok(manifest)
+    }
+
+    fn walk(&mut self, dir: &Path, base: &str) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = format!("{}{}", base, entry.file_name().to_string_lossy());
+
+            if entry.file_type()?.is_dir() {
+                self.walk(&entry.path(), &format!("{}/", name))?;
+            } else {
+                let hashed = hashed_name(&name, &fingerprint(&fs::read(entry.path())?));
+
+                self.files.insert(hashed.clone(), name.clone());
+                self.names.insert(name, hashed);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The fingerprinted name of `name`, a path relative to the mount's root.
+    pub fn hashed_name(&self, name: &str) -> Option<&str> {
+        self.names.get(name.trim_start_matches('/')).map(String::as_str)
+    }
+
+    /// The URL of the fingerprinted copy of `name`.
+    pub fn url(&self, name: &str) -> Option<String> {
+        self.hashed_name(name).map(|hashed| format!("{}/{}", self.prefix, hashed))
+    }
+
+    // the file a fingerprinted name refers to.
+    pub(crate) fn resolve(&self, hashed: &str) -> Option<&str> {
+        self.files.get(hashed.trim_start_matches('/')).map(String::as_str)
+    }
+}
+
+// a short FNV-1a hash of a file's contents.
+fn fingerprint(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+        (h ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+
+    format!("{:016x}", hash)[..8].to_string()
+}
+
+// put the hash before the extension: `css/app.css` becomes `css/app.<hash>.css`.
+fn hashed_name(name: &str, hash: &str) -> String {
+    let file = name.rfind('/').map(|i| i + 1).unwrap_or(0);
+
+    match name[file..].rfind('.') {
+        Some(dot) if dot > 0 => format!("{}.{}{}", &name[..file + dot], hash, &name[file + dot..]),
+        _                    => format!("{}.{}", name, hash),
+    }
+}
+
+// make a manifest's names available to `asset_url`, replacing any earlier
+// manifest for the same prefix.
+pub(crate) fn register(manifest: Arc<AssetManifest>) {
+    let mut all = MANIFESTS.write().unwrap_or_else(|e| e.into_inner());
+
+    all.retain(|m| m.prefix != manifest.prefix);
+    all.push(manifest);
+}
+
+// look `name` up among the fingerprinted mounts.
+pub(crate) fn lookup(name: &str) -> Option<String> {
+    let all = MANIFESTS.read().unwrap_or_else(|e| e.into_inner());
+
+    all.iter().find_map(|m| m.url(name))
+}
+
+/// Replace each `{{ asset "name" }}` in `template` with the URL of the
+/// fingerprinted asset, for pages built from templates. Unknown assets are
+/// left as they are.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::assets;
+///
+/// let page = assets::expand(r#"<link rel="stylesheet" href="{{ asset "missing.css" }}">"#);
+///
+/// assert_eq!(r#"<link rel="stylesheet" href="{{ asset "missing.css" }}">"#, page);
+/// ```
+pub fn expand(template: &str) -> String {
+    let re = Regex::new(r#"\{\{\s*asset\s+"([^"]+)"\s*\}\}"#).unwrap();
+
+    re.replace_all(template, |caps: &Captures| {
+        lookup(&caps[1]).unwrap_or_else(|| caps[0].to_string())
+    }).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashed_name() {
+        assert_eq!("app.abcd1234.css", hashed_name("app.css", "abcd1234"));
+        assert_eq!("css/app.min.abcd1234.js", hashed_name("css/app.min.js", "abcd1234"));
+        assert_eq!("v1.0/LICENSE.abcd1234", hashed_name("v1.0/LICENSE", "abcd1234"));
+        assert_eq!(".env.abcd1234", hashed_name(".env", "abcd1234"));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let root = std::env::temp_dir().join("canteen-assets-test");
+        fs::create_dir_all(root.join("css")).unwrap();
+        fs::write(root.join("css/site.css"), "body {}").unwrap();
+
+        let manifest = Arc::new(AssetManifest::build("/assets/", &root).unwrap());
+        let hashed = manifest.hashed_name("/css/site.css").unwrap().to_string();
+
+        assert_eq!(format!("css/site.{}.css", fingerprint(b"body {}")), hashed);
+        assert_eq!(Some("css/site.css"), manifest.resolve(&hashed));
+        assert_eq!(Some(format!("/assets/{}", hashed)), manifest.url("css/site.css"));
+        assert_eq!(None, manifest.url("css/other.css"));
+
+        register(manifest);
+        assert_eq!(format!(r#"<link href="/assets/{}">"#, hashed),
+                   expand(r#"<link href="{{ asset "css/site.css" }}">"#));
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! An audit trail of requests that change things: who did what, to which
+//! route, and how it went.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{SecondsFormat, Utc};
+use serde_derive::Serialize;
+use serde_json::Value;
+
+use crate::accesslog::{LogSink, RotatingFile};
+use crate::middleware::Middleware;
+use crate::query;
+use crate::request::{Method, Request};
+use crate::response::Response;
+
+/// One audited request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditEntry {
+    /// When the request finished, in RFC 3339 format.
+    pub timestamp: String,
+    /// The authenticated user, from the request's `Identity` extension.
+    pub identity:  Option<String>,
+    /// The request method.
+    pub method:    String,
+    /// The request path, without the query string.
+    pub path:      String,
+    /// The template of the route that handled the request.
+    pub route:     Option<String>,
+    /// The response status code.
+    pub status:    u16,
+    /// The selected fields of the body, where it had them.
+    pub fields:    BTreeMap<String, Value>,
+}
+
+/// Where audit entries are recorded.
+pub trait AuditSink: Send + Sync {
+    /// Record one entry.
+    fn record(&self, entry: &AuditEntry);
+}
+
+impl<F: Fn(&AuditEntry) + Send + Sync> AuditSink for F {
+    fn record(&self, entry: &AuditEntry) {
+        self(entry)
+    }
+}
+
+/// Entries are written to the file as one JSON object per line.
+impl AuditSink for RotatingFile {
+    fn record(&self, entry: &AuditEntry) {
+        match serde_json::to_string(entry) {
+            Ok(line) => self.write_line(&line),
+            Err(e)   => log::warn!("unable to serialize audit entry: {}", e),
+        }
+    }
+}
+
+/// Records an `AuditEntry` for every POST, PUT, PATCH and DELETE request,
+/// and for the WebDAV methods that change files. Only the body fields asked
+/// for are recorded, from JSON and form bodies, so secrets stay out of the
+/// trail. Add it after authentication middleware, so the identity is known.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::Canteen;
+/// use canteen::audit::{Audit, AuditEntry};
+///
+/// let audit = Audit::new(|entry: &AuditEntry| println!("{:?}", entry))
+///                   .field("account")
+///                   .field("amount");
+///
+/// let mut cnt = Canteen::new();
+/// cnt.add_middleware(audit);
+/// ```
+#[derive(Clone)]
+pub struct Audit {
+    sink:   Arc<dyn AuditSink>,
+    fields: Vec<String>,
+}
+
+impl Audit {
+    /// Record entries to `sink`.
+    pub fn new<S: AuditSink + 'static>(sink: S) -> Audit {
+        Audit {
+            sink:   Arc::new(sink),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Also record the top-level body field `name`.
+    pub fn field(mut self, name: &str) -> Audit {
+        self.fields.push(String::from(name));
+
+        self
+    }
+
+    fn audited(method: Method) -> bool {
+        matches!(method, Method::Post | Method::Put | Method::Delete | Method::Mkcol | Method::Copy | Method::Move)
+    }
+
+    // the selected fields of a JSON or form body.
+    fn body_fields(&self, req: &Request) -> BTreeMap<String, Value> {
+        let mut out = BTreeMap::new();
+
+        if self.fields.is_empty() || req.payload.is_empty() {
+            return out;
+        }
+
+        let ctype = req.get_header("Content-Type").unwrap_or_default().to_ascii_lowercase();
+
+        if ctype.starts_with("application/x-www-form-urlencoded") {
+            let form = query::parse_query(&String::from_utf8_lossy(&req.payload));
+
+            for name in &self.fields {
+                if let Some(value) = form.get(name).and_then(|v| v.as_str()) {
+                    out.insert(name.clone(), Value::from(value));
+                }
+            }
+        } else if let Ok(Value::Object(body)) = serde_json::from_slice::<Value>(&req.payload) {
+            for name in &self.fields {
+                if let Some(value) = body.get(name) {
+                    out.insert(name.clone(), value.clone());
+                }
+            }
+        }
+
+        out
+    }
+
+    /// The entry that would be recorded for a request and its response, or
+    /// `None` if the request isn't audited.
+    pub fn entry(&self, req: &Request, res: &Response) -> Option<AuditEntry> {
+        if !Audit::audited(req.method) {
+            return None;
+        }
+
+        Some(AuditEntry {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            identity:  req.identity().map(String::from),
+            method:    String::from(req.method.as_str()),
+            path:      String::from(req.path.split('?').next().unwrap_or("")),
+            route:     req.matched_route().map(|r| String::from(r.template())),
+            status:    res.get_status().as_u16(),
+            fields:    self.body_fields(req),
+        })
+    }
+}
+
+impl Middleware for Audit {
+    fn after(&self, req: &Request, res: &mut Response) {
+        if let Some(entry) = self.entry(req, res) {
+            self.sink.record(&entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::extensions::Identity;
+    use crate::utils;
+
+    #[test]
+    fn test_audit_entry() {
+        let audit = Audit::new(|_: &AuditEntry| {}).field("amount").field("to");
+        let mut req: Request = "POST /transfer?x=1 HTTP/1.1\r\nContent-Type: application/json\r\n\r\n\
+                                {\"amount\": 12.5, \"to\": \"bob\", \"pin\": \"1234\"}".parse().unwrap();
+
+        req.extensions_mut().insert(Identity(String::from("alice")));
+
+        let entry = audit.entry(&req, &utils::text("ok")).unwrap();
+
+        assert_eq!(Some(String::from("alice")), entry.identity);
+        assert_eq!("POST", entry.method);
+        assert_eq!("/transfer", entry.path);
+        assert_eq!(200, entry.status);
+        assert_eq!(Some(&Value::from(12.5)), entry.fields.get("amount"));
+        assert_eq!(Some(&Value::from("bob")), entry.fields.get("to"));
+        assert_eq!(None, entry.fields.get("pin"));
+
+        let req: Request = "GET /transfer HTTP/1.1\r\n\r\n".parse().unwrap();
+        assert_eq!(None, audit.entry(&req, &utils::text("ok")));
+    }
+
+    #[test]
+    fn test_audit_middleware() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let entries = seen.clone();
+        let audit = Audit::new(move |e: &AuditEntry| entries.lock().unwrap().push(e.clone())).field("name");
+        let mut req: Request = "DELETE /users/3 HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\n\
+                                name=jo&pass=x".parse().unwrap();
+
+        crate::middleware::run(&[Arc::new(audit)], &mut req, |_| utils::text("gone"));
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(1, seen.len());
+        assert_eq!(None, seen[0].identity);
+        assert_eq!(Some(&Value::from("jo")), seen[0].fields.get("name"));
+        assert_eq!(1, seen[0].fields.len());
+    }
+}
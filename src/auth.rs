@@ -0,0 +1,119 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Authentication middleware. Each identifies the client, storing who they
+//! are as the request's `extensions::Identity`, or turns the request away.
+//! Add them server-wide, to a group, or to single routes with
+//! `RouteOptions::with`.
+
+use std::sync::Arc;
+
+use crate::extensions::Identity;
+use crate::middleware::Middleware;
+use crate::request::Request;
+use crate::response::Response;
+use crate::utils;
+
+// checks a user name and password.
+type Validator = Arc<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+/// Middleware requiring HTTP Basic authentication. Requests without
+/// credentials, or whose credentials the validator rejects, get a
+/// `401 Unauthorized` asking for them; otherwise the user name becomes the
+/// request's identity. Basic credentials are sent in the clear, so only use
+/// this over HTTPS.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Canteen, Method, Request};
+/// use canteen::auth::BasicAuth;
+/// use canteen::route::RouteOptions;
+/// use canteen::utils;
+///
+/// let admin = BasicAuth::new(|user: &str, password: &str| user == "admin" && password == "hunter2")
+///                       .realm("admin area");
+///
+/// let mut cnt = Canteen::new();
+/// cnt.add_route_with("/admin", Method::Get, |req: &Request| utils::text(req.identity().unwrap_or("")),
+///                    RouteOptions::new().with(admin));
+///
+/// let res = cnt.dispatch("GET /admin HTTP/1.1\r\n\r\n".parse().unwrap());
+/// assert_eq!(401, res.get_status());
+/// ```
+#[derive(Clone)]
+pub struct BasicAuth {
+    check: Validator,
+    realm: String,
+}
+
+impl BasicAuth {
+    /// Accept the credentials for which `validator`, given the user name
+    /// and password, returns `true`. Comparing passwords in constant time
+    /// is up to it.
+    pub fn new<F>(validator: F) -> BasicAuth
+            where F: Fn(&str, &str) -> bool + Send + Sync + 'static {
+        BasicAuth {
+            check: Arc::new(validator),
+            realm: String::from("restricted"),
+        }
+    }
+
+    /// Name the protected area, which browsers show when asking for
+    /// credentials.
+    pub fn realm(mut self, realm: &str) -> BasicAuth {
+        self.realm = String::from(realm);
+
+        self
+    }
+
+    // the `WWW-Authenticate` challenge sent with a 401.
+    fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn before(&self, req: &mut Request) -> Option<Response> {
+        match req.basic_auth() {
+            Some((user, password)) if (self.check)(&user, &password) => {
+                req.extensions_mut().insert(Identity(user));
+                None
+            },
+            _                                                        => Some(utils::err_401(req, &self.challenge())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Canteen, Method};
+
+    #[test]
+    fn test_basic_auth() {
+        let mut cnt = Canteen::new();
+        let get = |cnt: &mut Canteen, auth: &str| {
+            cnt.dispatch(format!("GET /admin HTTP/1.1\r\nAuthorization: {}\r\n\r\n", auth).parse().unwrap())
+        };
+
+        cnt.add_middleware(BasicAuth::new(|user: &str, password: &str| user == "alice" && password == "pw").realm("a \"b\""));
+        cnt.add_route("/admin", Method::Get, |req: &Request| utils::text(req.identity().unwrap_or("")));
+
+        let res = get(&mut cnt, &format!("basic {}", utils::base64_encode(b"alice:pw")));
+        assert_eq!(200, res.get_status());
+        assert_eq!(b"alice", res.get_payload());
+
+        for auth in [format!("Basic {}", utils::base64_encode(b"alice:nope")), String::from("Basic !!"),
+                     String::from("Bearer abc"), format!("Basic {}", utils::base64_encode(b"alice"))] {
+            let res = get(&mut cnt, &auth);
+
+            assert_eq!(401, res.get_status());
+            assert_eq!(Some("Basic realm=\"a \\\"b\\\"\", charset=\"UTF-8\""), res.get_header("WWW-Authenticate"));
+        }
+    }
+}
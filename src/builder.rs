@@ -0,0 +1,683 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Building a server from a configuration file.
+//!
+//! A file describes the listeners, TLS certificates, limits, static mounts,
+//! and logging settings; anything set on the builder afterwards overrides
+//! it. Routes, middleware, and other code still have to be added to the
+//! `Canteen` that `build` returns.
+//!
+//! ```toml
+//! [[listener]]
+//! address = "0.0.0.0:8080"
+//! nodelay = true
+//!
+//! [[listener]]
+//! address = "0.0.0.0:8443"
+//! tls = { cert = "/etc/canteen/cert.pem", key = "/etc/canteen/key.pem" }
+//!
+//! [limits]
+//! preset = "strict"
+//! max_body = 1048576
+//! handler_timeout_ms = 5000
+//! header_timeout_ms = 10000
+//! max_connections_per_ip = 32
+//! max_connections = 4096
+//! workers = 64
+//!
+//! [keep_alive]
+//! max_requests = 100
+//! idle_timeout_secs = 5
+//!
+//! [[mount]]
+//! prefix = "/static"
+//! root = "/srv/www/static"
+//! fingerprint = true
+//!
+//! [logging]
+//! level = "info"
+//! slow_threshold_ms = 500
+//! access_log = "/var/log/canteen/access.log"
+//! access_log_rotate = "daily"
+//! access_log_keep = 14
+//! access_log_format = "common"
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::LevelFilter;
+use serde_derive::Deserialize;
+
+use crate::Canteen;
+use crate::accesslog::{AccessLog, LogFormat, RotatingFile, Rotation};
+use crate::config::{Config, Limits};
+use crate::files::StaticOptions;
+use crate::listener::{ListenerOptions, Mode};
+use crate::tls::TlsCerts;
+
+/// What went wrong loading a configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file couldn't be read.
+    Io(io::Error),
+    /// The file isn't valid TOML, or has settings we don't know.
+    Parse(toml::de::Error),
+    /// A setting has a value that can't be used.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref e)      => write!(f, "unable to read configuration: {}", e),
+            ConfigError::Parse(ref e)   => write!(f, "invalid configuration: {}", e),
+            ConfigError::Invalid(ref m) => write!(f, "invalid configuration: {}", m),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> ConfigError {
+        ConfigError::Parse(err)
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    #[serde(default)]
+    listener:   Vec<FileListener>,
+    #[serde(default)]
+    mount:      Vec<FileMount>,
+    #[serde(default)]
+    limits:     FileLimits,
+    keep_alive: Option<FileKeepAlive>,
+    #[serde(default)]
+    logging:    FileLogging,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileListener {
+    address:             String,
+    nodelay:             Option<bool>,
+    backlog:             Option<i32>,
+    recv_buffer:         Option<usize>,
+    send_buffer:         Option<usize>,
+    v6_only:             Option<bool>,
+    accept_rate:         Option<u64>,
+    accept_burst:        Option<u64>,
+    drop_excess_accepts: Option<bool>,
+    tls:                 Option<FileTls>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileTls {
+    cert: PathBuf,
+    key:  PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileMount {
+    prefix:      String,
+    root:        PathBuf,
+    rate_limit:  Option<u64>,
+    fingerprint: Option<bool>,
+    writable:    Option<bool>,
+    webdav:      Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FileLimits {
+    preset:                 Option<String>,
+    max_body:               Option<usize>,
+    max_header:             Option<usize>,
+    max_uri:                Option<usize>,
+    handler_timeout_ms:     Option<u64>,
+    header_timeout_ms:      Option<u64>,
+    body_timeout_ms:        Option<u64>,
+    write_timeout_ms:       Option<u64>,
+    max_connections_per_ip: Option<usize>,
+    rate_limit:             Option<u64>,
+    read_buffer_initial:    Option<usize>,
+    read_buffer_max:        Option<usize>,
+    workers:                Option<usize>,
+    max_connections:        Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileKeepAlive {
+    enabled:           Option<bool>,
+    max_requests:      Option<usize>,
+    max_age_secs:      Option<u64>,
+    idle_timeout_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FileLogging {
+    level:                   Option<String>,
+    slow_threshold_ms:       Option<u64>,
+    queue_wait_threshold_ms: Option<u64>,
+    access_log:              Option<PathBuf>,
+    access_log_rotate:       Option<String>,
+    access_log_max_bytes:    Option<u64>,
+    access_log_keep:         Option<usize>,
+    access_log_format:       Option<String>,
+}
+
+// where and how to write the access log once the server is built.
+#[derive(Debug, Clone)]
+struct AccessLogSpec {
+    path:     PathBuf,
+    rotation: Rotation,
+    keep:     usize,
+    format:   LogFormat,
+}
+
+// a listener to bind once the server is built.
+#[derive(Debug, Clone)]
+struct ListenerSpec {
+    addr: String,
+    opts: ListenerOptions,
+    tls:  Option<(PathBuf, PathBuf)>,
+}
+
+// a directory to serve once the server is built.
+#[derive(Debug, Clone)]
+struct MountSpec {
+    prefix: String,
+    root:   PathBuf,
+    opts:   StaticOptions,
+}
+
+/// Collects a server's settings, from a file and/or code, before the
+/// server is created.
+#[derive(Debug, Clone, Default)]
+pub struct CanteenBuilder {
+    listeners: Vec<ListenerSpec>,
+    mounts:    Vec<MountSpec>,
+    config:    Config,
+    access:    Option<AccessLogSpec>,
+}
+
+impl CanteenBuilder {
+    /// Create a builder with the default settings and no listeners.
+    pub fn new() -> CanteenBuilder {
+        CanteenBuilder::default()
+    }
+
+    /// Load the settings from a TOML file.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use canteen::builder::CanteenBuilder;
+    ///
+    /// let mut cnt = CanteenBuilder::from_file("canteen.toml")
+    ///     .expect("bad configuration")
+    ///     .config(|c| { c.set_handler_timeout(Some(Duration::from_secs(2))); })
+    ///     .build();
+    ///
+    /// cnt.run();
+    /// ```
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<CanteenBuilder, ConfigError> {
+        CanteenBuilder::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Load the settings from a string in the same format as `from_file`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::builder::CanteenBuilder;
+    ///
+    /// let builder = CanteenBuilder::from_toml(r#"
+    ///     [limits]
+    ///     max_body = 65536
+    /// "#).unwrap();
+    ///
+    /// assert_eq!(Some(65536), builder.get_config().get_max_body());
+    /// ```
+    pub fn from_toml(text: &str) -> Result<CanteenBuilder, ConfigError> {
+        let file: FileConfig = toml::from_str(text)?;
+        let mut builder = CanteenBuilder::new();
+
+        for l in file.listener {
+            let mut opts = ListenerOptions::new();
+
+            if let Some(on) = l.nodelay {
+                opts = opts.nodelay(on);
+            }
+            if let Some(n) = l.backlog {
+                opts = opts.backlog(n);
+            }
+            if let Some(bytes) = l.recv_buffer {
+                opts = opts.recv_buffer(bytes);
+            }
+            if let Some(bytes) = l.send_buffer {
+                opts = opts.send_buffer(bytes);
+            }
+            if let Some(only) = l.v6_only {
+                opts = opts.v6_only(only);
+            }
+            if let Some(per_sec) = l.accept_rate {
+                opts = opts.accept_rate(per_sec, l.accept_burst.unwrap_or(per_sec));
+            }
+            if let Some(drop) = l.drop_excess_accepts {
+                opts = opts.drop_excess_accepts(drop);
+            }
+
+            builder.listeners.push(ListenerSpec {
+                addr: l.address,
+                opts,
+                tls:  l.tls.map(|t| (t.cert, t.key)),
+            });
+        }
+
+        for m in file.mount {
+            let mut opts = StaticOptions::new();
+
+            if let Some(bps) = m.rate_limit {
+                opts = opts.rate_limit(bps);
+            }
+            if let Some(on) = m.fingerprint {
+                opts = opts.fingerprint(on);
+            }
+            if let Some(on) = m.writable {
+                opts = opts.writable(on);
+            }
+            if let Some(on) = m.webdav {
+                opts = opts.webdav(on);
+            }
+
+            builder.mounts.push(MountSpec { prefix: m.prefix, root: m.root, opts });
+        }
+
+        let config = &mut builder.config;
+        let limits = file.limits;
+
+        // a preset goes first, so the other keys can adjust it
+        if let Some(name) = limits.preset {
+            match Limits::preset(&name) {
+                Some(preset) => preset.apply(config),
+                None         => return Err(ConfigError::Invalid(format!("unknown limits preset {:?}", name))),
+            }
+        }
+
+        if let Some(bytes) = limits.max_body {
+            config.max_body = Some(bytes);
+        }
+        if let Some(bytes) = limits.max_header {
+            config.max_header = Some(bytes);
+        }
+        if let Some(bytes) = limits.max_uri {
+            config.max_uri = Some(bytes);
+        }
+        if let Some(ms) = limits.handler_timeout_ms {
+            config.timeout = Some(Duration::from_millis(ms));
+        }
+        if let Some(ms) = limits.header_timeout_ms {
+            config.head_timeout = Some(Duration::from_millis(ms));
+        }
+        if let Some(ms) = limits.body_timeout_ms {
+            config.body_timeout = Some(Duration::from_millis(ms));
+        }
+        if let Some(ms) = limits.write_timeout_ms {
+            config.write_timeout = Some(Duration::from_millis(ms));
+        }
+        if let Some(max) = limits.max_connections_per_ip {
+            config.per_ip = Some(max);
+        }
+        if let Some(bps) = limits.rate_limit {
+            config.rate_limit = Some(bps);
+        }
+
+        if let Some(n) = limits.workers {
+            config.set_workers(n);
+        }
+        if let Some(max) = limits.max_connections {
+            config.set_max_connections(max);
+        }
+
+        let (initial, max) = config.get_read_buffer();
+        config.set_read_buffer(limits.read_buffer_initial.unwrap_or(initial),
+                               limits.read_buffer_max.unwrap_or(max));
+
+        if let Some(ka) = file.keep_alive {
+            config.keep_alive = match ka.enabled {
+                Some(false) => None,
+                _           => {
+                    let mut keep = config.keep_alive.clone().unwrap_or_default();
+
+                    if let Some(n) = ka.max_requests {
+                        keep = keep.max_requests(n);
+                    }
+                    if let Some(secs) = ka.max_age_secs {
+                        keep = keep.max_age(Duration::from_secs(secs));
+                    }
+                    if let Some(secs) = ka.idle_timeout_secs {
+                        keep = keep.idle_timeout(Duration::from_secs(secs));
+                    }
+
+                    Some(keep)
+                },
+            };
+        }
+
+        config.slow = file.logging.slow_threshold_ms.map(Duration::from_millis);
+        config.queue_wait = file.logging.queue_wait_threshold_ms.map(Duration::from_millis);
+
+        if let Some(path) = file.logging.access_log {
+            let rotation = match (file.logging.access_log_max_bytes, file.logging.access_log_rotate.as_deref()) {
+                (Some(max), _)          => Rotation::Size(max),
+                (None, None)            => Rotation::Daily,
+                (None, Some("daily"))   => Rotation::Daily,
+                (None, Some("hourly"))  => Rotation::Hourly,
+                (None, Some("never"))   => Rotation::Never,
+                (None, Some(other))     => return Err(ConfigError::Invalid(format!("unknown log rotation {:?}", other))),
+            };
+
+            let format = match file.logging.access_log_format.as_deref() {
+                None | Some("combined") => LogFormat::Combined,
+                Some("common")          => LogFormat::Common,
+                Some(other)             => return Err(ConfigError::Invalid(format!("unknown log format {:?}", other))),
+            };
+
+            builder = builder.access_log(path, rotation, file.logging.access_log_keep.unwrap_or(7))
+                             .access_log_format(format);
+        }
+
+        if let Some(level) = file.logging.level {
+            match LevelFilter::from_str(&level) {
+                Ok(level) => builder.config.log_level = Some(level),
+                Err(_)    => return Err(ConfigError::Invalid(format!("unknown log level {:?}", level))),
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Add a plain HTTP listener.
+    pub fn bind(self, addr: &str) -> CanteenBuilder {
+        self.bind_with(addr, ListenerOptions::new())
+    }
+
+    /// Add a plain HTTP listener with socket options.
+    pub fn bind_with(mut self, addr: &str, opts: ListenerOptions) -> CanteenBuilder {
+        self.listeners.push(ListenerSpec { addr: String::from(addr), opts, tls: None });
+
+        self
+    }
+
+    /// Add an HTTPS listener.
+    pub fn bind_tls<P: AsRef<Path>>(mut self, addr: &str, cert_chain: P, private_key: P) -> CanteenBuilder {
+        self.listeners.push(ListenerSpec {
+            addr: String::from(addr),
+            opts: ListenerOptions::new(),
+            tls:  Some((cert_chain.as_ref().to_path_buf(), private_key.as_ref().to_path_buf())),
+        });
+
+        self
+    }
+
+    /// Serve the files under `root` at `prefix`.
+    pub fn mount_static<P: AsRef<Path>>(mut self, prefix: &str, root: P, opts: StaticOptions) -> CanteenBuilder {
+        self.mounts.push(MountSpec { prefix: String::from(prefix), root: root.as_ref().to_path_buf(), opts });
+
+        self
+    }
+
+    /// Change the server-wide settings, e.g. to override a limit from the
+    /// file.
+    pub fn config<F: FnOnce(&mut Config)>(mut self, change: F) -> CanteenBuilder {
+        change(&mut self.config);
+
+        self
+    }
+
+    /// The server-wide settings so far.
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Apply a bundle of limits, e.g. `Limits::strict()`, replacing the
+    /// body, header, URI, handler timeout, idle timeout, and per-IP
+    /// connection settings.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::builder::CanteenBuilder;
+    /// use canteen::config::Limits;
+    ///
+    /// let builder = CanteenBuilder::new().limits(Limits::strict());
+    ///
+    /// assert_eq!(Some(2048), builder.get_config().get_max_uri());
+    /// ```
+    pub fn limits(mut self, limits: Limits) -> CanteenBuilder {
+        limits.apply(&mut self.config);
+
+        self
+    }
+
+    /// Set the most verbose level of log messages that will be emitted.
+    pub fn log_level(mut self, level: LevelFilter) -> CanteenBuilder {
+        self.config.log_level = Some(level);
+
+        self
+    }
+
+    /// Write an access log to the file at `path`, rotated as `rotation`
+    /// says, keeping `keep` old files.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::builder::CanteenBuilder;
+    /// use canteen::accesslog::Rotation;
+    ///
+    /// let builder = CanteenBuilder::new().access_log("logs/access.log", Rotation::Size(32 << 20), 5);
+    /// ```
+    pub fn access_log<P: AsRef<Path>>(mut self, path: P, rotation: Rotation, keep: usize) -> CanteenBuilder {
+        self.access = Some(AccessLogSpec { path: path.as_ref().to_path_buf(), rotation, keep, format: LogFormat::Combined });
+
+        self
+    }
+
+    /// Write the access log in `format`, rather than the combined log
+    /// format. Has no effect unless there's an access log.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::builder::CanteenBuilder;
+    /// use canteen::accesslog::{LogFormat, Rotation};
+    ///
+    /// let builder = CanteenBuilder::new().access_log("logs/access.log", Rotation::Daily, 7)
+    ///                                    .access_log_format(LogFormat::Common);
+    /// ```
+    pub fn access_log_format(mut self, format: LogFormat) -> CanteenBuilder {
+        if let Some(ref mut access) = self.access {
+            access.format = format;
+        }
+
+        self
+    }
+
+    /// Create the server, binding its listeners and loading its
+    /// certificates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a listener can't be bound or its certificates loaded, as
+    /// `Canteen::bind` and `Canteen::bind_tls` do.
+    pub fn build(self) -> Canteen {
+        let mut cnt = Canteen::new();
+
+        if let Some(level) = self.config.log_level {
+            log::set_max_level(level);
+        }
+
+        cnt.config = Arc::new(self.config);
+        cnt.resize();
+
+        for l in self.listeners {
+            let tls = l.tls.map(|(cert, key)| match TlsCerts::new(&cert, &key, cnt.clock.now()) {
+                Ok(certs) => Arc::new(certs),
+                Err(e)    => panic!("unable to load TLS certificate: {}", e),
+            });
+
+            cnt.listen(l.addr.as_str(), Mode::Serve, l.opts, tls);
+        }
+
+        for m in self.mounts {
+            cnt.mount_static(&m.prefix, &m.root, m.opts);
+        }
+
+        if let Some(access) = self.access {
+            let file = RotatingFile::new(access.path).rotate(access.rotation).keep(access.keep);
+
+            cnt.log_requests(AccessLog::new(file).format(access.format));
+        }
+
+        cnt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::KeepAlive;
+
+    #[test]
+    fn test_from_toml() {
+        let builder = CanteenBuilder::from_toml(r#"
+            [[listener]]
+            address = "127.0.0.1:0"
+            nodelay = true
+            backlog = 64
+            accept_rate = 100
+
+            [[listener]]
+            address = "127.0.0.1:0"
+            tls = { cert = "testdata/tls/cert1.pem", key = "testdata/tls/key1.pem" }
+
+            [limits]
+            max_body = 1024
+            handler_timeout_ms = 1500
+            write_timeout_ms = 2000
+            read_buffer_initial = 512
+            workers = 8
+            max_connections = 16
+
+            [keep_alive]
+            max_requests = 10
+
+            [[mount]]
+            prefix = "/static"
+            root = "testdata"
+
+            [logging]
+            slow_threshold_ms = 250
+            access_log = "logs/access.log"
+            access_log_max_bytes = 1024
+            access_log_format = "common"
+        "#).unwrap();
+
+        assert_eq!(2, builder.listeners.len());
+        assert!(builder.listeners[0].opts.nodelay);
+        assert_eq!(64, builder.listeners[0].opts.backlog);
+        assert_eq!(Some((100, 100)), builder.listeners[0].opts.accepts);
+        assert!(builder.listeners[1].tls.is_some());
+        assert_eq!(1, builder.mounts.len());
+
+        let config = builder.get_config();
+        assert_eq!(Some(1024), config.get_max_body());
+        assert_eq!(Some(Duration::from_millis(1500)), config.get_handler_timeout());
+        assert_eq!(Some(Duration::from_millis(2000)), config.get_write_timeout());
+        assert_eq!((512, 64 * 1024), config.get_read_buffer());
+        assert_eq!((8, 16), (config.get_workers(), config.get_max_connections()));
+        assert_eq!(Some(&KeepAlive::new().max_requests(10)), config.get_keep_alive());
+        assert_eq!(Some(Duration::from_millis(250)), config.get_slow_threshold());
+        assert_eq!(Some(Rotation::Size(1024)), builder.access.as_ref().map(|a| a.rotation));
+        assert!(matches!(builder.access.as_ref().map(|a| &a.format), Some(LogFormat::Common)));
+
+        let cnt = builder.config(|c| { c.set_max_body(Some(2048)); }).build();
+        assert_eq!(2, cnt.local_addrs().len());
+        assert_eq!(Some(2048), cnt.config.get_max_body());
+        assert_eq!(8, cnt.pool_health().workers);
+        assert_eq!(16, cnt.conns.remaining());
+    }
+
+    #[test]
+    fn test_from_toml_errors() {
+        match CanteenBuilder::from_toml("[limits]\nmax_bodee = 1\n") {
+            Err(ConfigError::Parse(_)) => {},
+            other                      => panic!("expected a parse error, got {:?}", other),
+        }
+
+        match CanteenBuilder::from_toml("[logging]\nlevel = \"loud\"\n") {
+            Err(ConfigError::Invalid(_)) => {},
+            other                        => panic!("expected an invalid value, got {:?}", other),
+        }
+
+        match CanteenBuilder::from_file("/nonexistent/canteen.toml") {
+            Err(ConfigError::Io(_)) => {},
+            other                   => panic!("expected an I/O error, got {:?}", other),
+        }
+
+        match CanteenBuilder::from_toml("[limits]\npreset = \"lax\"\n") {
+            Err(ConfigError::Invalid(_)) => {},
+            other                        => panic!("expected an invalid value, got {:?}", other),
+        }
+
+        let builder = CanteenBuilder::from_toml("[keep_alive]\nenabled = false\n").unwrap();
+        assert_eq!(None, builder.get_config().get_keep_alive());
+    }
+
+    #[test]
+    fn test_limits_preset() {
+        let builder = CanteenBuilder::from_toml(r#"
+            [limits]
+            preset = "strict"
+            max_uri = 4096
+
+            [keep_alive]
+            max_requests = 10
+        "#).unwrap();
+        let config = builder.get_config();
+
+        assert_eq!(Some(1024 * 1024), config.get_max_body());
+        assert_eq!(Some(4096), config.get_max_uri());
+        assert_eq!(Some(&KeepAlive::new().max_requests(10)), config.get_keep_alive());
+
+        let relaxed = CanteenBuilder::new().limits(Limits::relaxed());
+        assert_eq!(Some(Duration::from_secs(30)), relaxed.get_config().get_keep_alive().map(|ka| ka.idle));
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Time sources for the server's timeout and scheduling logic.
+//!
+//! The event loop asks its `Clock` for the current time rather than reading
+//! the system clock directly, so tests can swap in a `ManualClock` and step
+//! time forward without sleeping.
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A source of the current time.
+pub trait Clock: Debug + Send + Sync {
+    /// The current time.
+    fn now(&self) -> Instant;
+}
+
+/// The real, monotonic system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so one
+/// can be handed to a `Canteen` and another kept to drive it.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use canteen::clock::{Clock, ManualClock};
+///
+/// let clock = ManualClock::new();
+/// let start = clock.now();
+///
+/// clock.advance(Duration::from_secs(30));
+/// assert_eq!(Duration::from_secs(30), clock.now() - start);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    base:    Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    /// Create a clock stopped at the current time.
+    pub fn new() -> ManualClock {
+        ManualClock {
+            base:    Instant::now(),
+            elapsed: Arc::new(Mutex::new(Duration::from_secs(0))),
+        }
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.base + *self.elapsed.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_is_shared() {
+        let clock = ManualClock::new();
+        let other = clock.clone();
+        let start = clock.now();
+
+        assert_eq!(start, clock.now());
+        other.advance(Duration::from_millis(250));
+        assert_eq!(start + Duration::from_millis(250), clock.now());
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Optimistic concurrency for REST handlers: checking a request's
+//! `If-Match` and `If-Unmodified-Since` headers against the current version
+//! of the resource it would change, so a client can't overwrite changes it
+//! hasn't seen.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::request::Request;
+use crate::response::Response;
+use crate::utils;
+
+/// The entity tags listed in an `If-Match` or `If-None-Match` header, or
+/// `None` for `*`.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::conditional;
+///
+/// assert_eq!(Some(vec![String::from("\"a\""), String::from("W/\"b\"")]), conditional::parse_etags("\"a\", W/\"b\""));
+/// assert_eq!(None, conditional::parse_etags(" * "));
+/// ```
+pub fn parse_etags(header: &str) -> Option<Vec<String>> {
+    if header.trim() == "*" {
+        return None;
+    }
+
+    Some(header.split(',').map(str::trim).filter(|t| !t.is_empty()).map(String::from).collect())
+}
+
+/// Parse an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn parse_http_date(text: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(text.trim(), "%a, %d %b %Y %H:%M:%S GMT").ok().map(|dt| dt.and_utc())
+}
+
+/// Format a time as an HTTP date, for `Last-Modified` headers.
+pub fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// strong comparison: weak tags never match.
+fn strong_match(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && a == b
+}
+
+// weak comparison: only the opaque parts need to match.
+fn weak_match(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+/// Whether the request's `If-None-Match` header lists `etag`, the current
+/// entity tag of the resource including its quotes, so the client's copy
+/// is current and can be answered with `304 Not Modified`. Returns `None`
+/// if there's no `If-None-Match`, in which case `If-Modified-Since`
+/// decides instead.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{conditional, Request};
+///
+/// let req: Request = "GET /app.js HTTP/1.1\r\nIf-None-Match: W/\"v7\"\r\n\r\n".parse().unwrap();
+///
+/// assert_eq!(Some(true), conditional::none_match(&req, "\"v7\""));
+/// assert_eq!(Some(false), conditional::none_match(&req, "\"v8\""));
+/// ```
+pub fn none_match(req: &Request, etag: &str) -> Option<bool> {
+    let header = req.get_header_combined("If-None-Match")?;
+
+    Some(match parse_etags(&header) {
+        None       => true,
+        Some(tags) => tags.iter().any(|t| weak_match(t, etag)),
+    })
+}
+
+/// Check the request's preconditions against the current version of the
+/// resource: its entity tag, including the quotes, and when it was last
+/// changed, or `None` for either if it has none, or doesn't exist yet.
+/// Returns the `412 Precondition Failed` to send if they don't hold.
+///
+/// `If-Match` wins over `If-Unmodified-Since`, as RFC 9110 says.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Request, Response};
+/// use canteen::{conditional, utils};
+///
+/// fn update(req: &Request) -> Response {
+///     let current = "\"v7\"";
+///
+///     if let Some(res) = conditional::check(req, Some(current), None) {
+///         return res;
+///     }
+///
+///     // ... apply the change ...
+///     let mut res = utils::text("updated");
+///     res.set_header("ETag", "\"v8\"");
+///     res
+/// }
+///
+/// let req: Request = "PUT /doc HTTP/1.1\r\nIf-Match: \"v6\"\r\n\r\n".parse().unwrap();
+/// assert_eq!(412, update(&req).get_status());
+/// ```
+pub fn check(req: &Request, etag: Option<&str>, modified: Option<DateTime<Utc>>) -> Option<Response> {
+    let holds = match req.get_header_combined("If-Match") {
+        Some(header) => match (parse_etags(&header), etag) {
+            (None, current)             => current.is_some(),
+            (Some(_), None)             => false,
+            (Some(tags), Some(current)) => tags.iter().any(|t| strong_match(t, current)),
+        },
+        None         => match (req.get_header("If-Unmodified-Since").and_then(|h| parse_http_date(&h)), modified) {
+            // HTTP dates only have whole seconds
+            (Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+            _                             => true,
+        },
+    };
+
+    match holds {
+        true  => None,
+        false => Some(utils::err_412(req)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn req(header: &str) -> Request {
+        format!("PUT /doc HTTP/1.1\r\n{}\r\n\r\n", header).parse().unwrap()
+    }
+
+    #[test]
+    fn test_if_match() {
+        assert!(check(&req("If-Match: \"a\", \"b\""), Some("\"b\""), None).is_none());
+        assert!(check(&req("If-Match: \"a\""), Some("\"b\""), None).is_some());
+        assert!(check(&req("If-Match: W/\"b\""), Some("W/\"b\""), None).is_some());
+        assert!(check(&req("If-Match: *"), Some("\"b\""), None).is_none());
+        assert!(check(&req("If-Match: *"), None, None).is_some());
+        assert!(check(&req("X-Other: 1"), None, None).is_none());
+    }
+
+    #[test]
+    fn test_none_match() {
+        let get = |header: &str| format!("GET /a HTTP/1.1\r\n{}\r\n\r\n", header).parse::<Request>().unwrap();
+
+        assert_eq!(Some(true), none_match(&get("If-None-Match: \"a\", \"b\""), "\"b\""));
+        assert_eq!(Some(true), none_match(&get("If-None-Match: \"b\""), "W/\"b\""));
+        assert_eq!(Some(true), none_match(&get("If-None-Match: *"), "\"b\""));
+        assert_eq!(Some(false), none_match(&get("If-None-Match: \"a\""), "\"b\""));
+        assert_eq!(None, none_match(&get("X-Other: 1"), "\"b\""));
+    }
+
+    #[test]
+    fn test_if_unmodified_since() {
+        let modified = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let header = |t: DateTime<Utc>| format!("If-Unmodified-Since: {}", http_date(t));
+
+        assert!(check(&req(&header(modified)), Some("\"x\""), Some(modified)).is_none());
+        assert_eq!(412, check(&req(&header(modified - chrono::Duration::seconds(1))), None, Some(modified)).unwrap().get_status());
+        assert!(check(&req("If-Unmodified-Since: yesterday"), None, Some(modified)).is_none());
+
+        // If-Match takes precedence
+        let both = format!("If-Match: \"x\"\r\n{}", header(modified - chrono::Duration::days(1)));
+        assert!(check(&req(&both), Some("\"x\""), Some(modified)).is_none());
+    }
+}
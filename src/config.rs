@@ -0,0 +1,1019 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Server-wide settings shared between the event loop and the workers.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use log::LevelFilter;
+
+use crate::request::Request;
+use crate::response::Response;
+use crate::accesslog::AccessLog;
+use crate::ipfilter::IpFilter;
+use crate::route::TrailingSlash;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What to send in the `Server` response header.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ServerHeader {
+    /// `canteen/<version>`.
+    #[default]
+    Default,
+    /// A fixed value, e.g. `"nginx"`.
+    Custom(String),
+    /// A value picked at random from the list for each response.
+    Random(Vec<String>),
+    /// No `Server` header at all.
+    Suppress,
+}
+
+impl ServerHeader {
+    /// The header value to send, if any.
+    pub fn value(&self) -> Option<String> {
+        match *self {
+            ServerHeader::Default          => Some(format!("canteen/{}", VERSION)),
+            ServerHeader::Custom(ref val)  => Some(val.clone()),
+            ServerHeader::Random(ref vals) => {
+                if vals.is_empty() {
+                    return None;
+                }
+
+                let pick = RandomState::new().build_hasher().finish() as usize % vals.len();
+                Some(vals[pick].clone())
+            },
+            ServerHeader::Suppress         => None,
+        }
+    }
+}
+
+/// Limits on persistent connections, set with `Canteen::set_keep_alive`.
+/// They are advertised to clients in the `Keep-Alive` response header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeepAlive {
+    pub(crate) max_requests: Option<usize>,
+    pub(crate) max_age:      Option<Duration>,
+    pub(crate) idle:         Duration,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        KeepAlive {
+            max_requests: None,
+            max_age:      None,
+            idle:         Duration::from_secs(5),
+        }
+    }
+}
+
+impl KeepAlive {
+    /// Keep connections open for as long as clients like, closing them
+    /// after 5 seconds without a request.
+    pub fn new() -> KeepAlive {
+        KeepAlive::default()
+    }
+
+    /// Close a connection once it has served `max` requests.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::config::KeepAlive;
+    ///
+    /// let ka = KeepAlive::new().max_requests(100);
+    /// ```
+    pub fn max_requests(mut self, max: usize) -> KeepAlive {
+        self.max_requests = Some(max.max(1));
+
+        self
+    }
+
+    /// Close a connection after the first response sent once it has been
+    /// open for `age`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::config::KeepAlive;
+    ///
+    /// let ka = KeepAlive::new().max_age(Duration::from_secs(300));
+    /// ```
+    pub fn max_age(mut self, age: Duration) -> KeepAlive {
+        self.max_age = Some(age);
+
+        self
+    }
+
+    /// Close a connection that has gone `idle` without a new request.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::config::KeepAlive;
+    ///
+    /// let ka = KeepAlive::new().idle_timeout(Duration::from_secs(15));
+    /// ```
+    pub fn idle_timeout(mut self, idle: Duration) -> KeepAlive {
+        self.idle = idle;
+
+        self
+    }
+
+    // the `Keep-Alive` header value for a connection that has served
+    // `served` requests, or None if it should be closed after this one.
+    pub(crate) fn params(&self, served: usize, age: Duration) -> Option<String> {
+        if self.max_requests.map(|max| served >= max).unwrap_or(false) {
+            return None;
+        }
+
+        if self.max_age.map(|max| age >= max).unwrap_or(false) {
+            return None;
+        }
+
+        let mut params = format!("timeout={}", self.idle.as_secs());
+
+        if let Some(max) = self.max_requests {
+            params.push_str(&format!(", max={}", max - served));
+        }
+
+        Some(params)
+    }
+}
+
+/// A `Strict-Transport-Security` policy, sent with every response over TLS
+/// so browsers go straight to HTTPS next time. Browsers ignore the header
+/// when it arrives over plain HTTP.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hsts {
+    pub(crate) max_age:    u32,
+    pub(crate) subdomains: bool,
+}
+
+impl Hsts {
+    /// Have browsers use HTTPS for this host for the next `max_age`
+    /// seconds.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::config::Hsts;
+    ///
+    /// let hsts = Hsts::new(31536000);
+    /// ```
+    pub fn new(max_age: u32) -> Hsts {
+        Hsts { max_age, subdomains: false }
+    }
+
+    /// Extend the policy to every subdomain of the host. Only set this if
+    /// all of them are served over HTTPS.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::config::Hsts;
+    ///
+    /// let hsts = Hsts::new(31536000).include_subdomains(true);
+    /// ```
+    pub fn include_subdomains(mut self, subdomains: bool) -> Hsts {
+        self.subdomains = subdomains;
+
+        self
+    }
+
+    // the `Strict-Transport-Security` header value.
+    pub(crate) fn value(&self) -> String {
+        match self.subdomains {
+            true  => format!("max-age={}; includeSubDomains", self.max_age),
+            false => format!("max-age={}", self.max_age),
+        }
+    }
+}
+
+/// A bundle of limits that protect the server from oversized or slow
+/// requests and greedy clients, applied with `CanteenBuilder::limits`.
+/// Start from one of the presets and adjust what doesn't fit.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::builder::CanteenBuilder;
+/// use canteen::config::Limits;
+///
+/// let cnt = CanteenBuilder::new()
+///     .limits(Limits::strict().max_body(8 * 1024 * 1024))
+///     .build();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    pub(crate) max_body:   Option<usize>,
+    pub(crate) max_header: Option<usize>,
+    pub(crate) max_uri:    Option<usize>,
+    pub(crate) timeout:    Option<Duration>,
+    pub(crate) idle:       Duration,
+    pub(crate) per_ip:     Option<usize>,
+}
+
+impl Limits {
+    /// For servers open to the internet: 1 MiB bodies, 8 KiB of headers,
+    /// 2 KiB request targets, 10 second handlers, connections idle for 5
+    /// seconds closed, and 16 connections per client IP.
+    pub fn strict() -> Limits {
+        Limits {
+            max_body:   Some(1024 * 1024),
+            max_header: Some(8 * 1024),
+            max_uri:    Some(2 * 1024),
+            timeout:    Some(Duration::from_secs(10)),
+            idle:       Duration::from_secs(5),
+            per_ip:     Some(16),
+        }
+    }
+
+    /// For public servers that take uploads or run long requests: 64 MiB
+    /// bodies, 64 KiB of headers, 16 KiB request targets, 2 minute
+    /// handlers, 30 seconds idle, and 256 connections per client IP.
+    pub fn relaxed() -> Limits {
+        Limits {
+            max_body:   Some(64 * 1024 * 1024),
+            max_header: Some(64 * 1024),
+            max_uri:    Some(16 * 1024),
+            timeout:    Some(Duration::from_secs(120)),
+            idle:       Duration::from_secs(30),
+            per_ip:     Some(256),
+        }
+    }
+
+    /// For services only reachable by trusted clients, where a few
+    /// sources, e.g. load balancers, make many connections: 256 MiB bodies,
+    /// 64 KiB of headers and request targets, no handler deadline, 60
+    /// seconds idle, and no per-IP cap.
+    pub fn internal() -> Limits {
+        Limits {
+            max_body:   Some(256 * 1024 * 1024),
+            max_header: Some(64 * 1024),
+            max_uri:    Some(64 * 1024),
+            timeout:    None,
+            idle:       Duration::from_secs(60),
+            per_ip:     None,
+        }
+    }
+
+    /// Set the largest request body, in bytes.
+    pub fn max_body(mut self, bytes: usize) -> Limits {
+        self.max_body = Some(bytes);
+
+        self
+    }
+
+    /// Set the largest request line and headers, in bytes.
+    pub fn max_header(mut self, bytes: usize) -> Limits {
+        self.max_header = Some(bytes);
+
+        self
+    }
+
+    /// Set the longest request target, in bytes.
+    pub fn max_uri(mut self, bytes: usize) -> Limits {
+        self.max_uri = Some(bytes);
+
+        self
+    }
+
+    /// Set how long handlers may run.
+    pub fn handler_timeout(mut self, timeout: Duration) -> Limits {
+        self.timeout = Some(timeout);
+
+        self
+    }
+
+    /// Set how long a kept-alive connection may wait for its next request.
+    pub fn idle_timeout(mut self, idle: Duration) -> Limits {
+        self.idle = idle;
+
+        self
+    }
+
+    /// Set the most connections a single client IP may have open.
+    pub fn max_connections_per_ip(mut self, max: usize) -> Limits {
+        self.per_ip = Some(max);
+
+        self
+    }
+
+    // look a preset up by name, for configuration files.
+    pub(crate) fn preset(name: &str) -> Option<Limits> {
+        match name {
+            "strict"   => Some(Limits::strict()),
+            "relaxed"  => Some(Limits::relaxed()),
+            "internal" => Some(Limits::internal()),
+            _          => None,
+        }
+    }
+
+    pub(crate) fn apply(&self, config: &mut Config) {
+        config.max_body = self.max_body;
+        config.max_header = self.max_header;
+        config.max_uri = self.max_uri;
+        config.timeout = self.timeout;
+        config.per_ip = self.per_ip;
+
+        if let Some(ref mut ka) = config.keep_alive {
+            ka.idle = self.idle;
+        }
+    }
+}
+
+// whether a content type is text, and so needs a charset to be read right.
+fn is_text(ctype: &str) -> bool {
+    let mime = ctype.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+
+    mime.starts_with("text/") || mime == "application/javascript" || mime == "application/xml"
+        || mime == "image/svg+xml"
+}
+
+/// Settings that apply to every request the server handles. These can be
+/// changed while the server runs; see `Canteen::on_reload`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub(crate) rate_limit:    Option<u64>,
+    pub(crate) server_header: ServerHeader,
+    pub(crate) timeout:       Option<Duration>,
+    pub(crate) head_timeout:  Option<Duration>,
+    pub(crate) body_timeout:  Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    pub(crate) max_body:      Option<usize>,
+    pub(crate) max_header:    Option<usize>,
+    pub(crate) max_uri:       Option<usize>,
+    pub(crate) slow:          Option<Duration>,
+    pub(crate) queue_wait:    Option<Duration>,
+    pub(crate) read_initial:  usize,
+    pub(crate) read_max:      usize,
+    pub(crate) workers:       usize,
+    pub(crate) max_conns:     usize,
+    pub(crate) per_ip:        Option<usize>,
+    pub(crate) keep_alive:    Option<KeepAlive>,
+    pub(crate) trace:         bool,
+    pub(crate) debug:         bool,
+    pub(crate) charset:       Option<String>,
+    pub(crate) inflate:       Option<usize>,
+    pub(crate) headers:       Vec<(String, String)>,
+    pub(crate) slash:         TrailingSlash,
+    pub(crate) route_cache:   usize,
+    pub(crate) ip_filter:     Option<IpFilter>,
+    pub(crate) access_log:    Option<AccessLog>,
+    pub(crate) log_level:     Option<LevelFilter>,
+    pub(crate) static_cache:  Option<String>,
+    pub(crate) hosts:         Option<Vec<String>>,
+    pub(crate) hsts:          Option<Hsts>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            rate_limit:    None,
+            server_header: ServerHeader::Default,
+            timeout:       None,
+            head_timeout:  Some(Duration::from_secs(10)),
+            body_timeout:  Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            max_body:      Some(64 * 1024 * 1024),
+            max_header:    Some(64 * 1024),
+            max_uri:       Some(16 * 1024),
+            slow:          None,
+            queue_wait:    None,
+            read_initial:  2048,
+            read_max:      64 * 1024,
+            workers:       255,
+            max_conns:     2048,
+            per_ip:        None,
+            keep_alive:    Some(KeepAlive::default()),
+            trace:         false,
+            debug:         false,
+            charset:       Some(String::from("utf-8")),
+            inflate:       Some(16 * 1024 * 1024),
+            headers:       Vec::new(),
+            slash:         TrailingSlash::Ignore,
+            route_cache:   1024,
+            ip_filter:     None,
+            access_log:    None,
+            log_level:     None,
+            static_cache:  None,
+            hosts:         None,
+            hsts:          None,
+        }
+    }
+}
+
+impl Config {
+    /// The default bandwidth limit per connection, in bytes per second.
+    pub fn get_rate_limit(&self) -> Option<u64> {
+        self.rate_limit
+    }
+
+    /// Set or clear the default bandwidth limit per connection.
+    pub fn set_rate_limit(&mut self, bytes_per_sec: Option<u64>) -> &mut Config {
+        self.rate_limit = bytes_per_sec;
+
+        self
+    }
+
+    /// What is sent in the `Server` header.
+    pub fn get_server_header(&self) -> &ServerHeader {
+        &self.server_header
+    }
+
+    /// Set what is sent in the `Server` header.
+    pub fn set_server_header(&mut self, header: ServerHeader) -> &mut Config {
+        self.server_header = header;
+
+        self
+    }
+
+    /// How long a handler may run before the client gets a 504.
+    pub fn get_handler_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Set or clear the default handler deadline.
+    pub fn set_handler_timeout(&mut self, timeout: Option<Duration>) -> &mut Config {
+        self.timeout = timeout;
+
+        self
+    }
+
+    /// How long a client has to send a request's line and headers.
+    pub fn get_header_timeout(&self) -> Option<Duration> {
+        self.head_timeout
+    }
+
+    /// Set how long a client has to send a request's line and headers,
+    /// from its first byte, or from the connection opening for its first
+    /// request. `None` waits for as long as it takes.
+    pub fn set_header_timeout(&mut self, timeout: Option<Duration>) -> &mut Config {
+        self.head_timeout = timeout;
+
+        self
+    }
+
+    /// How long a client may pause while sending a request's body.
+    pub fn get_body_timeout(&self) -> Option<Duration> {
+        self.body_timeout
+    }
+
+    /// Set how long a client may go without sending any of a request's
+    /// body, once it has started. `None` waits for as long as it takes.
+    pub fn set_body_timeout(&mut self, timeout: Option<Duration>) -> &mut Config {
+        self.body_timeout = timeout;
+
+        self
+    }
+
+    /// How long a client may go without reading any of its response.
+    pub fn get_write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// Set how long a client may go without reading any of its response
+    /// before the connection is closed. `None` waits for as long as it
+    /// takes.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> &mut Config {
+        self.write_timeout = timeout;
+
+        self
+    }
+
+    /// The largest request body, in bytes, accepted by default: 64 MiB
+    /// unless set.
+    pub fn get_max_body(&self) -> Option<usize> {
+        self.max_body
+    }
+
+    /// Set or clear the default request body limit.
+    pub fn set_max_body(&mut self, bytes: Option<usize>) -> &mut Config {
+        self.max_body = bytes;
+
+        self
+    }
+
+    /// The largest request line and headers, in bytes, accepted: 64 KiB
+    /// unless set.
+    pub fn get_max_header(&self) -> Option<usize> {
+        self.max_header
+    }
+
+    /// Set or clear the request header limit. Larger requests are answered
+    /// with `431 Request Header Fields Too Large`.
+    pub fn set_max_header(&mut self, bytes: Option<usize>) -> &mut Config {
+        self.max_header = bytes;
+
+        self
+    }
+
+    /// The longest request target, in bytes, accepted: 16 KiB unless set.
+    pub fn get_max_uri(&self) -> Option<usize> {
+        self.max_uri
+    }
+
+    /// Set or clear the request target limit. Longer targets are answered
+    /// with `414 URI Too Long`.
+    pub fn set_max_uri(&mut self, bytes: Option<usize>) -> &mut Config {
+        self.max_uri = bytes;
+
+        self
+    }
+
+    /// How long a handler may take before the request is logged as slow.
+    pub fn get_slow_threshold(&self) -> Option<Duration> {
+        self.slow
+    }
+
+    /// Set or clear the slow request threshold.
+    pub fn set_slow_threshold(&mut self, threshold: Option<Duration>) -> &mut Config {
+        self.slow = threshold;
+
+        self
+    }
+
+    /// How long jobs may wait for a worker before it is logged.
+    pub fn get_queue_wait_threshold(&self) -> Option<Duration> {
+        self.queue_wait
+    }
+
+    /// Set or clear the queue wait threshold.
+    pub fn set_queue_wait_threshold(&mut self, threshold: Option<Duration>) -> &mut Config {
+        self.queue_wait = threshold;
+
+        self
+    }
+
+    /// The size of the first read from a connection, and the most that a
+    /// single read may grow to.
+    pub fn get_read_buffer(&self) -> (usize, usize) {
+        (self.read_initial, self.read_max)
+    }
+
+    /// Set the read sizes. Each read that fills its buffer doubles the size
+    /// of the next, up to `max`. Both are at least one byte.
+    pub fn set_read_buffer(&mut self, initial: usize, max: usize) -> &mut Config {
+        self.read_initial = initial.max(1);
+        self.read_max = max.max(self.read_initial);
+
+        self
+    }
+
+    /// How many worker threads run handlers.
+    pub fn get_workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Set the number of worker threads, at least one.
+    pub fn set_workers(&mut self, workers: usize) -> &mut Config {
+        self.workers = workers.max(1);
+
+        self
+    }
+
+    /// The most connections the server will have open at once.
+    pub fn get_max_connections(&self) -> usize {
+        self.max_conns
+    }
+
+    /// Set the connection limit, at least one. It's read when the server
+    /// is created, so a reload doesn't change it.
+    pub fn set_max_connections(&mut self, max: usize) -> &mut Config {
+        self.max_conns = max.max(1);
+
+        self
+    }
+
+    /// The most connections a single client IP may have open at once.
+    pub fn get_max_connections_per_ip(&self) -> Option<usize> {
+        self.per_ip
+    }
+
+    /// Set or clear the per-IP connection limit.
+    pub fn set_max_connections_per_ip(&mut self, max: Option<usize>) -> &mut Config {
+        self.per_ip = max;
+
+        self
+    }
+
+    /// The limits on persistent connections, or None if every connection
+    /// is closed after one request.
+    pub fn get_keep_alive(&self) -> Option<&KeepAlive> {
+        self.keep_alive.as_ref()
+    }
+
+    /// Set the keep-alive limits, or turn keep-alive off.
+    pub fn set_keep_alive(&mut self, keep_alive: Option<KeepAlive>) -> &mut Config {
+        self.keep_alive = keep_alive;
+
+        self
+    }
+
+    /// Whether TRACE requests are echoed back.
+    pub fn get_trace(&self) -> bool {
+        self.trace
+    }
+
+    /// Echo TRACE requests back, or answer them with `405 Method Not
+    /// Allowed`.
+    pub fn set_trace(&mut self, enabled: bool) -> &mut Config {
+        self.trace = enabled;
+
+        self
+    }
+
+    /// What happens to requests whose path differs from a route's only by a
+    /// trailing slash.
+    pub fn get_trailing_slash(&self) -> TrailingSlash {
+        self.slash
+    }
+
+    /// Set what happens to requests whose path differs from a route's only
+    /// by a trailing slash.
+    pub fn set_trailing_slash(&mut self, slash: TrailingSlash) -> &mut Config {
+        self.slash = slash;
+
+        self
+    }
+
+    /// How many request paths have their route remembered.
+    pub fn get_route_cache_size(&self) -> usize {
+        self.route_cache
+    }
+
+    /// Set how many request paths have their route remembered, dropping the
+    /// least recently used beyond that; zero turns the cache off.
+    pub fn set_route_cache_size(&mut self, size: usize) -> &mut Config {
+        self.route_cache = size;
+
+        self
+    }
+
+    /// The addresses connections are accepted from.
+    pub fn get_ip_filter(&self) -> Option<&IpFilter> {
+        self.ip_filter.as_ref()
+    }
+
+    /// Only accept connections from the addresses `filter` permits, or from
+    /// anywhere with `None`.
+    pub fn set_ip_filter(&mut self, filter: Option<IpFilter>) -> &mut Config {
+        self.ip_filter = filter;
+
+        self
+    }
+
+    /// Where requests are logged.
+    pub fn get_access_log(&self) -> Option<&AccessLog> {
+        self.access_log.as_ref()
+    }
+
+    /// Log every request to `log`, or stop logging them with `None`.
+    pub fn set_access_log(&mut self, log: Option<AccessLog>) -> &mut Config {
+        self.access_log = log;
+
+        self
+    }
+
+    /// Whether panicking handlers get a detailed error page.
+    pub fn get_debug(&self) -> bool {
+        self.debug
+    }
+
+    /// Show detailed error pages when handlers panic, or keep to plain
+    /// `500 Internal Server Error` responses.
+    pub fn set_debug(&mut self, enabled: bool) -> &mut Config {
+        self.debug = enabled;
+
+        self
+    }
+
+    /// The largest size, in bytes, that compressed request bodies are
+    /// decoded to, or `None` if they're passed to handlers as they are.
+    pub fn get_max_decoded_body(&self) -> Option<usize> {
+        self.inflate
+    }
+
+    /// Decode request bodies sent with `Content-Encoding: gzip` or
+    /// `deflate` up to `bytes`, answering larger ones with `413 Content
+    /// Too Large`, or turn decoding off with `None`. Defaults to 16 MiB;
+    /// the body size limit applies too, if it's smaller.
+    pub fn set_max_decoded_body(&mut self, bytes: Option<usize>) -> &mut Config {
+        self.inflate = bytes;
+
+        self
+    }
+
+    /// The charset added to text content types that don't name one.
+    pub fn get_default_charset(&self) -> Option<&str> {
+        self.charset.as_deref()
+    }
+
+    /// Set the charset added to the content type of text responses, e.g.
+    /// `text/html` becomes `text/html; charset=utf-8`, or leave content
+    /// types as they are. Defaults to `utf-8`.
+    pub fn set_default_charset(&mut self, charset: Option<&str>) -> &mut Config {
+        self.charset = charset.map(String::from);
+
+        self
+    }
+
+    /// The headers added to every response that doesn't set them itself.
+    pub fn get_default_headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Add `name` to every response that doesn't set it itself, replacing
+    /// any earlier default value for it.
+    pub fn set_default_header(&mut self, name: &str, value: &str) -> &mut Config {
+        self.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+        self.headers.push((String::from(name), String::from(value)));
+
+        self
+    }
+
+    /// Stop adding `name` to every response.
+    pub fn remove_default_header(&mut self, name: &str) -> &mut Config {
+        self.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+
+        self
+    }
+
+    /// The most verbose level of log messages emitted, if the server sets
+    /// it rather than leaving it to the logger.
+    pub fn get_log_level(&self) -> Option<LevelFilter> {
+        self.log_level
+    }
+
+    /// Set the most verbose level of log messages emitted, or leave it as
+    /// the logger has it with `None`.
+    pub fn set_log_level(&mut self, level: Option<LevelFilter>) -> &mut Config {
+        self.log_level = level;
+
+        self
+    }
+
+    /// The `Cache-Control` header given to files from static mounts.
+    pub fn get_static_cache_control(&self) -> Option<&str> {
+        self.static_cache.as_deref()
+    }
+
+    /// Send `policy` as the `Cache-Control` header of files served from
+    /// static mounts, unless the mount sets one itself, as it does for
+    /// fingerprinted assets.
+    pub fn set_static_cache_control(&mut self, policy: Option<&str>) -> &mut Config {
+        self.static_cache = policy.map(String::from);
+
+        self
+    }
+
+    /// The host names requests may be addressed to, if only some are.
+    pub fn get_allowed_hosts(&self) -> Option<&[String]> {
+        self.hosts.as_deref()
+    }
+
+    /// Only answer requests whose `Host` header, without its port, is one
+    /// of `hosts`, compared without regard to case. Others get a `400 Bad
+    /// Request`. With `None`, any host is answered.
+    pub fn set_allowed_hosts(&mut self, hosts: Option<&[&str]>) -> &mut Config {
+        self.hosts = hosts.map(|hosts| hosts.iter().map(|h| h.to_ascii_lowercase()).collect());
+
+        self
+    }
+
+    // whether `req` is addressed to a host that's answered.
+    pub(crate) fn host_allowed(&self, req: &Request) -> bool {
+        let hosts = match self.hosts {
+            Some(ref hosts) => hosts,
+            None            => return true,
+        };
+        let host = req.get_header("Host").unwrap_or_default().to_ascii_lowercase();
+
+        // drop any port, taking care not to mangle IPv6 literals
+        let name = match host.rfind(':') {
+            Some(i) if !host[i..].contains(']') => &host[..i],
+            _                                   => &host[..],
+        };
+
+        hosts.iter().any(|h| h == name)
+    }
+
+    // give a response from a static mount the cache policy, if there is one
+    // and the mount didn't set its own.
+    pub(crate) fn cache_static(&self, res: &mut Response) {
+        if let Some(ref policy) = self.static_cache {
+            let status = res.get_status();
+
+            if (status.is_success() || status == 304) && res.get_header("Cache-Control").is_none() {
+                res.set_header("Cache-Control", policy);
+            }
+        }
+    }
+
+    // the content type with the default charset added, if it's a text type
+    // without one.
+    pub(crate) fn content_type(&self, ctype: &str) -> String {
+        if let Some(ref charset) = self.charset {
+            if is_text(ctype) && !ctype.to_ascii_lowercase().contains("charset=") {
+                return format!("{}; charset={}", ctype, charset);
+            }
+        }
+
+        String::from(ctype)
+    }
+
+    /// The `Strict-Transport-Security` policy sent over TLS.
+    pub fn get_hsts(&self) -> Option<&Hsts> {
+        self.hsts.as_ref()
+    }
+
+    /// Set or clear the `Strict-Transport-Security` policy sent over TLS.
+    pub fn set_hsts(&mut self, hsts: Option<Hsts>) -> &mut Config {
+        self.hsts = hsts;
+
+        self
+    }
+
+    /// Apply the server-wide settings to a finished response.
+    pub(crate) fn finish(&self, res: &mut Response) {
+        match self.server_header.value() {
+            Some(val) => res.set_header("Server", &val),
+            None      => res.remove_header("Server"),
+        }
+
+        for (name, value) in &self.headers {
+            if !res.headers().any(|(k, _)| k.eq_ignore_ascii_case(name)) {
+                res.set_header(name, value);
+            }
+        }
+
+        let ctype = self.content_type(res.get_content_type());
+
+        res.set_content_type(&ctype);
+    }
+
+    // add the HSTS policy, if any, to a response sent over TLS.
+    pub(crate) fn finish_secure(&self, res: &mut Response) {
+        if let Some(ref hsts) = self.hsts {
+            if res.get_header("Strict-Transport-Security").is_none() {
+                res.set_header("Strict-Transport-Security", &hsts.value());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_header_values() {
+        assert_eq!(Some(format!("canteen/{}", VERSION)), ServerHeader::Default.value());
+        assert_eq!(Some(String::from("nginx")), ServerHeader::Custom(String::from("nginx")).value());
+        assert_eq!(None, ServerHeader::Suppress.value());
+        assert_eq!(None, ServerHeader::Random(vec![]).value());
+
+        let choices = vec![String::from("a"), String::from("b")];
+        for _ in 0..10 {
+            assert!(choices.contains(&ServerHeader::Random(choices.clone()).value().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_finish_server_header() {
+        let mut cfg = Config::default();
+        let mut res = Response::new();
+
+        cfg.server_header = ServerHeader::Suppress;
+        cfg.finish(&mut res);
+        assert!(!String::from_utf8(res.gen_output()).unwrap().contains("Server:"));
+
+        cfg.server_header = ServerHeader::Custom(String::from("teapot"));
+        cfg.finish(&mut res);
+        assert!(String::from_utf8(res.gen_output()).unwrap().contains("Server: teapot\r\n"));
+    }
+
+    #[test]
+    fn test_keep_alive_params() {
+        let ka = KeepAlive::new();
+        assert_eq!(Some(String::from("timeout=5")), ka.params(1000, Duration::from_secs(3600)));
+
+        let ka = KeepAlive::new().max_requests(3).idle_timeout(Duration::from_secs(10));
+        assert_eq!(Some(String::from("timeout=10, max=2")), ka.params(1, Duration::from_secs(0)));
+        assert_eq!(Some(String::from("timeout=10, max=1")), ka.params(2, Duration::from_secs(0)));
+        assert_eq!(None, ka.params(3, Duration::from_secs(0)));
+
+        let ka = KeepAlive::new().max_age(Duration::from_secs(60));
+        assert!(ka.params(1, Duration::from_secs(59)).is_some());
+        assert_eq!(None, ka.params(1, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_finish_secure_hsts() {
+        let mut cfg = Config::default();
+        let mut res = Response::new();
+
+        cfg.finish_secure(&mut res);
+        assert_eq!(None, res.get_header("Strict-Transport-Security"));
+
+        cfg.set_hsts(Some(Hsts::new(3600)));
+        cfg.finish_secure(&mut res);
+        assert_eq!(Some("max-age=3600"), res.get_header("Strict-Transport-Security"));
+
+        let mut res = Response::new();
+
+        cfg.set_hsts(Some(Hsts::new(3600).include_subdomains(true)));
+        cfg.finish_secure(&mut res);
+        assert_eq!(Some("max-age=3600; includeSubDomains"), res.get_header("Strict-Transport-Security"));
+    }
+
+    #[test]
+    fn test_limits_apply() {
+        let mut cfg = Config::default();
+
+        // the defaults are finite too
+        assert_eq!(Some(64 * 1024 * 1024), cfg.get_max_body());
+        assert_eq!(Some(64 * 1024), cfg.get_max_header());
+        assert_eq!(Some(16 * 1024), cfg.get_max_uri());
+
+        Limits::strict().max_body(10).apply(&mut cfg);
+        assert_eq!(Some(10), cfg.get_max_body());
+        assert_eq!(Some(8 * 1024), cfg.get_max_header());
+        assert_eq!(Some(2 * 1024), cfg.get_max_uri());
+        assert_eq!(Some(16), cfg.get_max_connections_per_ip());
+        assert_eq!(Duration::from_secs(5), cfg.get_keep_alive().unwrap().idle);
+
+        Limits::internal().apply(&mut cfg);
+        assert_eq!(None, cfg.get_handler_timeout());
+        assert_eq!(None, cfg.get_max_connections_per_ip());
+
+        cfg.set_keep_alive(None);
+        Limits::relaxed().apply(&mut cfg);
+        assert_eq!(None, cfg.get_keep_alive());
+        assert_eq!(Some(Limits::relaxed()), Limits::preset("relaxed"));
+        assert_eq!(None, Limits::preset("lax"));
+    }
+
+    #[test]
+    fn test_default_charset() {
+        let mut cfg = Config::default();
+        let finished = |cfg: &Config, ctype: &str| {
+            let mut res = Response::new();
+
+            res.set_content_type(ctype);
+            cfg.finish(&mut res);
+            String::from(res.get_content_type())
+        };
+
+        assert_eq!("text/html; charset=utf-8", finished(&cfg, "text/html"));
+        assert_eq!("application/javascript; charset=utf-8", finished(&cfg, "application/javascript"));
+        assert_eq!("text/plain; Charset=latin1", finished(&cfg, "text/plain; Charset=latin1"));
+        assert_eq!("application/json", finished(&cfg, "application/json"));
+        assert_eq!("image/png", finished(&cfg, "image/png"));
+
+        cfg.set_default_charset(Some("iso-8859-1"));
+        assert_eq!("text/css; charset=iso-8859-1", finished(&cfg, "text/css"));
+
+        cfg.set_default_charset(None);
+        assert_eq!("text/css", finished(&cfg, "text/css"));
+    }
+
+    #[test]
+    fn test_pool_sizes() {
+        let mut cfg = Config::default();
+
+        assert_eq!((255, 2048), (cfg.get_workers(), cfg.get_max_connections()));
+
+        cfg.set_workers(0).set_max_connections(0);
+        assert_eq!((1, 1), (cfg.get_workers(), cfg.get_max_connections()));
+    }
+
+    #[test]
+    fn test_default_headers() {
+        let mut cfg = Config::default();
+
+        cfg.set_default_header("X-Environment", "staging")
+           .set_default_header("Cache-Control", "no-store")
+           .set_default_header("x-environment", "prod");
+
+        let mut res = Response::new();
+        res.set_header("cache-control", "max-age=60");
+        cfg.finish(&mut res);
+
+        assert_eq!(Some("prod"), res.get_header("x-environment"));
+        assert_eq!(Some("max-age=60"), res.get_header("cache-control"));
+        assert_eq!(None, res.get_header("Cache-Control"));
+
+        cfg.remove_default_header("X-ENVIRONMENT");
+        assert_eq!(1, cfg.get_default_headers().len());
+    }
+}
@@ -0,0 +1,296 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! HTTP cookies (RFC 6265).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::conditional;
+
+/// When browsers send a cookie with requests from other sites.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    /// Only with requests from the site that set it.
+    Strict,
+    /// Also when following a link from another site.
+    Lax,
+    /// With every request. Browsers ignore this unless the cookie is
+    /// also `Secure`.
+    None,
+}
+
+impl SameSite {
+    /// The attribute's value.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax    => "Lax",
+            SameSite::None   => "None",
+        }
+    }
+}
+
+/// A cookie to set on the client, with `Response::set_cookie`.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use canteen::cookie::{Cookie, SameSite};
+///
+/// let cookie = Cookie::new("session", "abc123")
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .secure(true)
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+///
+/// assert_eq!("session=abc123; Max-Age=3600; Path=/; Secure; HttpOnly; SameSite=Lax", cookie.to_string());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    name:      String,
+    value:     String,
+    max_age:   Option<Duration>,
+    expires:   Option<DateTime<Utc>>,
+    path:      Option<String>,
+    domain:    Option<String>,
+    secure:    bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// A cookie named `name`, with no attributes, so the browser keeps it
+    /// until it's closed.
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name:      String::from(name),
+            value:     String::from(value),
+            max_age:   None,
+            expires:   None,
+            path:      None,
+            domain:    None,
+            secure:    false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that removes the one named `name` from the client. Its
+    /// path and domain must match the cookie being removed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::cookie::Cookie;
+    ///
+    /// let cookie = Cookie::removal("session").path("/");
+    ///
+    /// assert!(cookie.to_string().starts_with("session=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"));
+    /// ```
+    pub fn removal(name: &str) -> Cookie {
+        Cookie::new(name, "").max_age(Duration::from_secs(0)).expires(DateTime::UNIX_EPOCH)
+    }
+
+    /// The cookie's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The cookie's value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Keep the cookie for `age`, from when it's received.
+    pub fn max_age(mut self, age: Duration) -> Cookie {
+        self.max_age = Some(age);
+
+        self
+    }
+
+    /// Keep the cookie until `time`. Clients that understand `Max-Age`
+    /// prefer it to this.
+    pub fn expires(mut self, time: DateTime<Utc>) -> Cookie {
+        self.expires = Some(time);
+
+        self
+    }
+
+    /// Only send the cookie for paths under `path`.
+    pub fn path(mut self, path: &str) -> Cookie {
+        self.path = Some(String::from(path));
+
+        self
+    }
+
+    /// Send the cookie to `domain` and its subdomains, rather than only to
+    /// the host that set it.
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        self.domain = Some(String::from(domain));
+
+        self
+    }
+
+    /// Only send the cookie over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+
+        self
+    }
+
+    /// Hide the cookie from scripts.
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+
+        self
+    }
+
+    /// Limit when the cookie is sent with requests from other sites.
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+
+        self
+    }
+}
+
+// whether `b` may be in a cookie's name: a token, as in RFC 7230.
+fn name_byte(b: u8) -> bool {
+    b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b)
+}
+
+// whether `b` may be in a cookie's value unquoted.
+fn value_byte(b: u8) -> bool {
+    b.is_ascii_graphic() && !b"\",;\\".contains(&b)
+}
+
+// whether `b` may be in an attribute's value.
+fn attribute_byte(b: u8) -> bool {
+    (b == b' ' || b.is_ascii_graphic()) && b != b';'
+}
+
+// percent-encode the bytes of `text` that `allowed` refuses, so none of them
+// can end the cookie early or start a header of their own.
+fn encode(text: &str, allowed: fn(u8) -> bool) -> String {
+    let mut out = String::new();
+
+    for &b in text.as_bytes() {
+        match allowed(b) {
+            true  => out.push(b as char),
+            false => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    out
+}
+
+/// Formats the cookie as the value of a `Set-Cookie` header. Characters a
+/// name, value, path or domain can't hold, such as `;` or a line break, are
+/// percent-encoded.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", encode(&self.name, name_byte), encode(&self.value, value_byte))?;
+
+        if let Some(age) = self.max_age {
+            write!(f, "; Max-Age={}", age.as_secs())?;
+        }
+        if let Some(time) = self.expires {
+            write!(f, "; Expires={}", conditional::http_date(time))?;
+        }
+        if let Some(ref path) = self.path {
+            write!(f, "; Path={}", encode(path, attribute_byte))?;
+        }
+        if let Some(ref domain) = self.domain {
+            write!(f, "; Domain={}", encode(domain, attribute_byte))?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the value of a `Cookie` header into a map of names to values.
+/// Surrounding quotes are removed from values, and pairs without a name
+/// are skipped. If a name appears more than once, the first value wins, as
+/// browsers send the cookie with the most specific path first.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::cookie;
+///
+/// let jar = cookie::parse("session=abc123; theme=\"dark\"; session=old");
+///
+/// assert_eq!(Some(&String::from("abc123")), jar.get("session"));
+/// assert_eq!(Some(&String::from("dark")), jar.get("theme"));
+/// ```
+pub fn parse(header: &str) -> HashMap<String, String> {
+    let mut jar = HashMap::new();
+
+    for pair in header.split(';') {
+        let (name, value) = match pair.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None                => continue,
+        };
+
+        if name.is_empty() {
+            continue;
+        }
+
+        let value = match value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            true  => &value[1..value.len() - 1],
+            false => value,
+        };
+
+        jar.entry(String::from(name)).or_insert_with(|| String::from(value));
+    }
+
+    jar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let jar = parse(" a=1;b = 2 ;; =nameless; flag; c=\"\"; d=x=y");
+
+        assert_eq!(4, jar.len());
+        assert_eq!("1", jar["a"]);
+        assert_eq!("2", jar["b"]);
+        assert_eq!("", jar["c"]);
+        assert_eq!("x=y", jar["d"]);
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn test_set_cookie_value() {
+        let cookie = Cookie::new("id", "7").domain("example.com").expires(DateTime::UNIX_EPOCH);
+
+        assert_eq!("id=7; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Domain=example.com", cookie.to_string());
+
+        let cookie = Cookie::new("a b", "x; Secure\r\nSet-Cookie: y=1").path("/p;q").domain("e.com\n");
+
+        assert_eq!("a%20b=x%3B%20Secure%0D%0ASet-Cookie:%20y=1; Path=/p%3Bq; Domain=e.com%0A", cookie.to_string());
+        assert_eq!("t=a+b/c==", Cookie::new("t", "a+b/c==").to_string());
+        assert_eq!("id=7", Cookie::new("id", "7").to_string());
+    }
+}
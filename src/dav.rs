@@ -0,0 +1,401 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! WebDAV for static mounts made with `StaticOptions::webdav`: enough of
+//! RFC 4918 (PROPFIND, MKCOL, COPY, MOVE, and stub locks, along with the
+//! PUT and DELETE of writable mounts) for
+//! simple file-sync clients and Finder/Explorer network drives.
+
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use mime_guess::MimeGuess;
+
+use crate::files::{self, Mount};
+use crate::request::{Method, Request};
+use crate::response::Response;
+use crate::utils;
+
+const XML: &str = "application/xml; charset=utf-8";
+
+// how long clients are told their (unenforced) locks last.
+const LOCK_SECS: u32 = 3600;
+
+// answer a WebDAV request for `rel`, the escaped path below the mount.
+pub(crate) fn handle(req: &Request, mount: &Mount, rel: &str) -> Response {
+    let path = files::resolve(&mount.root, rel);
+
+    match req.method {
+        Method::Options   => options(mount),
+        Method::Propfind  => propfind(req, mount, &path),
+        Method::Proppatch => proppatch(mount, &path),
+        Method::Mkcol     => mkcol(req, &path),
+        Method::Copy      => transfer(req, mount, &path, false),
+        Method::Move      => transfer(req, mount, &path, true),
+        Method::Lock      => lock(mount, &path),
+        Method::Unlock    => files::status(204),
+        _                 => utils::err_405(req, &methods(mount)),
+    }
+}
+
+fn methods(mount: &Mount) -> Vec<Method> {
+    Method::all().iter().cloned().filter(|&m| mount.allows(m)).collect()
+}
+
+// the URL of `path`, a file or directory under the mount.
+fn href(mount: &Mount, path: &Path, dir: bool) -> String {
+    let mut url = mount.prefix.clone();
+
+    if let Ok(rel) = path.strip_prefix(&mount.root) {
+        for part in rel.iter() {
+            url.push('/');
+            url.push_str(&utils::encode_segment(&part.to_string_lossy()));
+        }
+    }
+
+    if dir || url.is_empty() {
+        url.push('/');
+    }
+
+    url
+}
+
+fn options(mount: &Mount) -> Response {
+    let mut res = Response::new();
+    let mut names: Vec<&str> = methods(mount).iter().map(Method::as_str).collect();
+
+    names.sort();
+    res.set_header("DAV", "1, 2");
+    res.set_header("MS-Author-Via", "DAV");
+    res.set_header("Allow", &names.join(", "));
+    res
+}
+
+// the properties of one file or directory, as a `response` element.
+fn entry(mount: &Mount, path: &Path, md: &fs::Metadata) -> String {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut props = format!("<D:displayname>{}</D:displayname>", utils::escape_html(&name));
+
+    if let Ok(st) = md.modified() {
+        let modified = utils::_conv_systemtime(st).format("%a, %d %b %Y %H:%M:%S GMT");
+
+        props.push_str(&format!("<D:getlastmodified>{}</D:getlastmodified>", modified));
+    }
+
+    if md.is_dir() {
+        props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        let ctype = MimeGuess::from_path(path).first_raw().unwrap_or("application/octet-stream");
+
+        props.push_str(&format!("<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+                                 <D:getcontenttype>{}</D:getcontenttype>", md.len(), ctype));
+    }
+
+    props.push_str("<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+                    <D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>");
+
+    format!("<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop>\
+             <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+            utils::escape_html(&href(mount, path, md.is_dir())), props)
+}
+
+fn multistatus(body: &str) -> Response {
+    utils::make_response(format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+                                  <D:multistatus xmlns:D=\"DAV:\">{}</D:multistatus>", body), XML, 207)
+}
+
+// every property of `path`, and of its children unless `Depth: 0` is asked
+// for. `Depth: infinity` is treated as 1, as most servers do.
+fn propfind(req: &Request, mount: &Mount, path: &Path) -> Response {
+    let md = match fs::metadata(path) {
+        Ok(md) => md,
+        Err(_) => return utils::err_404(req),
+    };
+    let mut body = entry(mount, path, &md);
+
+    if md.is_dir() && req.get_header("Depth").as_deref().map(str::trim) != Some("0") {
+        let mut children: Vec<_> = match fs::read_dir(path) {
+            Ok(dir) => dir.filter_map(Result::ok).collect(),
+            Err(_)  => return utils::err_500(req),
+        };
+
+        children.sort_by_key(|e| e.file_name());
+
+        for child in children {
+            if let Ok(md) = child.metadata() {
+                body.push_str(&entry(mount, &child.path(), &md));
+            }
+        }
+    }
+
+    multistatus(&body)
+}
+
+// properties can't be set, but clients like Finder give up on a drive that
+// refuses, so pretend they were.
+fn proppatch(mount: &Mount, path: &Path) -> Response {
+    multistatus(&format!("<D:response><D:href>{}</D:href><D:propstat><D:prop/>\
+                          <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+                         utils::escape_html(&href(mount, path, path.is_dir()))))
+}
+
+fn mkcol(req: &Request, path: &Path) -> Response {
+    if !req.payload.is_empty() {
+        return utils::err_415(req);
+    }
+
+    if path.exists() {
+        return utils::err_405(req, &[Method::Get]);
+    }
+
+    if !files::parent_exists(path) {
+        return files::status(409);
+    }
+
+    match fs::create_dir(path) {
+        Ok(_)  => files::status(201),
+        Err(_) => utils::err_500(req),
+    }
+}
+
+// copy a file or a directory and everything in it. symbolic links are left
+// out: following them could loop forever, or copy in what's outside the
+// mount.
+fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    let kind = fs::symlink_metadata(from)?.file_type();
+
+    if kind.is_symlink() {
+        return Ok(());
+    }
+
+    if !kind.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+
+    fs::create_dir(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_symlink() {
+            continue;
+        }
+
+        copy_all(&entry.path(), &to.join(entry.file_name()))?;
+    }
+
+    Ok(())
+}
+
+// the file the `Destination` header of a COPY or MOVE names, if it's under
+// the same mount. the header is usually a full URL.
+fn destination(req: &Request, mount: &Mount) -> Option<PathBuf> {
+    let dest = req.get_header("Destination")?;
+    let path = match dest.find("://") {
+        Some(i) => {
+            let rest = &dest[i + 3..];
+            &rest[rest.find('/').unwrap_or(rest.len())..]
+        },
+        None    => dest.as_str(),
+    };
+
+    mount.strip(path).map(|rel| files::resolve(&mount.root, rel))
+}
+
+fn transfer(req: &Request, mount: &Mount, path: &Path, moving: bool) -> Response {
+    let dest = match (req.get_header("Destination"), destination(req, mount)) {
+        (None, _)          => return utils::err_400(req),
+        (_, None)          => return files::status(502),
+        (_, Some(dest))    => dest,
+    };
+
+    if !path.exists() {
+        return utils::err_404(req);
+    }
+
+    // nothing may be copied or moved over the root, or over a directory
+    // holding the source, as that would delete it first
+    if dest.starts_with(path) || path.starts_with(&dest) || path == mount.root || dest == mount.root {
+        return utils::err_403(req);
+    }
+
+    if !files::parent_exists(&dest) {
+        return files::status(409);
+    }
+
+    let existed = dest.exists();
+
+    if existed {
+        if req.get_header("Overwrite").map(|o| o.trim().eq_ignore_ascii_case("F")).unwrap_or(false) {
+            return files::status(412);
+        }
+
+        if files::remove(&dest).is_err() {
+            return utils::err_500(req);
+        }
+    }
+
+    let done = match moving {
+        true  => fs::rename(path, &dest),
+        false => copy_all(path, &dest),
+    };
+
+    match (done, existed) {
+        (Err(_), _)    => utils::err_500(req),
+        (Ok(_), true)  => files::status(204),
+        (Ok(_), false) => files::status(201),
+    }
+}
+
+// locks aren't enforced, but clients won't write to a drive without them.
+// locking a missing file creates it empty, as RFC 4918 says.
+fn lock(mount: &Mount, path: &Path) -> Response {
+    let mut created = false;
+
+    if !path.exists() {
+        if !files::parent_exists(path) || fs::write(path, b"").is_err() {
+            return files::status(409);
+        }
+
+        created = true;
+    }
+
+    let token = format!("opaquelocktoken:{:016x}", RandomState::new().build_hasher().finish());
+    let body = format!("<?xml version=\"1.0\" encoding=\"utf-8\"?><D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>\
+                        <D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope>\
+                        <D:depth>0</D:depth><D:timeout>Second-{}</D:timeout>\
+                        <D:locktoken><D:href>{}</D:href></D:locktoken>\
+                        <D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock></D:lockdiscovery></D:prop>",
+                       LOCK_SECS, token, utils::escape_html(&href(mount, path, path.is_dir())));
+    let mut res = utils::make_response(body, XML, if created { 201 } else { 200 });
+
+    res.set_header("Lock-Token", &format!("<{}>", token));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::StaticOptions;
+
+    fn setup(name: &str) -> Mount {
+        let root = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a b.txt"), "hello").unwrap();
+
+        Mount::new("/dav", &root, StaticOptions::new().webdav(true))
+    }
+
+    fn run(mount: &Mount, raw: &str) -> Response {
+        mount.serve(&raw.parse().unwrap())
+    }
+
+    #[test]
+    fn test_propfind() {
+        let mount = setup("canteen-dav-propfind");
+        let res = run(&mount, "PROPFIND /dav/docs HTTP/1.1\r\nDepth: 1\r\n\r\n");
+        let body = String::from_utf8_lossy(res.get_payload()).into_owned();
+
+        assert_eq!(207, res.get_status());
+        assert!(body.contains("<D:href>/dav/docs/</D:href>"));
+        assert!(body.contains("<D:href>/dav/docs/a%20b.txt</D:href>"));
+        assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+
+        let res = run(&mount, "PROPFIND /dav/docs HTTP/1.1\r\nDepth: 0\r\n\r\n");
+        assert!(!String::from_utf8_lossy(res.get_payload()).contains("a%20b.txt"));
+        assert_eq!(404, run(&mount, "PROPFIND /dav/none HTTP/1.1\r\n\r\n").get_status());
+    }
+
+    #[test]
+    fn test_mkcol_put_delete() {
+        let mount = setup("canteen-dav-write");
+
+        assert_eq!(201, run(&mount, "MKCOL /dav/new HTTP/1.1\r\n\r\n").get_status());
+        assert_eq!(405, run(&mount, "MKCOL /dav/new HTTP/1.1\r\n\r\n").get_status());
+        assert_eq!(409, run(&mount, "MKCOL /dav/x/y HTTP/1.1\r\n\r\n").get_status());
+
+        assert_eq!(201, run(&mount, "PUT /dav/new/f.txt HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc").get_status());
+        assert_eq!(204, run(&mount, "PUT /dav/new/f.txt HTTP/1.1\r\nContent-Length: 2\r\n\r\nde").get_status());
+        assert_eq!("de", fs::read_to_string(mount.root.join("new/f.txt")).unwrap());
+        assert_eq!(1, fs::read_dir(mount.root.join("new")).unwrap().count());
+
+        assert_eq!(204, run(&mount, "DELETE /dav/new HTTP/1.1\r\n\r\n").get_status());
+        assert!(!mount.root.join("new").exists());
+        assert_eq!(404, run(&mount, "DELETE /dav/new HTTP/1.1\r\n\r\n").get_status());
+        assert_eq!(403, run(&mount, "DELETE /dav HTTP/1.1\r\n\r\n").get_status());
+    }
+
+    #[test]
+    fn test_copy_move() {
+        let mount = setup("canteen-dav-copy");
+
+        let res = run(&mount, "COPY /dav/docs HTTP/1.1\r\nDestination: http://localhost/dav/copy\r\n\r\n");
+        assert_eq!(201, res.get_status());
+        assert_eq!("hello", fs::read_to_string(mount.root.join("copy/a b.txt")).unwrap());
+
+        let res = run(&mount, "MOVE /dav/copy/a%20b.txt HTTP/1.1\r\nDestination: /dav/docs/a%20b.txt\r\nOverwrite: F\r\n\r\n");
+        assert_eq!(412, res.get_status());
+
+        let res = run(&mount, "MOVE /dav/copy/a%20b.txt HTTP/1.1\r\nDestination: /dav/docs/a%20b.txt\r\n\r\n");
+        assert_eq!(204, res.get_status());
+        assert!(!mount.root.join("copy/a b.txt").exists());
+
+        let res = run(&mount, "MOVE /dav/docs HTTP/1.1\r\nDestination: http://other/elsewhere\r\n\r\n");
+        assert_eq!(502, res.get_status());
+        assert_eq!(400, run(&mount, "COPY /dav/docs HTTP/1.1\r\n\r\n").get_status());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_skips_links() {
+        let mount = setup("canteen-dav-links");
+
+        std::os::unix::fs::symlink(&mount.root, mount.root.join("docs/loop")).unwrap();
+        std::os::unix::fs::symlink("/etc", mount.root.join("docs/etc")).unwrap();
+
+        let res = run(&mount, "COPY /dav/docs HTTP/1.1\r\nDestination: /dav/copy\r\n\r\n");
+        assert_eq!(201, res.get_status());
+        assert!(mount.root.join("copy/a b.txt").exists());
+        assert!(fs::symlink_metadata(mount.root.join("copy/loop")).is_err());
+        assert!(fs::symlink_metadata(mount.root.join("copy/etc")).is_err());
+    }
+
+    #[test]
+    fn test_transfer_over_root_or_parent() {
+        let mount = setup("canteen-dav-over");
+
+        for dest in &["/dav/", "/dav", "/dav/docs", "http://localhost/dav/docs/"] {
+            let res = run(&mount, &format!("COPY /dav/docs/a%20b.txt HTTP/1.1\r\nDestination: {}\r\n\r\n", dest));
+            assert_eq!(403, res.get_status());
+
+            let res = run(&mount, &format!("MOVE /dav/docs/a%20b.txt HTTP/1.1\r\nDestination: {}\r\n\r\n", dest));
+            assert_eq!(403, res.get_status());
+        }
+
+        assert!(mount.root.is_dir());
+        assert_eq!("hello", fs::read_to_string(mount.root.join("docs/a b.txt")).unwrap());
+    }
+
+    #[test]
+    fn test_options_and_lock() {
+        let mount = setup("canteen-dav-lock");
+
+        let res = run(&mount, "OPTIONS /dav/ HTTP/1.1\r\n\r\n");
+        assert_eq!(Some("1, 2"), res.get_header("DAV"));
+        assert!(res.get_header("Allow").unwrap().contains("PROPFIND"));
+
+        let res = run(&mount, "LOCK /dav/docs/new.txt HTTP/1.1\r\n\r\n");
+        assert_eq!(201, res.get_status());
+        assert!(res.get_header("Lock-Token").unwrap().starts_with("<opaquelocktoken:"));
+        assert!(mount.root.join("docs/new.txt").exists());
+        assert_eq!(204, run(&mount, "UNLOCK /dav/docs/new.txt HTTP/1.1\r\n\r\n").get_status());
+    }
+}
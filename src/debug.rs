@@ -0,0 +1,202 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Detailed error pages for development, turned on with
+//! `Canteen::enable_debug`. A panicking handler gets the client a page with
+//! the panic message, where it happened, the backtrace, and the request; one
+//! that returns an error, a page with the error, its causes, and the request.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::panic;
+use std::sync::Once;
+
+use crate::error::Error;
+use crate::request::Request;
+use crate::response::Response;
+use crate::route::ResponseContract;
+use crate::utils;
+
+// headers whose values aren't shown, even in debug mode.
+const SENSITIVE: [&str; 3] = ["authorization", "proxy-authorization", "cookie"];
+
+// how much of the request body is shown.
+const MAX_BODY: usize = 4096;
+
+static HOOK: Once = Once::new();
+
+thread_local! {
+    // where the last panic on this thread happened, and how it got there.
+    static LAST_PANIC: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+}
+
+// capture the location and backtrace of panics, which are gone by the time
+// `catch_unwind` returns. the previous hook still runs, so panics are
+// reported as usual.
+pub(crate) fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            let location = info.location().map(|l| l.to_string()).unwrap_or_default();
+            let trace = Backtrace::force_capture().to_string();
+
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some((location, trace)));
+            previous(info);
+        }));
+    });
+}
+
+// the message a panic was started with.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(s) => String::from(*s),
+        None    => match payload.downcast_ref::<String>() {
+            Some(s) => s.clone(),
+            None    => String::from("(no message)"),
+        },
+    }
+}
+
+// render the debug page for a handler for `route` that panicked with
+// `payload`.
+pub(crate) fn panic_page(req: &Request, route: &str, payload: &(dyn Any + Send)) -> Response {
+    let (location, trace) = LAST_PANIC.with(|last| last.borrow_mut().take()).unwrap_or_default();
+    let page = page(req, route, &panic_message(payload), &format!("at {}", location), "Backtrace", &trace);
+
+    utils::make_response(page, "text/html; charset=utf-8", 500)
+}
+
+// render the debug page for a handler for `route` that returned `err`. it's
+// answered with the error's status, as it would have been otherwise.
+pub(crate) fn error_page(req: &Request, route: &str, err: &Error) -> Response {
+    let mut causes = String::new();
+    let mut source = err.source().map(|e| e as &(dyn std::error::Error + 'static));
+
+    while let Some(e) = source {
+        causes.push_str(&format!("{}\n", e));
+        source = e.source();
+    }
+
+    if causes.is_empty() {
+        causes.push_str("(none)");
+    }
+
+    let page = page(req, route, err.message(), &format!("returned {}", err.status().as_u16()), "Caused by", &causes);
+
+    utils::make_response(page, "text/html; charset=utf-8", err.status())
+}
+
+// the page for a handler for `route` that failed with `message`, followed by
+// `detail`, the request, and a `section` of `text`.
+fn page(req: &Request, route: &str, message: &str, detail: &str, section: &str, text: &str) -> String {
+    let mut headers = String::new();
+    let mut params: Vec<_> = req.params.iter().collect();
+
+    for (key, value) in req.headers() {
+        let value = match SENSITIVE.contains(&key.to_ascii_lowercase().as_str()) {
+            true  => "[redacted]",
+            false => value,
+        };
+
+        headers.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>", utils::escape_html(key), utils::escape_html(value)));
+    }
+
+    params.sort();
+
+    let body = String::from_utf8_lossy(&req.payload[..req.payload.len().min(MAX_BODY)]);
+
+    format!(
+        "<html><head><title>{message}</title>\
+         <style>body {{ font-family: helvetica, sans-serif; }} pre {{ background: #eee; padding: 8px; overflow: auto; }} \
+         th {{ text-align: left; padding-right: 16px; }}</style></head><body>\
+         <h2>{message}</h2><p>{detail}</p>\
+         <h3>Request</h3><table>\
+         <tr><th>Method</th><td>{method}</td></tr><tr><th>Path</th><td>{path}</td></tr>\
+         <tr><th>Route</th><td>{route}</td></tr><tr><th>Params</th><td>{params}</td></tr></table>\
+         <h3>Headers</h3><table>{headers}</table>\
+         <h3>Body ({length} bytes)</h3><pre>{body}</pre>\
+         <h3>{section}</h3><pre>{text}</pre></body></html>",
+        message = utils::escape_html(message),
+        detail = utils::escape_html(detail),
+        method = req.method.as_str(),
+        path = utils::escape_html(&req.path),
+        route = utils::escape_html(route),
+        params = utils::escape_html(&format!("{:?}", params)),
+        headers = headers,
+        length = req.payload.len(),
+        body = utils::escape_html(&body),
+        section = section,
+        text = utils::escape_html(text),
+    )
+}
+
+// check a successful response against the contract its route declared,
+// replacing it with an explanation if it doesn't match.
+pub(crate) fn check_response(req: &Request, res: Response) -> Response {
+    let contract = match req.matched_route().and_then(|r| r.meta::<ResponseContract>()) {
+        Some(contract) if res.get_status().is_success() => contract,
+        _                                               => return res,
+    };
+
+    let problems = match (contract.0)(res.get_payload()) {
+        Ok(())    => return res,
+        Err(list) => list,
+    };
+
+    log::error!("response from {} doesn't match its declared type: {}", req.path, problems.join("; "));
+
+    let items: String = problems.iter().map(|p| format!("<li>{}</li>", utils::escape_html(p))).collect();
+    let page = format!("<html><head><title>response contract violated</title></head><body>\
+                        <h2>The response from {} doesn't match what its route declares</h2>\
+                        <ul>{}</ul><h3>Body</h3><pre>{}</pre></body></html>",
+                       utils::escape_html(&req.path), items,
+                       utils::escape_html(&String::from_utf8_lossy(&res.get_payload()[..res.get_payload().len().min(MAX_BODY)])));
+
+    utils::make_response(page, "text/html; charset=utf-8", 500)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_page() {
+        install_hook();
+
+        let payload = panic::catch_unwind(|| panic!("bad <input> {}", 7)).unwrap_err();
+        let req: Request = "POST /users HTTP/1.1\r\nCookie: id=secret\r\n\r\nname=x".parse().unwrap();
+        let res = panic_page(&req, "/users", payload.as_ref());
+        let page = String::from_utf8_lossy(res.get_payload()).into_owned();
+
+        assert_eq!(500, res.get_status());
+        assert!(page.contains("<h2>bad &lt;input&gt; 7</h2>"));
+        assert!(page.contains("src/debug.rs"));
+        assert!(page.contains("<tr><th>Cookie</th><td>[redacted]</td></tr>"));
+        assert!(page.contains("<pre>name=x</pre>"));
+        assert!(!page.contains("secret"));
+    }
+
+    #[test]
+    fn test_error_page() {
+        let err = Error::from("12x".parse::<u32>().unwrap_err());
+        let req: Request = "GET /users/12x HTTP/1.1\r\n\r\n".parse().unwrap();
+        let res = error_page(&req, "/users/<id>", &err);
+        let page = String::from_utf8_lossy(res.get_payload()).into_owned();
+
+        assert_eq!(500, res.get_status());
+        assert!(page.contains("<h2>invalid digit found in string</h2><p>returned 500</p>"));
+        assert!(page.contains("<h3>Caused by</h3><pre>invalid digit found in string\n</pre>"));
+
+        let res = error_page(&req, "/users/<id>", &Error::not_found("no such user"));
+        let page = String::from_utf8_lossy(res.get_payload()).into_owned();
+
+        assert_eq!(404, res.get_status());
+        assert!(page.contains("<pre>(none)</pre>"));
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! A debugging middleware that logs full requests and responses.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde_json::Value;
+
+use crate::middleware::Middleware;
+use crate::request::Request;
+use crate::response::Response;
+
+const REDACTED: &str = "[redacted]";
+
+/// Logs the headers and body of every request and its response at INFO
+/// level, under the `canteen::dump` target. Sensitive headers and JSON
+/// fields are redacted, and bodies are truncated to a maximum size.
+///
+/// Clones of a `Dump` share the same on/off switch, so one can be kept to
+/// toggle dumping while the server runs.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::Canteen;
+/// use canteen::dump::Dump;
+///
+/// let dump = Dump::new().redact_field("password").max_body(1024);
+/// let mut cnt = Canteen::new();
+///
+/// cnt.add_middleware(dump.clone());
+/// dump.set_enabled(false);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Dump {
+    enabled:  Arc<AtomicBool>,
+    headers:  Vec<String>,
+    fields:   Vec<String>,
+    max_body: usize,
+}
+
+impl Dump {
+    /// Create an enabled dump that redacts the `Authorization`,
+    /// `Proxy-Authorization`, `Cookie`, and `Set-Cookie` headers and shows at
+    /// most 4KiB of each body.
+    pub fn new() -> Dump {
+        Dump {
+            enabled:  Arc::new(AtomicBool::new(true)),
+            headers:  vec![String::from("authorization"), String::from("proxy-authorization"),
+                           String::from("cookie"), String::from("set-cookie")],
+            fields:   Vec::new(),
+            max_body: 4096,
+        }
+    }
+
+    /// Redact another header, matched case-insensitively.
+    pub fn redact_header(mut self, name: &str) -> Dump {
+        self.headers.push(name.to_lowercase());
+
+        self
+    }
+
+    /// Redact a field, at any depth, in JSON bodies.
+    pub fn redact_field(mut self, name: &str) -> Dump {
+        self.fields.push(String::from(name));
+
+        self
+    }
+
+    /// Set the maximum number of body bytes shown.
+    pub fn max_body(mut self, bytes: usize) -> Dump {
+        self.max_body = bytes;
+
+        self
+    }
+
+    /// Turn dumping on or off.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Whether dumping is currently on.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    fn header_line(&self, key: &str, value: &str) -> String {
+        match self.headers.contains(&key.to_lowercase()) {
+            true  => format!("{}: {}\n", key, REDACTED),
+            false => format!("{}: {}\n", key, value),
+        }
+    }
+
+    fn redact_json(&self, val: &mut Value) {
+        match *val {
+            Value::Object(ref mut map) => {
+                for (key, item) in map.iter_mut() {
+                    if self.fields.contains(key) {
+                        *item = Value::String(String::from(REDACTED));
+                    } else {
+                        self.redact_json(item);
+                    }
+                }
+            },
+            Value::Array(ref mut items) => {
+                for item in items.iter_mut() {
+                    self.redact_json(item);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn body(&self, payload: &[u8]) -> String {
+        let text = match String::from_utf8(payload.to_vec()) {
+            Ok(text) => text,
+            Err(_)   => return format!("<{} bytes of binary data>", payload.len()),
+        };
+
+        let text = match serde_json::from_str::<Value>(&text) {
+            Ok(mut val) if !self.fields.is_empty() => {
+                self.redact_json(&mut val);
+                val.to_string()
+            },
+            _ => text,
+        };
+
+        if text.len() <= self.max_body {
+            return text;
+        }
+
+        let mut cut = self.max_body;
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+
+        format!("{}... ({} bytes total)", &text[..cut], text.len())
+    }
+
+    /// Format a request as it will be logged.
+    pub fn format_request(&self, req: &Request) -> String {
+        let mut out = format!("--> {:?} {}\n", req.method, req.path);
+
+        for (key, value) in req.headers() {
+            out.push_str(&self.header_line(key, value));
+        }
+
+        out + "\n" + &self.body(&req.payload)
+    }
+
+    /// Format a response as it will be logged.
+    pub fn format_response(&self, res: &Response) -> String {
+        let mut out = format!("<-- {}\n", res.get_status());
+
+        for (key, value) in res.headers() {
+            out.push_str(&self.header_line(key, value));
+        }
+
+        out.push_str(&format!("Content-Type: {}\n", res.get_content_type()));
+        out + "\n" + &self.body(res.get_payload())
+    }
+}
+
+impl Default for Dump {
+    fn default() -> Self {
+        Dump::new()
+    }
+}
+
+impl Middleware for Dump {
+    fn after(&self, req: &Request, res: &mut Response) {
+        if self.is_enabled() {
+            log::info!(target: "canteen::dump", "{}\n{}", self.format_request(req), self.format_response(res));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn test_dump_redacts_headers() {
+        let req: Request = "POST /login HTTP/1.1\r\nAuthorization: Basic Zm9vOmJhcg==\r\nHost: x\r\n\r\n".parse().unwrap();
+        let out = Dump::new().format_request(&req);
+
+        assert!(out.starts_with("--> Post /login\n"));
+        assert!(out.contains("Authorization: [redacted]\n"));
+        assert!(out.contains("Host: x\n"));
+        assert!(!out.contains("Zm9v"));
+    }
+
+    #[test]
+    fn test_dump_redacts_json_fields() {
+        let mut res = utils::make_response(r#"{"user":{"name":"jeff","password":"hunter2"},"list":[{"password":"x"}]}"#,
+                                           "application/json", 200);
+        res.add_header("Set-Cookie", "session=abc");
+
+        let out = Dump::new().redact_field("password").format_response(&res);
+
+        assert!(out.starts_with("<-- 200\n"));
+        assert!(out.contains("Set-Cookie: [redacted]\n"));
+        assert!(out.contains(r#""name":"jeff""#));
+        assert!(!out.contains("hunter2"));
+        assert!(!out.contains(r#""x""#));
+    }
+
+    #[test]
+    fn test_dump_truncates_body() {
+        let res = utils::make_response("héllo world", "text/plain", 200);
+        let out = Dump::new().max_body(2).format_response(&res);
+
+        assert!(out.ends_with("\n\nh... (12 bytes total)"));
+
+        let bin = utils::make_response(vec![0xff, 0xfe], "application/octet-stream", 200);
+        assert!(Dump::new().format_response(&bin).ends_with("<2 bytes of binary data>"));
+    }
+
+    #[test]
+    fn test_dump_toggle_is_shared() {
+        let dump = Dump::new();
+        let other = dump.clone();
+
+        other.set_enabled(false);
+        assert!(!dump.is_enabled());
+    }
+}
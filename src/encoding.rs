@@ -0,0 +1,150 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Decoding of request bodies sent with `Content-Encoding: gzip` or
+//! `deflate`, so handlers always see the plain body.
+
+use std::fmt;
+use std::io::Read;
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+
+use crate::request::Request;
+
+/// Why a request body couldn't be decoded.
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// The decoded body would be larger than allowed.
+    TooLarge,
+    /// The body isn't valid for its encoding.
+    Invalid,
+    /// The body uses an encoding that isn't supported, e.g. `br`.
+    Unsupported(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::TooLarge           => write!(f, "decoded body too large"),
+            DecodeError::Invalid            => write!(f, "body doesn't match its encoding"),
+            DecodeError::Unsupported(ref e) => write!(f, "unsupported content encoding {:?}", e),
+        }
+    }
+}
+
+// read all of `reader`, failing if it gives more than `limit` bytes.
+fn read_limited<R: Read>(reader: R, limit: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut out = Vec::new();
+
+    reader.take(limit as u64 + 1).read_to_end(&mut out).map_err(|_| DecodeError::Invalid)?;
+
+    match out.len() > limit {
+        true  => Err(DecodeError::TooLarge),
+        false => Ok(out),
+    }
+}
+
+/// Undo a single content encoding.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Write;
+/// use flate2::Compression;
+/// use flate2::write::GzEncoder;
+/// use canteen::encoding;
+///
+/// let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+/// gz.write_all(b"hello").unwrap();
+///
+/// assert_eq!(b"hello".to_vec(), encoding::decode("gzip", &gz.finish().unwrap(), 1024).unwrap());
+/// ```
+pub fn decode(coding: &str, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+    match coding.to_ascii_lowercase().as_str() {
+        "identity"         => Ok(data.to_vec()),
+        "gzip" | "x-gzip"  => read_limited(MultiGzDecoder::new(data), limit),
+        // `deflate` means zlib-wrapped, but some clients send raw deflate
+        "deflate"          => match read_limited(ZlibDecoder::new(data), limit) {
+            Err(DecodeError::Invalid) => read_limited(DeflateDecoder::new(data), limit),
+            other                     => other,
+        },
+        other              => Err(DecodeError::Unsupported(String::from(other))),
+    }
+}
+
+// decode the body of `req` in place, undoing each of its content encodings
+// in turn. returns whether there was anything to decode.
+pub(crate) fn decode_body(req: &mut Request, limit: usize) -> Result<bool, DecodeError> {
+    let codings = match req.get_header_combined("Content-Encoding") {
+        Some(value) => value,
+        None        => return Ok(false),
+    };
+    let mut body = std::mem::take(&mut req.payload);
+
+    // the encodings are listed in the order they were applied
+    for coding in codings.split(',').map(str::trim).filter(|c| !c.is_empty()).rev() {
+        body = decode(coding, &body, limit)?;
+    }
+
+    req.payload = body;
+    req.headers.retain(|(k, _)| !k.eq_ignore_ascii_case("Content-Encoding") && !k.eq_ignore_ascii_case("Content-Length"));
+    req.headers.push((String::from("Content-Length"), req.payload.len().to_string()));
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(data).unwrap();
+        enc.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(b"zlib").unwrap();
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(b"raw").unwrap();
+
+        assert_eq!(Ok(b"zlib".to_vec()), decode("deflate", &zlib.finish().unwrap(), 100));
+        assert_eq!(Ok(b"raw".to_vec()), decode("Deflate", &raw.finish().unwrap(), 100));
+        assert_eq!(Err(DecodeError::Invalid), decode("gzip", b"not gzip", 100));
+        assert_eq!(Err(DecodeError::Unsupported(String::from("br"))), decode("br", b"", 100));
+    }
+
+    #[test]
+    fn test_decode_limit() {
+        let bomb = gzip(&vec![0; 1 << 20]);
+
+        assert!(bomb.len() < 4096);
+        assert_eq!(Err(DecodeError::TooLarge), decode("gzip", &bomb, 64 * 1024));
+        assert_eq!(1 << 20, decode("gzip", &bomb, 1 << 20).unwrap().len());
+    }
+
+    #[test]
+    fn test_decode_body() {
+        let mut req = Request::new();
+
+        req.headers.push((String::from("Content-Encoding"), String::from("gzip, identity")));
+        req.headers.push((String::from("Content-Length"), String::from("99")));
+        req.payload = gzip(b"name=jo");
+
+        assert_eq!(Ok(true), decode_body(&mut req, 1024));
+        assert_eq!(b"name=jo", &req.payload[..]);
+        assert_eq!(None, req.get_header("Content-Encoding"));
+        assert_eq!(Some(String::from("7")), req.get_header("Content-Length"));
+
+        assert_eq!(Ok(false), decode_body(&mut req, 1024));
+    }
+}
@@ -0,0 +1,187 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Errors returned by handlers, so they can use `?` and leave turning the
+//! failure into a response to the server.
+
+use std::fmt;
+
+use crate::request::{JsonError, ParamError, Request};
+use crate::response::Response;
+use crate::status::StatusCode;
+use crate::utils;
+
+// turns a handler's error into the response sent for it.
+pub(crate) type ErrorFn = dyn Fn(&Request, &Error) -> Response + Send + Sync;
+
+/// A failed request, with the status it should be answered with. Any other
+/// error converts into one with `?`, as a `500 Internal Server Error`.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Error, Request, Response};
+/// use canteen::utils;
+///
+/// fn double(req: &Request) -> Result<Response, Error> {
+///     let n: i64 = req.get_header("X-Number")
+///                     .ok_or_else(|| Error::bad_request("missing X-Number"))?
+///                     .parse()?;
+///
+///     Ok(utils::text(format!("{}", n * 2)))
+/// }
+/// ```
+pub struct Error {
+    status:  StatusCode,
+    message: String,
+    source:  Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+impl Error {
+    /// An error answered with `status`, described by `message`.
+    pub fn new<S: Into<StatusCode>>(status: S, message: &str) -> Error {
+        Error {
+            status:  status.into(),
+            message: String::from(message),
+            source:  None,
+        }
+    }
+
+    /// A `400 Bad Request`.
+    pub fn bad_request(message: &str) -> Error {
+        Error::new(400, message)
+    }
+
+    /// A `404 Not Found`.
+    pub fn not_found(message: &str) -> Error {
+        Error::new(404, message)
+    }
+
+    /// A `500 Internal Server Error`.
+    pub fn internal(message: &str) -> Error {
+        Error::new(500, message)
+    }
+
+    /// The status the request should be answered with.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// What went wrong.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The error this one was converted from, if any.
+    pub fn source(&self) -> Option<&(dyn std::error::Error + Send + Sync + 'static)> {
+        self.source.as_deref()
+    }
+
+    /// The response sent for an error when no error handler is set. Server
+    /// errors get the generic 500 page, so their details stay in the logs.
+    pub fn respond(req: &Request, err: &Error) -> Response {
+        match err.status.is_server_error() {
+            true  => utils::make_response(utils::err_body("internal server error", &req.path), "text/html", err.status),
+            false => utils::make_response(utils::err_body(&err.message, &req.path), "text/html", err.status),
+        }
+    }
+}
+
+impl<E: std::error::Error + Send + Sync + 'static> From<E> for Error {
+    fn from(err: E) -> Error {
+        Error {
+            status:  StatusCode::INTERNAL_SERVER_ERROR,
+            message: err.to_string(),
+            source:  Some(Box::new(err)),
+        }
+    }
+}
+
+impl From<JsonError> for Error {
+    fn from(err: JsonError) -> Error {
+        Error {
+            status:  StatusCode::BAD_REQUEST,
+            message: err.to_string(),
+            source:  Some(Box::new(err.into_inner())),
+        }
+    }
+}
+
+impl From<ParamError> for Error {
+    fn from(err: ParamError) -> Error {
+        let status = match err {
+            ParamError::Missing(_)     => StatusCode::INTERNAL_SERVER_ERROR,
+            ParamError::Invalid { .. } => StatusCode::BAD_REQUEST,
+        };
+
+        Error::new(status, &err.to_string())
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Error")
+         .field("status", &self.status)
+         .field("message", &self.message)
+         .finish()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.status)
+    }
+}
+
+/// What a handler may return: a `Response`, or a `Result` whose error is
+/// turned into one.
+pub trait IntoResult {
+    /// The response, or the error to answer with.
+    fn into_result(self) -> Result<Response, Error>;
+}
+
+impl IntoResult for Response {
+    fn into_result(self) -> Result<Response, Error> {
+        Ok(self)
+    }
+}
+
+impl<E: Into<Error>> IntoResult for Result<Response, E> {
+    fn into_result(self) -> Result<Response, Error> {
+        self.map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Response, Error> {
+        let n: u8 = s.parse()?;
+
+        match n {
+            0 => Err(Error::not_found("no such number")),
+            _ => Ok(utils::text(format!("{}", n))),
+        }
+    }
+
+    #[test]
+    fn test_error_conversion() {
+        let req: Request = "GET /n HTTP/1.1\r\n\r\n".parse().unwrap();
+
+        let err = parse("nope").unwrap_err();
+        assert_eq!(500, err.status());
+        assert!(err.source().is_some());
+        assert!(!String::from_utf8_lossy(Error::respond(&req, &err).get_payload()).contains("invalid digit"));
+
+        let err = parse("0").unwrap_err();
+        assert_eq!(404, err.status());
+        assert!(String::from_utf8_lossy(Error::respond(&req, &err).get_payload()).contains("no such number"));
+
+        assert_eq!(200, parse("7").into_result().unwrap().get_status());
+    }
+}
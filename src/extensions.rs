@@ -0,0 +1,112 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Typed values attached to a request, so middleware can pass what it
+//! learns, like who the client is, on to handlers and other middleware.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A map holding at most one value of each type.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::extensions::Extensions;
+///
+/// let mut ext = Extensions::new();
+/// ext.insert(5u32);
+///
+/// assert_eq!(Some(&5u32), ext.get::<u32>());
+/// assert_eq!(None, ext.get::<i64>());
+/// ```
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    /// Create an empty map.
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    /// Store `value`, returning the value of the same type it replaces.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.map.insert(TypeId::of::<T>(), Box::new(value))
+                .and_then(|old| old.downcast().ok().map(|b| *b))
+    }
+
+    /// The stored value of type `T`.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>()).and_then(|v| v.downcast_ref())
+    }
+
+    /// The stored value of type `T`, mutably.
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>()).and_then(|v| v.downcast_mut())
+    }
+
+    /// Take the stored value of type `T` out of the map.
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.map.remove(&TypeId::of::<T>()).and_then(|old| old.downcast().ok().map(|b| *b))
+    }
+
+    /// The number of values stored.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether nothing is stored.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Extensions({} values)", self.map.len())
+    }
+}
+
+/// Who made a request, as established by authentication middleware, which
+/// stores it in the request's extensions.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::Request;
+/// use canteen::extensions::Identity;
+///
+/// let mut req = Request::new();
+/// req.extensions_mut().insert(Identity(String::from("alice")));
+///
+/// assert_eq!(Some("alice"), req.identity());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity(pub String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extensions() {
+        let mut ext = Extensions::new();
+
+        assert_eq!(None, ext.insert(String::from("a")));
+        assert_eq!(Some(String::from("a")), ext.insert(String::from("b")));
+        ext.get_mut::<String>().unwrap().push('c');
+        ext.insert(Identity(String::from("bob")));
+
+        assert_eq!(2, ext.len());
+        assert_eq!(Some(String::from("bc")), ext.remove::<String>());
+        assert_eq!(Some(&Identity(String::from("bob"))), ext.get());
+        assert_eq!(None, ext.get::<String>());
+    }
+}
@@ -0,0 +1,568 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Static file serving, either through `utils::static_file` or by mounting a
+//! directory with `Canteen::mount_static`.
+
+use std::fs::{self, File, Metadata};
+use std::path::{Path, PathBuf};
+use std::io::{self, prelude::*};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+use chrono::Utc;
+use mime_guess::MimeGuess;
+
+use crate::assets::AssetManifest;
+use crate::conditional;
+use crate::dav;
+use crate::range;
+use crate::request::{Method, Request};
+use crate::response::Response;
+use crate::stats::RouteStats;
+use crate::utils::{self, err_404, err_500};
+
+/// Options for a static file mount.
+#[derive(Debug, Clone, Default)]
+pub struct StaticOptions {
+    pub(crate) rate_limit:  Option<u64>,
+    pub(crate) fingerprint: bool,
+    pub(crate) writable:    bool,
+    pub(crate) webdav:      bool,
+    pub(crate) listing:     bool,
+}
+
+impl StaticOptions {
+    /// Create a set of static mount options with the defaults.
+    pub fn new() -> StaticOptions {
+        StaticOptions::default()
+    }
+
+    /// Cap the rate, in bytes per second, at which responses from this mount
+    /// are written to each connection. This overrides the server-wide limit.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::files::StaticOptions;
+    ///
+    /// // serve large downloads at no more than 512KiB/s per client
+    /// let opts = StaticOptions::new().rate_limit(512 * 1024);
+    /// ```
+    pub fn rate_limit(mut self, bytes_per_sec: u64) -> StaticOptions {
+        self.rate_limit = Some(bytes_per_sec);
+
+        self
+    }
+
+    /// Also serve each file under a name with a hash of its contents, e.g.
+    /// `app.3f9a1b2c.css`, with headers letting clients cache it forever.
+    /// The files are hashed when the directory is mounted, and
+    /// `utils::asset_url` gives the URL to link to.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::files::StaticOptions;
+    ///
+    /// let opts = StaticOptions::new().fingerprint(true);
+    /// ```
+    pub fn fingerprint(mut self, on: bool) -> StaticOptions {
+        self.fingerprint = on;
+
+        self
+    }
+
+    /// Accept PUT requests, which create or replace the file at the path
+    /// with the request body, and DELETE requests, which remove it. Uploads
+    /// are written to a temporary file and renamed into place, so they're
+    /// never seen half-written. Mounts run through the middleware like any
+    /// other request, so put authentication middleware in front of this.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    /// use canteen::files::StaticOptions;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.mount_static("/artifacts", "/srv/artifacts", StaticOptions::new().writable(true));
+    /// ```
+    pub fn writable(mut self, on: bool) -> StaticOptions {
+        self.writable = on;
+
+        self
+    }
+
+    /// Also answer WebDAV requests, and PUT and DELETE as `writable` does,
+    /// so the directory can be used as a network drive by Finder, Explorer
+    /// and file-sync clients. This lets anyone who can reach the mount
+    /// change the files under it; put it behind authentication middleware.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::files::StaticOptions;
+    ///
+    /// let opts = StaticOptions::new().webdav(true);
+    /// ```
+    pub fn webdav(mut self, on: bool) -> StaticOptions {
+        self.webdav = on;
+
+        self
+    }
+
+    /// List the contents of directories that have no `index.html`, rather
+    /// than answering with a 404. Names starting with `.` aren't listed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::files::StaticOptions;
+    ///
+    /// let opts = StaticOptions::new().listing(true);
+    /// ```
+    pub fn listing(mut self, on: bool) -> StaticOptions {
+        self.listing = on;
+
+        self
+    }
+}
+
+// tells temporary files from concurrent uploads apart.
+static UPLOADS: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory mounted at a URL prefix.
+#[derive(Debug)]
+pub(crate) struct Mount {
+    pub prefix: String,
+    pub root:   PathBuf,
+    pub opts:   StaticOptions,
+    pub stats:  Arc<RouteStats>,
+    pub assets: Option<Arc<AssetManifest>>,
+}
+
+impl Mount {
+    pub fn new(prefix: &str, root: &Path, opts: StaticOptions) -> Mount {
+        let prefix = String::from(prefix.trim_end_matches('/'));
+        let stats = Arc::new(RouteStats::new(&format!("{}/<path:path>", prefix), Method::Get));
+
+        Mount {
+            prefix,
+            root:   PathBuf::from(root),
+            opts,
+            stats,
+            assets: None,
+        }
+    }
+
+    /// Whether this mount answers requests with method `method`.
+    pub fn allows(&self, method: Method) -> bool {
+        match method {
+            Method::Get                  => true,
+            Method::Put | Method::Delete => self.opts.writable || self.opts.webdav,
+            _                            => self.opts.webdav && Method::webdav().contains(&method),
+        }
+    }
+
+    /// Serve a request for a file under this mount, looking fingerprinted
+    /// names up in the manifest.
+    pub fn serve(&self, req: &Request) -> Response {
+        let rel = self.strip(&req.path).unwrap_or("");
+
+        match req.method {
+            Method::Get    => (),
+            Method::Put    => return put(req, &resolve(&self.root, rel)),
+            Method::Delete => return delete(req, &self.root, &resolve(&self.root, rel)),
+            _              => return dav::handle(req, self, rel),
+        }
+
+        if let Some(ref assets) = self.assets {
+            let clean = utils::replace_escape(rel.split('?').next().unwrap_or(""));
+
+            if let Some(name) = assets.resolve(&clean) {
+                let mut res = serve(req, &self.root, name);
+
+                // the name changes with the contents, so this never goes stale
+                if res.get_status().is_success() || res.get_status() == 304 {
+                    res.set_header("Cache-Control", "public, max-age=31536000, immutable");
+                }
+
+                return res;
+            }
+        }
+
+        serve_path(req, &self.root, rel, self.opts.listing)
+    }
+
+    /// Returns the part of `path` below this mount's prefix, if it falls
+    /// under the mount at all.
+    pub fn strip<'a>(&self, path: &'a str) -> Option<&'a str> {
+        match path.strip_prefix(self.prefix.as_str()) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') || rest.starts_with('?') => Some(rest),
+            _                                                                                 => None,
+        }
+    }
+}
+
+// the file `rel`, an escaped URL path, refers to under `root`. any query
+// string is ignored, and `.` and `..` segments are dropped so the path
+// can't escape `root`.
+pub(crate) fn resolve(root: &Path, rel: &str) -> PathBuf {
+    let clean = utils::replace_escape(rel.split('?').next().unwrap_or(""));
+    let mut fpath = PathBuf::from(root);
+
+    for chunk in clean.split('/') {
+        if chunk.is_empty() || chunk == "." || chunk == ".." {
+            /* bzzzzt */
+            continue;
+        }
+
+        fpath.push(chunk);
+    }
+
+    fpath
+}
+
+// an entity tag for a file, from its size and when it was last changed.
+fn etag(md: &Metadata) -> String {
+    let mtime = md.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+
+    format!("\"{:x}-{:x}.{:x}\"", md.len(), mtime.as_secs(), mtime.subsec_nanos())
+}
+
+/// Serve the file at `rel`, an escaped URL path, from the directory `root`.
+/// Any query string is ignored, and `.` and `..` segments are dropped so the
+/// request can't escape `root`. Responses carry an `ETag`, and clients
+/// whose `If-None-Match` lists it get a `304 Not Modified`. A directory is
+/// answered with its `index.html`.
+pub fn serve(req: &Request, root: &Path, rel: &str) -> Response {
+    serve_path(req, root, rel, false)
+}
+
+// serve a file or directory, listing directories without an index if
+// `listing` is set.
+pub(crate) fn serve_path(req: &Request, root: &Path, rel: &str, listing: bool) -> Response {
+    let fpath = resolve(root, rel);
+
+    match fpath.is_dir() {
+        true  => directory(req, &fpath, listing),
+        false => serve_file(req, &fpath),
+    }
+}
+
+// answer a request for a directory with its index, or a listing.
+fn directory(req: &Request, dir: &Path, listing: bool) -> Response {
+    let path = req.path.split('?').next().unwrap_or("");
+
+    // links in the page are relative to the directory
+    if !path.ends_with('/') {
+        return Response::redirect_preserving_query(req, &format!("{}/", path));
+    }
+
+    let index = dir.join("index.html");
+
+    if index.is_file() {
+        return serve_file(req, &index);
+    }
+
+    if !listing {
+        return err_404(req);
+    }
+
+    let mut entries: Vec<(bool, String)> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| {
+            (!e.file_type().map(|t| t.is_dir()).unwrap_or(false), e.file_name().to_string_lossy().into_owned())
+        }).filter(|(_, name)| !name.starts_with('.')).collect(),
+        Err(_)      => return err_500(req),
+    };
+
+    // directories first, then files, each by name
+    entries.sort();
+
+    let title = utils::escape_html(&utils::replace_escape(path));
+    let mut body = format!("<!DOCTYPE html>\n<html><head><title>Index of {0}</title></head><body>\n<h1>Index of {0}</h1>\n<ul>\n", title);
+
+    if path != "/" {
+        body.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+
+    for (file, name) in entries {
+        let slash = if file { "" } else { "/" };
+
+        body.push_str(&format!("<li><a href=\"{}{}\">{}{}</a></li>\n", utils::encode_segment(&name), slash, utils::escape_html(&name), slash));
+    }
+
+    body.push_str("</ul>\n</body></html>\n");
+
+    utils::html(body)
+}
+
+// serve the file at `fpath`.
+fn serve_file(req: &Request, fpath: &Path) -> Response {
+    let mut res = Response::new();
+    let mut fbuf: Vec<u8> = Vec::new();
+
+    let file = File::open(fpath);
+
+    match file {
+        Ok(mut f)   => {
+            let md = f.metadata().ok();
+            let last = match md {
+                None         => Utc::now(),
+                Some(ref md) => {
+                    match md.modified() {
+                        Err(_)  => Utc::now(), // should never happen...
+                        Ok(st)  => utils::_conv_systemtime(st),
+                    }
+                }
+            };
+            let tag = md.as_ref().map(etag);
+
+            // If-None-Match wins over If-Modified-Since when both are sent
+            let cached = match tag.as_ref().and_then(|tag| conditional::none_match(req, tag)) {
+                Some(cached) => cached,
+                None         => match req.get_header("If-Modified-Since") {
+                    // HTTP dates only go down to the second
+                    Some(hdr) => conditional::parse_http_date(&hdr)
+                                     .map(|since| since.timestamp() >= last.timestamp()).unwrap_or(false),
+                    None      => false,
+                },
+            };
+
+            if let Some(ref tag) = tag {
+                res.set_header("ETag", tag);
+            }
+
+            if cached {
+                // it hasn't been modified, return a 304
+                res.set_status(304);
+                return res;
+            }
+
+            match f.read_to_end(&mut fbuf) {
+                Ok(_)   => {
+                    res.add_header("Last-Modified", &conditional::http_date(last));
+                    res.set_status(200);
+
+                    match MimeGuess::from_path(fpath).first_raw() {
+                        Some(ftype) => res.set_content_type(ftype),
+                        None        => res.set_content_type("text/plain"),
+                    };
+
+                    res.append(fbuf);
+                    res = range::apply(req, res);
+                },
+                Err(_)  => {
+                    return err_500(req);
+                },
+            }
+        },
+        Err(_)      => {
+            return err_404(req);
+        }
+    }
+
+    res
+}
+
+pub(crate) fn status(code: u16) -> Response {
+    let mut res = Response::new();
+
+    res.set_status(code);
+    res
+}
+
+pub(crate) fn parent_exists(path: &Path) -> bool {
+    path.parent().map(Path::is_dir).unwrap_or(false)
+}
+
+// write the body to a temporary file next to `path` and rename it into
+// place, so readers never see a partial upload.
+pub(crate) fn put(req: &Request, path: &Path) -> Response {
+    if path.is_dir() {
+        return utils::err_405(req, &[Method::Get]);
+    }
+
+    if !parent_exists(path) {
+        return status(409);
+    }
+
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{}.upload-{}-{}", name, std::process::id(),
+                                          UPLOADS.fetch_add(1, Ordering::Relaxed)));
+    let existed = path.exists();
+
+    if fs::write(&tmp, &req.payload).and_then(|_| fs::rename(&tmp, path)).is_err() {
+        let _ = fs::remove_file(&tmp);
+        return utils::err_500(req);
+    }
+
+    match existed {
+        true  => status(204),
+        false => status(201),
+    }
+}
+
+pub(crate) fn remove(path: &Path) -> io::Result<()> {
+    match path.is_dir() {
+        true  => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    }
+}
+
+// delete the file or directory at `path`, but never the mount's `root`.
+pub(crate) fn delete(req: &Request, root: &Path, path: &Path) -> Response {
+    if path == root {
+        return utils::err_403(req);
+    }
+
+    if !path.exists() {
+        return utils::err_404(req);
+    }
+
+    match remove(path) {
+        Ok(_)  => status(204),
+        Err(_) => utils::err_500(req),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mount_strip() {
+        let mnt = Mount::new("/static/", Path::new("public"), StaticOptions::new());
+
+        assert_eq!(Some("/css/app.css"), mnt.strip("/static/css/app.css"));
+        assert_eq!(Some(""), mnt.strip("/static"));
+        assert_eq!(Some("?v=2"), mnt.strip("/static?v=2"));
+        assert_eq!(None, mnt.strip("/staticfoo/app.css"));
+        assert_eq!(None, mnt.strip("/other"));
+    }
+
+    #[test]
+    fn test_serve_no_escape() {
+        let mut req = Request::new();
+        req.path = String::from("/../../etc/passwd");
+
+        let root = std::env::temp_dir().join("canteen-files-test-empty");
+        let res = serve(&req, &root, &req.path);
+
+        assert!(String::from_utf8(res.gen_output()).unwrap().starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_writable_mount() {
+        let root = std::env::temp_dir().join("canteen-files-test-writable");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mnt = Mount::new("/up", &root, StaticOptions::new().writable(true));
+        let put = |raw: &str| mnt.serve(&raw.parse().unwrap()).get_status();
+
+        assert!(mnt.allows(Method::Put) && !mnt.allows(Method::Mkcol));
+        assert_eq!(201, put("PUT /up/a.bin HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc"));
+        assert_eq!(204, put("PUT /up/a.bin HTTP/1.1\r\nContent-Length: 1\r\n\r\nd"));
+        assert_eq!(409, put("PUT /up/no/a.bin HTTP/1.1\r\nContent-Length: 1\r\n\r\nd"));
+        assert_eq!(b"d".to_vec(), std::fs::read(root.join("a.bin")).unwrap());
+        assert_eq!(1, std::fs::read_dir(&root).unwrap().count());
+
+        assert_eq!(204, put("DELETE /up/a.bin HTTP/1.1\r\n\r\n"));
+        assert_eq!(404, put("DELETE /up/a.bin HTTP/1.1\r\n\r\n"));
+        assert_eq!(403, put("DELETE /up HTTP/1.1\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_serve_etag() {
+        let root = std::env::temp_dir().join("canteen-files-test-etag");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "hello").unwrap();
+
+        let get = |header: &str| serve(&format!("GET /a.txt HTTP/1.1\r\n{}\r\n\r\n", header).parse().unwrap(), &root, "/a.txt");
+        let res = get("");
+        let tag = String::from(res.get_header("ETag").unwrap());
+
+        assert_eq!(200, res.get_status());
+        assert!(tag.starts_with("\"5-") && tag.ends_with('"'));
+
+        let res = get(&format!("If-None-Match: \"other\", {}", tag));
+        assert_eq!(304, res.get_status());
+        assert_eq!(Some(tag.as_str()), res.get_header("ETag"));
+        assert!(res.get_payload().is_empty());
+
+        // a tag that doesn't match overrides a date that would
+        let res = get("If-None-Match: \"other\"\r\nIf-Modified-Since: Fri, 01 Jan 2100 00:00:00 GMT");
+        assert_eq!(200, res.get_status());
+        assert_eq!(b"hello", res.get_payload());
+    }
+
+    #[test]
+    fn test_serve_if_modified_since() {
+        let root = std::env::temp_dir().join("canteen-files-test-modified");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "hello").unwrap();
+
+        let get = |header: &str| serve(&format!("GET /a.txt HTTP/1.1\r\n{}\r\n\r\n", header).parse().unwrap(), &root, "/a.txt");
+        let res = get("");
+        let last = String::from(res.get_header("Last-Modified").unwrap());
+
+        assert!(conditional::parse_http_date(&last).is_some());
+
+        // as a browser sends it back
+        assert_eq!(304, get(&format!("If-Modified-Since: {}", last)).get_status());
+        assert_eq!(200, get("If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT").get_status());
+        assert_eq!(200, get("If-Modified-Since: yesterday").get_status());
+    }
+
+    #[test]
+    fn test_serve_directory() {
+        let root = std::env::temp_dir().join("canteen-files-test-dir");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("site/sub dir")).unwrap();
+        std::fs::create_dir_all(root.join("bare")).unwrap();
+        std::fs::write(root.join("site/index.html"), "<h1>home</h1>").unwrap();
+        std::fs::write(root.join("bare/a<b>.txt"), "a").unwrap();
+        std::fs::write(root.join("bare/.hidden"), "h").unwrap();
+        std::fs::create_dir_all(root.join("bare/z")).unwrap();
+
+        let get = |mnt: &Mount, path: &str| mnt.serve(&format!("GET {} HTTP/1.1\r\nHost: x\r\n\r\n", path).parse().unwrap());
+        let plain = Mount::new("/files", &root, StaticOptions::new());
+        let listed = Mount::new("/files", &root, StaticOptions::new().listing(true));
+
+        let res = get(&plain, "/files/site?v=1");
+        assert_eq!(301, res.get_status());
+        assert_eq!(Some("http://x/files/site/?v=1"), res.get_header("Location"));
+
+        let res = get(&plain, "/files/site/");
+        assert_eq!(200, res.get_status());
+        assert_eq!(b"<h1>home</h1>", res.get_payload());
+        assert_eq!(404, get(&plain, "/files/bare/").get_status());
+
+        let res = get(&listed, "/files/bare/");
+        let page = String::from_utf8(res.get_payload().to_vec()).unwrap();
+        assert_eq!(200, res.get_status());
+        assert!(page.contains("<h1>Index of /files/bare/</h1>"));
+        assert!(page.find("<a href=\"../\">").unwrap() < page.find("<a href=\"z/\">z/</a>").unwrap());
+        assert!(page.find("<a href=\"z/\">").unwrap() < page.find("<a href=\"a%3Cb%3E.txt\">a&lt;b&gt;.txt</a>").unwrap());
+        assert!(!page.contains("hidden"));
+    }
+
+    #[test]
+    fn test_serve_ignores_query() {
+        let root = std::env::temp_dir().join("canteen-files-test-query");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "hello").unwrap();
+
+        let req = Request::new();
+        let out = String::from_utf8(serve(&req, &root, "/a.txt?v=3").gen_output()).unwrap();
+
+        assert!(out.starts_with("HTTP/1.1 200 OK"));
+        assert!(out.ends_with("\r\n\r\nhello"));
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Route groups: routes registered under a shared prefix, with their own
+//! middleware and default handler, made with `Canteen::group`.
+
+use std::sync::Arc;
+
+use crate::Canteen;
+use crate::error::IntoResult;
+use crate::middleware::{Chain, Middleware};
+use crate::request::{MethodList, Request};
+use crate::route::{HandlerFn, RouteOptions};
+
+// what answers paths under a group's prefix that no route matches.
+pub(crate) struct GroupDefault {
+    pub(crate) prefix:  String,
+    pub(crate) handler: HandlerFn,
+    pub(crate) chain:   Chain,
+}
+
+impl GroupDefault {
+    // whether `path` falls under the group's prefix.
+    pub(crate) fn covers(&self, path: &str) -> bool {
+        match path.strip_prefix(self.prefix.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || rest.starts_with('?'),
+            None       => false,
+        }
+    }
+}
+
+/// A set of routes sharing a path prefix, and optionally middleware and a
+/// default handler. Middleware added to a group run inside the server-wide
+/// middleware, and only for the group's routes; add them before the routes
+/// they should apply to.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Canteen, Method, Request, Response};
+/// use canteen::utils;
+///
+/// fn users(_: &Request) -> Response {
+///     utils::text("[]")
+/// }
+///
+/// let mut cnt = Canteen::new();
+/// let mut api = cnt.group("/api/v1");
+///
+/// api.add_route("/users", Method::Get, users)
+///    .set_default(utils::err_404);
+///
+/// let req: Request = "GET /api/v1/users HTTP/1.1\r\n\r\n".parse().unwrap();
+/// assert_eq!(200, cnt.dispatch(req).get_status());
+/// ```
+pub struct RouteGroup<'a> {
+    cnt:    &'a mut Canteen,
+    prefix: String,
+    chain:  Chain,
+}
+
+impl<'a> RouteGroup<'a> {
+    pub(crate) fn new(cnt: &'a mut Canteen, prefix: &str) -> RouteGroup<'a> {
+        RouteGroup {
+            cnt,
+            prefix: String::from(prefix.trim_end_matches('/')),
+            chain:  Chain::default(),
+        }
+    }
+
+    /// The prefix the group's routes are under.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Adds a route at `path` under the group's prefix. See
+    /// `Canteen::add_route`.
+    pub fn add_route<M, H, R>(&mut self, path: &str, mlist: M, handler: H) -> &mut RouteGroup<'a>
+        where M: MethodList,
+              H: Fn(&Request) -> R + Send + Sync + 'static,
+              R: IntoResult {
+        self.add_route_with(path, mlist, handler, RouteOptions::new())
+    }
+
+    /// Adds a route at `path` under the group's prefix, with its own
+    /// options. See `Canteen::add_route_with`.
+    pub fn add_route_with<M, H, R>(&mut self, path: &str, mlist: M, handler: H,
+                                   mut opts: RouteOptions) -> &mut RouteGroup<'a>
+        where M: MethodList,
+              H: Fn(&Request) -> R + Send + Sync + 'static,
+              R: IntoResult {
+        let path = format!("{}{}", self.prefix, path);
+
+        opts.chain.0.splice(0..0, self.chain.0.iter().cloned());
+        self.cnt.add_route_with(&path, mlist, handler, opts);
+
+        self
+    }
+
+    /// Adds a middleware to run around the handlers of routes added to the
+    /// group after it, and its default handler.
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, mw: M) -> &mut RouteGroup<'a> {
+        self.chain.0.push(Arc::new(mw));
+
+        self
+    }
+
+    /// Sets what answers paths under the group's prefix that none of the
+    /// routes match, in place of the server's default.
+    pub fn set_default<H, R>(&mut self, handler: H) -> &mut RouteGroup<'a>
+        where H: Fn(&Request) -> R + Send + Sync + 'static,
+              R: IntoResult {
+        self.cnt.group_default(GroupDefault {
+            prefix:  self.prefix.clone(),
+            handler: Arc::new(move |req: &Request| handler(req).into_result()),
+            chain:   self.chain.clone(),
+        });
+
+        self
+    }
+
+    /// Starts a group nested under this one, with its middleware.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Canteen, Method, Request};
+    /// use canteen::utils;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// let mut api = cnt.group("/api");
+    ///
+    /// api.group("/v2").add_route("/ping", Method::Get, |_: &Request| utils::text("pong"));
+    ///
+    /// let req: Request = "GET /api/v2/ping HTTP/1.1\r\n\r\n".parse().unwrap();
+    /// assert_eq!(200, cnt.dispatch(req).get_status());
+    /// ```
+    pub fn group(&mut self, prefix: &str) -> RouteGroup<'_> {
+        let mut group = RouteGroup::new(self.cnt, &format!("{}{}", self.prefix, prefix));
+
+        group.chain = self.chain.clone();
+        group
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! A handle onto a server running on a background thread.
+
+use std::net::SocketAddr;
+use std::thread::{self, JoinHandle};
+
+use mio::Sender;
+
+use crate::Message;
+
+/// A server started with `Canteen::spawn`.
+#[derive(Debug)]
+pub struct ServerHandle {
+    addrs:  Vec<SocketAddr>,
+    chan:   Option<Sender<Message>>,
+    thread: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(addrs: Vec<SocketAddr>, chan: Option<Sender<Message>>, thread: JoinHandle<()>) -> ServerHandle {
+        ServerHandle { addrs, chan, thread }
+    }
+
+    /// The address of the first listener, with the actual port if it was
+    /// bound to port 0.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addrs.first().cloned()
+    }
+
+    /// The addresses of all listeners, in the order they were bound.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Ask the server to stop. Returns immediately; use `join` to wait for it
+    /// to finish.
+    pub fn shutdown(&self) {
+        if let Some(ref chan) = self.chan {
+            let _ = chan.send(Message::Shutdown);
+        }
+    }
+
+    /// Wait for the server thread to exit, returning the panic payload if it
+    /// panicked.
+    pub fn join(self) -> thread::Result<()> {
+        self.thread.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use crate::{Canteen, Method, Request, Response};
+    use crate::utils;
+
+    fn hello(_: &Request) -> Response {
+        utils::make_response("hello", "text/plain", 200)
+    }
+
+    #[test]
+    fn test_spawn_and_shutdown() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/", &[Method::Get], hello);
+
+        let server = cnt.spawn();
+        let addr = server.addr().unwrap();
+        assert_ne!(0, addr.port());
+
+        let mut conn = TcpStream::connect(addr).unwrap();
+        let mut out = String::new();
+
+        conn.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        conn.read_to_string(&mut out).unwrap();
+
+        assert!(out.starts_with("HTTP/1.1 200"));
+        assert!(out.ends_with("hello"));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! `103 Early Hints` interim responses, which let the client start fetching
+//! a page's critical assets while its handler is still running.
+
+use mio::{Sender, Token};
+
+use crate::Message;
+
+/// Build a `103 Early Hints` response with a `Link` header for each of
+/// `links`. Links holding control characters, such as a CR or LF that would
+/// end the header and start another, are left out.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::hints;
+///
+/// let out = hints::early_hints(&["</app.css>; rel=preload; as=style", "</x>\r\nSet-Cookie: a=b"]);
+///
+/// assert_eq!(b"HTTP/1.1 103 Early Hints\r\nLink: </app.css>; rel=preload; as=style\r\n\r\n".to_vec(), out);
+/// ```
+pub fn early_hints(links: &[&str]) -> Vec<u8> {
+    let mut out = String::from("HTTP/1.1 103 Early Hints\r\n");
+
+    for link in links {
+        if link.chars().any(|c| c.is_control() && c != '\t') {
+            log::warn!("leaving out an early hint with control characters: {:?}", link);
+            continue;
+        }
+
+        out.push_str(&format!("Link: {}\r\n", link));
+    }
+
+    out.push_str("\r\n");
+
+    out.into_bytes()
+}
+
+// the way back to the connection a request came in on, for interim responses.
+#[derive(Debug)]
+pub(crate) struct HintSender {
+    tx:    Sender<Message>,
+    token: Token,
+    id:    u64,
+}
+
+impl HintSender {
+    pub(crate) fn new(tx: Sender<Message>, token: Token, id: u64) -> HintSender {
+        HintSender { tx, token, id }
+    }
+
+    pub(crate) fn send(&self, links: &[&str]) -> bool {
+        self.tx.send(Message::Interim(self.token, self.id, early_hints(links))).is_ok()
+    }
+}
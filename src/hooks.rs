@@ -0,0 +1,109 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Extension points on the event loop, for integrations like custom
+//! metrics, connection trackers or external wakeups.
+//!
+//! Everything registered here runs on the event loop's thread, so it must
+//! be quick: anything slow holds up every connection.
+
+use std::io;
+use std::net::SocketAddr;
+
+use mio::{EventLoop, PollOpt, Token};
+
+pub use mio::{EventSet, Evented};
+
+use crate::Canteen;
+
+// tokens from here on belong to user sources, well clear of the listeners,
+// the connections and the tokens mio reserves at the very top.
+pub(crate) const SOURCE_BASE: usize = usize::MAX / 2;
+
+// a function run on every turn of the event loop.
+pub(crate) type TickFn = Box<dyn FnMut() + Send>;
+
+// a function told about a connection opening or closing.
+pub(crate) type ConnectionFn = Box<dyn Fn(&ConnectionInfo) + Send>;
+
+/// A connection that has just been opened or closed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectionInfo {
+    /// The connection's token on the event loop. Tokens are reused once a
+    /// connection closes.
+    pub token: usize,
+    /// The client's address, if it's known.
+    pub peer:  Option<SocketAddr>,
+}
+
+// an evented source owned by the user, with what to do when it's ready.
+pub(crate) trait Source: Send {
+    fn register(&self, evl: &mut EventLoop<Canteen>, token: Token) -> io::Result<()>;
+    fn ready(&mut self, events: EventSet);
+}
+
+pub(crate) struct UserSource<E, F> {
+    pub(crate) source:   E,
+    pub(crate) interest: EventSet,
+    pub(crate) on_ready: F,
+}
+
+impl<E, F> Source for UserSource<E, F>
+    where E: Evented + Send,
+          F: FnMut(&mut E, EventSet) + Send {
+    fn register(&self, evl: &mut EventLoop<Canteen>, token: Token) -> io::Result<()> {
+        evl.register(&self.source, token, self.interest, PollOpt::level())
+    }
+
+    fn ready(&mut self, events: EventSet) {
+        (self.on_ready)(&mut self.source, events)
+    }
+}
+
+// everything registered through `Canteen::on_tick` and friends.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    pub(crate) tick:    Vec<TickFn>,
+    pub(crate) open:    Vec<ConnectionFn>,
+    pub(crate) close:   Vec<ConnectionFn>,
+    pub(crate) sources: Vec<Box<dyn Source>>,
+}
+
+impl Hooks {
+    pub(crate) fn tick(&mut self) {
+        for tick in self.tick.iter_mut() {
+            tick();
+        }
+    }
+
+    pub(crate) fn opened(&self, info: &ConnectionInfo) {
+        for hook in &self.open {
+            hook(info);
+        }
+    }
+
+    pub(crate) fn closed(&self, info: &ConnectionInfo) {
+        for hook in &self.close {
+            hook(info);
+        }
+    }
+
+    pub(crate) fn register(&self, evl: &mut EventLoop<Canteen>) -> io::Result<()> {
+        for (i, source) in self.sources.iter().enumerate() {
+            source.register(evl, Token(SOURCE_BASE + i))?;
+        }
+
+        Ok(())
+    }
+
+    // pass an event on to the source it's for.
+    pub(crate) fn ready(&mut self, token: Token, events: EventSet) {
+        if let Some(source) = self.sources.get_mut(token.as_usize() - SOURCE_BASE) {
+            source.ready(events);
+        }
+    }
+}
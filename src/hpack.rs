@@ -0,0 +1,552 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! HPACK (RFC 7541), the header compression used by HTTP/2.
+//!
+//! Header blocks from clients are decoded in full, Huffman coding and the
+//! dynamic table included. Responses are encoded without the dynamic table,
+//! so encoding needs no state and can happen on the worker that produced
+//! the response.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// The size of the dynamic table a decoder starts with, and the most it may
+/// grow to unless the decoder is told otherwise.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+// the entries every HPACK context starts with, numbered from 1.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""), (":method", "GET"), (":method", "POST"), (":path", "/"),
+    (":path", "/index.html"), (":scheme", "http"), (":scheme", "https"), (":status", "200"),
+    (":status", "204"), (":status", "206"), (":status", "304"), (":status", "400"),
+    (":status", "404"), (":status", "500"), ("accept-charset", ""), ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""), ("accept-ranges", ""), ("accept", ""), ("access-control-allow-origin", ""),
+    ("age", ""), ("allow", ""), ("authorization", ""), ("cache-control", ""),
+    ("content-disposition", ""), ("content-encoding", ""), ("content-language", ""), ("content-length", ""),
+    ("content-location", ""), ("content-range", ""), ("content-type", ""), ("cookie", ""),
+    ("date", ""), ("etag", ""), ("expect", ""), ("expires", ""),
+    ("from", ""), ("host", ""), ("if-match", ""), ("if-modified-since", ""),
+    ("if-none-match", ""), ("if-range", ""), ("if-unmodified-since", ""), ("last-modified", ""),
+    ("link", ""), ("location", ""), ("max-forwards", ""), ("proxy-authenticate", ""),
+    ("proxy-authorization", ""), ("range", ""), ("referer", ""), ("refresh", ""),
+    ("retry-after", ""), ("server", ""), ("set-cookie", ""), ("strict-transport-security", ""),
+    ("transfer-encoding", ""), ("user-agent", ""), ("vary", ""), ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// the Huffman code of each byte, and of end-of-string at 256, as (code, bits).
+const HUFFMAN: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28),
+    (0xfffffe4, 28), (0xfffffe5, 28), (0xfffffe6, 28), (0xfffffe7, 28),
+    (0xfffffe8, 28), (0xffffea, 24), (0x3ffffffc, 30), (0xfffffe9, 28),
+    (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28), (0xfffffec, 28),
+    (0xfffffed, 28), (0xfffffee, 28), (0xfffffef, 28), (0xffffff0, 28),
+    (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28),
+    (0xffffff4, 28), (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28),
+    (0xffffff8, 28), (0xffffff9, 28), (0xffffffa, 28), (0xffffffb, 28),
+    (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12),
+    (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11),
+    (0x3fa, 10), (0x3fb, 10), (0xf9, 8), (0x7fb, 11),
+    (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6),
+    (0x0, 5), (0x1, 5), (0x2, 5), (0x19, 6),
+    (0x1a, 6), (0x1b, 6), (0x1c, 6), (0x1d, 6),
+    (0x1e, 6), (0x1f, 6), (0x5c, 7), (0xfb, 8),
+    (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10),
+    (0x1ffa, 13), (0x21, 6), (0x5d, 7), (0x5e, 7),
+    (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7),
+    (0x63, 7), (0x64, 7), (0x65, 7), (0x66, 7),
+    (0x67, 7), (0x68, 7), (0x69, 7), (0x6a, 7),
+    (0x6b, 7), (0x6c, 7), (0x6d, 7), (0x6e, 7),
+    (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7),
+    (0xfc, 8), (0x73, 7), (0xfd, 8), (0x1ffb, 13),
+    (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6),
+    (0x7ffd, 15), (0x3, 5), (0x23, 6), (0x4, 5),
+    (0x24, 6), (0x5, 5), (0x25, 6), (0x26, 6),
+    (0x27, 6), (0x6, 5), (0x74, 7), (0x75, 7),
+    (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5),
+    (0x2b, 6), (0x76, 7), (0x2c, 6), (0x8, 5),
+    (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7),
+    (0x79, 7), (0x7a, 7), (0x7b, 7), (0x7ffe, 15),
+    (0x7fc, 11), (0x3ffd, 14), (0x1ffd, 13), (0xffffffc, 28),
+    (0xfffe6, 20), (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20),
+    (0x3fffd3, 22), (0x3fffd4, 22), (0x3fffd5, 22), (0x7fffd9, 23),
+    (0x3fffd6, 22), (0x7fffda, 23), (0x7fffdb, 23), (0x7fffdc, 23),
+    (0x7fffdd, 23), (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23),
+    (0xffffec, 24), (0xffffed, 24), (0x3fffd7, 22), (0x7fffe0, 23),
+    (0xffffee, 24), (0x7fffe1, 23), (0x7fffe2, 23), (0x7fffe3, 23),
+    (0x7fffe4, 23), (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23),
+    (0x3fffd9, 22), (0x7fffe6, 23), (0x7fffe7, 23), (0xffffef, 24),
+    (0x3fffda, 22), (0x1fffdd, 21), (0xfffe9, 20), (0x3fffdb, 22),
+    (0x3fffdc, 22), (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21),
+    (0x7fffea, 23), (0x3fffdd, 22), (0x3fffde, 22), (0xfffff0, 24),
+    (0x1fffdf, 21), (0x3fffdf, 22), (0x7fffeb, 23), (0x7fffec, 23),
+    (0x1fffe0, 21), (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21),
+    (0x7fffed, 23), (0x3fffe1, 22), (0x7fffee, 23), (0x7fffef, 23),
+    (0xfffea, 20), (0x3fffe2, 22), (0x3fffe3, 22), (0x3fffe4, 22),
+    (0x7ffff0, 23), (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23),
+    (0x3ffffe0, 26), (0x3ffffe1, 26), (0xfffeb, 20), (0x7fff1, 19),
+    (0x3fffe7, 22), (0x7ffff2, 23), (0x3fffe8, 22), (0x1ffffec, 25),
+    (0x3ffffe2, 26), (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27),
+    (0x7ffffdf, 27), (0x3ffffe5, 26), (0xfffff1, 24), (0x1ffffed, 25),
+    (0x7fff2, 19), (0x1fffe3, 21), (0x3ffffe6, 26), (0x7ffffe0, 27),
+    (0x7ffffe1, 27), (0x3ffffe7, 26), (0x7ffffe2, 27), (0xfffff2, 24),
+    (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26),
+    (0xffffffd, 28), (0x7ffffe3, 27), (0x7ffffe4, 27), (0x7ffffe5, 27),
+    (0xfffec, 20), (0xfffff3, 24), (0xfffed, 20), (0x1fffe6, 21),
+    (0x3fffe9, 22), (0x1fffe7, 21), (0x1fffe8, 21), (0x7ffff3, 23),
+    (0x3fffea, 22), (0x3fffeb, 22), (0x1ffffee, 25), (0x1ffffef, 25),
+    (0xfffff4, 24), (0xfffff5, 24), (0x3ffffea, 26), (0x7ffff4, 23),
+    (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26), (0x3ffffed, 26),
+    (0x7ffffe7, 27), (0x7ffffe8, 27), (0x7ffffe9, 27), (0x7ffffea, 27),
+    (0x7ffffeb, 27), (0xffffffe, 28), (0x7ffffec, 27), (0x7ffffed, 27),
+    (0x7ffffee, 27), (0x7ffffef, 27), (0x7fffff0, 27), (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+// each entry's size counts its name and value, plus this much overhead.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Why a header block couldn't be decoded. Any of these is a connection
+/// error, as the decoder's state can no longer be trusted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HpackError {
+    /// The block ended in the middle of a representation.
+    Truncated,
+    /// An integer was too large to be meaningful.
+    Overflow,
+    /// An index pointed past the end of the tables.
+    BadIndex(usize),
+    /// A Huffman-coded string was malformed.
+    BadHuffman,
+    /// A table size update asked for more than the decoder allows.
+    TableTooLarge(usize),
+}
+
+impl fmt::Display for HpackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HpackError::Truncated          => write!(f, "truncated header block"),
+            HpackError::Overflow           => write!(f, "integer overflow in header block"),
+            HpackError::BadIndex(i)        => write!(f, "invalid header table index {}", i),
+            HpackError::BadHuffman         => write!(f, "invalid Huffman-coded string"),
+            HpackError::TableTooLarge(max) => write!(f, "header table size {} exceeds the limit", max),
+        }
+    }
+}
+
+impl Error for HpackError {}
+
+// reads the representations of a header block in turn.
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos:  usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn byte(&mut self) -> Result<u8, HpackError> {
+        let b = self.peek().ok_or(HpackError::Truncated)?;
+
+        self.pos += 1;
+        Ok(b)
+    }
+
+    // an integer with an `n`-bit prefix in the current byte.
+    fn integer(&mut self, n: u8) -> Result<usize, HpackError> {
+        let mask = (1usize << n) - 1;
+        let mut value = self.byte()? as usize & mask;
+
+        if value < mask {
+            return Ok(value);
+        }
+
+        let mut shift = 0;
+
+        loop {
+            let b = self.byte()?;
+
+            if shift > 28 {
+                return Err(HpackError::Overflow);
+            }
+
+            value += ((b & 0x7f) as usize) << shift;
+            shift += 7;
+
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, HpackError> {
+        let huffman = self.peek().ok_or(HpackError::Truncated)? & 0x80 != 0;
+        let len = self.integer(7)?;
+        let raw = self.data.get(self.pos..self.pos + len).ok_or(HpackError::Truncated)?;
+
+        self.pos += len;
+
+        let bytes = match huffman {
+            true  => huffman_decode(raw)?,
+            false => raw.to_vec(),
+        };
+
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+// the canonical form of the Huffman code: for each length, the first code
+// of that length, how many codes have it, and where their symbols start.
+struct Canonical {
+    first:   [u32; 31],
+    count:   [u32; 31],
+    start:   [usize; 31],
+    symbols: Vec<u16>,
+}
+
+fn canonical() -> &'static Canonical {
+    static CANONICAL: OnceLock<Canonical> = OnceLock::new();
+
+    CANONICAL.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..257).collect();
+        let mut table = Canonical { first: [0; 31], count: [0; 31], start: [0; 31], symbols: Vec::new() };
+
+        symbols.sort_by_key(|&s| (HUFFMAN[s as usize].1, s));
+
+        for (i, &s) in symbols.iter().enumerate() {
+            let (code, bits) = HUFFMAN[s as usize];
+            let bits = bits as usize;
+
+            if table.count[bits] == 0 {
+                table.first[bits] = code;
+                table.start[bits] = i;
+            }
+
+            table.count[bits] += 1;
+        }
+
+        table.symbols = symbols;
+        table
+    })
+}
+
+/// Decode a Huffman-coded string.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::hpack;
+///
+/// let coded = [0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff];
+/// assert_eq!(b"www.example.com".to_vec(), hpack::huffman_decode(&coded).unwrap());
+/// ```
+pub fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, HpackError> {
+    let table = canonical();
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let mut code = 0u32;
+    let mut bits = 0usize;
+
+    for byte in data {
+        for i in (0..8).rev() {
+            code = (code << 1) | ((*byte as u32 >> i) & 1);
+            bits += 1;
+
+            if bits > 30 {
+                return Err(HpackError::BadHuffman);
+            }
+
+            let offset = code.wrapping_sub(table.first[bits]);
+
+            if table.count[bits] > 0 && code >= table.first[bits] && offset < table.count[bits] {
+                match table.symbols[table.start[bits] + offset as usize] {
+                    256 => return Err(HpackError::BadHuffman),
+                    sym => out.push(sym as u8),
+                }
+
+                code = 0;
+                bits = 0;
+            }
+        }
+    }
+
+    // what's left must be padding: fewer than eight bits, all ones
+    if bits >= 8 || code != (1 << bits) - 1 {
+        return Err(HpackError::BadHuffman);
+    }
+
+    Ok(out)
+}
+
+/// Decodes the header blocks of one connection, keeping the dynamic table
+/// they build up between blocks.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::hpack::Decoder;
+///
+/// let mut decoder = Decoder::new();
+/// let headers = decoder.decode(&[0x82, 0x86, 0x84]).unwrap();
+///
+/// assert_eq!(vec![(String::from(":method"), String::from("GET")),
+///                 (String::from(":scheme"), String::from("http")),
+///                 (String::from(":path"), String::from("/"))], headers);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    table: VecDeque<(String, String)>,
+    size:  usize,
+    max:   usize,
+    limit: usize,
+}
+
+impl Decoder {
+    /// Create a decoder with the default table size.
+    pub fn new() -> Decoder {
+        Decoder {
+            table: VecDeque::new(),
+            size:  0,
+            max:   DEFAULT_TABLE_SIZE,
+            limit: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    /// The entries in the dynamic table, newest first.
+    pub fn dynamic_table(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.table.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String), HpackError> {
+        match index {
+            0                                   => Err(HpackError::BadIndex(0)),
+            i if i <= STATIC_TABLE.len()        => {
+                let (name, value) = STATIC_TABLE[i - 1];
+                Ok((String::from(name), String::from(value)))
+            },
+            i                                   => {
+                self.table.get(i - STATIC_TABLE.len() - 1).cloned().ok_or(HpackError::BadIndex(i))
+            },
+        }
+    }
+
+    // drop the oldest entries until the table fits in `max`.
+    fn evict(&mut self) {
+        while self.size > self.max {
+            match self.table.pop_back() {
+                Some((n, v)) => self.size -= n.len() + v.len() + ENTRY_OVERHEAD,
+                None         => break,
+            }
+        }
+    }
+
+    fn insert(&mut self, name: &str, value: &str) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+
+        // an entry larger than the table just empties it
+        self.size += size;
+        self.table.push_front((String::from(name), String::from(value)));
+        self.evict();
+    }
+
+    /// Decode a complete header block into its fields, in order.
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, HpackError> {
+        let mut cur = Cursor { data: block, pos: 0 };
+        let mut fields = Vec::new();
+
+        while let Some(b) = cur.peek() {
+            if b & 0x80 != 0 {
+                // indexed field
+                let index = cur.integer(7)?;
+                fields.push(self.entry(index)?);
+            } else if b & 0xe0 == 0x20 {
+                // dynamic table size update
+                let max = cur.integer(5)?;
+
+                if max > self.limit {
+                    return Err(HpackError::TableTooLarge(max));
+                }
+
+                self.max = max;
+                self.evict();
+            } else {
+                // a literal, added to the table or not
+                let (prefix, indexing) = match b & 0x40 != 0 {
+                    true  => (6, true),
+                    false => (4, false),
+                };
+                let name = match cur.integer(prefix)? {
+                    0     => cur.string()?,
+                    index => self.entry(index)?.0,
+                };
+                let value = cur.string()?;
+
+                if indexing {
+                    self.insert(&name, &value);
+                }
+
+                fields.push((name, value));
+            }
+        }
+
+        Ok(fields)
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+fn integer(out: &mut Vec<u8>, flags: u8, n: u8, mut value: usize) {
+    let mask = (1usize << n) - 1;
+
+    if value < mask {
+        out.push(flags | value as u8);
+        return;
+    }
+
+    out.push(flags | mask as u8);
+    value -= mask;
+
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+fn string(out: &mut Vec<u8>, value: &str) {
+    integer(out, 0, 7, value.len());
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// Encode header fields into a block, using the static table where it has
+/// the field or its name, and literals without indexing otherwise. Names
+/// should already be lowercase.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::hpack::{self, Decoder};
+///
+/// let block = hpack::encode(&[(":status", "200"), ("x-thing", "yes")]);
+///
+/// assert_eq!(0x88, block[0]);
+/// assert_eq!(2, Decoder::new().decode(&block).unwrap().len());
+/// ```
+pub fn encode(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for &(name, value) in fields {
+        let exact = STATIC_TABLE.iter().position(|&(n, v)| n == name && v == value);
+        let named = STATIC_TABLE.iter().position(|&(n, _)| n == name);
+
+        match (exact, named) {
+            (Some(i), _)    => integer(&mut out, 0x80, 7, i + 1),
+            (None, Some(i)) => {
+                integer(&mut out, 0, 4, i + 1);
+                string(&mut out, value);
+            },
+            (None, None)    => {
+                out.push(0);
+                string(&mut out, name);
+                string(&mut out, value);
+            },
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn fields(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|&(n, v)| (String::from(n), String::from(v))).collect()
+    }
+
+    #[test]
+    fn test_integers() {
+        let mut out = Vec::new();
+
+        integer(&mut out, 0, 5, 10);
+        integer(&mut out, 0, 5, 1337);
+        integer(&mut out, 0, 8, 42);
+        assert_eq!(vec![0x0a, 0x1f, 0x9a, 0x0a, 0x2a], out);
+
+        let mut cur = Cursor { data: &out, pos: 0 };
+        assert_eq!(10, cur.integer(5).unwrap());
+        assert_eq!(1337, cur.integer(5).unwrap());
+        assert_eq!(42, cur.integer(8).unwrap());
+        assert_eq!(Err(HpackError::Overflow), Cursor { data: &[0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], pos: 0 }.integer(7));
+    }
+
+    // the requests of RFC 7541, appendix C.4, which share a dynamic table
+    #[test]
+    fn test_decode_requests() {
+        let mut decoder = Decoder::new();
+
+        assert_eq!(fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")]),
+                   decoder.decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff")).unwrap());
+        assert_eq!(fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"),
+                            ("cache-control", "no-cache")]),
+                   decoder.decode(&hex("8286 84be 5886 a8eb 1064 9cbf")).unwrap());
+        assert_eq!(fields(&[(":method", "GET"), (":scheme", "https"), (":path", "/index.html"),
+                            (":authority", "www.example.com"), ("custom-key", "custom-value")]),
+                   decoder.decode(&hex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf")).unwrap());
+
+        let table: Vec<_> = decoder.dynamic_table().collect();
+        assert_eq!(vec![("custom-key", "custom-value"), ("cache-control", "no-cache"), (":authority", "www.example.com")], table);
+        assert_eq!(164, decoder.size);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Err(HpackError::BadIndex(0)), Decoder::new().decode(&[0x80]));
+        assert_eq!(Err(HpackError::BadIndex(62)), Decoder::new().decode(&[0xbe]));
+        assert_eq!(Err(HpackError::Truncated), Decoder::new().decode(&[0x04, 0x05, b'/', b'a']));
+        assert_eq!(Err(HpackError::TableTooLarge(8192)), Decoder::new().decode(&hex("3fe1 3f")));
+
+        // padding longer than seven bits, or not all ones
+        assert_eq!(Err(HpackError::BadHuffman), huffman_decode(&[0x1f, 0xff]));
+        assert_eq!(Err(HpackError::BadHuffman), huffman_decode(&[0x00]));
+    }
+
+    #[test]
+    fn test_table_eviction() {
+        let mut decoder = Decoder::new();
+
+        // shrink the table to fit one entry, then add two
+        decoder.decode(&hex("3f15")).unwrap();
+        decoder.decode(&[0x40, 0x01, b'a', 0x01, b'1', 0x40, 0x01, b'b', 0x01, b'2']).unwrap();
+
+        assert_eq!(vec![("b", "2")], decoder.dynamic_table().collect::<Vec<_>>());
+        assert_eq!(fields(&[("b", "2")]), decoder.decode(&[0xbe]).unwrap());
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let list = [(":status", "404"), ("content-type", "text/html"), ("x-long", &"y".repeat(300)[..])];
+        let block = encode(&list);
+
+        assert_eq!(0x8d, block[0]);
+        assert_eq!(fields(&list), Decoder::new().decode(&block).unwrap());
+    }
+}
@@ -0,0 +1,952 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! HTTP/2 (RFC 9113), built with the `h2` feature.
+//!
+//! TLS listeners offer `h2` with ALPN. Plaintext listeners accept HTTP/2
+//! from clients that start with the connection preface (prior knowledge),
+//! or that upgrade an HTTP/1.1 request with `Upgrade: h2c`. Each stream's
+//! request is routed and handled like any other, and the response goes back
+//! as HEADERS and DATA frames as the client's flow control windows allow.
+//! Server push and stream priorities aren't supported, and interim (1xx)
+//! responses are only sent over HTTP/1.1.
+
+use std::collections::HashMap;
+
+use crate::hpack::{self, Decoder};
+use crate::request::{Method, Request};
+use crate::response::Response;
+use crate::utils;
+
+/// What a client sends first on an HTTP/2 connection.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+// the largest frame we accept; we never ask for more than the default.
+const MAX_FRAME: usize = 16384;
+
+// the largest header block we'll reassemble from CONTINUATION frames.
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+
+// how many streams a client may have open at once.
+const MAX_STREAMS: u32 = 100;
+
+// the window every stream and the connection start with.
+const DEFAULT_WINDOW: i64 = 65535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+// headers that only mean something to an HTTP/1.1 connection.
+const CONNECTION_HEADERS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+// a connection error: the code to go away with, and why.
+type Fault = (u32, &'static str);
+
+fn frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    let len = payload.len();
+
+    out.extend_from_slice(&[(len >> 16) as u8, (len >> 8) as u8, len as u8, kind, flags]);
+    out.extend_from_slice(&(stream & 0x7fff_ffff).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+// strip a frame's padding, if it has any.
+fn unpad(flags: u8, payload: &[u8]) -> Result<&[u8], Fault> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+
+    match payload.first() {
+        Some(&pad) if (pad as usize) < payload.len() => Ok(&payload[1..payload.len() - pad as usize]),
+        _                                            => Err((PROTOCOL_ERROR, "bad padding")),
+    }
+}
+
+/// Encode a response's status and headers into a header block. Headers
+/// that only apply to HTTP/1.1 connections are left out.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{hpack, http2, utils};
+///
+/// let block = http2::encode_head(&utils::text("hello"));
+/// let fields = hpack::Decoder::new().decode(&block).unwrap();
+///
+/// assert_eq!((String::from(":status"), String::from("200")), fields[0]);
+/// ```
+pub fn encode_head(res: &Response) -> Vec<u8> {
+    let status = res.get_status().as_u16().to_string();
+    let length = res.get_payload().len().to_string();
+    let mut names = Vec::new();
+
+    for (name, value) in res.headers() {
+        let name = name.to_ascii_lowercase();
+
+        if !CONNECTION_HEADERS.contains(&name.as_str()) {
+            names.push((name, value));
+        }
+    }
+
+    let mut fields: Vec<(&str, &str)> = vec![(":status", &status)];
+
+    fields.extend(names.iter().map(|(n, v)| (n.as_str(), *v)));
+    fields.push(("content-type", res.get_content_type()));
+    fields.push(("content-length", &length));
+
+    hpack::encode(&fields)
+}
+
+// whether an HTTP/1.1 request asks to continue the connection in HTTP/2.
+pub(crate) fn wants_upgrade(req: &Request) -> bool {
+    let upgrade = req.get_header("Upgrade").unwrap_or_default();
+
+    req.version == "HTTP/1.1"
+        && upgrade.split(',').any(|p| p.trim().eq_ignore_ascii_case("h2c"))
+        && req.get_header("HTTP2-Settings").is_some()
+}
+
+// build a request from a stream's header fields and body, if they make one.
+fn request(fields: Vec<(String, String)>, body: Vec<u8>) -> Option<Request> {
+    let mut req = Request::new();
+    let mut method = None;
+    let mut path = None;
+    let mut authority = None;
+    let mut cookies = Vec::new();
+
+    for (name, value) in fields {
+        match name.as_str() {
+            ":method"                => method = Some(Method::from(value.as_str())),
+            ":path"                  => path = Some(value),
+            ":authority"             => authority = Some(value),
+            ":scheme"                => {},
+            n if n.starts_with(':')  => return None,
+            "cookie"                 => cookies.push(value),
+            n if n.bytes().any(|b| b.is_ascii_uppercase()) => return None,
+            _                        => req.headers.push((name, value)),
+        }
+    }
+
+    // cookies may be split across fields, but handlers expect one header
+    if !cookies.is_empty() {
+        req.headers.push((String::from("cookie"), cookies.join("; ")));
+    }
+
+    if let Some(host) = authority {
+        if req.get_header("Host").is_none() {
+            req.headers.push((String::from("host"), host));
+        }
+    }
+
+    req.method = method?;
+    req.path = path.filter(|p| !p.is_empty())?;
+    req.version = String::from("HTTP/2.0");
+    req.payload = body;
+
+    Some(req)
+}
+
+// a stream the client has opened.
+struct Stream {
+    fields:  Vec<(String, String)>,
+    body:    Vec<u8>,
+    // whether the client has finished sending
+    closed:  bool,
+    // how much we may send on it
+    window:  i64,
+    // response data waiting for the window to open, and how much has gone
+    pending: Option<(Vec<u8>, usize)>,
+}
+
+/// The HTTP/2 side of a connection: it takes in the bytes the client sends,
+/// hands back the requests they complete, and frames the responses.
+pub(crate) struct Session {
+    preface:   bool,
+    inbuf:     Vec<u8>,
+    out:       Vec<u8>,
+    decoder:   Decoder,
+    streams:   HashMap<u32, Stream>,
+    jobs:      HashMap<u64, u32>,
+    last:      u32,
+    window:    i64,
+    initial:   i64,
+    frame_max: usize,
+    block:     Option<(u32, Vec<u8>, bool)>,
+    goaway:    bool,
+    max_body:  Option<usize>,
+}
+
+impl Session {
+    // a connection that starts with the client's preface.
+    pub(crate) fn new() -> Session {
+        let mut session = Session {
+            preface:   false,
+            inbuf:     Vec::new(),
+            out:       Vec::new(),
+            decoder:   Decoder::new(),
+            streams:   HashMap::new(),
+            jobs:      HashMap::new(),
+            last:      0,
+            window:    DEFAULT_WINDOW,
+            initial:   DEFAULT_WINDOW,
+            frame_max: MAX_FRAME,
+            block:     None,
+            goaway:    false,
+            max_body:  None,
+        };
+
+        let mut settings = Vec::new();
+
+        settings.extend_from_slice(&0x3u16.to_be_bytes());
+        settings.extend_from_slice(&MAX_STREAMS.to_be_bytes());
+        frame(&mut session.out, SETTINGS, 0, 0, &settings);
+
+        session
+    }
+
+    // a connection upgraded from HTTP/1.1, whose request becomes stream 1.
+    // `settings` is the client's HTTP2-Settings header, which the 101
+    // acknowledges.
+    pub(crate) fn upgraded(settings: &str) -> Option<Session> {
+        let standard: String = settings.trim().chars().map(|c| match c {
+            '-' => '+',
+            '_' => '/',
+            c   => c,
+        }).collect();
+        let payload = utils::base64_decode(&standard)?;
+        let mut session = Session::new();
+
+        session.settings(&payload).ok()?;
+        session.last = 1;
+        session.streams.insert(1, Stream {
+            fields:  Vec::new(),
+            body:    Vec::new(),
+            closed:  true,
+            window:  session.initial,
+            pending: None,
+        });
+
+        Some(session)
+    }
+
+    // refuse request bodies larger than `limit` with a 413.
+    pub(crate) fn max_body(mut self, limit: Option<usize>) -> Session {
+        self.max_body = limit;
+        self
+    }
+
+    /// Whether `data` opens an HTTP/2 connection with prior knowledge.
+    pub(crate) fn is_preface(data: &[u8]) -> bool {
+        data.starts_with(PREFACE)
+    }
+
+    // the frames waiting to be written.
+    pub(crate) fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out)
+    }
+
+    // whether no stream is open.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.streams.is_empty() && self.block.is_none()
+    }
+
+    // whether the connection is finished with, either side having gone away.
+    pub(crate) fn is_done(&self) -> bool {
+        self.goaway && self.is_idle()
+    }
+
+    // the handler for job `id` is answering stream `stream`.
+    pub(crate) fn start(&mut self, stream: u32, id: u64) {
+        self.jobs.insert(id, stream);
+    }
+
+    // whether job `id` is answering one of this connection's streams.
+    pub(crate) fn owns(&self, id: u64) -> bool {
+        self.jobs.contains_key(&id)
+    }
+
+    fn reset(&mut self, stream: u32, code: u32) {
+        self.streams.remove(&stream);
+        frame(&mut self.out, RST_STREAM, 0, stream, &code.to_be_bytes());
+    }
+
+    fn go_away(&mut self, code: u32, why: &str) {
+        let mut payload = Vec::new();
+
+        log::debug!("closing HTTP/2 connection: {}", why);
+        payload.extend_from_slice(&self.last.to_be_bytes());
+        payload.extend_from_slice(&code.to_be_bytes());
+        frame(&mut self.out, GOAWAY, 0, 0, &payload);
+
+        self.goaway = true;
+        self.streams.clear();
+    }
+
+    // take in what the client sent, returning the requests it completes
+    // with their stream ids. on a connection error, a GOAWAY is queued and
+    // `Err` returned; the connection should be closed once it's written.
+    pub(crate) fn receive(&mut self, data: &[u8]) -> Result<Vec<(u32, Request)>, ()> {
+        let mut done = Vec::new();
+
+        self.inbuf.extend_from_slice(data);
+
+        if !self.preface {
+            if self.inbuf.len() < PREFACE.len() {
+                return match PREFACE.starts_with(&self.inbuf) {
+                    true  => Ok(done),
+                    false => { self.go_away(PROTOCOL_ERROR, "bad preface"); Err(()) },
+                };
+            }
+
+            if !Session::is_preface(&self.inbuf) {
+                self.go_away(PROTOCOL_ERROR, "bad preface");
+                return Err(());
+            }
+
+            self.inbuf.drain(..PREFACE.len());
+            self.preface = true;
+        }
+
+        while self.inbuf.len() >= 9 {
+            let len = (self.inbuf[0] as usize) << 16 | (self.inbuf[1] as usize) << 8 | self.inbuf[2] as usize;
+
+            if len > MAX_FRAME {
+                self.go_away(FRAME_SIZE_ERROR, "frame too large");
+                return Err(());
+            }
+
+            if self.inbuf.len() < 9 + len {
+                break;
+            }
+
+            let head: Vec<u8> = self.inbuf.drain(..9).collect();
+            let payload: Vec<u8> = self.inbuf.drain(..len).collect();
+            let stream = u32_at(&head, 5) & 0x7fff_ffff;
+
+            if let Err((code, why)) = self.frame(head[3], head[4], stream, &payload, &mut done) {
+                self.go_away(code, why);
+                return Err(());
+            }
+        }
+
+        Ok(done)
+    }
+
+    fn frame(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8],
+             done: &mut Vec<(u32, Request)>) -> Result<(), Fault> {
+        // a header block must be finished before anything else is sent
+        if let Some((id, _, _)) = self.block {
+            if kind != CONTINUATION || stream != id {
+                return Err((PROTOCOL_ERROR, "expected CONTINUATION"));
+            }
+        }
+
+        match kind {
+            DATA          => self.data(flags, stream, payload, done),
+            HEADERS       => {
+                if stream == 0 {
+                    return Err((PROTOCOL_ERROR, "HEADERS on stream 0"));
+                }
+
+                let mut fragment = unpad(flags, payload)?;
+
+                if flags & PRIORITY != 0 {
+                    fragment = fragment.get(5..).ok_or((FRAME_SIZE_ERROR, "short HEADERS"))?;
+                }
+
+                match flags & END_HEADERS != 0 {
+                    true  => self.headers(stream, fragment, flags & END_STREAM != 0, done),
+                    false => {
+                        self.block = Some((stream, fragment.to_vec(), flags & END_STREAM != 0));
+                        Ok(())
+                    },
+                }
+            },
+            CONTINUATION  => {
+                let (id, mut block, end) = self.block.take().ok_or((PROTOCOL_ERROR, "unexpected CONTINUATION"))?;
+
+                block.extend_from_slice(payload);
+
+                if block.len() > MAX_HEADER_BLOCK {
+                    return Err((ENHANCE_YOUR_CALM, "header block too large"));
+                }
+
+                match flags & END_HEADERS != 0 {
+                    true  => self.headers(id, &block, end, done),
+                    false => {
+                        self.block = Some((id, block, end));
+                        Ok(())
+                    },
+                }
+            },
+            RST_STREAM    => {
+                if payload.len() != 4 || stream == 0 {
+                    return Err((PROTOCOL_ERROR, "bad RST_STREAM"));
+                }
+
+                self.streams.remove(&stream);
+                Ok(())
+            },
+            SETTINGS      => {
+                if stream != 0 {
+                    return Err((PROTOCOL_ERROR, "SETTINGS on a stream"));
+                }
+
+                if flags & ACK != 0 {
+                    return match payload.is_empty() {
+                        true  => Ok(()),
+                        false => Err((FRAME_SIZE_ERROR, "SETTINGS ack with a payload")),
+                    };
+                }
+
+                self.settings(payload)?;
+                frame(&mut self.out, SETTINGS, ACK, 0, &[]);
+                self.flush_all();
+                Ok(())
+            },
+            PUSH_PROMISE  => Err((PROTOCOL_ERROR, "clients can't push")),
+            PING          => {
+                if payload.len() != 8 || stream != 0 {
+                    return Err((FRAME_SIZE_ERROR, "bad PING"));
+                }
+
+                if flags & ACK == 0 {
+                    frame(&mut self.out, PING, ACK, 0, payload);
+                }
+
+                Ok(())
+            },
+            GOAWAY        => {
+                // finish what's open, but take no more streams
+                self.goaway = true;
+                Ok(())
+            },
+            WINDOW_UPDATE => {
+                if payload.len() != 4 {
+                    return Err((FRAME_SIZE_ERROR, "bad WINDOW_UPDATE"));
+                }
+
+                let inc = (u32_at(payload, 0) & 0x7fff_ffff) as i64;
+
+                if stream == 0 {
+                    if inc == 0 {
+                        return Err((PROTOCOL_ERROR, "empty WINDOW_UPDATE"));
+                    }
+
+                    self.window += inc;
+
+                    if self.window > MAX_WINDOW {
+                        return Err((FLOW_CONTROL_ERROR, "connection window too large"));
+                    }
+
+                    self.flush_all();
+                } else if let Some(s) = self.streams.get_mut(&stream) {
+                    s.window += inc;
+
+                    if inc == 0 || s.window > MAX_WINDOW {
+                        self.reset(stream, if inc == 0 { PROTOCOL_ERROR } else { FLOW_CONTROL_ERROR });
+                    } else {
+                        self.flush(stream);
+                    }
+                }
+
+                Ok(())
+            },
+            // PRIORITY, and frame types we don't know, are ignored
+            _             => Ok(()),
+        }
+    }
+
+    fn settings(&mut self, payload: &[u8]) -> Result<(), Fault> {
+        if !payload.len().is_multiple_of(6) {
+            return Err((FRAME_SIZE_ERROR, "bad SETTINGS"));
+        }
+
+        for setting in payload.chunks(6) {
+            let value = u32_at(setting, 2);
+
+            match u16::from_be_bytes([setting[0], setting[1]]) {
+                0x4 => {
+                    if value as i64 > MAX_WINDOW {
+                        return Err((FLOW_CONTROL_ERROR, "initial window too large"));
+                    }
+
+                    let delta = value as i64 - self.initial;
+
+                    self.initial = value as i64;
+
+                    for s in self.streams.values_mut() {
+                        s.window += delta;
+                    }
+                },
+                0x5 => {
+                    if !(MAX_FRAME as u32..=0xff_ffff).contains(&value) {
+                        return Err((PROTOCOL_ERROR, "bad maximum frame size"));
+                    }
+
+                    self.frame_max = value as usize;
+                },
+                // the others either don't apply to a server or are only
+                // limits on what we send, which we stay well within
+                _   => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    fn headers(&mut self, stream: u32, block: &[u8], end: bool, done: &mut Vec<(u32, Request)>) -> Result<(), Fault> {
+        // decode even blocks we'll ignore, to keep the table in step
+        let fields = self.decoder.decode(block).map_err(|_| (COMPRESSION_ERROR, "bad header block"))?;
+
+        if let Some(s) = self.streams.get_mut(&stream) {
+            // trailers, which must end the stream; their fields are dropped
+            if s.closed || !end {
+                return Err((PROTOCOL_ERROR, "unexpected HEADERS"));
+            }
+
+            s.closed = true;
+            self.complete(stream, done);
+            return Ok(());
+        }
+
+        if stream.is_multiple_of(2) || stream <= self.last {
+            return Err((PROTOCOL_ERROR, "bad stream id"));
+        }
+
+        self.last = stream;
+
+        if self.goaway {
+            return Ok(());
+        }
+
+        // a stream the client reset still counts while its handler runs
+        let running = self.jobs.values().filter(|s| !self.streams.contains_key(s)).count();
+
+        if self.streams.len() + running >= MAX_STREAMS as usize {
+            self.reset(stream, REFUSED_STREAM);
+            return Ok(());
+        }
+
+        self.streams.insert(stream, Stream {
+            fields,
+            body:    Vec::new(),
+            closed:  end,
+            window:  self.initial,
+            pending: None,
+        });
+
+        if end {
+            self.complete(stream, done);
+        }
+
+        Ok(())
+    }
+
+    fn data(&mut self, flags: u8, stream: u32, payload: &[u8], done: &mut Vec<(u32, Request)>) -> Result<(), Fault> {
+        if stream == 0 {
+            return Err((PROTOCOL_ERROR, "DATA on stream 0"));
+        }
+
+        let body = unpad(flags, payload)?;
+        let end = flags & END_STREAM != 0;
+
+        // give back what the frame used of the connection window straight
+        // away; the stream's only if more is coming on it
+        if !payload.is_empty() {
+            frame(&mut self.out, WINDOW_UPDATE, 0, 0, &(payload.len() as u32).to_be_bytes());
+        }
+
+        match self.streams.get_mut(&stream) {
+            Some(s) if !s.closed => {
+                if self.max_body.is_some_and(|max| s.body.len() + body.len() > max) {
+                    s.fields.clear();
+                    s.body = Vec::new();
+                    s.closed = true;
+                    self.too_large(stream);
+                    return Ok(());
+                }
+
+                s.body.extend_from_slice(body);
+                s.closed = end;
+            },
+            _                    => {
+                if stream > self.last {
+                    return Err((PROTOCOL_ERROR, "DATA on an idle stream"));
+                }
+
+                self.reset(stream, STREAM_CLOSED);
+                return Ok(());
+            },
+        }
+
+        match end {
+            true  => self.complete(stream, done),
+            false => {
+                if !payload.is_empty() {
+                    frame(&mut self.out, WINDOW_UPDATE, 0, stream, &(payload.len() as u32).to_be_bytes());
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    // the client has finished sending on `stream`: hand over its request.
+    fn complete(&mut self, stream: u32, done: &mut Vec<(u32, Request)>) {
+        let (fields, body) = match self.streams.get_mut(&stream) {
+            Some(s) => (std::mem::take(&mut s.fields), std::mem::take(&mut s.body)),
+            None    => return,
+        };
+
+        match request(fields, body) {
+            Some(req) => done.push((stream, req)),
+            None      => self.reset(stream, PROTOCOL_ERROR),
+        }
+    }
+
+    // answer a stream whose body went over the limit without waiting for the
+    // rest, then tell the client to stop sending it.
+    fn too_large(&mut self, stream: u32) {
+        let res = utils::err_413(&Request::new());
+
+        self.answer(stream, &encode_head(&res), res.get_payload().to_vec());
+
+        if !self.streams.contains_key(&stream) {
+            frame(&mut self.out, RST_STREAM, 0, stream, &NO_ERROR.to_be_bytes());
+        }
+    }
+
+    // send the response of job `id`, as far as flow control allows. the
+    // rest goes as the client opens its windows.
+    pub(crate) fn respond(&mut self, id: u64, head: &[u8], body: Vec<u8>) {
+        let stream = match self.jobs.remove(&id) {
+            Some(stream) => stream,
+            None         => return,
+        };
+
+        // the client may have reset the stream while the handler ran
+        if self.streams.contains_key(&stream) {
+            self.answer(stream, head, body);
+        }
+    }
+
+    fn answer(&mut self, stream: u32, head: &[u8], body: Vec<u8>) {
+        let mut chunks = head.chunks(self.frame_max).peekable();
+        let mut kind = HEADERS;
+
+        // an empty block still needs its frame
+        if head.is_empty() {
+            let flags = END_HEADERS | if body.is_empty() { END_STREAM } else { 0 };
+            frame(&mut self.out, HEADERS, flags, stream, &[]);
+        }
+
+        while let Some(chunk) = chunks.next() {
+            let mut flags = 0;
+
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+
+            if kind == HEADERS && body.is_empty() {
+                flags |= END_STREAM;
+            }
+
+            frame(&mut self.out, kind, flags, stream, chunk);
+            kind = CONTINUATION;
+        }
+
+        match body.is_empty() {
+            true  => { self.streams.remove(&stream); },
+            false => {
+                if let Some(s) = self.streams.get_mut(&stream) {
+                    s.pending = Some((body, 0));
+                }
+
+                self.flush(stream);
+            },
+        }
+    }
+
+    // write as much of a stream's pending data as the windows allow.
+    fn flush(&mut self, stream: u32) {
+        let s = match self.streams.get_mut(&stream) {
+            Some(s) => s,
+            None    => return,
+        };
+        let mut finished = false;
+
+        if let Some((ref data, ref mut sent)) = s.pending {
+            while *sent < data.len() {
+                let room = self.window.min(s.window).min(self.frame_max as i64);
+
+                if room <= 0 {
+                    break;
+                }
+
+                let n = room.min((data.len() - *sent) as i64) as usize;
+                let last = *sent + n == data.len();
+
+                frame(&mut self.out, DATA, if last { END_STREAM } else { 0 }, stream, &data[*sent..*sent + n]);
+                *sent += n;
+                self.window -= n as i64;
+                s.window -= n as i64;
+                finished = last;
+            }
+        }
+
+        if finished {
+            self.streams.remove(&stream);
+        }
+    }
+
+    fn flush_all(&mut self) {
+        let mut waiting: Vec<u32> = self.streams.iter()
+                                                .filter(|(_, s)| s.pending.is_some())
+                                                .map(|(&id, _)| id)
+                                                .collect();
+
+        waiting.sort();
+
+        for stream in waiting {
+            self.flush(stream);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the frames in `data`, as (type, flags, stream, payload).
+    fn frames(mut data: &[u8]) -> Vec<(u8, u8, u32, Vec<u8>)> {
+        let mut out = Vec::new();
+
+        while data.len() >= 9 {
+            let len = (data[0] as usize) << 16 | (data[1] as usize) << 8 | data[2] as usize;
+
+            out.push((data[3], data[4], u32_at(data, 5), data[9..9 + len].to_vec()));
+            data = &data[9 + len..];
+        }
+
+        out
+    }
+
+    fn client_hello(path: &str, end: bool) -> Vec<u8> {
+        let mut out = PREFACE.to_vec();
+
+        frame(&mut out, SETTINGS, 0, 0, &[]);
+        frame(&mut out, HEADERS, END_HEADERS | if end { END_STREAM } else { 0 }, 1,
+              &hpack::encode(&[(":method", "POST"), (":scheme", "http"), (":path", path),
+                               (":authority", "example.com"), ("cookie", "a=1"), ("cookie", "b=2")]));
+        out
+    }
+
+    #[test]
+    fn test_request() {
+        let mut session = Session::new();
+        let mut input = client_hello("/upload?x=1", false);
+
+        frame(&mut input, DATA, 0, 1, b"hello ");
+        frame(&mut input, DATA, END_STREAM, 1, b"world");
+
+        // the bytes may arrive in any pieces
+        let mut done = Vec::new();
+
+        for piece in input.chunks(7) {
+            done.extend(session.receive(piece).unwrap());
+        }
+
+        assert_eq!(1, done.len());
+
+        let (stream, ref req) = done[0];
+        assert_eq!(1, stream);
+        assert_eq!(Method::Post, req.method);
+        assert_eq!("/upload?x=1", req.path);
+        assert_eq!(Some(String::from("example.com")), req.get_header("Host"));
+        assert_eq!(Some(String::from("a=1; b=2")), req.get_header("Cookie"));
+        assert_eq!(b"hello world".to_vec(), req.payload);
+        assert_eq!("HTTP/2.0", req.version());
+
+        let kinds: Vec<u8> = frames(&session.take_output()).iter().map(|f| f.0).collect();
+        assert_eq!(vec![SETTINGS, SETTINGS, WINDOW_UPDATE, WINDOW_UPDATE, WINDOW_UPDATE], kinds);
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let mut session = Session::new();
+
+        session.receive(&client_hello("/big", true)).unwrap();
+        session.take_output();
+        session.start(1, 42);
+        session.respond(42, &encode_head(&utils::text("")), vec![7; 70000]);
+
+        let out = frames(&session.take_output());
+        let sent: usize = out.iter().filter(|f| f.0 == DATA).map(|f| f.3.len()).sum();
+
+        assert_eq!((HEADERS, END_HEADERS), (out[0].0, out[0].1));
+        assert_eq!(65535, sent);
+        assert!(!session.is_idle());
+
+        // open both windows, and the rest follows
+        let mut update = Vec::new();
+        frame(&mut update, WINDOW_UPDATE, 0, 0, &10000u32.to_be_bytes());
+        frame(&mut update, WINDOW_UPDATE, 0, 1, &10000u32.to_be_bytes());
+        session.receive(&update).unwrap();
+
+        let out = frames(&session.take_output());
+        assert_eq!(vec![(DATA, END_STREAM, 1, vec![7; 4465])], out);
+        assert!(session.is_idle());
+    }
+
+    #[test]
+    fn test_connection_errors() {
+        let mut session = Session::new();
+
+        assert!(session.receive(b"GET / HTTP/1.1\r\n\r\n").is_err());
+        assert_eq!(GOAWAY, frames(&session.take_output()).last().unwrap().0);
+
+        let mut session = Session::new();
+        let mut input = client_hello("/", true);
+
+        frame(&mut input, HEADERS, END_HEADERS | END_STREAM, 1, &hpack::encode(&[(":method", "GET")]));
+        assert!(session.receive(&input).is_err());
+    }
+
+    #[test]
+    fn test_ping_and_reset() {
+        let mut session = Session::new();
+        let mut input = client_hello("/", true);
+
+        frame(&mut input, PING, 0, 0, b"12345678");
+        frame(&mut input, RST_STREAM, 0, 1, &0x8u32.to_be_bytes());
+        session.receive(&input).unwrap();
+        session.start(1, 1);
+        session.respond(1, &encode_head(&utils::text("late")), b"late".to_vec());
+
+        let out = frames(&session.take_output());
+        assert!(out.contains(&(PING, ACK, 0, b"12345678".to_vec())));
+        assert!(!out.iter().any(|f| f.0 == HEADERS || f.0 == DATA));
+        assert!(session.is_idle());
+    }
+
+    #[test]
+    fn test_body_limit() {
+        let mut session = Session::new().max_body(Some(8));
+        let mut input = client_hello("/upload", false);
+
+        frame(&mut input, DATA, 0, 1, b"hello ");
+        frame(&mut input, DATA, 0, 1, b"world");
+        frame(&mut input, DATA, END_STREAM, 1, b"!");
+
+        assert!(session.receive(&input).unwrap().is_empty());
+
+        let out = frames(&session.take_output());
+        let stream: Vec<&(u8, u8, u32, Vec<u8>)> = out.iter().filter(|f| f.2 == 1).collect();
+        let status = Decoder::new().decode(&stream[1].3).unwrap();
+
+        // one window update for the first frame, none after the limit
+        assert_eq!(WINDOW_UPDATE, stream[0].0);
+        assert_eq!((":status", "413"), (status[0].0.as_str(), status[0].1.as_str()));
+        assert!(out.contains(&(RST_STREAM, 0, 1, NO_ERROR.to_be_bytes().to_vec())));
+        assert!(session.is_idle());
+    }
+
+    #[test]
+    fn test_reset_streams_count() {
+        let mut session = Session::new();
+        let mut input = client_hello("/", true);
+
+        session.receive(&input).unwrap();
+        session.start(1, 1);
+
+        // each stream is reset as soon as it's dispatched, but its handler
+        // still runs, so it holds its place
+        for n in 1..MAX_STREAMS {
+            let stream = 2 * n + 1;
+
+            input.clear();
+            frame(&mut input, HEADERS, END_HEADERS | END_STREAM, stream,
+                  &hpack::encode(&[(":method", "GET"), (":scheme", "http"), (":path", "/")]));
+            frame(&mut input, RST_STREAM, 0, stream, &0x8u32.to_be_bytes());
+            assert_eq!(1, session.receive(&input).unwrap().len());
+            session.start(stream, n as u64 + 1);
+        }
+
+        session.take_output();
+        input.clear();
+        frame(&mut input, HEADERS, END_HEADERS | END_STREAM, 2 * MAX_STREAMS + 1,
+              &hpack::encode(&[(":method", "GET"), (":scheme", "http"), (":path", "/")]));
+
+        assert!(session.receive(&input).unwrap().is_empty());
+        assert_eq!(RST_STREAM, frames(&session.take_output())[0].0);
+
+        // once a handler replies, its place is free again
+        session.respond(1, &encode_head(&utils::text("x")), b"x".to_vec());
+        input.clear();
+        frame(&mut input, HEADERS, END_HEADERS | END_STREAM, 2 * MAX_STREAMS + 3,
+              &hpack::encode(&[(":method", "GET"), (":scheme", "http"), (":path", "/")]));
+        assert_eq!(1, session.receive(&input).unwrap().len());
+    }
+
+    #[test]
+    fn test_upgrade_settings() {
+        // HTTP2-Settings: an initial window of 1000, base64url without padding
+        let mut session = Session::upgraded("AAQAAAPo").unwrap();
+
+        session.receive(PREFACE).unwrap();
+        session.start(1, 5);
+        session.respond(5, &encode_head(&utils::text("x")), vec![1; 1500]);
+
+        let sent: usize = frames(&session.take_output()).iter().filter(|f| f.0 == DATA).map(|f| f.3.len()).sum();
+        assert_eq!(1000, sent);
+        assert!(Session::upgraded("!!").is_none());
+    }
+
+    #[test]
+    fn test_encode_head() {
+        let mut res = utils::text("hi");
+
+        res.set_header("Connection", "keep-alive");
+        res.set_header("X-Thing", "1");
+
+        let fields = Decoder::new().decode(&encode_head(&res)).unwrap();
+        let names: Vec<&str> = fields.iter().map(|(n, _)| n.as_str()).collect();
+
+        assert_eq!(":status", names[0]);
+        assert!(names.contains(&"x-thing"));
+        assert!(!names.contains(&"connection"));
+        assert_eq!(("content-length", "2"), (names[names.len() - 1], fields[fields.len() - 1].1.as_str()));
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! End-to-end integrity checks with the `Digest` (RFC 3230) and
+//! `Content-MD5` headers: the `Integrity` middleware adds them to responses
+//! and rejects uploads whose body doesn't match them.
+
+use ring::digest;
+
+use crate::middleware::Middleware;
+use crate::request::Request;
+use crate::response::Response;
+use crate::utils;
+
+/// A digest algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// MD5, as used by `Content-MD5`. Only good for catching accidents.
+    Md5,
+    /// SHA-256, `sha-256` in `Digest`.
+    Sha256,
+    /// SHA-512, `sha-512` in `Digest`.
+    Sha512,
+}
+
+impl Algorithm {
+    /// The algorithm's name in a `Digest` header.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Md5    => "md5",
+            Algorithm::Sha256 => "sha-256",
+            Algorithm::Sha512 => "sha-512",
+        }
+    }
+
+    /// The algorithm named `name` in a `Digest` header, if it's supported.
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "md5"     => Some(Algorithm::Md5),
+            "sha-256" => Some(Algorithm::Sha256),
+            "sha-512" => Some(Algorithm::Sha512),
+            _         => None,
+        }
+    }
+
+    /// The digest of `data`, base64-encoded as the headers carry it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::integrity::Algorithm;
+    ///
+    /// assert_eq!("XrY7u+Ae7tCTyyK7j1rNww==", Algorithm::Md5.encoded(b"hello world"));
+    /// ```
+    pub fn encoded(self, data: &[u8]) -> String {
+        match self {
+            Algorithm::Md5    => utils::base64_encode(&md5(data)),
+            Algorithm::Sha256 => utils::base64_encode(digest::digest(&digest::SHA256, data).as_ref()),
+            Algorithm::Sha512 => utils::base64_encode(digest::digest(&digest::SHA512, data).as_ref()),
+        }
+    }
+}
+
+// the MD5 of `data`, per RFC 1321. ring doesn't offer it, and it's only
+// needed for `Content-MD5`.
+fn md5(data: &[u8]) -> [u8; 16] {
+    const S: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+        5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+        4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+        6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    let k: Vec<u32> = (0..64).map(|i: u32| (f64::from(i + 1).sin().abs() * 4294967296.0) as u32).collect();
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    let mut msg = data.to_vec();
+
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    for block in msg.chunks(64) {
+        let m: Vec<u32> = block.chunks(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect();
+        let [mut a, mut b, mut c, mut d] = state;
+
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a.wrapping_add(f).wrapping_add(k[i]).wrapping_add(m[g]).rotate_left(S[i]);
+
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+
+        for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; 16];
+
+    for (i, word) in state.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+
+    out
+}
+
+/// Adds `Digest` and/or `Content-MD5` headers to responses, and checks the
+/// bodies of requests that carry them, answering mismatches with `400 Bad
+/// Request`. Digests with algorithms that aren't supported are ignored.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::Canteen;
+/// use canteen::integrity::{Algorithm, Integrity};
+///
+/// let mut cnt = Canteen::new();
+/// cnt.add_middleware(Integrity::new().emit(Algorithm::Sha256).emit(Algorithm::Md5));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Integrity {
+    emit:   Vec<Algorithm>,
+    verify: bool,
+}
+
+impl Integrity {
+    /// Verify request digests, without adding any to responses.
+    pub fn new() -> Integrity {
+        Integrity {
+            emit:   Vec::new(),
+            verify: true,
+        }
+    }
+
+    /// Add a digest made with `algorithm` to every response: `Content-MD5`
+    /// for MD5, and an entry in `Digest` for the others.
+    pub fn emit(mut self, algorithm: Algorithm) -> Integrity {
+        if !self.emit.contains(&algorithm) {
+            self.emit.push(algorithm);
+        }
+
+        self
+    }
+
+    /// Set whether request bodies are checked against their digests.
+    pub fn verify(mut self, on: bool) -> Integrity {
+        self.verify = on;
+
+        self
+    }
+
+    /// Check a request body against its `Digest` and `Content-MD5` headers,
+    /// returning a description of the first mismatch.
+    pub fn check(req: &Request) -> Result<(), String> {
+        if let Some(md5) = req.get_header("Content-MD5") {
+            if Algorithm::Md5.encoded(&req.payload) != md5.trim() {
+                return Err(String::from("body doesn't match Content-MD5"));
+            }
+        }
+
+        if let Some(digests) = req.get_header_combined("Digest") {
+            for entry in digests.split(',') {
+                let (name, value) = match entry.find('=') {
+                    Some(i) => (&entry[..i], entry[i + 1..].trim()),
+                    None    => continue,
+                };
+
+                if let Some(algorithm) = Algorithm::from_name(name) {
+                    if algorithm.encoded(&req.payload) != value {
+                        return Err(format!("body doesn't match its {} digest", algorithm.name()));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Integrity {
+    fn default() -> Self {
+        Integrity::new()
+    }
+}
+
+impl Middleware for Integrity {
+    fn before(&self, req: &mut Request) -> Option<Response> {
+        if !self.verify {
+            return None;
+        }
+
+        match Integrity::check(req) {
+            Ok(())       => None,
+            Err(problem) => {
+                log::debug!("rejecting {}: {}", req.path, problem);
+                Some(utils::make_response(problem, "text/plain", 400))
+            },
+        }
+    }
+
+    fn after(&self, _: &Request, res: &mut Response) {
+        let mut digests = Vec::new();
+
+        for &algorithm in &self.emit {
+            let encoded = algorithm.encoded(res.get_payload());
+
+            match algorithm {
+                Algorithm::Md5 => res.set_header("Content-MD5", &encoded),
+                _              => digests.push(format!("{}={}", algorithm.name(), encoded)),
+            }
+        }
+
+        if !digests.is_empty() {
+            res.set_header("Digest", &digests.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_md5() {
+        let hex = |d: [u8; 16]| d.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+
+        assert_eq!("d41d8cd98f00b204e9800998ecf8427e", hex(md5(b"")));
+        assert_eq!("9e107d9d372bb6826bd81d3542a419d6", hex(md5(b"The quick brown fox jumps over the lazy dog")));
+        assert_eq!("57edf4a22be3c955ac49da2e2107b67a", hex(md5("12345678901234567890123456789012345678901234567890123456789012345678901234567890".as_bytes())));
+    }
+
+    #[test]
+    fn test_sha() {
+        assert_eq!("uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=", Algorithm::Sha256.encoded(b"hello world"));
+    }
+
+    #[test]
+    fn test_verify() {
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(Integrity::new())];
+        let run = |raw: &str| {
+            let mut req: Request = raw.parse().unwrap();
+            crate::middleware::run(&chain, &mut req, |_| utils::text("ok")).get_status()
+        };
+
+        assert_eq!(200, run("PUT /f HTTP/1.1\r\nContent-MD5: XrY7u+Ae7tCTyyK7j1rNww==\r\n\r\nhello world"));
+        assert_eq!(400, run("PUT /f HTTP/1.1\r\nContent-MD5: XrY7u+Ae7tCTyyK7j1rNww==\r\n\r\nhello there"));
+        assert_eq!(200, run("PUT /f HTTP/1.1\r\nDigest: unixsum=30637, SHA-256=uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=\r\n\r\nhello world"));
+        assert_eq!(400, run("PUT /f HTTP/1.1\r\nDigest: sha-256=uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=\r\n\r\nhello"));
+        assert_eq!(200, run("PUT /f HTTP/1.1\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_emit() {
+        let mut res = utils::text("hello world");
+
+        Integrity::new().emit(Algorithm::Md5).emit(Algorithm::Sha256).after(&Request::new(), &mut res);
+
+        assert_eq!(Some("XrY7u+Ae7tCTyyK7j1rNww=="), res.get_header("Content-MD5"));
+        assert_eq!(Some("sha-256=uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek="), res.get_header("Digest"));
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Allowing and denying clients by IP address. A filter given to
+//! `Canteen::filter_connections` closes connections from other addresses as
+//! soon as they're accepted; as middleware, it answers requests from them
+//! with `403 Forbidden`, for routes or groups that only some may use.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::middleware::Middleware;
+use crate::request::Request;
+use crate::response::Response;
+use crate::utils;
+
+/// A block of IP addresses in CIDR notation, like `10.0.0.0/8` or
+/// `2001:db8::/32`. A lone address is a block of one.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::net::IpAddr;
+/// use canteen::ipfilter::Cidr;
+///
+/// let block: Cidr = "192.168.0.0/16".parse().unwrap();
+///
+/// assert!(block.contains("192.168.4.20".parse().unwrap()));
+/// assert!(!block.contains("192.169.0.1".parse().unwrap()));
+/// assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr:   IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Whether `ip` is in the block. IPv4 addresses mapped into IPv6, as
+    /// dual-stack sockets report them, count as their IPv4 selves.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+
+                u32::from(net) & mask == u32::from(ip) & mask
+            },
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+
+                u128::from(net) & mask == u128::from(ip) & mask
+            },
+            _                                 => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Cidr, String> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None                 => (s, None),
+        };
+        let addr = canonical(addr.trim().parse().map_err(|_| format!("bad address in {:?}", s))?);
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|&p| p <= max).ok_or_else(|| format!("bad prefix in {:?}", s))?,
+            None         => max,
+        };
+
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+// an IPv4-mapped IPv6 address as the IPv4 address it is.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        IpAddr::V4(_)  => ip,
+    }
+}
+
+/// Lists of addresses to allow and deny. An address on the deny list is
+/// refused; otherwise, if there's an allow list, only addresses on it are
+/// let in.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Canteen, Method, Request};
+/// use canteen::ipfilter::IpFilter;
+/// use canteen::route::RouteOptions;
+/// use canteen::utils;
+///
+/// let mut cnt = Canteen::new();
+///
+/// // nobody from this block at all
+/// cnt.filter_connections(IpFilter::new().deny("203.0.113.0/24"));
+///
+/// // and the admin pages only from the office
+/// cnt.add_route_with("/admin", Method::Get, |_: &Request| utils::text("hi"),
+///                    RouteOptions::new().with(IpFilter::new().allow("10.1.0.0/16").allow("::1")));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny:  Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Create a filter that lets everyone in.
+    pub fn new() -> IpFilter {
+        IpFilter::default()
+    }
+
+    /// Let in addresses in `block`, and, once there's an allow list, only
+    /// those on it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block` isn't an address or a block in CIDR notation.
+    pub fn allow(mut self, block: &str) -> IpFilter {
+        self.allow.push(block.parse().unwrap_or_else(|e| panic!("unable to allow {}", e)));
+
+        self
+    }
+
+    /// Refuse addresses in `block`, even if they're also allowed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block` isn't an address or a block in CIDR notation.
+    pub fn deny(mut self, block: &str) -> IpFilter {
+        self.deny.push(block.parse().unwrap_or_else(|e| panic!("unable to deny {}", e)));
+
+        self
+    }
+
+    /// Whether `ip` may come in.
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|block| block.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|block| block.contains(ip)))
+    }
+
+    // whether a client whose address isn't known may come in, which it can't
+    // once there's an allow list it would have to be on.
+    pub(crate) fn permits_unknown(&self) -> bool {
+        self.allow.is_empty()
+    }
+}
+
+/// Requests from addresses the filter refuses get a `403 Forbidden`, as do
+/// those whose address isn't known if there's an allow list.
+impl Middleware for IpFilter {
+    fn before(&self, req: &mut Request) -> Option<Response> {
+        match req.peer {
+            Some(ip) if self.permits(ip)   => None,
+            None if self.permits_unknown() => None,
+            _                              => Some(utils::err_403(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        let one: Cidr = "10.0.0.7".parse().unwrap();
+
+        assert!(v6.contains(ip("2001:db8:ffff::1")));
+        assert!(!v6.contains(ip("2001:db9::1")));
+        assert!(all.contains(ip("8.8.8.8")));
+        assert!(!all.contains(ip("::1")));
+        assert!(one.contains(ip("::ffff:10.0.0.7")));
+        assert!(!one.contains(ip("10.0.0.8")));
+        assert_eq!("10.0.0.7/32", one.to_string());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("localhost".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_permits() {
+        let filter = IpFilter::new().allow("10.0.0.0/8").deny("10.9.0.0/16");
+
+        assert!(filter.permits(ip("10.1.2.3")));
+        assert!(!filter.permits(ip("10.9.2.3")));
+        assert!(!filter.permits(ip("192.168.1.1")));
+        assert!(IpFilter::new().deny("10.9.0.0/16").permits(ip("192.168.1.1")));
+        assert!(IpFilter::new().deny("10.9.0.0/16").permits_unknown());
+        assert!(!filter.permits_unknown());
+
+        let mut req = Request::new();
+        assert_eq!(Some(403), filter.before(&mut req).map(|res| res.get_status().as_u16()));
+
+        req.peer = Some(ip("10.1.2.3"));
+        assert!(filter.before(&mut req).is_none());
+
+        let mut req = Request::new();
+        assert!(IpFilter::new().deny("10.9.0.0/16").before(&mut req).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "unable to allow bad prefix")]
+    fn test_bad_block() {
+        IpFilter::new().allow("10.0.0.0/99");
+    }
+}
@@ -0,0 +1,402 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! JSON Web Tokens (RFC 7519) sent as bearer tokens: checking their
+//! signature and claims, and handing the claims to handlers.
+
+use std::time::{Duration, SystemTime};
+
+use ring::{hmac, signature};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::extensions::Identity;
+use crate::middleware::Middleware;
+use crate::request::Request;
+use crate::response::Response;
+use crate::utils;
+
+// what a token's signature is checked with.
+#[derive(Clone)]
+enum Key {
+    Hs256(hmac::Key),
+    Rs256(Vec<u8>),
+    Es256(Vec<u8>),
+}
+
+impl Key {
+    // the `alg` a token must declare to be checked with this key.
+    fn alg(&self) -> &'static str {
+        match *self {
+            Key::Hs256(_) => "HS256",
+            Key::Rs256(_) => "RS256",
+            Key::Es256(_) => "ES256",
+        }
+    }
+
+    fn verify(&self, message: &[u8], sig: &[u8]) -> bool {
+        match *self {
+            Key::Hs256(ref key) => hmac::verify(key, message, sig).is_ok(),
+            Key::Rs256(ref der) => {
+                signature::UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256, der).verify(message, sig).is_ok()
+            },
+            Key::Es256(ref point) => {
+                signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point).verify(message, sig).is_ok()
+            },
+        }
+    }
+}
+
+/// The claims of a verified token, stored in the request's extensions by
+/// `Jwt`.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Request, Response};
+/// use canteen::jwt::Claims;
+/// use canteen::utils;
+///
+/// fn tenant(req: &Request) -> Response {
+///     match req.extensions().get::<Claims>().and_then(|c| c.get::<u64>("tenant")) {
+///         Some(tenant) => utils::text(format!("tenant {}", tenant)),
+///         None         => utils::err_403(req),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claims(pub Map<String, Value>);
+
+impl Claims {
+    /// The claim `name`, if there is one and it reads as a `T`.
+    pub fn get<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        self.0.get(name).and_then(|v| serde_json::from_value(v.clone()).ok())
+    }
+
+    /// The `sub` claim: who the token was issued to.
+    pub fn subject(&self) -> Option<&str> {
+        self.0.get("sub").and_then(Value::as_str)
+    }
+}
+
+/// Why a token was refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JwtError {
+    /// The token isn't three base64url parts holding JSON.
+    Malformed,
+    /// The token's `alg` isn't the one the key is for.
+    Algorithm,
+    /// The signature doesn't match.
+    Signature,
+    /// The `exp` claim is in the past.
+    Expired,
+    /// The `nbf` claim is in the future.
+    NotYetValid,
+    /// The `aud` claim doesn't name the expected audience.
+    Audience,
+    /// The `iss` claim isn't the expected issuer.
+    Issuer,
+}
+
+/// Middleware requiring a valid JSON Web Token as a bearer token. The
+/// signature must match, `exp` and `nbf` are checked when present (and must
+/// be numbers), and `aud` and `iss` when set with `audience` and `issuer`.
+/// Tokens signed with any algorithm other than the key's, including `none`,
+/// are refused.
+///
+/// Requests with a valid token have its `Claims` stored in their extensions,
+/// and its `sub` claim as their identity; others get a `401 Unauthorized`.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Canteen, Method, Request};
+/// use canteen::jwt::Jwt;
+/// use canteen::utils;
+///
+/// let mut cnt = Canteen::new();
+///
+/// cnt.group("/api")
+///    .add_middleware(Jwt::hs256(b"a secret of at least thirty-two bytes")
+///                        .audience("orders")
+///                        .issuer("https://auth.example.com"))
+///    .add_route("/orders", Method::Get, |req: &Request| utils::text(req.identity().unwrap_or("")));
+///
+/// let res = cnt.dispatch("GET /api/orders HTTP/1.1\r\n\r\n".parse().unwrap());
+/// assert_eq!(401, res.get_status());
+/// ```
+#[derive(Clone)]
+pub struct Jwt {
+    key:      Key,
+    audience: Option<String>,
+    issuer:   Option<String>,
+    leeway:   Duration,
+}
+
+impl Jwt {
+    fn new(key: Key) -> Jwt {
+        Jwt {
+            key,
+            audience: None,
+            issuer:   None,
+            leeway:   Duration::from_secs(0),
+        }
+    }
+
+    /// Accept tokens signed with HMAC-SHA256 using `secret`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `secret` is shorter than 32 bytes.
+    pub fn hs256(secret: &[u8]) -> Jwt {
+        if secret.len() < 32 {
+            panic!("JWT secret must be at least 32 bytes, not {}", secret.len());
+        }
+
+        Jwt::new(Key::Hs256(hmac::Key::new(hmac::HMAC_SHA256, secret)))
+    }
+
+    /// Accept tokens signed with RSA PKCS#1 v1.5 and SHA-256, by the key
+    /// whose public half is `public_key`, a DER-encoded `RSAPublicKey`.
+    pub fn rs256(public_key: &[u8]) -> Jwt {
+        Jwt::new(Key::Rs256(public_key.to_vec()))
+    }
+
+    /// Accept tokens signed with ECDSA on P-256 and SHA-256, by the key
+    /// whose public half is `public_key`, an uncompressed curve point.
+    pub fn es256(public_key: &[u8]) -> Jwt {
+        Jwt::new(Key::Es256(public_key.to_vec()))
+    }
+
+    /// Only accept tokens whose `aud` claim names `audience`.
+    pub fn audience(mut self, audience: &str) -> Jwt {
+        self.audience = Some(String::from(audience));
+
+        self
+    }
+
+    /// Only accept tokens whose `iss` claim is `issuer`.
+    pub fn issuer(mut self, issuer: &str) -> Jwt {
+        self.issuer = Some(String::from(issuer));
+
+        self
+    }
+
+    /// Allow for clocks disagreeing by up to `leeway` when checking `exp`
+    /// and `nbf`.
+    pub fn leeway(mut self, leeway: Duration) -> Jwt {
+        self.leeway = leeway;
+
+        self
+    }
+
+    /// Check `token`, returning its claims if it's valid.
+    pub fn verify(&self, token: &str) -> Result<Claims, JwtError> {
+        let mut parts = token.split('.');
+        let (header, payload, sig) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(h), Some(p), Some(s), None) => (h, p, s),
+            _                                 => return Err(JwtError::Malformed),
+        };
+
+        let header: Value = decode_json(header)?;
+
+        if header.get("alg").and_then(Value::as_str) != Some(self.key.alg()) {
+            return Err(JwtError::Algorithm);
+        }
+
+        let signed = &token[..token.len() - sig.len() - 1];
+        let sig = base64url_decode(sig).ok_or(JwtError::Malformed)?;
+
+        if !self.key.verify(signed.as_bytes(), &sig) {
+            return Err(JwtError::Signature);
+        }
+
+        let claims = match decode_json(payload)? {
+            Value::Object(claims) => Claims(claims),
+            _                     => return Err(JwtError::Malformed),
+        };
+
+        self.check(&claims)?;
+
+        Ok(claims)
+    }
+
+    // checks the time, audience and issuer claims.
+    fn check(&self, claims: &Claims) -> Result<(), JwtError> {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        let leeway = self.leeway.as_secs_f64();
+
+        if matches!(numeric_date(claims, "exp")?, Some(exp) if exp + leeway <= now) {
+            return Err(JwtError::Expired);
+        }
+
+        if matches!(numeric_date(claims, "nbf")?, Some(nbf) if nbf > now + leeway) {
+            return Err(JwtError::NotYetValid);
+        }
+
+        if let Some(ref audience) = self.audience {
+            let named = match claims.0.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+                _                        => false,
+            };
+
+            if !named {
+                return Err(JwtError::Audience);
+            }
+        }
+
+        match self.issuer {
+            Some(ref issuer) if claims.get::<String>("iss").as_ref() != Some(issuer) => Err(JwtError::Issuer),
+            _                                                                        => Ok(()),
+        }
+    }
+}
+
+impl Middleware for Jwt {
+    fn before(&self, req: &mut Request) -> Option<Response> {
+        let token = match req.bearer_token() {
+            Some(token) => token,
+            None        => return Some(utils::err_401(req, "Bearer")),
+        };
+
+        match self.verify(&token) {
+            Ok(claims) => {
+                if let Some(sub) = claims.subject() {
+                    req.extensions_mut().insert(Identity(String::from(sub)));
+                }
+
+                req.extensions_mut().insert(claims);
+                None
+            },
+            Err(_)     => Some(utils::err_401(req, "Bearer error=\"invalid_token\"")),
+        }
+    }
+}
+
+// a time claim, in seconds since the epoch. they may have a fraction, and a
+// token with one that isn't a number at all can't be checked, so is refused.
+fn numeric_date(claims: &Claims, name: &str) -> Result<Option<f64>, JwtError> {
+    match claims.0.get(name) {
+        Some(value) => value.as_f64().map(Some).ok_or(JwtError::Malformed),
+        None        => Ok(None),
+    }
+}
+
+fn base64url_decode(text: &str) -> Option<Vec<u8>> {
+    if text.contains(['+', '/', '=']) {
+        return None;
+    }
+
+    utils::base64_decode(&text.replace('-', "+").replace('_', "/"))
+}
+
+fn decode_json<T: DeserializeOwned>(part: &str) -> Result<T, JwtError> {
+    let bytes = base64url_decode(part).ok_or(JwtError::Malformed)?;
+
+    serde_json::from_slice(&bytes).map_err(|_| JwtError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::KeyPair;
+    use serde_json::json;
+    use crate::{Canteen, Method};
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn base64url(data: &[u8]) -> String {
+        utils::base64_encode(data).trim_end_matches('=').replace('+', "-").replace('/', "_")
+    }
+
+    fn unsigned(alg: &str, claims: &Value) -> String {
+        format!("{}.{}", base64url(json!({ "alg": alg, "typ": "JWT" }).to_string().as_bytes()),
+                base64url(claims.to_string().as_bytes()))
+    }
+
+    fn hs256(claims: &Value) -> String {
+        let signed = unsigned("HS256", claims);
+        let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, SECRET), signed.as_bytes());
+
+        format!("{}.{}", signed, base64url(tag.as_ref()))
+    }
+
+    #[test]
+    fn test_verify() {
+        let jwt = Jwt::hs256(SECRET).audience("api").issuer("me");
+        let good = json!({ "sub": "alice", "aud": ["web", "api"], "iss": "me", "exp": 4_000_000_000u64 });
+
+        assert_eq!(Some("alice"), jwt.verify(&hs256(&good)).unwrap().subject());
+        assert_eq!(Err(JwtError::Expired), jwt.verify(&hs256(&json!({ "aud": "api", "iss": "me", "exp": 1 }))));
+        assert_eq!(Err(JwtError::NotYetValid), jwt.verify(&hs256(&json!({ "aud": "api", "iss": "me", "nbf": 4_000_000_000u64 }))));
+        assert_eq!(Err(JwtError::Audience), jwt.verify(&hs256(&json!({ "aud": "web", "iss": "me" }))));
+        assert_eq!(Err(JwtError::Issuer), jwt.verify(&hs256(&json!({ "aud": "api", "iss": "you" }))));
+        assert_eq!(Ok(()), Jwt::hs256(SECRET).leeway(Duration::from_secs(u64::MAX)).check(&Claims(Map::new())));
+
+        // tampering, unsigned tokens, and the wrong key are all refused
+        let token = hs256(&good);
+        let (_, sig) = token.rsplit_once('.').unwrap();
+        let forged = format!("{}.{}", unsigned("HS256", &json!({ "sub": "mallory", "aud": "api", "iss": "me" })), sig);
+
+        assert_eq!(Err(JwtError::Signature), jwt.verify(&forged));
+        assert_eq!(Err(JwtError::Algorithm), jwt.verify(&format!("{}.", unsigned("none", &good))));
+        assert_eq!(Err(JwtError::Signature), Jwt::hs256(b"another secret, also 32 bytes long").verify(&token));
+        assert_eq!(Err(JwtError::Malformed), jwt.verify("a.b"));
+
+        // time claims may have fractions, but must be numbers
+        assert_eq!(Err(JwtError::Expired), jwt.verify(&hs256(&json!({ "aud": "api", "iss": "me", "exp": 1.5 }))));
+        assert!(jwt.verify(&hs256(&json!({ "aud": "api", "iss": "me", "exp": 4_000_000_000.5 }))).is_ok());
+        assert_eq!(Err(JwtError::Malformed), jwt.verify(&hs256(&json!({ "aud": "api", "iss": "me", "exp": "1" }))));
+        assert_eq!(Err(JwtError::Malformed), jwt.verify(&hs256(&json!({ "aud": "api", "iss": "me", "nbf": null }))));
+    }
+
+    #[test]
+    fn test_es256() {
+        let rng = SystemRandom::new();
+        let pkcs8 = signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let pair = signature::EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+        let signed = unsigned("ES256", &json!({ "sub": "bob" }));
+        let sig = pair.sign(&rng, signed.as_bytes()).unwrap();
+        let token = format!("{}.{}", signed, base64url(sig.as_ref()));
+
+        assert_eq!(Some("bob"), Jwt::es256(pair.public_key().as_ref()).verify(&token).unwrap().subject());
+        assert_eq!(Err(JwtError::Algorithm), Jwt::hs256(SECRET).verify(&token));
+    }
+
+    #[test]
+    fn test_jwt_middleware() {
+        let mut cnt = Canteen::new();
+        let get = |cnt: &mut Canteen, auth: &str| {
+            cnt.dispatch(format!("GET / HTTP/1.1\r\nAuthorization: {}\r\n\r\n", auth).parse().unwrap())
+        };
+
+        cnt.add_middleware(Jwt::hs256(SECRET));
+        cnt.add_route("/", Method::Get, |req: &Request| {
+            let claims = req.extensions().get::<Claims>().unwrap();
+
+            utils::text(format!("{}:{}", req.identity().unwrap_or(""), claims.get::<u32>("tenant").unwrap_or(0)))
+        });
+
+        let res = get(&mut cnt, &format!("Bearer {}", hs256(&json!({ "sub": "alice", "tenant": 7 }))));
+        assert_eq!(b"alice:7", res.get_payload());
+
+        let res = get(&mut cnt, "Basic YTpi");
+        assert_eq!(401, res.get_status());
+        assert_eq!(Some("Bearer"), res.get_header("WWW-Authenticate"));
+
+        let res = get(&mut cnt, "Bearer nope");
+        assert_eq!(Some("Bearer error=\"invalid_token\""), res.get_header("WWW-Authenticate"));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 32 bytes")]
+    fn test_short_secret() {
+        Jwt::hs256(b"hunter2");
+    }
+}
@@ -24,11 +24,17 @@
 //!   - ex: `cnt.add_route("/api/foo/<int:foo_id>", &[Method::Get], my_handler)` will match
 //!   `"/api/foo/123"` but not `"/api/foo/123.34"` or `"/api/foo/bar"`
 //! - `<uint:name>` will return an unsigned integer (`u32`)
+//! - `<i64:name>` and `<u64:name>` do the same for 64-bit integers (`i64` and `u64`), such
+//!   as database IDs that don't fit in an `i32`
 //! - `<float:name>` does the same thing as the `int` parameter definition, but matches numbers
 //! with decimal points and returns an `f32`
 //! - `<path:name>` will greedily take all path data contained, returns a `String`
 //!   - ex: `cnt.add_route("/static/<path:name>", &[Method::Get], utils::static_file)` will
 //!   serve anything in the `/static/` directory as a file
+//! - `<uuid:name>` matches a hyphenated UUID and returns a `uuid::Uuid`, with the `uuid`
+//!   feature
+//! - `<re"[a-z]{2,5}":name>` matches a path segment against the regular expression given
+//!   inline, returns a `String`
 //!
 //! After the handlers are attached to routes, the next step is to simply start the
 //! server. Any time a request is received, it is dispatched with the associated handler
@@ -57,7 +63,7 @@
 //!     let to_dbl: i32 = req.get("to_dbl");
 //!
 //!     /* simpler response generation syntax */
-//!     utils::make_response(format!("{}", to_dbl * 2), "text/plain", 200)
+//!     utils::text(format!("{}", to_dbl * 2))
 //! }
 //!
 //! fn main() {
@@ -84,42 +90,255 @@ pub mod utils;
 pub mod route;
 pub mod request;
 pub mod response;
+pub mod rewrite;
+pub mod listener;
+pub mod files;
+pub mod throttle;
+pub mod stats;
+pub mod config;
+pub mod middleware;
+pub mod dump;
+pub mod testing;
+pub mod parse;
+pub mod transport;
+pub mod clock;
+pub mod handle;
+pub mod tls;
+pub mod reload;
+pub mod hints;
+pub mod status;
+pub mod query;
+pub mod builder;
+pub mod assets;
+pub mod debug;
+pub mod mmap;
+pub mod encoding;
+pub mod range;
+pub mod dav;
+pub mod accesslog;
+pub mod extensions;
+pub mod audit;
+pub mod integrity;
+pub mod conditional;
+pub mod hooks;
+pub mod error;
+pub mod state;
+pub mod cookie;
+pub mod stream;
+pub mod group;
+pub mod router;
+pub mod session;
+pub mod auth;
+pub mod ratelimit;
+pub mod ipfilter;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+#[cfg(feature = "h2")]
+pub mod hpack;
+#[cfg(feature = "h2")]
+pub mod http2;
+#[cfg(unix)]
+pub mod privilege;
 
 #[cfg(test)]
 #[macro_use]
 extern crate serde_derive;
 
-use std::str::FromStr;
-use std::io::Result;
-use std::io::prelude::*;
-use std::net::ToSocketAddrs;
+use std::io::{ErrorKind, Read, Write, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 
 use threadpool::ThreadPool;
-use mio::tcp::{TcpListener, TcpStream};
+use mio::tcp::TcpListener;
 use mio::util::Slab;
 use mio::*;
 
 pub use crate::request::*;
 pub use crate::response::*;
+pub use crate::status::StatusCode;
+pub use crate::error::Error;
+
+use crate::listener::{Listener, ListenerOptions, Mode};
+use crate::files::{Mount, StaticOptions};
+use crate::throttle::TokenBucket;
+use crate::stats::{PoolSnapshot, PoolStats, RouteStats, RouteSnapshot, Stats};
+use crate::config::{Config, Hsts, KeepAlive, ServerHeader};
+use crate::middleware::Middleware;
+use crate::transport::Transport;
+use crate::clock::{Clock, SystemClock};
+use crate::handle::ServerHandle;
+use crate::tls::{TlsCerts, TlsReloader};
+use crate::reload::{ReloadFn, ReloadHandle};
+use crate::hints::HintSender;
+use crate::mmap::MappedFile;
+use crate::hooks::{ConnectionInfo, Hooks, UserSource};
+use crate::error::{ErrorFn, IntoResult};
+use crate::state::State;
+use crate::stream::{Backlog, ChunkWriter, Streamer};
+use crate::group::{GroupDefault, RouteGroup};
+#[cfg(feature = "h2")]
+use crate::http2::Session;
+
+/// A finished response on its way from a worker back to the event loop.
+#[doc(hidden)]
+pub struct Reply {
+    output:     Vec<u8>,
+    mapped:     Option<Arc<MappedFile>>,
+    rate:       Option<u64>,
+    keep_alive: bool,
+    // set when the body follows in chunks, as the worker writes it
+    backlog:    Option<Arc<Backlog>>,
+    // the header block of an HTTP/2 response, whose body is the output
+    #[cfg(feature = "h2")]
+    head:       Option<Vec<u8>>,
+}
+
+/// A message to the event loop.
+#[doc(hidden)]
+pub enum Message {
+    /// A worker has finished the response to a connection's request.
+    Reply(Token, u64, Reply),
+    /// A worker has an interim (1xx) response for a connection's request.
+    Interim(Token, u64, Vec<u8>),
+    /// A worker has written the next chunk of a streamed response.
+    Chunk(Token, u64, Vec<u8>),
+    /// A worker has finished writing a streamed response.
+    End(Token, u64),
+    /// Stop the event loop.
+    Shutdown,
+}
+
+// a request being held to a deadline.
+struct Deadline {
+    path:    String,
+    stats:   Arc<RouteStats>,
+    start:   Instant,
+    expired: bool,
+}
+
+/// Something the event loop has set a timer for.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timer {
+    /// A throttled connection may write again.
+    Resume(Token),
+    /// A connection's request has run out of time.
+    Deadline(Token, u64),
+    /// A rate-limited listener may accept again.
+    Accept(usize),
+    /// Periodic housekeeping is due.
+    Housekeeping,
+}
+
+// how often the event loop's housekeeping runs.
+const HOUSEKEEPING_MS: u64 = 250;
+
+// tokens below this value are reserved for listening sockets; connections
+// are allocated from the slab starting here.
+const LISTENER_SLOTS: usize = 16;
 
 struct Client {
-    sock:   TcpStream,
-    token:  Token,
-    events: EventSet,
-    i_buf:  Vec<u8>,
-    o_buf:  Vec<u8>,
+    sock:     Box<dyn Transport>,
+    token:    Token,
+    listener: usize,
+    events:   EventSet,
+    i_buf:    Vec<u8>,
+    o_buf:    Vec<u8>,
+    o_map:    Option<(Arc<MappedFile>, usize)>,
+    bucket:   Option<TokenBucket>,
+    ip:       Option<IpAddr>,
+    addr:     Option<SocketAddr>,
+    chunk:    usize,
+    max:      usize,
+    limit:    Option<usize>,
+    oversize: bool,
+    pending:  Option<u64>,
+    stream:   Option<Arc<Backlog>>,
+    deadline: Option<Timeout>,
+    opened:   Instant,
+    reading:  Option<Instant>,
+    read_at:  Instant,
+    wrote_at: Option<Instant>,
+    served:   usize,
+    persist:  bool,
+    hungup:   bool,
+    idle:     Option<Instant>,
+    gone:     Arc<AtomicBool>,
+    #[cfg(feature = "h2")]
+    h2:       Option<Box<Session>>,
 }
 
 impl Client {
-    fn new(sock: TcpStream, token: Token) -> Client {
+    fn new(sock: Box<dyn Transport>, token: Token, listener: usize, config: &Config, now: Instant) -> Client {
         Client {
             sock,
             token,
-            events: EventSet::hup(),
-            i_buf:  Vec::with_capacity(2048),
-            o_buf:  Vec::new(),
+            listener,
+            events:   EventSet::hup(),
+            i_buf:    Vec::with_capacity(config.read_initial),
+            o_buf:    Vec::new(),
+            o_map:    None,
+            bucket:   None,
+            ip:       None,
+            addr:     None,
+            chunk:    config.read_initial,
+            max:      config.read_max,
+            limit:    None,
+            oversize: false,
+            pending:  None,
+            stream:   None,
+            deadline: None,
+            opened:   now,
+            reading:  Some(now),
+            read_at:  now,
+            wrote_at: None,
+            served:   0,
+            persist:  false,
+            hungup:   false,
+            idle:     None,
+            gone:     Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "h2")]
+            h2:       None,
+        }
+    }
+
+    // whether a request on the connection is still being answered.
+    fn busy(&self) -> bool {
+        #[cfg(feature = "h2")]
+        if let Some(ref session) = self.h2 {
+            return !session.is_idle();
+        }
+
+        self.pending.is_some()
+    }
+
+    // whether the connection speaks HTTP/2, or is about to: it has a
+    // session, the client sent the preface, or TLS negotiated `h2`.
+    #[cfg(feature = "h2")]
+    fn is_h2(&self) -> bool {
+        self.h2.is_some() || Session::is_preface(&self.i_buf) || self.sock.protocol() == Some(b"h2")
+    }
+
+    // queue whatever frames the session has ready to go out.
+    #[cfg(feature = "h2")]
+    fn flush_h2(&mut self) {
+        if let Some(ref mut session) = self.h2 {
+            let out = session.take_output();
+
+            if !out.is_empty() {
+                self.o_buf.extend_from_slice(&out);
+                self.events.insert(EventSet::writable());
+            }
         }
     }
 
@@ -127,22 +346,39 @@ impl Client {
         let mut bytes_read: usize = 0;
 
         loop {
-            let mut buf: Vec<u8> = Vec::with_capacity(2048);
-            match self.sock.try_read_buf(&mut buf) {
-                Ok(size)  => {
-                    match size {
-                        Some(bytes) => {
-                            self.i_buf.extend(buf);
-                            bytes_read += bytes;
-                        },
-                        None    => {
-                            self.events.remove(EventSet::readable());
-                            self.events.insert(EventSet::writable());
-                            break;
-                        },
+            // read straight into the end of the input buffer
+            let start = self.i_buf.len();
+            self.i_buf.resize(start + self.chunk, 0);
+
+            let res = self.sock.read(&mut self.i_buf[start..]);
+            self.i_buf.truncate(start + *res.as_ref().unwrap_or(&0));
+
+            match res {
+                Ok(size) if size > 0 => {
+                    bytes_read += size;
+
+                    // stop reading a body nothing would accept, and answer
+                    if self.over_limit() {
+                        self.oversize = true;
+                        self.events.remove(EventSet::readable());
+                        self.events.insert(EventSet::writable());
+                        break;
+                    }
+
+                    // a full read means there's likely more, so ask for more
+                    if size == self.chunk {
+                        self.chunk = (self.chunk * 2).min(self.max);
                     }
                 },
-                Err(_)  => {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock && bytes_read == 0 => {
+                    // nothing for us yet, e.g. only a TLS handshake record
+                    break;
+                },
+                Err(e) if e.kind() != ErrorKind::WouldBlock && bytes_read == 0 => {
+                    // reset, there's nobody left to answer
+                    return Err(e);
+                },
+                _                    => {
                     self.events.remove(EventSet::readable());
                     self.events.insert(EventSet::writable());
                     break;
@@ -153,45 +389,438 @@ impl Client {
         Ok(bytes_read > 0)
     }
 
-    // write the client's output buffer to the socket.
+    // whether the request being read has a larger body than any route
+    // takes, by what's arrived of it or by what its `Content-Length` says.
+    fn over_limit(&self) -> bool {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None        => return false,
+        };
+
+        // HTTP/2 frames its bodies, and the session refuses those over the
+        // limit itself
+        #[cfg(feature = "h2")]
+        if self.is_h2() {
+            return false;
+        }
+
+        match parse::head_length(&self.i_buf) {
+            Some(head) => self.i_buf.len() - head > limit
+                              || parse::content_length(&self.i_buf[..head]).ok().flatten().map(|len| len > limit).unwrap_or(false),
+            None       => false,
+        }
+    }
+
+    // what's left to write: the buffer, then any mapped file after it.
+    fn unsent(&self) -> &[u8] {
+        match self.o_map {
+            Some((ref map, off)) if self.o_buf.is_empty() => &map[off..],
+            _                                             => &self.o_buf,
+        }
+    }
+
+    // drop the `sz` bytes just written from the front of what's left.
+    fn sent(&mut self, sz: usize) {
+        if !self.o_buf.is_empty() {
+            self.o_buf.drain(..sz);
+        } else if let Some((ref map, ref mut off)) = self.o_map {
+            *off += sz;
+
+            if *off == map.len() {
+                self.o_map = None;
+            }
+        }
+    }
+
+    // write the client's output buffer to the socket, as far as the socket
+    // and the client's token bucket (if any) allow.
     //
     // the following return values mean:
     //  - Ok(true):  we can close the connection
     //  - Ok(false): keep listening for writeable event and continue next time
-    //  - Err(e):    something dun fucked up
-    fn send(&mut self) -> Result<bool> {
-        if self.o_buf.is_empty() {
+    //  - Err(e):    something dun fucked up, e.g. the client reset the
+    //               connection, so drop it
+    fn send(&mut self, now: Instant) -> Result<bool> {
+        if self.o_map.as_ref().map(|(map, _)| map.is_empty()).unwrap_or(false) {
+            self.o_map = None;
+        }
+
+        if self.unsent().is_empty() {
             return Ok(false);
         }
 
-        while !self.o_buf.is_empty() {
-            match self.sock.write(&self.o_buf.as_slice()) {
-                Ok(sz)  => {
-                    if sz == self.o_buf.len() {
-                        // we did it!
-                        self.events.remove(EventSet::writable());
-                        break;
-                    } else {
-                        // keep going
-                        self.o_buf = self.o_buf.split_off(sz);
+        while !self.unsent().is_empty() {
+            let want = self.unsent().len();
+            let budget = match self.bucket {
+                Some(ref mut tb) => tb.take(want, now),
+                None             => want,
+            };
+
+            if budget == 0 {
+                // out of tokens, wait for the bucket to refill
+                return Ok(false);
+            }
+
+            let written = match self.o_map {
+                Some((ref map, off)) if self.o_buf.is_empty() => self.sock.write(&map[off..off + budget]),
+                _                                             => self.sock.write(&self.o_buf[..budget]),
+            };
+
+            match written {
+                Ok(sz) if sz > 0 => {
+                    self.sent(sz);
+                    self.wrote_at = Some(now);
+
+                    if let Some(ref mut tb) = self.bucket {
+                        tb.give_back(budget - sz);
                     }
                 },
-                Err(_)  => {
-                    return Ok(true);
-                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    // the socket is full, keep going next time
+                    if let Some(ref mut tb) = self.bucket {
+                        tb.give_back(budget);
+                    }
+
+                    return Ok(false);
+                },
+                Ok(_)            => return Err(ErrorKind::WriteZero.into()),
+                Err(e)           => return Err(e),
             }
         }
 
+        // anything the transport buffered (e.g. TLS records) must go out too
+        match self.sock.flush() {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            _                                               => {},
+        }
+
+        // we did it!
+        self.events.remove(EventSet::writable());
+
         Ok(true)
     }
 
+    // get ready for the next request on a kept-alive connection, some of
+    // which may already have arrived.
+    fn recycle(&mut self, now: Instant) {
+        let waiting = !self.i_buf.is_empty();
+
+        self.reading = if waiting { Some(now) } else { None };
+        self.o_map = None;
+        self.bucket = None;
+        self.persist = false;
+        self.idle = if waiting { None } else { Some(now) };
+        self.events.remove(EventSet::writable());
+        self.events.insert(EventSet::readable());
+    }
+
+    // the length of the request at the front of the input buffer, once all
+    // of it has arrived: its head, and as much body as `Content-Length`
+    // gives. without one there's no body to wait for, nor is there when the
+    // length is unclear, as the head alone is enough to refuse it.
+    fn request_length(&self) -> Option<usize> {
+        let head = parse::head_length(&self.i_buf)?;
+        let len = head + parse::content_length(&self.i_buf[..head]).ok().flatten().unwrap_or(0);
+
+        match self.i_buf.len() >= len {
+            true  => Some(len),
+            false => None,
+        }
+    }
+
+    // what the client has been too slow at, if anything: sending a request's
+    // head, sending the next part of its body, or reading its response.
+    fn too_slow(&mut self, now: Instant, config: &Config) -> Option<&'static str> {
+        let over = |limit: Option<Duration>, since: Instant| limit.map(|l| now.saturating_duration_since(since) >= l).unwrap_or(false);
+
+        if self.unsent().is_empty() {
+            self.wrote_at = None;
+        } else if over(config.write_timeout, *self.wrote_at.get_or_insert(now)) {
+            return Some("reading its response");
+        }
+
+        // HTTP/2 connections read requests while others are answered
+        #[cfg(feature = "h2")]
+        if self.h2.is_some() {
+            return None;
+        }
+
+        let started = match self.reading {
+            Some(started) if self.pending.is_none() => started,
+            _                                       => return None,
+        };
+
+        match parse::head_length(&self.i_buf) {
+            None if over(config.head_timeout, started)         => Some("sending its request head"),
+            Some(_) if over(config.body_timeout, self.read_at) => Some("sending its request body"),
+            _                                                  => None,
+        }
+    }
+
+    // how long to hold off writing while the token bucket refills, if at all.
+    fn throttled_for(&mut self, now: Instant) -> Option<u64> {
+        match self.bucket {
+            Some(ref mut tb) => tb.delay(now).map(|d| d.as_millis() as u64 + 1),
+            None             => None,
+        }
+    }
+
     fn register(&mut self, evl: &mut EventLoop<Canteen>) -> Result<()> {
         self.events.insert(EventSet::readable());
-        evl.register(&self.sock, self.token, self.events, PollOpt::edge() | PollOpt::oneshot())
+        self.sock.register(evl, self.token, self.events, PollOpt::edge() | PollOpt::oneshot())
     }
 
     fn reregister(&mut self, evl: &mut EventLoop<Canteen>) -> Result<()> {
-        evl.reregister(&self.sock, self.token, self.events, PollOpt::edge() | PollOpt::oneshot())
+        self.sock.reregister(evl, self.token, self.events, PollOpt::edge() | PollOpt::oneshot())
+    }
+}
+
+// what answers a request once it has been routed.
+enum Target {
+    Handler(route::HandlerFn),
+    Mount(Arc<Mount>),
+    Trace,
+    NotAllowed(Vec<Method>),
+    // the route's canonical path, which differs by a trailing slash
+    Redirect(String),
+    // over a request limit, for this long yet
+    Limited(Duration),
+}
+
+// what a response tells the client about its connection.
+enum Persist {
+    // the response isn't going out over a connection, e.g. from `dispatch`
+    Unknown,
+    Close,
+    // keep the connection open, with these `Keep-Alive` parameters
+    KeepAlive(String),
+}
+
+// a routed request, ready to be run on a worker.
+struct Job {
+    req:      Request,
+    target:   Target,
+    stats:    Arc<RouteStats>,
+    rate:     Option<u64>,
+    timeout:  Option<Duration>,
+    max_body: Option<usize>,
+    config:   Arc<Config>,
+    chain:    Arc<Vec<Arc<dyn Middleware>>>,
+    on_error: Arc<ErrorFn>,
+    pool:     Arc<PoolStats>,
+    clock:    Arc<dyn Clock>,
+    persist:  Persist,
+    // whether the response goes back over HTTP/2
+    #[cfg(feature = "h2")]
+    h2:       bool,
+}
+
+impl Job {
+    // run the middleware and the handler, then apply the server-wide settings.
+    fn respond(&mut self) -> Response {
+        let mut too_large = self.max_body.map(|max| self.req.payload.len() > max).unwrap_or(false);
+        let mut refused = None;
+
+        if let (false, Some(limit)) = (too_large, self.config.inflate) {
+            let limit = self.max_body.map(|max| max.min(limit)).unwrap_or(limit);
+
+            match encoding::decode_body(&mut self.req, limit) {
+                Ok(_)                                 => {},
+                Err(encoding::DecodeError::TooLarge)  => too_large = true,
+                Err(e)                                => {
+                    log::debug!("unable to decode body of {}: {}", self.req.path, e);
+
+                    refused = Some(match e {
+                        encoding::DecodeError::Unsupported(_) => utils::err_415(&self.req),
+                        _                                     => utils::err_400(&self.req),
+                    });
+                },
+            }
+        }
+
+        if too_large {
+            refused = Some(utils::err_413(&self.req));
+        }
+
+        if let Some(mut res) = refused {
+            self.finish(&mut res);
+            self.connection(&mut res);
+            return res;
+        }
+
+        if self.config.debug {
+            debug::install_hook();
+        }
+
+        let target = &self.target;
+        let chain = &self.chain;
+        let on_error = &self.on_error;
+        let debug = self.config.debug;
+        let pattern = self.stats.pattern();
+        let req = &mut self.req;
+        let run = panic::catch_unwind(AssertUnwindSafe(|| middleware::run(chain, req, |req| {
+            let handle = |handler: &route::HandlerFn| handler(req).unwrap_or_else(|e| {
+                match e.status().is_server_error() {
+                    true  => log::error!("handler for {} failed: {}", req.path, e),
+                    false => log::debug!("handler for {} failed: {}", req.path, e),
+                }
+
+                match debug {
+                    true  => debug::error_page(req, pattern, &e),
+                    false => on_error(req, &e),
+                }
+            });
+
+            match *target {
+                Target::Handler(ref handler) if debug => debug::check_response(req, handle(handler)),
+                Target::Handler(ref handler)          => handle(handler),
+                Target::Mount(ref mount)          => mount.serve(req),
+                Target::Trace                     => utils::trace_echo(req),
+                Target::NotAllowed(ref allow)     => utils::err_405(req, allow),
+                Target::Redirect(ref path)        => Response::redirect_preserving_query(req, path),
+                Target::Limited(wait)             => utils::err_429(req, wait),
+            }
+        })));
+
+        // a panicking handler gets the client a 500 rather than no answer,
+        // and leaves the worker alive to take the next job
+        let mut res = match run {
+            Ok(res) => res,
+            Err(payload) => {
+                log::error!("handler for {} panicked", self.req.path);
+                self.pool.record_panic();
+
+                match self.config.debug {
+                    true  => debug::panic_page(&self.req, self.stats.pattern(), payload.as_ref()),
+                    false => utils::err_500(&self.req),
+                }
+            },
+        };
+
+        if let Target::Mount(_) = self.target {
+            self.config.cache_static(&mut res);
+        }
+
+        self.finish(&mut res);
+        self.connection(&mut res);
+
+        res
+    }
+
+    // apply the server-wide settings, and those for responses over TLS.
+    fn finish(&self, res: &mut Response) {
+        self.config.finish(res);
+
+        if self.req.secure {
+            self.config.finish_secure(res);
+        }
+    }
+
+    // tell the client whether the connection stays open, unless the handler
+    // has already asked for it to be closed.
+    fn connection(&self, res: &mut Response) {
+        let closing = res.get_header("Connection").map(|v| v.eq_ignore_ascii_case("close")).unwrap_or(false);
+
+        match self.persist {
+            Persist::KeepAlive(ref params) if !closing => {
+                res.set_header("Connection", "keep-alive");
+                res.set_header("Keep-Alive", params);
+            },
+            Persist::Unknown                           => {},
+            _                                          => {
+                res.set_header("Connection", "close");
+                res.remove_header("Keep-Alive");
+            },
+        }
+    }
+
+    // describe the request if it took longer than the slow threshold.
+    fn slow_warning(&self, elapsed: Duration) -> Option<String> {
+        match self.config.slow {
+            Some(limit) if elapsed > limit => {
+                let mut params: Vec<_> = self.req.params.iter().collect();
+
+                params.sort();
+                Some(format!("slow request: {} {} (route {}) took {:?}, params {:?}",
+                             self.req.method.as_str(), self.req.path, self.stats.pattern(), elapsed, params))
+            },
+            _                              => None,
+        }
+    }
+
+    // whether the response's body can be sent in chunks as it's written.
+    fn can_stream(&self) -> bool {
+        #[cfg(feature = "h2")]
+        if self.h2 {
+            return false;
+        }
+
+        self.req.version != "HTTP/1.0"
+    }
+
+    // run the job and package the response up for the event loop, along
+    // with the body writer if the body is to be streamed.
+    fn reply(mut self) -> (Reply, Option<Streamer>) {
+        let start = self.clock.now();
+        let mut res = self.respond();
+
+        if !self.can_stream() {
+            res.buffer_stream();
+        }
+
+        #[cfg(feature = "h2")]
+        let (output, mapped, head) = match self.h2 {
+            true  => (res.get_payload().to_vec(), None, Some(http2::encode_head(&res))),
+            false => { let (output, mapped) = res.gen_parts(); (output, mapped, None) },
+        };
+        #[cfg(not(feature = "h2"))]
+        let (output, mapped) = res.gen_parts();
+        let elapsed = self.clock.now().saturating_duration_since(start);
+        let keep_alive = res.get_header("Connection") == Some("keep-alive");
+        let size = output.len() + mapped.as_ref().map(|m| m.len()).unwrap_or(0);
+
+        self.stats.record(res.get_status().as_u16(), elapsed, size);
+
+        if let Some(warning) = self.slow_warning(elapsed) {
+            log::warn!("{}", warning);
+        }
+
+        if let Some(ref log) = self.config.access_log {
+            log.log(&accesslog::LogEntry::new(&self.req, &res, self.req.elapsed()));
+        }
+
+        let reply = Reply {
+            output,
+            mapped,
+            rate: self.rate,
+            keep_alive,
+            backlog: None,
+            #[cfg(feature = "h2")]
+            head,
+        };
+
+        (reply, res.take_stream())
+    }
+
+    // run the job on a worker, and send the response back to the event loop.
+    fn run(self, tx: &Sender<Message>, token: Token, id: u64) {
+        let gone = self.req.gone.clone().unwrap_or_default();
+        let stats = self.stats.clone();
+
+        match self.reply() {
+            (mut reply, Some(stream)) => {
+                let backlog = Arc::new(Backlog::default());
+
+                reply.backlog = Some(backlog.clone());
+
+                if tx.send(Message::Reply(token, id, reply)).is_ok() {
+                    let sent = ChunkWriter::new(tx.clone(), token, id, gone, backlog).run(stream);
+
+                    stats.record_bytes(sent);
+                }
+            },
+            (reply, None)             => { let _ = tx.send(Message::Reply(token, id, reply)); },
+        }
     }
 }
 
@@ -199,57 +828,246 @@ impl Client {
 /// interface to Flask, the Python microframework.
 pub struct Canteen {
     routes:  HashMap<route::RouteDef, route::Route>,
-    rcache:  HashMap<route::RouteDef, route::RouteDef>,
-    server:  Option<TcpListener>,
-    token:   Token,
+    router:  router::Router,
+    rcache:  router::RouteCache,
+    lsocks:  Vec<Listener>,
+    mounts:  Vec<Arc<Mount>>,
+    config:  Arc<Config>,
+    chain:   Arc<Vec<Arc<dyn Middleware>>>,
+    state:   Arc<State>,
+    runas:   Option<(String, Option<String>)>,
+    clock:   Arc<dyn Clock>,
+    reload:  Option<ReloadFn>,
+    reloads: ReloadHandle,
+    sighup:  bool,
+    hooks:   Hooks,
+    seq:     u64,
+    dlines:  HashMap<u64, Deadline>,
+    per_ip:  HashMap<IpAddr, usize>,
     conns:   Slab<Client>,
-    default: fn(&Request) -> Response,
+    default: route::HandlerFn,
+    groups:  Vec<GroupDefault>,
+    limiter: Option<Arc<ratelimit::RateLimiter>>,
+    bodymax: usize,
+    on_err:  Arc<ErrorFn>,
+    dstats:  Arc<RouteStats>,
+    stats:   Stats,
     tpool:   ThreadPool,
 }
 
 impl Handler for Canteen {
-    type Timeout = ();
-    type Message = (Token, Vec<u8>);
+    type Timeout = Timer;
+    type Message = Message;
 
     fn ready(&mut self, evl: &mut EventLoop<Canteen>, token: Token, events: EventSet) {
-        if events.is_error() || events.is_hup() {
+        if token.as_usize() >= hooks::SOURCE_BASE {
+            self.hooks.ready(token, events);
+            return;
+        }
+
+        if events.is_error() {
             self.reset_connection(token);
             return;
         }
 
+        if events.is_hup() && !self.hung_up(evl, token, events) {
+            return;
+        }
+
         if events.is_readable() {
-            if self.token == token {
-                let sock = self.accept().unwrap();
+            if token.as_usize() < LISTENER_SLOTS {
+                let listener = token.as_usize();
+
+                if let Some(wait) = self.accept_delay(listener) {
+                    if !self.lsocks[listener].opts.shed {
+                        // leave the connection in the backlog until there's room
+                        let _ = evl.timeout_ms(Timer::Accept(listener), wait.as_millis() as u64 + 1);
+                        return;
+                    }
+
+                    if let Ok(Some((_, addr))) = self.lsocks[listener].sock.accept() {
+                        log::debug!("dropping connection from {}: over the accept rate", addr);
+                    }
+                } else if let Ok(sock) = self.accept(listener).map_err(|e| log::debug!("unable to accept connection: {}", e)) {
+                    let addr = sock.peer_addr().ok();
+                    let ip = addr.map(|a| a.ip());
+                    let config = self.config.clone();
+                    let now = self.clock.now();
+                    let limit = self.body_limit();
+
+                    // over the limit, the socket is dropped and so closed
+                    if self.admit(ip) {
+                        match self.conns.insert_with(|token| Client::new(sock, token, listener, &config, now)) {
+                            Some(token) => {
+                                let client = self.get_client(token);
 
-                if let Some(token) = self.conns.insert_with(|token| Client::new(sock, token)) {
-                    self.get_client(token).register(evl).ok();
+                                client.ip = ip;
+                                client.addr = addr;
+                                client.limit = limit;
+                                client.register(evl).ok();
+                                log::trace!("accepted connection {} from {}", token.as_usize(),
+                                            addr.map(|a| a.to_string()).unwrap_or_else(|| String::from("an unknown address")));
+                                self.hooks.opened(&ConnectionInfo { token: token.as_usize(), peer: addr });
+                            },
+                            None        => {
+                                log::debug!("refusing connection: no room for more");
+                                self.release(ip);
+                            },
+                        }
+                    }
                 }
 
-                self.reregister(evl);
+                self.reregister(evl, listener);
             } else {
-                self.readable(evl, token)
-                    .and_then(|_| self.get_client(token)
-                                      .reregister(evl)).ok();
+                let res = self.readable(evl, token)
+                              .and_then(|_| self.get_client(token).reregister(evl));
+
+                if res.is_err() {
+                    self.reset_connection(token);
+                }
             }
 
             return;
         }
 
         if events.is_writable() {
-            match self.get_client(token).send() {
-                Ok(true)    => { self.reset_connection(token); },
-                Ok(false)   => { let _ = self.get_client(token).reregister(evl); },
-                Err(_)      => {},
+            let now = self.clock.now();
+            let client = self.get_client(token);
+            let sent = client.send(now);
+
+            // let a streaming worker know how much is still waiting to go
+            if let Some(ref backlog) = client.stream {
+                backlog.set(client.o_buf.len());
+            }
+
+            match sent {
+                Ok(true)    => {
+                    let client = self.get_client(token);
+
+                    #[cfg(feature = "h2")]
+                    if let Some(ref session) = client.h2 {
+                        // the connection stays open for more streams, until
+                        // either side has gone away and they're all answered
+                        let ended = client.hungup || !client.events.is_readable();
+
+                        if session.is_done() || (ended && session.is_idle()) {
+                            self.reset_connection(token);
+                        } else {
+                            client.idle = if session.is_idle() { Some(now) } else { None };
+                            let _ = client.reregister(evl);
+                        }
+
+                        return;
+                    }
+
+                    if client.pending.is_some() {
+                        // only an interim response or part of a streamed one
+                        // went out, wait for the rest
+                        client.events.remove(EventSet::writable());
+                        let _ = client.reregister(evl);
+                    } else if client.persist {
+                        client.recycle(now);
+
+                        if !client.i_buf.is_empty() {
+                            self.next_request(evl, token);
+                        }
+
+                        let _ = self.get_client(token).reregister(evl);
+                    } else {
+                        self.reset_connection(token);
+                    }
+                },
+                Ok(false)   => {
+                    let client = self.get_client(token);
+
+                    match client.throttled_for(now) {
+                        Some(ms) => { let _ = evl.timeout_ms(Timer::Resume(token), ms); },
+                        None     => { let _ = client.reregister(evl); },
+                    }
+                },
+                Err(e)      => {
+                    log::debug!("unable to write to connection {}: {}", token.as_usize(), e);
+                    self.reset_connection(token);
+                },
             }
         }
     }
 
-    fn notify(&mut self, evl: &mut EventLoop<Canteen>, msg: (Token, Vec<u8>)) {
-        let (token, output) = msg;
-        let client = self.get_client(token);
+    fn notify(&mut self, evl: &mut EventLoop<Canteen>, msg: Message) {
+        let (token, id, reply) = match msg {
+            Message::Reply(token, id, reply)  => (token, id, reply),
+            Message::Interim(token, id, out)  => return self.interim(evl, token, id, out),
+            Message::Chunk(token, id, out)    => return self.interim(evl, token, id, out),
+            Message::End(token, id)           => return self.end_stream(evl, token, id),
+            Message::Shutdown                 => return evl.shutdown(),
+        };
+        let now = self.clock.now();
+
+        if let Some(dline) = self.dlines.remove(&id) {
+            if dline.expired {
+                log::warn!("handler for {} finished after {:?}, past its deadline; response discarded",
+                           dline.path, now.saturating_duration_since(dline.start));
+                return;
+            }
+        }
+
+        if let Some(client) = self.conns.get_mut(token) {
+            #[cfg(feature = "h2")]
+            if let Some(ref mut session) = client.h2 {
+                if session.owns(id) {
+                    session.respond(id, reply.head.as_deref().unwrap_or_default(), reply.output);
+                    client.flush_h2();
+                    let _ = client.reregister(evl);
+                }
+
+                return;
+            }
+
+            if client.pending != Some(id) {
+                // the client has already been sent a 504
+                return;
+            }
+
+            if let Some(deadline) = client.deadline.take() {
+                evl.clear_timeout(deadline);
+            }
+
+            // a streamed body keeps the request pending until it's ended
+            client.pending = reply.backlog.as_ref().map(|_| id);
+            client.stream = reply.backlog;
+            client.persist = reply.keep_alive && !client.hungup;
+            client.o_buf.extend_from_slice(&reply.output);
+            client.o_map = reply.mapped.map(|map| (map, 0));
+            client.events.insert(EventSet::writable());
+            client.bucket = reply.rate.map(|bps| TokenBucket::new(bps, bps, now));
+            let _ = client.reregister(evl);
+        }
+    }
+
+    fn timeout(&mut self, evl: &mut EventLoop<Canteen>, timer: Timer) {
+        match timer {
+            Timer::Resume(token)       => {
+                // a throttled client's bucket has refilled, resume writing
+                if let Some(client) = self.conns.get_mut(token) {
+                    let _ = client.reregister(evl);
+                }
+            },
+            Timer::Deadline(token, id) => self.expire(evl, token, id),
+            Timer::Accept(listener)    => self.reregister(evl, listener),
+            Timer::Housekeeping        => {
+                self.housekeeping();
+                let _ = evl.timeout_ms(Timer::Housekeeping, HOUSEKEEPING_MS);
+            },
+        }
+    }
+
+    fn tick(&mut self, _: &mut EventLoop<Canteen>) {
+        self.hooks.tick();
+    }
 
-        client.o_buf = output;
-        let _ = client.reregister(evl);
+    fn interrupted(&mut self, _: &mut EventLoop<Canteen>) {
+        // most likely a signal, which may have asked for a reload
+        self.housekeeping();
     }
 }
 
@@ -264,18 +1082,49 @@ impl Canteen {
     /// let cnt = Canteen::new();
     /// ```
     pub fn new() -> Canteen {
+        let stats = Stats::new();
+        let dstats = Arc::new(RouteStats::new("<default>", Method::NoImpl));
+
+        let config = Config::default();
+
+        stats.add(dstats.clone());
+        stats.set_workers(config.workers);
+
         Canteen {
             routes:  HashMap::new(),
-            rcache:  HashMap::new(),
-            server:  None,
-            token:   Token(1),
-            conns:   Slab::new_starting_at(Token(2), 2048),
-            default: utils::err_404,
-            tpool:   ThreadPool::new(255),
+            router:  router::Router::new(),
+            rcache:  router::RouteCache::new(config.route_cache),
+            lsocks:  Vec::new(),
+            mounts:  Vec::new(),
+            conns:   Slab::new_starting_at(Token(LISTENER_SLOTS), config.max_conns),
+            tpool:   ThreadPool::new(config.workers),
+            config:  Arc::new(config),
+            chain:   Arc::new(Vec::new()),
+            state:   Arc::new(State::new()),
+            runas:   None,
+            clock:   Arc::new(SystemClock),
+            reload:  None,
+            reloads: ReloadHandle::default(),
+            sighup:  false,
+            hooks:   Hooks::default(),
+            seq:     0,
+            dlines:  HashMap::new(),
+            per_ip:  HashMap::new(),
+            default: Arc::new(|req: &Request| Ok(utils::err_404(req))),
+            groups:  Vec::new(),
+            limiter: None,
+            bodymax: 0,
+            on_err:  Arc::new(Error::respond),
+            dstats,
+            stats,
         }
     }
 
-    /// Bind to an address on which to listen for connections
+    /// Bind to an address on which to listen for connections. If the address
+    /// resolves to more than one, e.g. `localhost` to both `127.0.0.1` and
+    /// `::1`, each of them is bound. Use port 0 to have the operating system
+    /// pick a free port, and `local_addr` to find out which.
+    ///
     /// # Examples
     /// ```rust,ignore
     /// use canteen::Canteen;
@@ -284,179 +1133,3771 @@ impl Canteen {
     /// cnt.bind(("127.0.0.1", 8080));
     /// ```
     pub fn bind<A: ToSocketAddrs>(&mut self, addr: A) {
-        self.server = Some(TcpListener::bind(&addr.to_socket_addrs().unwrap().next().unwrap()).unwrap());
+        self.listen(addr, Mode::Serve, ListenerOptions::new(), None);
     }
 
-
-    /// Adds a new route definition to be handled by Canteen.
+    /// Listen on `port` on every IPv4 and IPv6 address. The two families get
+    /// separate sockets; if IPv6 isn't available, only IPv4 is served. With
+    /// a port of 0, both share the port picked for IPv4.
     ///
     /// # Examples
     ///
-    /// ```rust
-    /// use canteen::{Canteen, Request, Response, Method};
-    /// use canteen::utils;
-    ///
-    /// fn handler(_: &Request) -> Response {
-    ///     utils::make_response("<b>Hello, world!</b>", "text/html", 200)
-    /// }
+    /// ```rust,ignore
+    /// use canteen::Canteen;
     ///
-    /// fn main() {
-    ///     let mut cnt = Canteen::new();
-    ///     cnt.add_route("/hello", &[Method::Get], handler);
-    /// }
+    /// let mut cnt = Canteen::new();
+    /// cnt.bind_dual_stack(8080);
     /// ```
-    pub fn add_route(&mut self, path: &str, mlist: &[Method],
-                     handler: fn(&Request) -> Response) -> &mut Canteen {
-        let mut methods: HashSet<Method> = HashSet::new();
+    pub fn bind_dual_stack(&mut self, port: u16) -> &mut Canteen {
+        let v4 = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
 
-        // make them unique
-        for m in mlist {
-            methods.insert(*m);
+        if let Err(e) = self.try_listen(&v4, Mode::Serve, &ListenerOptions::new(), &None) {
+            panic!("unable to bind {}: {}", v4, e);
         }
 
-        for m in methods {
-            let rd = route::RouteDef {
-                pathdef: String::from(path),
-                method:  m,
-            };
-
-            if self.routes.contains_key(&rd) {
-                panic!("a route handler for {} has already been defined!", path);
-            }
+        let port = self.lsocks.last().and_then(|l| l.sock.local_addr().ok()).map(|a| a.port()).unwrap_or(port);
+        let v6 = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
 
-            self.routes.insert(rd, route::Route::new(&path, m, handler));
+        if let Err(e) = self.try_listen(&v6, Mode::Serve, &ListenerOptions::new().v6_only(true), &None) {
+            log::warn!("unable to bind {}, serving IPv4 only: {}", v6, e);
         }
 
         self
     }
 
-    /// Defines a default route for undefined paths.
+    /// The address of the first listener, with the actual port if it was
+    /// bound to port 0. Returns `None` if nothing has been bound.
     ///
     /// # Examples
     ///
     /// ```rust
     /// use canteen::Canteen;
-    /// use canteen::utils;
     ///
     /// let mut cnt = Canteen::new();
-    /// cnt.set_default(utils::err_404);
+    /// cnt.bind(("127.0.0.1", 0));
+    ///
+    /// let addr = cnt.local_addr().unwrap();
+    /// assert_ne!(0, addr.port());
     /// ```
-    pub fn set_default(&mut self, handler: fn(&Request) -> Response) -> &mut Canteen {
-        self.default = handler;
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.lsocks.first().and_then(|l| l.sock.local_addr().ok())
+    }
+
+    /// The addresses being listened on, with the actual ports of any bound
+    /// to port 0, in the order they were bound.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.bind(("127.0.0.1", 0));
+    ///
+    /// assert!(cnt.local_addrs()[0].is_ipv4());
+    /// ```
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.lsocks.iter().filter_map(|l| l.sock.local_addr().ok()).collect()
+    }
+
+    /// Bind to an address with options that apply only to this listener.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// use canteen::Canteen;
+    /// use canteen::listener::ListenerOptions;
+    /// use canteen::rewrite::Rewrite;
+    ///
+    /// // deployed under /app behind a proxy
+    /// let mut cnt = Canteen::new();
+    /// cnt.bind_with(("127.0.0.1", 8080), ListenerOptions::new().rewrite(Rewrite::strip_prefix("/app")));
+    /// ```
+    pub fn bind_with<A: ToSocketAddrs>(&mut self, addr: A, opts: ListenerOptions) -> &mut Canteen {
+        self.listen(addr, Mode::Serve, opts, None);
 
         self
     }
 
-    fn get_client(&mut self, token: Token) -> &mut Client {
-        self.conns.get_mut(token).unwrap()
+    /// Bind an HTTPS listener using a PEM certificate chain and private key.
+    /// The files are re-read when they change, so renewed certificates are
+    /// picked up without a restart; see also `tls_reloader`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.bind_tls(("0.0.0.0", 443), "/etc/ssl/example.com/fullchain.pem",
+    ///              "/etc/ssl/example.com/privkey.pem");
+    /// ```
+    pub fn bind_tls<A: ToSocketAddrs, P: AsRef<Path>>(&mut self, addr: A, cert_chain: P,
+                                                      private_key: P) -> &mut Canteen {
+        let certs = match TlsCerts::new(cert_chain.as_ref(), private_key.as_ref(), self.clock.now()) {
+            Ok(certs)   => Arc::new(certs),
+            Err(e)      => panic!("unable to load TLS certificate: {}", e),
+        };
+
+        self.listen(addr, Mode::Serve, ListenerOptions::new(), Some(certs));
+
+        self
     }
 
-    fn accept(&mut self) -> Result<TcpStream> {
-        if let Some(ref server) = self.server {
-            if let Ok(s) = server.accept() {
-                if let Some((sock, _)) = s {
-                    return Ok(sock);
-                }
+    /// Get a handle that reloads the certificates of every TLS listener
+    /// bound so far, for use while the server runs.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.bind_tls(("0.0.0.0", 443), "cert.pem", "key.pem");
+    ///
+    /// let reloader = cnt.tls_reloader();
+    /// ```
+    pub fn tls_reloader(&self) -> TlsReloader {
+        TlsReloader::new(self.lsocks.iter().filter_map(|l| l.tls.clone()).collect())
+    }
+
+    /// Bind a companion plain-HTTP listener whose only job is to redirect
+    /// every request to the HTTPS equivalent on `https_port`, keeping the
+    /// path and query string. If `hsts` is set, the TLS listeners send it
+    /// as a `Strict-Transport-Security` header with every response; it isn't
+    /// sent with the redirects, as browsers ignore it over plain HTTP.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// use canteen::Canteen;
+    /// use canteen::config::Hsts;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.redirect_https(("0.0.0.0", 80), 443, Some(Hsts::new(31536000)));
+    /// ```
+    pub fn redirect_https<A: ToSocketAddrs>(&mut self, addr: A, https_port: u16,
+                                            hsts: Option<Hsts>) -> &mut Canteen {
+        self.listen(addr, Mode::RedirectHttps { port: https_port }, ListenerOptions::new(), None);
+        Arc::make_mut(&mut self.config).hsts = hsts;
+
+        self
+    }
+
+    // periodic work done on the event loop.
+    fn housekeeping(&mut self) {
+        if let Some(limit) = self.config.queue_wait {
+            let worst = self.stats.pool_stats().take_worst_wait();
+
+            if worst > limit {
+                let pool = self.stats.pool();
+                log::warn!("requests waited up to {:?} for a worker ({} queued, {} of {} workers busy)",
+                           worst, pool.queued, pool.active, pool.workers);
             }
         }
 
-        Err(std::io::Error::new(
-            std::io::ErrorKind::ConnectionAborted,
-            "connection aborted prematurely".to_string()
-        ))
+        self.close_idle();
+        self.close_slow();
+
+        let signalled = self.sighup && reload::take_sighup();
+
+        if self.reloads.take() || signalled {
+            self.apply_reload();
+        }
     }
 
-    fn handle_request(&mut self, token: Token, tx: Sender<(Token, Vec<u8>)>, rqstr: &str) {
-        let mut req = Request::from_str(&rqstr).unwrap();
-        let mut handler: fn(&Request) -> Response = self.default;
-        let resolved = route::RouteDef {
-            pathdef: req.path.clone(),
-            method:  req.method,
+    // close kept-alive connections that have waited too long for their next
+    // request.
+    fn close_idle(&mut self) {
+        let limit = match self.config.keep_alive {
+            Some(ref ka) => ka.idle,
+            None         => Duration::from_secs(0),
         };
+        let now = self.clock.now();
+        let idle: Vec<Token> = self.conns.iter()
+                                         .filter(|c| c.idle.map(|t| now.saturating_duration_since(t) >= limit).unwrap_or(false))
+                                         .map(|c| c.token)
+                                         .collect();
+
+        for token in idle {
+            self.reset_connection(token);
+        }
+    }
 
-        if self.rcache.contains_key(&resolved) {
-            let route = &self.routes[&self.rcache[&resolved]];
+    // close connections whose clients are too slow sending their requests
+    // or reading their responses, so a few can't hold every slot open.
+    fn close_slow(&mut self) {
+        let now = self.clock.now();
+        let config = self.config.clone();
+        let slow: Vec<(Token, &str)> = self.conns.iter_mut()
+                                                 .filter_map(|c| c.too_slow(now, &config).map(|why| (c.token, why)))
+                                                 .collect();
 
-            handler = route.handler;
-            req.params = route.parse(&req.path);
-        } else {
-            for (path, route) in &self.routes {
-                if route.is_match(&req) {
-                    handler = route.handler;
-                    req.params = route.parse(&req.path);
-                    self.rcache.insert(resolved, (*path).clone());
-                    break;
-                }
+        for (token, why) in slow {
+            log::debug!("closing connection {}: too slow {}", token.as_usize(), why);
+            self.reset_connection(token);
+        }
+    }
+
+    fn apply_reload(&mut self) {
+        if let Some(ref reload) = self.reload {
+            let mut config = (*self.config).clone();
+
+            reload(&mut config);
+            self.config = Arc::new(config);
+            self.resize();
+
+            if let Some(level) = self.config.log_level {
+                log::set_max_level(level);
             }
         }
 
-        self.tpool.execute(move || {
-            let _ = tx.send((token, handler(&req).gen_output()));
-        });
+        if let Err(e) = self.tls_reloader().reload() {
+            log::warn!("unable to reload TLS certificates: {}", e);
+        }
+
+        log::info!("configuration reloaded");
     }
 
-    fn readable(&mut self, evl: &mut EventLoop<Canteen>, token: Token) -> Result<bool> {
-        if let Ok(true) = self.get_client(token).receive() {
-            let buf = self.get_client(token).i_buf.clone();
-            if let Ok(rqstr) = String::from_utf8(buf) {
-                self.handle_request(token, evl.channel(), &rqstr);
-            } else {
-                return Ok(false);
+    // bind every address `addr` resolves to. failing to bind some of them
+    // is only fatal if none could be bound.
+    fn listen<A: ToSocketAddrs>(&mut self, addr: A, mode: Mode, opts: ListenerOptions,
+                                tls: Option<Arc<TlsCerts>>) {
+        let addrs: Vec<SocketAddr> = match addr.to_socket_addrs() {
+            Ok(addrs)   => addrs.collect(),
+            Err(e)      => panic!("unable to resolve listening address: {}", e),
+        };
+        let mut bound = 0;
+        let mut failure = String::from("no addresses given");
+
+        for addr in &addrs {
+            match self.try_listen(addr, mode, &opts, &tls) {
+                Ok(())  => bound += 1,
+                Err(e)  => {
+                    log::warn!("unable to bind {}: {}", addr, e);
+                    failure = format!("{}: {}", addr, e);
+                },
             }
         }
 
-        Ok(true)
+        if bound == 0 {
+            panic!("unable to bind to an address: {}", failure);
+        }
     }
 
-    fn reset_connection(&mut self, token: Token) {
-        // kill the connection
-        self.conns.remove(token);
+    fn try_listen(&mut self, addr: &SocketAddr, mode: Mode, opts: &ListenerOptions,
+                  tls: &Option<Arc<TlsCerts>>) -> Result<()> {
+        if self.lsocks.len() == LISTENER_SLOTS {
+            panic!("no more than {} listeners may be bound!", LISTENER_SLOTS);
+        }
+
+        let sock = listener::bind(addr, opts).and_then(|l| TcpListener::from_listener(l, addr))?;
+
+        log::info!("listening on {}", sock.local_addr().unwrap_or(*addr));
+        let rate = opts.accepts.map(|(per_sec, burst)| TokenBucket::new(per_sec, burst, self.clock.now()));
+
+        self.lsocks.push(Listener { sock, mode, opts: opts.clone(), tls: tls.clone(), rate });
+
+        Ok(())
     }
 
-    fn register(&mut self, evl: &mut EventLoop<Canteen>) -> Result<()> {
-        if let Some(ref server) = self.server {
-            return evl.register(server, self.token, EventSet::readable(), PollOpt::edge() | PollOpt::oneshot());
+
+    /// Adds a new route definition to be handled by Canteen. The methods
+    /// may be a single `Method`, a slice of them, or one of the sets like
+    /// `Method::all()`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Canteen, Request, Response, Method};
+    /// use canteen::utils;
+    ///
+    /// fn handler(_: &Request) -> Response {
+    ///     utils::make_response("<b>Hello, world!</b>", "text/html", 200)
+    /// }
+    ///
+    /// fn main() {
+    ///     let mut cnt = Canteen::new();
+    ///     cnt.add_route("/hello", &[Method::Get], handler);
+    ///     cnt.add_route("/hi", Method::Get, handler);
+    ///     cnt.add_route("/hey", Method::all(), handler);
+    ///
+    ///     // closures can capture what the handler needs
+    ///     let greeting = String::from("howdy");
+    ///     cnt.add_route("/howdy", Method::Get, move |_: &Request| utils::text(greeting.as_str()));
+    /// }
+    /// ```
+    pub fn add_route<M, H, R>(&mut self, path: &str, mlist: M, handler: H) -> &mut Canteen
+        where M: MethodList,
+              H: Fn(&Request) -> R + Send + Sync + 'static,
+              R: IntoResult {
+        self.add_route_with(path, mlist, handler, route::RouteOptions::new())
+    }
+
+    /// Adds a new route definition with its own options, such as a longer
+    /// handler deadline than the server's default.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::{Canteen, Request, Response, Method};
+    /// use canteen::route::RouteOptions;
+    /// use canteen::utils;
+    ///
+    /// fn report(_: &Request) -> Response {
+    ///     utils::make_response("all done", "text/plain", 200)
+    /// }
+    ///
+    /// let mut cnt = Canteen::new();
+    ///
+    /// cnt.set_handler_timeout(Duration::from_secs(5));
+    /// cnt.add_route_with("/report", &[Method::Post], report,
+    ///                    RouteOptions::new().timeout(Duration::from_secs(60)));
+    /// ```
+    pub fn add_route_with<M, H, R>(&mut self, path: &str, mlist: M, handler: H,
+                                   opts: route::RouteOptions) -> &mut Canteen
+        where M: MethodList,
+              H: Fn(&Request) -> R + Send + Sync + 'static,
+              R: IntoResult {
+        let handler: route::HandlerFn = Arc::new(move |req: &Request| handler(req).into_result());
+        let mut methods: HashSet<Method> = HashSet::new();
+
+        // make them unique
+        for m in mlist.methods() {
+            methods.insert(*m);
         }
 
-        Ok(())
+        for m in methods {
+            let rd = route::RouteDef {
+                pathdef: String::from(path),
+                method:  m,
+            };
+
+            if self.routes.contains_key(&rd) {
+                panic!("a route handler for {} has already been defined!", path);
+            }
+
+            let mut route = route::Route::shared(path, m, handler.clone());
+
+            self.bodymax = self.bodymax.max(opts.max_body.unwrap_or(0));
+
+            route.set_options(opts.clone());
+            self.stats.add(route.stats.clone());
+            self.router.insert(route.segments(), rd.clone());
+            self.routes.insert(rd, route);
+        }
+
+        self.rcache.clear();
+
+        self
     }
 
-    fn reregister(&mut self, evl: &mut EventLoop<Canteen>) {
-        if let Some(ref server) = self.server {
-            evl.reregister(server, self.token,
-                                 EventSet::readable(),
-                                 PollOpt::edge() | PollOpt::oneshot()).ok();
+    /// Builds the path for the route named `name`, see `RouteOptions::name`,
+    /// with the parameters `params`. Returns `None` if there's no route by
+    /// that name, a parameter is missing, or a value doesn't fit its type.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Canteen, Method, Request};
+    /// use canteen::route::RouteOptions;
+    /// use canteen::utils;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.add_route_with("/user/<int:id>", Method::Get, |_: &Request| utils::text("hi"),
+    ///                    RouteOptions::new().name("user_detail"));
+    ///
+    /// assert_eq!(Some(String::from("/user/42")), cnt.url_for("user_detail", &[("id", "42")]));
+    /// ```
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        self.routes.values().find(|route| route.info.name() == Some(name)).and_then(|route| route.url(params))
+    }
+
+    /// The routes that have been added, as the pattern each was declared
+    /// with, the methods it answers and its name, see `RouteOptions::name`.
+    /// Patterns are listed in order, and their methods in the order they're
+    /// declared in `Method`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Canteen, Method, Request};
+    /// use canteen::route::RouteOptions;
+    /// use canteen::utils;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.add_route("/", Method::Get, |_: &Request| utils::text("hi"));
+    /// cnt.add_route_with("/user/<int:id>", &[Method::Get, Method::Delete][..], |_: &Request| utils::text("hi"),
+    ///                    RouteOptions::new().name("user_detail"));
+    ///
+    /// for (pattern, methods, name) in cnt.routes() {
+    ///     println!("{:<20} {:?} {}", pattern, methods, name.unwrap_or(""));
+    /// }
+    /// ```
+    pub fn routes(&self) -> impl Iterator<Item = (&str, Vec<Method>, Option<&str>)> {
+        let mut table: BTreeMap<&str, (Vec<Method>, Option<&str>)> = BTreeMap::new();
+
+        for (def, route) in &self.routes {
+            let entry = table.entry(&def.pathdef).or_default();
+
+            entry.0.push(def.method);
+            entry.1 = entry.1.or(route.info.name());
         }
+
+        table.into_iter().map(|(pattern, (mut methods, name))| {
+            methods.sort_by_key(|&m| m as u8);
+            (pattern, methods, name)
+        })
     }
 
-    /// Creates the listener and starts a Canteen server's event loop.
+    /// Starts a group of routes under the path prefix `prefix`, which may
+    /// have its own middleware and default handler. See `group::RouteGroup`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Canteen, Method, Request};
+    /// use canteen::dump::Dump;
+    /// use canteen::utils;
+    ///
+    /// let mut cnt = Canteen::new();
+    ///
+    /// cnt.group("/admin")
+    ///    .add_middleware(Dump::new())
+    ///    .add_route("/stats", Method::Get, |_: &Request| utils::text("ok"));
+    /// ```
+    pub fn group(&mut self, prefix: &str) -> RouteGroup<'_> {
+        RouteGroup::new(self, prefix)
+    }
+
+    // set the default handler for a group's prefix, replacing any earlier
+    // one. the longest prefix is checked first.
+    pub(crate) fn group_default(&mut self, default: GroupDefault) {
+        self.groups.retain(|g| g.prefix != default.prefix);
+        self.groups.push(default);
+        self.groups.sort_by_key(|g| std::cmp::Reverse(g.prefix.len()));
+    }
+
+    /// Serve the files under the directory `root` at the URL prefix `prefix`.
+    /// Mounts are checked before the route table, for GET requests only
+    /// unless the mount is writable or also serves WebDAV.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mount is fingerprinted and the files under `root`
+    /// can't be read.
     ///
     /// # Examples
     ///
     /// ```rust
     /// use canteen::Canteen;
+    /// use canteen::files::StaticOptions;
     ///
     /// let mut cnt = Canteen::new();
-    /// cnt.run();
+    /// cnt.mount_static("/assets", "public", StaticOptions::new().rate_limit(1 << 20));
     /// ```
-    pub fn run(&mut self) {
-        let mut evl = match EventLoop::new() {
-            Ok(event_loop)  => event_loop,
-            Err(_)          => panic!("unable to initiate event loop"),
-        };
+    pub fn mount_static<P: AsRef<Path>>(&mut self, prefix: &str, root: P, opts: StaticOptions) -> &mut Canteen {
+        let mut mount = Mount::new(prefix, root.as_ref(), opts);
 
-        match self.server {
-            None    => println!("server not bound to an address!"),
-            Some(_) => {
-                self.register(&mut evl).ok();
-                evl.run(self).unwrap();
-            },
-        };
+        if mount.opts.fingerprint {
+            match assets::AssetManifest::build(&mount.prefix, &mount.root) {
+                Ok(manifest) => {
+                    let manifest = Arc::new(manifest);
+
+                    assets::register(manifest.clone());
+                    mount.assets = Some(manifest);
+                },
+                Err(e)       => panic!("unable to fingerprint the files in {}: {}", mount.root.display(), e),
+            }
+        }
+
+        self.stats.add(mount.stats.clone());
+        self.mounts.push(Arc::new(mount));
+
+        self
     }
-}
 
-impl Default for Canteen {
-    fn default() -> Self {
-        Canteen::new()
+    /// Cap the rate, in bytes per second, at which responses are written to
+    /// each connection, so a single fast client can't starve the others.
+    /// Static mounts may set their own limit.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_rate_limit(4 * 1024 * 1024);
+    /// ```
+    pub fn set_rate_limit(&mut self, bytes_per_sec: u64) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).rate_limit = Some(bytes_per_sec);
+
+        self
+    }
+
+    /// Set how long handlers may run before the client is sent a `504
+    /// Gateway Timeout`. Routes may set their own deadline with
+    /// `add_route_with`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_handler_timeout(Duration::from_secs(5));
+    /// ```
+    pub fn set_handler_timeout(&mut self, timeout: Duration) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).timeout = Some(timeout);
+
+        self
+    }
+
+    /// Set how long clients have to send a request's line and headers, and
+    /// how long they may pause while sending its body, before the connection
+    /// is closed. These keep clients that trickle their requests in a byte
+    /// at a time from holding connections open. The defaults are 10 and 30
+    /// seconds.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_read_timeouts(Duration::from_secs(5), Duration::from_secs(60));
+    /// ```
+    pub fn set_read_timeouts(&mut self, header: Duration, body: Duration) -> &mut Canteen {
+        let config = Arc::make_mut(&mut self.config);
+
+        config.head_timeout = Some(header);
+        config.body_timeout = Some(body);
+
+        self
+    }
+
+    /// Set how long a client may go without reading any of its response
+    /// before the connection is closed. Defaults to 30 seconds.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_write_timeout(Duration::from_secs(60));
+    /// ```
+    pub fn set_write_timeout(&mut self, timeout: Duration) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).write_timeout = Some(timeout);
+
+        self
+    }
+
+    /// Log requests whose handler takes longer than `threshold` at WARN
+    /// level, with the route, the time taken, and the route parameters.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_slow_threshold(Duration::from_millis(500));
+    /// ```
+    pub fn set_slow_threshold(&mut self, threshold: Duration) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).slow = Some(threshold);
+
+        self
+    }
+
+    /// Log a warning when requests wait longer than `threshold` for a
+    /// worker, a sign that the pool is overloaded.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_queue_wait_threshold(Duration::from_millis(100));
+    /// ```
+    pub fn set_queue_wait_threshold(&mut self, threshold: Duration) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).queue_wait = Some(threshold);
+
+        self
+    }
+
+    /// Set how much is read from a connection at a time: `initial` bytes at
+    /// first, doubling after every read that fills the buffer, up to `max`.
+    /// Small values save memory when requests are tiny; larger ones save
+    /// system calls when they carry a lot of headers.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_read_buffer(512, 16 * 1024);
+    /// ```
+    pub fn set_read_buffer(&mut self, initial: usize, max: usize) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).set_read_buffer(initial, max);
+
+        self
+    }
+
+    /// Set how many worker threads run handlers. Handlers that block, e.g.
+    /// on a database, need more of them; the default is 255.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_workers(32);
+    ///
+    /// assert_eq!(32, cnt.pool_health().workers);
+    /// ```
+    pub fn set_workers(&mut self, workers: usize) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).set_workers(workers);
+        self.resize();
+
+        self
+    }
+
+    /// Limit how many connections the server has open at once, 2048 by
+    /// default. Connections beyond the limit are closed as soon as they are
+    /// accepted. This must be set before the server starts.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_max_connections(10000);
+    /// ```
+    pub fn set_max_connections(&mut self, max: usize) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).set_max_connections(max);
+        self.resize();
+
+        self
+    }
+
+    // size the worker pool, and the connection slab if there are no
+    // connections in it yet, to match the settings.
+    pub(crate) fn resize(&mut self) {
+        let workers = self.config.workers;
+        let max = self.config.max_conns;
+
+        if self.tpool.max_count() != workers {
+            self.tpool.set_num_threads(workers);
+            self.stats.set_workers(workers);
+        }
+
+        if self.conns.is_empty() && self.conns.remaining() != max {
+            self.conns = Slab::new_starting_at(Token(LISTENER_SLOTS), max);
+        }
+    }
+
+    /// Limit how many connections a single client IP address may have open
+    /// at once. Connections beyond the limit are closed as soon as they are
+    /// accepted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_max_connections_per_ip(32);
+    /// ```
+    pub fn set_max_connections_per_ip(&mut self, max: usize) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).per_ip = Some(max);
+
+        self
+    }
+
+    /// Set the limits on persistent connections. Connections are kept open
+    /// between requests by default, as HTTP/1.1 clients expect, and closed
+    /// after 5 seconds without a new request.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::Canteen;
+    /// use canteen::config::KeepAlive;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_keep_alive(KeepAlive::new().max_requests(100)
+    ///                                    .max_age(Duration::from_secs(600)));
+    /// ```
+    pub fn set_keep_alive(&mut self, keep_alive: KeepAlive) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).keep_alive = Some(keep_alive);
+
+        self
+    }
+
+    /// Answer TRACE requests by echoing them back, as RFC 7231 describes.
+    /// This is off by default, since the echo can help cross-site tracing
+    /// attacks; while it's off, TRACE requests get `405 Method Not Allowed`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.enable_trace();
+    /// ```
+    pub fn enable_trace(&mut self) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).trace = true;
+
+        self
+    }
+
+    /// Limit how many request paths have their route remembered, 1024 by
+    /// default, so repeat requests skip looking it up. The least recently
+    /// used are dropped to make room; a size of zero turns the cache off.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_route_cache_size(10000);
+    /// ```
+    pub fn set_route_cache_size(&mut self, size: usize) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).set_route_cache_size(size);
+        self.rcache.resize(size);
+
+        self
+    }
+
+    /// Forget the routes remembered for request paths.
+    pub fn clear_route_cache(&mut self) -> &mut Canteen {
+        self.rcache.clear();
+
+        self
+    }
+
+    /// Set what happens when a request's path differs from a route's only
+    /// by a trailing slash: by default both forms are answered alike.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Canteen, Method, Request};
+    /// use canteen::route::TrailingSlash;
+    /// use canteen::utils;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_trailing_slash(TrailingSlash::Redirect);
+    /// cnt.add_route("/docs/", Method::Get, |_: &Request| utils::text("docs"));
+    ///
+    /// let res = cnt.dispatch("GET /docs HTTP/1.1\r\nHost: example.com\r\n\r\n".parse().unwrap());
+    /// assert_eq!(301, res.get_status());
+    /// assert_eq!(Some("http://example.com/docs/"), res.get_header("Location"));
+    /// ```
+    pub fn set_trailing_slash(&mut self, slash: route::TrailingSlash) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).slash = slash;
+
+        self
+    }
+
+    /// Answer requests whose handler panics with a page showing the panic
+    /// message, backtrace, request, and matched route, instead of a bare
+    /// `500 Internal Server Error`. Handlers that return an error get a
+    /// page showing it and its causes, in place of the error handler's
+    /// response. This is for development only: the pages give away details
+    /// of the code and the request.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    ///
+    /// if cfg!(debug_assertions) {
+    ///     cnt.enable_debug();
+    /// }
+    /// ```
+    pub fn enable_debug(&mut self) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).debug = true;
+
+        self
+    }
+
+    /// Close every connection after its first response.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.disable_keep_alive();
+    /// ```
+    pub fn disable_keep_alive(&mut self) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).keep_alive = None;
+
+        self
+    }
+
+    /// Set the largest request body, in bytes, that handlers will be given.
+    /// Larger requests are answered with `413 Content Too Large`, and once
+    /// a body is larger than any route takes, the rest of it isn't read.
+    /// Routes may set their own limit with `add_route_with`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_max_body(1024 * 1024);
+    /// ```
+    pub fn set_max_body(&mut self, bytes: usize) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).max_body = Some(bytes);
+
+        self
+    }
+
+    /// Set the charset added to text responses whose content type doesn't
+    /// name one, e.g. `text/html` is sent as `text/html; charset=utf-8`.
+    /// This covers static files too. Defaults to `utf-8`; `None` leaves
+    /// content types as handlers set them.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_default_charset(Some("iso-8859-1"));
+    /// ```
+    pub fn set_default_charset(&mut self, charset: Option<&str>) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).set_default_charset(charset);
+
+        self
+    }
+
+    /// Add the header `name` to every response that doesn't set it itself,
+    /// including error responses and static files.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_default_header("X-Environment", "staging")
+    ///    .set_default_header("X-Content-Type-Options", "nosniff");
+    /// ```
+    pub fn set_default_header(&mut self, name: &str, value: &str) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).set_default_header(name, value);
+
+        self
+    }
+
+    /// Set what is sent in the `Server` header of every response: the
+    /// default `canteen/<version>`, a fixed value, one picked at random from
+    /// a list, or nothing at all.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    /// use canteen::config::ServerHeader;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_server_header(ServerHeader::Suppress);
+    /// ```
+    pub fn set_server_header(&mut self, header: ServerHeader) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).server_header = header;
+
+        self
+    }
+
+    /// Set a function to run whenever the server is asked to reload, which
+    /// may change any of the server-wide settings, e.g. after re-reading a
+    /// configuration file: limits, timeouts, the log level, the cache policy
+    /// for static mounts, the allowed hosts, and so on. TLS certificates are
+    /// re-read on reload as well. Requests already in progress keep the
+    /// settings they started with, and open connections keep the body size
+    /// limit they were accepted with.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    /// use canteen::config::ServerHeader;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.on_reload(|cfg| { cfg.set_server_header(ServerHeader::Suppress); });
+    /// ```
+    pub fn on_reload<F: Fn(&mut Config) + Send + 'static>(&mut self, reload: F) -> &mut Canteen {
+        self.reload = Some(Box::new(reload));
+
+        self
+    }
+
+    /// Get a handle that can ask the running server to reload, e.g. from an
+    /// admin route.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let cnt = Canteen::new();
+    /// let handle = cnt.reload_handle();
+    /// ```
+    pub fn reload_handle(&self) -> ReloadHandle {
+        self.reloads.clone()
+    }
+
+    /// Reload when the process receives SIGHUP.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.reload_on_sighup();
+    /// ```
+    #[cfg(unix)]
+    pub fn reload_on_sighup(&mut self) -> &mut Canteen {
+        self.sighup = true;
+
+        self
+    }
+
+    /// Switch to an unprivileged user (and optionally group) once the server
+    /// starts, after its sockets have been bound, so low ports can be used
+    /// without serving requests as root. Supplementary groups are reset to
+    /// those of the new user. The server refuses to start if this fails.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.drop_privileges("www-data", Some("www-data"));
+    /// ```
+    #[cfg(unix)]
+    pub fn drop_privileges(&mut self, user: &str, group: Option<&str>) -> &mut Canteen {
+        self.runas = Some((String::from(user), group.map(String::from)));
+
+        self
+    }
+
+    /// Write a line to `log` for every request the server answers, including
+    /// those turned away before reaching a handler, with how long each took
+    /// from being read to its response being ready.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    /// use canteen::accesslog::{AccessLog, LogEntry, LogFormat};
+    ///
+    /// let mut cnt = Canteen::new();
+    /// let format = LogFormat::custom(|e: &LogEntry| {
+    ///     format!("{} {} {} {}ms", e.req.method.as_str(), e.req.path, e.status, e.latency.as_millis())
+    /// });
+    ///
+    /// cnt.log_requests(AccessLog::new(|line: &str| println!("{}", line)).format(format));
+    /// ```
+    pub fn log_requests(&mut self, log: accesslog::AccessLog) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).access_log = Some(log);
+
+        self
+    }
+
+    /// Only accept connections from the addresses `filter` permits. Others
+    /// are closed as soon as they're accepted, before anything is read.
+    /// Clients whose address isn't known are only accepted if the filter
+    /// has no allow list. To keep clients from only some routes, use the
+    /// filter as middleware.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    /// use canteen::ipfilter::IpFilter;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.filter_connections(IpFilter::new().allow("10.0.0.0/8").allow("127.0.0.1"));
+    /// ```
+    pub fn filter_connections(&mut self, filter: ipfilter::IpFilter) -> &mut Canteen {
+        Arc::make_mut(&mut self.config).ip_filter = Some(filter);
+
+        self
+    }
+
+    /// Limit how often each client may make requests, to any route. Requests
+    /// over the limit get a `429 Too Many Requests` with a `Retry-After`
+    /// header, without being handed to a worker. Routes can have limits of
+    /// their own, see `RouteOptions::limit_requests`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::Canteen;
+    /// use canteen::ratelimit::RateLimiter;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.limit_requests(RateLimiter::new(100, Duration::from_secs(1)).burst(200));
+    /// ```
+    pub fn limit_requests(&mut self, limiter: ratelimit::RateLimiter) -> &mut Canteen {
+        self.limiter = Some(Arc::new(limiter));
+
+        self
+    }
+
+    /// Adds a middleware to run around every handler. See
+    /// `middleware::Middleware` for the order hooks run in.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    /// use canteen::dump::Dump;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.add_middleware(Dump::new());
+    /// ```
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, mw: M) -> &mut Canteen {
+        Arc::make_mut(&mut self.chain).push(Arc::new(mw));
+
+        self
+    }
+
+    /// Share `state` with every handler, which reach it with
+    /// `Request::state`. State of the same type given earlier is replaced.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::sync::Mutex;
+    /// use canteen::Canteen;
+    ///
+    /// struct Visits(Mutex<u64>);
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.manage(Visits(Mutex::new(0)));
+    /// ```
+    pub fn manage<T: std::any::Any + Send + Sync>(&mut self, state: T) -> &mut Canteen {
+        Arc::make_mut(&mut self.state).insert(state);
+
+        self
+    }
+
+    /// Run `tick` on every turn of the event loop, which is at least a few
+    /// times a second even when the server is idle.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut ticks = 0u64;
+    /// let mut cnt = Canteen::new();
+    /// cnt.on_tick(move || ticks += 1);
+    /// ```
+    pub fn on_tick<F: FnMut() + Send + 'static>(&mut self, tick: F) -> &mut Canteen {
+        self.hooks.tick.push(Box::new(tick));
+
+        self
+    }
+
+    /// Run `hook` whenever a connection is accepted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    /// use canteen::hooks::ConnectionInfo;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.on_connection_open(|conn: &ConnectionInfo| println!("{} from {:?}", conn.token, conn.peer));
+    /// ```
+    pub fn on_connection_open<F: Fn(&ConnectionInfo) + Send + 'static>(&mut self, hook: F) -> &mut Canteen {
+        self.hooks.open.push(Box::new(hook));
+
+        self
+    }
+
+    /// Run `hook` whenever a connection is closed, by either side.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    /// use canteen::hooks::ConnectionInfo;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.on_connection_close(|conn: &ConnectionInfo| println!("{} closed", conn.token));
+    /// ```
+    pub fn on_connection_close<F: Fn(&ConnectionInfo) + Send + 'static>(&mut self, hook: F) -> &mut Canteen {
+        self.hooks.close.push(Box::new(hook));
+
+        self
+    }
+
+    /// Watch an evented source of your own, such as a pipe another thread
+    /// writes to, calling `on_ready` with it whenever it's ready for what's
+    /// in `interest`. It's registered level-triggered, so `on_ready` should
+    /// drain it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::io::Read;
+    /// use canteen::Canteen;
+    /// use canteen::hooks::EventSet;
+    ///
+    /// let (reader, writer) = mio::unix::pipe().unwrap();
+    /// let mut cnt = Canteen::new();
+    ///
+    /// cnt.add_source(reader, EventSet::readable(), |pipe: &mut mio::unix::PipeReader, _| {
+    ///     let mut buf = [0u8; 64];
+    ///     while let Ok(n) = pipe.read(&mut buf) {
+    ///         if n == 0 { break; }
+    ///     }
+    /// });
+    /// ```
+    pub fn add_source<E, F>(&mut self, source: E, interest: EventSet, on_ready: F) -> &mut Canteen
+        where E: Evented + Send + 'static,
+              F: FnMut(&mut E, EventSet) + Send + 'static {
+        self.hooks.sources.push(Box::new(UserSource { source, interest, on_ready }));
+
+        self
+    }
+
+    /// Replaces the clock used for timeouts and rate limiting. Intended for
+    /// tests, which can pass a `clock::ManualClock` to control time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    /// use canteen::clock::ManualClock;
+    ///
+    /// let clock = ManualClock::new();
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_clock(clock.clone());
+    /// ```
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) -> &mut Canteen {
+        self.clock = Arc::new(clock);
+
+        self
+    }
+
+    /// Defines a default route for undefined paths.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    /// use canteen::utils;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_default(utils::err_404);
+    /// ```
+    pub fn set_default<H, R>(&mut self, handler: H) -> &mut Canteen
+        where H: Fn(&Request) -> R + Send + Sync + 'static,
+              R: IntoResult {
+        self.default = Arc::new(move |req: &Request| handler(req).into_result());
+
+        self
+    }
+
+    /// Sets what answers a request whose handler returned an error, in place
+    /// of `Error::respond`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Canteen, Error, Request};
+    /// use canteen::utils;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.set_error_handler(|_: &Request, err: &Error| {
+    ///     utils::make_response(format!("{{\"error\": \"{}\"}}", err.message()), "application/json", err.status())
+    /// });
+    /// ```
+    pub fn set_error_handler<F>(&mut self, handler: F) -> &mut Canteen
+        where F: Fn(&Request, &Error) -> Response + Send + Sync + 'static {
+        self.on_err = Arc::new(handler);
+
+        self
+    }
+
+    /// Take a snapshot of the request statistics for every route, static
+    /// mount, and the default handler.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let cnt = Canteen::new();
+    ///
+    /// for route in cnt.stats() {
+    ///     println!("{:?} {}: {} requests, p99 {:?}", route.method, route.pattern, route.requests, route.p99);
+    /// }
+    /// ```
+    pub fn stats(&self) -> Vec<RouteSnapshot> {
+        self.stats.snapshot()
+    }
+
+    /// Take a snapshot of the worker pool's health: how many workers are
+    /// busy, how many requests are waiting for one and for how long, and how
+    /// many handlers have panicked.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let cnt = Canteen::new();
+    /// let pool = cnt.pool_health();
+    ///
+    /// println!("{} queued, p99 wait {:?}", pool.queued, pool.wait_p99);
+    /// ```
+    pub fn pool_health(&self) -> PoolSnapshot {
+        self.stats.pool()
+    }
+
+    /// Get a handle onto the server's statistics that can be kept and read
+    /// from other threads, e.g. by an admin handler, while the server runs.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let cnt = Canteen::new();
+    /// let stats = cnt.stats_handle();
+    ///
+    /// std::thread::spawn(move || println!("{:?}", stats.snapshot()));
+    /// ```
+    pub fn stats_handle(&self) -> Stats {
+        self.stats.clone()
+    }
+
+    fn get_client(&mut self, token: Token) -> &mut Client {
+        self.conns.get_mut(token).unwrap()
+    }
+
+    // how long until a rate-limited listener may accept again, taking a
+    // token if it may now.
+    fn accept_delay(&mut self, listener: usize) -> Option<Duration> {
+        let now = self.clock.now();
+        let bucket = self.lsocks.get_mut(listener)?.rate.as_mut()?;
+
+        match bucket.delay(now) {
+            None => {
+                bucket.take(1, now);
+                None
+            },
+            wait => wait,
+        }
+    }
+
+    fn accept(&mut self, listener: usize) -> Result<Box<dyn Transport>> {
+        if let Some(l) = self.lsocks.get(listener) {
+            if let Ok(Some((sock, _))) = l.sock.accept() {
+                if l.opts.nodelay {
+                    let _ = sock.set_nodelay(true);
+                }
+
+                return match l.tls {
+                    Some(ref certs) => {
+                        certs.refresh(self.clock.now());
+                        Ok(Box::new(certs.accept(sock)?))
+                    },
+                    None            => Ok(Box::new(sock)),
+                };
+            }
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            "connection aborted prematurely".to_string()
+        ))
+    }
+
+    fn handle_request(&mut self, evl: &mut EventLoop<Canteen>, token: Token, raw: &[u8]) {
+        let tx = evl.channel();
+        let id = self.seq;
+        let listener = self.get_client(token).listener;
+
+        self.seq += 1;
+        self.get_client(token).pending = Some(id);
+        self.get_client(token).reading = None;
+
+        let head = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap_or(raw.len());
+
+        if self.config.max_header.map(|max| head > max).unwrap_or(false) {
+            let req = self.unparsed(token);
+
+            log::debug!("rejecting request on connection {}: {} byte header", token.as_usize(), head);
+
+            return self.reject(&tx, token, id, &req, utils::err_431(&req));
+        }
+
+        if self.get_client(token).oversize {
+            let req = match parse::parse_request(raw) {
+                Ok(mut req) => {
+                    self.prepare(token, &mut req);
+                    req
+                },
+                Err(_)      => self.unparsed(token),
+            };
+
+            log::debug!("rejecting {} {}: body over {} bytes", req.method.as_str(), req.path, self.body_limit().unwrap_or(0));
+            return self.reject(&tx, token, id, &req, utils::err_413(&req));
+        }
+
+        if parse::content_length(&raw[..head.min(raw.len())]).is_err() {
+            let req = self.unparsed(token);
+
+            log::debug!("rejecting request on connection {}: unclear body length", token.as_usize());
+            return self.reject(&tx, token, id, &req, utils::err_400(&req));
+        }
+
+        let mut req = match parse::parse_request(raw) {
+            Ok(req) => req,
+            Err(e)  => {
+                let req = self.unparsed(token);
+
+                log::debug!("unable to parse request on connection {}: {:?}", token.as_usize(), e);
+                return self.reject(&tx, token, id, &req, utils::err_400(&req));
+            },
+        };
+
+        log::trace!("connection {}: {} {} {}", token.as_usize(), req.method.as_str(), req.path, req.version);
+
+        if self.config.max_uri.map(|max| req.path.len() > max).unwrap_or(false) {
+            log::debug!("rejecting request on connection {}: {} byte URI", token.as_usize(), req.path.len());
+
+            let req = self.unparsed(token);
+
+            return self.reject(&tx, token, id, &req, utils::err_414(&req));
+        }
+
+        if let Mode::RedirectHttps { port } = self.lsocks[listener].mode {
+            self.prepare(token, &mut req);
+
+            let res = utils::https_redirect(&req, port);
+
+            return self.reject(&tx, token, id, &req, res);
+        }
+
+        #[cfg(feature = "h2")]
+        if self.lsocks[listener].tls.is_none() && http2::wants_upgrade(&req) {
+            return self.upgrade_h2(evl, token, id, req);
+        }
+
+        let persist = self.persist(token, &req);
+
+        self.prepare(token, &mut req);
+
+        let mut job = self.route(req);
+
+        // turned away before it takes up a worker
+        if let Target::Limited(wait) = job.target {
+            let res = utils::err_429(&job.req, wait);
+
+            return self.reject(&tx, token, id, &job.req, res);
+        }
+
+        job.persist = persist;
+
+        if job.req.version != "HTTP/1.0" {
+            job.req.hints = Some(HintSender::new(evl.channel(), token, id));
+        }
+
+        self.get_client(token).deadline = self.submit(evl, token, id, job);
+    }
+
+    // fill in what a request can only learn from its connection.
+    fn prepare(&mut self, token: Token, req: &mut Request) {
+        let listener = self.get_client(token).listener;
+
+        req.secure = self.lsocks[listener].tls.is_some();
+        req.peer = self.get_client(token).ip;
+        req.gone = Some(self.get_client(token).gone.clone());
+        req.received = Some((self.clock.now(), self.clock.clone()));
+
+        if !self.lsocks[listener].opts.rewrites.is_empty() {
+            req.path = rewrite::apply_all(&self.lsocks[listener].opts.rewrites, &req.path);
+        }
+    }
+
+    // the largest body any request may have, which routes can raise above
+    // the server-wide limit. with no server-wide limit there's none at all.
+    fn body_limit(&self) -> Option<usize> {
+        self.config.max_body.map(|max| max.max(self.bodymax))
+    }
+
+    // stands in for a request that couldn't be read, so it can be logged.
+    fn unparsed(&mut self, token: Token) -> Request {
+        let mut req = Request::new();
+
+        req.peer = self.get_client(token).ip;
+        req.received = Some((self.clock.now(), self.clock.clone()));
+        req
+    }
+
+    // hand a job to a worker, returning the timer for its deadline if it
+    // has one.
+    fn submit(&mut self, evl: &mut EventLoop<Canteen>, token: Token, id: u64, job: Job) -> Option<Timeout> {
+        let tx = evl.channel();
+        let mut deadline = None;
+
+        if let Some(limit) = job.timeout {
+            let ms = limit.as_millis() as u64;
+
+            self.dlines.insert(id, Deadline {
+                path:    job.req.path.clone(),
+                stats:   job.stats.clone(),
+                start:   self.clock.now(),
+                expired: false,
+            });
+            deadline = evl.timeout_ms(Timer::Deadline(token, id), ms).ok();
+        }
+
+        let pool = self.stats.pool_stats();
+        let clock = self.clock.clone();
+        let queued = clock.now();
+
+        pool.enqueue();
+        self.tpool.execute(move || {
+            pool.start(clock.now().saturating_duration_since(queued));
+
+            // the client may have gone while the job waited for a worker
+            if !job.req.is_aborted() {
+                job.run(&tx, token, id);
+            }
+
+            pool.finish();
+        });
+
+        deadline
+    }
+
+    // take in what an HTTP/2 client sent, starting the session if this is
+    // the connection's first read, and hand out the requests it completes.
+    #[cfg(feature = "h2")]
+    fn receive_h2(&mut self, evl: &mut EventLoop<Canteen>, token: Token) -> Result<bool> {
+        let limit = self.body_limit();
+        let client = self.get_client(token);
+        let data = std::mem::take(&mut client.i_buf);
+        let session = client.h2.get_or_insert_with(|| Box::new(Session::new().max_body(limit)));
+
+        // a connection error leaves a GOAWAY to send, then the connection
+        // closes once it has been written
+        let received = session.receive(&data).unwrap_or_default();
+
+        // streams are read while others are answered, so keep reading
+        client.events.insert(EventSet::readable());
+        client.events.remove(EventSet::writable());
+
+        for (stream, req) in received {
+            self.dispatch_h2(evl, token, stream, req);
+        }
+
+        let now = self.clock.now();
+        let client = self.get_client(token);
+
+        client.idle = match client.h2 {
+            Some(ref session) if session.is_idle() => Some(now),
+            _                                      => None,
+        };
+        client.flush_h2();
+
+        Ok(true)
+    }
+
+    // route and run the request on an HTTP/2 stream, after the same checks
+    // `handle_request` makes of HTTP/1 requests.
+    #[cfg(feature = "h2")]
+    fn dispatch_h2(&mut self, evl: &mut EventLoop<Canteen>, token: Token, stream: u32, mut req: Request) {
+        let id = self.seq;
+        let listener = self.get_client(token).listener;
+        let head: usize = req.headers.iter().map(|(k, v)| k.len() + v.len() + 4).sum();
+
+        self.seq += 1;
+
+        if let Some(ref mut session) = self.get_client(token).h2 {
+            session.start(stream, id);
+        }
+
+        if self.config.max_header.map(|max| head > max).unwrap_or(false) {
+            let req = self.unparsed(token);
+
+            log::debug!("rejecting stream {} on connection {}: {} byte header", stream, token.as_usize(), head);
+            return self.reject_h2(token, id, &req, utils::err_431(&req));
+        }
+
+        if self.config.max_uri.map(|max| req.path.len() > max).unwrap_or(false) {
+            log::debug!("rejecting stream {} on connection {}: {} byte URI", stream, token.as_usize(), req.path.len());
+
+            let req = self.unparsed(token);
+
+            return self.reject_h2(token, id, &req, utils::err_414(&req));
+        }
+
+        self.prepare(token, &mut req);
+
+        // prior knowledge gets a client past the redirect listener's HTTP/1
+        // parsing, so each stream is redirected instead
+        if let Mode::RedirectHttps { port } = self.lsocks[listener].mode {
+            let res = utils::https_redirect(&req, port);
+
+            return self.reject_h2(token, id, &req, res);
+        }
+
+        let mut job = self.route(req);
+
+        job.h2 = true;
+
+        self.submit(evl, token, id, job);
+    }
+
+    // answer a request on an HTTP/2 stream without involving the workers.
+    // the connection stays open for the client's other streams.
+    #[cfg(feature = "h2")]
+    fn reject_h2(&mut self, token: Token, id: u64, req: &Request, mut res: Response) {
+        self.config.finish(&mut res);
+
+        if req.secure {
+            self.config.finish_secure(&mut res);
+        }
+
+        if let Some(ref log) = self.config.access_log {
+            log.log(&accesslog::LogEntry::new(req, &res, req.elapsed()));
+        }
+
+        if let Some(ref mut session) = self.get_client(token).h2 {
+            session.respond(id, &http2::encode_head(&res), res.get_payload().to_vec());
+        }
+    }
+
+    // switch a plaintext connection to HTTP/2 at the client's request,
+    // answering the request that asked on stream 1.
+    #[cfg(feature = "h2")]
+    fn upgrade_h2(&mut self, evl: &mut EventLoop<Canteen>, token: Token, id: u64, req: Request) {
+        let settings = req.get_header("HTTP2-Settings").unwrap_or_default();
+        let session = match Session::upgraded(&settings) {
+            Some(session) => session.max_body(self.body_limit()),
+            None          => return self.reject(&evl.channel(), token, id, &req, utils::err_400(&req)),
+        };
+        let client = self.get_client(token);
+
+        client.pending = None;
+        client.served += 1;
+        client.o_buf.extend_from_slice(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n");
+        client.h2 = Some(Box::new(session));
+        client.events.insert(EventSet::readable());
+
+        self.dispatch_h2(evl, token, 1, req);
+        self.get_client(token).flush_h2();
+    }
+
+    // answer a request without involving the workers, closing the
+    // connection afterwards.
+    fn reject(&self, tx: &Sender<Message>, token: Token, id: u64, req: &Request, mut res: Response) {
+        self.config.finish(&mut res);
+
+        if req.secure {
+            self.config.finish_secure(&mut res);
+        }
+
+        res.set_header("Connection", "close");
+
+        if let Some(ref log) = self.config.access_log {
+            log.log(&accesslog::LogEntry::new(req, &res, req.elapsed()));
+        }
+
+        let _ = tx.send(Message::Reply(token, id, Reply {
+            output:     res.gen_output(),
+            mapped:     None,
+            rate:       None,
+            keep_alive: false,
+            backlog:    None,
+            #[cfg(feature = "h2")]
+            head:       None,
+        }));
+    }
+
+    // queue an interim response ahead of whatever the worker answers with.
+    fn interim(&mut self, evl: &mut EventLoop<Canteen>, token: Token, id: u64, out: Vec<u8>) {
+        if let Some(client) = self.conns.get_mut(token) {
+            if client.pending == Some(id) {
+                client.o_buf.extend_from_slice(&out);
+                client.events.insert(EventSet::writable());
+                let _ = client.reregister(evl);
+            }
+        }
+    }
+
+    // a streamed response has been written: end the body, and let the
+    // connection go on to its next request once it's sent.
+    fn end_stream(&mut self, evl: &mut EventLoop<Canteen>, token: Token, id: u64) {
+        if let Some(client) = self.conns.get_mut(token) {
+            if client.pending == Some(id) && client.stream.take().is_some() {
+                client.pending = None;
+                client.o_buf.extend_from_slice(&stream::chunk(b""));
+                client.events.insert(EventSet::writable());
+                let _ = client.reregister(evl);
+            }
+        }
+    }
+
+    // count a request on a connection and decide whether the connection may
+    // stay open after it's answered.
+    fn persist(&mut self, token: Token, req: &Request) -> Persist {
+        let now = self.clock.now();
+        let config = self.config.clone();
+        let client = self.get_client(token);
+
+        client.served += 1;
+        client.idle = None;
+
+        let params = match config.keep_alive {
+            Some(ref ka) if req.wants_keep_alive() => {
+                ka.params(client.served, now.saturating_duration_since(client.opened))
+            },
+            _                                      => None,
+        };
+
+        match params {
+            Some(params) => Persist::KeepAlive(params),
+            None         => Persist::Close,
+        }
+    }
+
+    // a request has run past its deadline: answer it with a 504 and ignore
+    // whatever the handler eventually comes up with.
+    fn expire(&mut self, evl: &mut EventLoop<Canteen>, token: Token, id: u64) {
+        let mut res = utils::err_504(&Request::new());
+
+        if let Some(dline) = self.dlines.get_mut(&id) {
+            log::warn!("handler for {} timed out after {:?}", dline.path,
+                       self.clock.now().saturating_duration_since(dline.start));
+            dline.stats.record_timeout();
+            dline.expired = true;
+        }
+
+        self.config.finish(&mut res);
+        res.set_header("Connection", "close");
+
+        if let Some(client) = self.conns.get_mut(token) {
+            #[cfg(feature = "h2")]
+            if let Some(ref mut session) = client.h2 {
+                if session.owns(id) {
+                    session.respond(id, &http2::encode_head(&res), res.get_payload().to_vec());
+                    client.flush_h2();
+                    let _ = client.reregister(evl);
+                }
+
+                return;
+            }
+
+            // once a streamed body has started, it's too late for a 504
+            if client.pending != Some(id) || client.stream.is_some() {
+                return;
+            }
+
+            client.pending = None;
+            client.persist = false;
+            client.deadline = None;
+            client.o_buf.extend_from_slice(&res.gen_output());
+            let _ = client.reregister(evl);
+        }
+    }
+
+    // find what should answer a request, among the static mounts and routes.
+    fn route(&mut self, mut req: Request) -> Job {
+        let mut target = Target::Handler(self.default.clone());
+        let mut stats = self.dstats.clone();
+        let mut rate = self.config.rate_limit;
+        let mut opts = route::RouteOptions::default();
+
+        req.shared = Some(self.state.clone());
+
+        let found = self.mounts.iter().find(|m| m.allows(req.method) && m.strip(&req.path).is_some());
+
+        if req.method == Method::Trace {
+            if self.config.trace {
+                target = Target::Trace;
+            } else {
+                let allow = self.allowed(&req.path);
+
+                if !allow.is_empty() {
+                    target = Target::NotAllowed(allow);
+                }
+            }
+        } else if let Some(mount) = found {
+            stats = mount.stats.clone();
+            rate = mount.opts.rate_limit.or(rate);
+            target = Target::Mount(mount.clone());
+        } else {
+            // keyed without the query, which doesn't affect routing
+            let resolved = route::RouteDef {
+                pathdef: String::from(req.path.split('?').next().unwrap_or("")),
+                method:  req.method,
+            };
+
+            let slash = self.config.slash;
+
+            // the size may have changed with a reload
+            self.rcache.resize(self.config.route_cache);
+
+            if let Some(def) = self.rcache.get(&resolved) {
+                let route = &self.routes[def];
+
+                target = Target::Handler(route.handler.clone());
+                stats = route.stats.clone();
+                opts = route.opts.clone();
+                req.params = route.parse(&resolved.pathdef);
+                req.route = Some(route.info.clone());
+
+                if slash == route::TrailingSlash::Redirect && !route.slash_matches(&req.path) {
+                    target = Target::Redirect(route.canonical(&req.path));
+                }
+            } else {
+                let mut found = None;
+
+                // a route declared with the request's exact form wins over
+                // one that only differs by a trailing slash
+                for m in self.router.lookup(&req.path) {
+                    let route = &self.routes[m.def];
+
+                    if m.def.method == req.method && self.slash_allowed(route, &req.path) {
+                        let exact = route.slash_matches(&req.path);
+
+                        if exact || found.is_none() {
+                            found = Some((m.def, route, route.bind(&m.values)));
+                        }
+
+                        if exact {
+                            break;
+                        }
+                    }
+                }
+
+                let matched = found.is_some();
+
+                if let Some((path, route, params)) = found {
+                    target = Target::Handler(route.handler.clone());
+                    stats = route.stats.clone();
+                    opts = route.opts.clone();
+                    req.params = params;
+                    req.route = Some(route.info.clone());
+
+                    if slash == route::TrailingSlash::Redirect && !route.slash_matches(&req.path) {
+                        target = Target::Redirect(route.canonical(&req.path));
+                    }
+
+                    self.rcache.insert(resolved, path.clone());
+                }
+
+                // something answers the path, just not with this method
+                if !matched {
+                    let allow = self.allowed(&req.path);
+
+                    if !allow.is_empty() {
+                        target = Target::NotAllowed(allow);
+                    } else if let Some(group) = self.groups.iter().find(|g| g.covers(&req.path)) {
+                        target = Target::Handler(group.handler.clone());
+                        opts.chain = group.chain.clone();
+                    }
+                }
+            }
+        }
+
+        // parameters are matched as sent, then decoded for the handler. a
+        // request for a host the server doesn't answer is as bad.
+        if !req.decode_params() || !self.config.host_allowed(&req) {
+            target = Target::Handler(Arc::new(|req: &Request| Ok(utils::err_400(req))));
+        }
+
+        let now = self.clock.now();
+        let limiters = opts.limiter.iter().chain(self.limiter.iter());
+
+        for limiter in limiters {
+            if let Err(wait) = limiter.check(&req, now) {
+                log::debug!("limiting {} {}: retry in {:?}", req.method.as_str(), req.path, wait);
+                target = Target::Limited(wait);
+                break;
+            }
+        }
+
+        log::trace!("{} {} routed to {}", req.method.as_str(), req.path, stats.pattern());
+
+        Job {
+            req,
+            target,
+            stats,
+            rate,
+            timeout:  opts.timeout.or(self.config.timeout),
+            max_body: opts.max_body.or(self.config.max_body),
+            config:   self.config.clone(),
+            chain:    opts.chain.inside(&self.chain),
+            on_error: self.on_err.clone(),
+            pool:     self.stats.pool_stats(),
+            clock:    self.clock.clone(),
+            persist:  Persist::Unknown,
+            #[cfg(feature = "h2")]
+            h2:       false,
+        }
+    }
+
+    // whether the trailing slash setting lets `route` answer `path`.
+    fn slash_allowed(&self, route: &route::Route, path: &str) -> bool {
+        self.config.slash != route::TrailingSlash::Strict || route.slash_matches(path)
+    }
+
+    // the methods that something will answer for `path`.
+    fn allowed(&self, path: &str) -> Vec<Method> {
+        let mut allow: Vec<Method> = Vec::new();
+
+        for m in self.router.lookup(path) {
+            if self.slash_allowed(&self.routes[m.def], path) && !allow.contains(&m.def.method) {
+                allow.push(m.def.method);
+            }
+        }
+
+        for mount in self.mounts.iter().filter(|m| m.strip(path).is_some()) {
+            for method in Method::all().iter().filter(|&&m| mount.allows(m)) {
+                if !allow.contains(method) {
+                    allow.push(*method);
+                }
+            }
+        }
+
+        allow
+    }
+
+    /// Route a request and run it through the middleware and its handler on
+    /// the current thread, without involving any sockets. This is mostly
+    /// useful for testing.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Canteen, Request, Response, Method};
+    /// use canteen::utils;
+    ///
+    /// fn handler(_: &Request) -> Response {
+    ///     utils::make_response("hi", "text/plain", 200)
+    /// }
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.add_route("/hello", &[Method::Get], handler);
+    ///
+    /// let req: Request = "GET /hello HTTP/1.1\r\n\r\n".parse().unwrap();
+    /// assert_eq!(200, cnt.dispatch(req).get_status());
+    /// ```
+    pub fn dispatch(&mut self, req: Request) -> Response {
+        let mut res = self.route(req).respond();
+
+        res.buffer_stream();
+        res
+    }
+
+    fn readable(&mut self, evl: &mut EventLoop<Canteen>, token: Token) -> Result<bool> {
+        match self.get_client(token).receive() {
+            Ok(true)  => {
+                let now = self.clock.now();
+                let client = self.get_client(token);
+
+                client.read_at = now;
+                client.reading.get_or_insert(now);
+                client.idle = None;
+
+                #[cfg(feature = "h2")]
+                if self.get_client(token).is_h2() {
+                    return self.receive_h2(evl, token);
+                }
+
+                self.next_request(evl, token);
+            },
+            Ok(false) => {
+                let client = self.get_client(token);
+
+                // the client hung up between requests
+                if !client.events.is_readable() && !client.busy() && client.unsent().is_empty() {
+                    self.reset_connection(token);
+                    return Err(std::io::Error::from(ErrorKind::UnexpectedEof));
+                }
+            },
+            Err(e)    => return Err(e),
+        }
+
+        Ok(true)
+    }
+
+    // handle the request at the front of the input buffer if all of it has
+    // arrived, leaving anything after it, e.g. the next of several requests
+    // sent at once, for when this one is answered. otherwise keep reading.
+    fn next_request(&mut self, evl: &mut EventLoop<Canteen>, token: Token) {
+        let max_header = self.config.max_header;
+        let client = self.get_client(token);
+
+        // one at a time, so responses go back in order
+        if client.busy() {
+            return;
+        }
+
+        let buf = match client.request_length() {
+            Some(len) => {
+                let rest = client.i_buf.split_off(len);
+
+                client.events.remove(EventSet::readable());
+                std::mem::replace(&mut client.i_buf, rest)
+            },
+            // a body too large to take, or a head that's run on too long, is
+            // answered without waiting for the rest
+            None if client.oversize || max_header.map(|max| client.i_buf.len() > max).unwrap_or(false) => {
+                std::mem::take(&mut client.i_buf)
+            },
+            None      => {
+                client.events.insert(EventSet::readable());
+                client.events.remove(EventSet::writable());
+                return;
+            },
+        };
+
+        self.handle_request(evl, token, &buf);
+    }
+
+    // the client has shut down its side of the connection. it may still be
+    // waiting for the answer to what it sent, so that's finished and sent,
+    // but the connection is closed afterwards. returns whether it's open.
+    fn hung_up(&mut self, evl: &mut EventLoop<Canteen>, token: Token, events: EventSet) -> bool {
+        let client = match self.conns.get_mut(token) {
+            Some(client) => client,
+            None         => return false,
+        };
+
+        client.hungup = true;
+        client.persist = false;
+        client.events.remove(EventSet::hup());
+
+        if !client.busy() && client.unsent().is_empty() && !events.is_readable() {
+            self.reset_connection(token);
+            return false;
+        }
+
+        if !events.is_readable() && !events.is_writable() {
+            let _ = client.reregister(evl);
+        }
+
+        true
+    }
+
+    fn reset_connection(&mut self, token: Token) {
+        // kill the connection, and with it whatever was still being done for it
+        if let Some(client) = self.conns.remove(token) {
+            if let Some(id) = client.pending {
+                log::debug!("client went away before request {} was answered", id);
+                self.dlines.remove(&id);
+            }
+
+            client.gone.store(true, Ordering::SeqCst);
+            self.release(client.ip);
+            self.hooks.closed(&ConnectionInfo { token: token.as_usize(), peer: client.addr });
+        }
+    }
+
+    // count a new connection from `ip`, unless it's filtered out or already
+    // has as many as it's allowed.
+    fn admit(&mut self, ip: Option<IpAddr>) -> bool {
+        let ip = match ip {
+            Some(ip) => ip,
+            None     => return self.config.ip_filter.as_ref().is_none_or(ipfilter::IpFilter::permits_unknown),
+        };
+
+        if let Some(ref filter) = self.config.ip_filter {
+            if !filter.permits(ip) {
+                log::debug!("refusing connection from {}: filtered", ip);
+                return false;
+            }
+        }
+
+        let count = self.per_ip.entry(ip).or_insert(0);
+
+        if let Some(max) = self.config.per_ip {
+            if *count >= max {
+                log::debug!("refusing connection from {}: {} already open", ip, count);
+                return false;
+            }
+        }
+
+        *count += 1;
+
+        true
+    }
+
+    // forget a connection from `ip`.
+    fn release(&mut self, ip: Option<IpAddr>) {
+        if let Some(ip) = ip {
+            if let Some(count) = self.per_ip.get_mut(&ip) {
+                *count -= 1;
+
+                if *count == 0 {
+                    self.per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+
+    fn register(&mut self, evl: &mut EventLoop<Canteen>) -> Result<()> {
+        for (i, l) in self.lsocks.iter().enumerate() {
+            evl.register(&l.sock, Token(i), EventSet::readable(), PollOpt::edge() | PollOpt::oneshot())?;
+        }
+
+        self.hooks.register(evl)
+    }
+
+    fn reregister(&mut self, evl: &mut EventLoop<Canteen>, listener: usize) {
+        if let Some(l) = self.lsocks.get(listener) {
+            evl.reregister(&l.sock, Token(listener),
+                                 EventSet::readable(),
+                                 PollOpt::edge() | PollOpt::oneshot()).ok();
+        }
+    }
+
+    /// Creates the listener and starts a Canteen server's event loop.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.run();
+    /// ```
+    pub fn run(&mut self) {
+        let mut evl = match EventLoop::new() {
+            Ok(event_loop)  => event_loop,
+            Err(_)          => panic!("unable to initiate event loop"),
+        };
+
+        self.serve(&mut evl);
+    }
+
+    /// Runs the server on a background thread, returning a handle that can
+    /// be used to find its addresses, shut it down, and wait for it to exit.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use canteen::Canteen;
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.bind(("127.0.0.1", 0));
+    ///
+    /// let server = cnt.spawn();
+    /// println!("listening on {}", server.addr().unwrap());
+    ///
+    /// server.shutdown();
+    /// server.join().unwrap();
+    /// ```
+    pub fn spawn(mut self) -> ServerHandle {
+        let addrs = self.lsocks.iter().filter_map(|l| l.sock.local_addr().ok()).collect();
+        let (tx, rx) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            let mut evl = match EventLoop::new() {
+                Ok(event_loop)  => event_loop,
+                Err(_)          => panic!("unable to initiate event loop"),
+            };
+
+            let _ = tx.send(evl.channel());
+            self.serve(&mut evl);
+        });
+
+        ServerHandle::new(addrs, rx.recv().ok(), thread)
+    }
+
+    fn serve(&mut self, evl: &mut EventLoop<Canteen>) {
+        if self.lsocks.is_empty() {
+            log::error!("server not bound to an address!");
+        } else {
+            for addr in self.local_addrs() {
+                log::info!("listening on {}", addr);
+            }
+
+            #[cfg(unix)]
+            {
+                if let Some((ref user, ref group)) = self.runas {
+                    if let Err(e) = privilege::drop_to(user, group.as_deref()) {
+                        panic!("unable to drop privileges to {}: {}", user, e);
+                    }
+                }
+            }
+
+            #[cfg(unix)]
+            {
+                if self.sighup {
+                    if let Err(e) = reload::watch_sighup() {
+                        panic!("unable to handle SIGHUP: {}", e);
+                    }
+                }
+            }
+
+            self.register(evl).ok();
+            let _ = evl.timeout_ms(Timer::Housekeeping, HOUSEKEEPING_MS);
+            evl.run(self).unwrap();
+        }
+    }
+}
+
+impl Default for Canteen {
+    fn default() -> Self {
+        Canteen::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::clock::ManualClock;
+    use crate::transport::{MockTransport, Step};
+
+    fn client(mock: MockTransport) -> Client {
+        Client::new(Box::new(mock), Token(LISTENER_SLOTS), 0, &Config::default(), Instant::now())
+    }
+
+    #[test]
+    fn test_client_receive_partial_reads() {
+        let mut cl = client(MockTransport::new().incoming(b"GET / HT").incoming(b"TP/1.1\r\n\r\n").block());
+
+        assert!(cl.receive().unwrap());
+        assert_eq!(b"GET / HTTP/1.1\r\n\r\n".to_vec(), cl.i_buf);
+        assert!(cl.events.is_writable());
+        assert!(!cl.events.is_readable());
+    }
+
+    #[test]
+    fn test_client_receive_oversized_body() {
+        let mut cl = client(MockTransport::new().incoming(b"POST / HTTP/1.1\r\n\r\n0123").incoming(b"4567").incoming(b"89").block());
+
+        cl.limit = Some(6);
+        assert!(cl.receive().unwrap());
+        assert!(cl.oversize);
+        assert_eq!(b"POST / HTTP/1.1\r\n\r\n01234567".to_vec(), cl.i_buf);
+        assert!(!cl.events.is_readable());
+
+        // a Content-Length over the limit is enough, without the body
+        let mut cl = client(MockTransport::new().incoming(b"POST / HTTP/1.1\r\nContent-Length: 7\r\n\r\n").block());
+
+        cl.limit = Some(6);
+        assert!(cl.receive().unwrap());
+        assert!(cl.oversize);
+
+        let mut cl = client(MockTransport::new().incoming(b"POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\n012345").block());
+
+        cl.limit = Some(6);
+        assert!(cl.receive().unwrap());
+        assert!(!cl.oversize);
+    }
+
+    #[test]
+    fn test_client_too_slow() {
+        let config = Config::default();
+        let start = Instant::now();
+        let secs = |n| start + Duration::from_secs(n);
+        let mut cl = Client::new(Box::new(MockTransport::new()), Token(LISTENER_SLOTS), 0, &config, start);
+
+        // nothing sent at all, then only part of a head
+        assert_eq!(None, cl.too_slow(secs(9), &config));
+        assert_eq!(Some("sending its request head"), cl.too_slow(secs(10), &config));
+
+        cl.i_buf = b"GET / HTTP/1.1\r\nHost: lo".to_vec();
+        assert_eq!(Some("sending its request head"), cl.too_slow(secs(10), &config));
+
+        // a body is timed from the last read
+        cl.i_buf = b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n01".to_vec();
+        cl.read_at = secs(20);
+        assert_eq!(None, cl.too_slow(secs(49), &config));
+        assert_eq!(Some("sending its request body"), cl.too_slow(secs(50), &config));
+
+        // and a response from when it was first seen waiting to go out
+        cl.pending = Some(1);
+        cl.o_buf = b"HTTP/1.1 200 OK\r\n".to_vec();
+        assert_eq!(None, cl.too_slow(secs(60), &config));
+        assert_eq!(None, cl.too_slow(secs(89), &config));
+        assert_eq!(Some("reading its response"), cl.too_slow(secs(90), &config));
+
+        cl.o_buf.clear();
+        assert_eq!(None, cl.too_slow(secs(1000), &config));
+    }
+
+    #[test]
+    fn test_client_request_length() {
+        let mut cl = client(MockTransport::new());
+
+        cl.i_buf = b"GET / HTTP/1.1\r\nHost: lo".to_vec();
+        assert_eq!(None, cl.request_length());
+
+        cl.i_buf.extend_from_slice(b"calhost\r\n\r\n");
+        assert_eq!(Some(cl.i_buf.len()), cl.request_length());
+
+        cl.i_buf = b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nab".to_vec();
+        assert_eq!(None, cl.request_length());
+
+        cl.i_buf.extend_from_slice(b"cdGET");
+        assert_eq!(Some(cl.i_buf.len() - 3), cl.request_length());
+    }
+
+    #[test]
+    fn test_client_receive_eof_and_reset() {
+        let mut cl = client(MockTransport::new().eof());
+        assert!(!cl.receive().unwrap());
+
+        let mut cl = client(MockTransport::new().incoming(b"GET").reset());
+        assert!(cl.receive().unwrap());
+        assert_eq!(b"GET".to_vec(), cl.i_buf);
+
+        let mut cl = client(MockTransport::new().reset());
+        assert_eq!(ErrorKind::ConnectionReset, cl.receive().unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_client_send_slow_writes() {
+        let mock = MockTransport::new()
+            .write_step(Step::Accept(3))
+            .write_step(Step::Block)
+            .write_step(Step::Accept(2));
+        let out = mock.output();
+        let mut cl = client(mock);
+
+        cl.o_buf = b"hello world".to_vec();
+        cl.events.insert(EventSet::writable());
+
+        assert!(!cl.send(Instant::now()).unwrap());
+        assert_eq!(b"lo world".to_vec(), cl.o_buf);
+
+        assert!(cl.send(Instant::now()).unwrap());
+        assert!(cl.o_buf.is_empty());
+        assert!(!cl.events.is_writable());
+        assert_eq!(b"hello world".to_vec(), *out.lock().unwrap());
+    }
+
+    #[test]
+    fn test_client_send_reset() {
+        let mut cl = client(MockTransport::new().write_step(Step::Fail(ErrorKind::ConnectionReset)));
+
+        cl.o_buf = b"hello".to_vec();
+        assert_eq!(ErrorKind::ConnectionReset, cl.send(Instant::now()).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_client_send_throttled() {
+        let clock = ManualClock::new();
+        let mut cl = client(MockTransport::new());
+
+        cl.o_buf = vec![0; 100];
+        cl.bucket = Some(TokenBucket::new(10, 40, clock.now()));
+
+        assert!(!cl.send(clock.now()).unwrap());
+        assert_eq!(60, cl.o_buf.len());
+        assert_eq!(Some(101), cl.throttled_for(clock.now()));
+
+        // two seconds buys another 20 bytes
+        clock.advance(Duration::from_secs(2));
+        assert!(!cl.send(clock.now()).unwrap());
+        assert_eq!(40, cl.o_buf.len());
+
+        clock.advance(Duration::from_secs(60));
+        assert!(cl.send(clock.now()).unwrap());
+        assert!(cl.o_buf.is_empty());
+    }
+
+    fn hello(_: &Request) -> Response {
+        utils::make_response("hello", "text/plain", 200)
+    }
+
+    #[test]
+    fn test_reload_applies_new_config() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/", &[Method::Get], hello);
+        cnt.on_reload(|cfg| { cfg.set_server_header(ServerHeader::Custom(String::from("reloaded"))); });
+
+        let handle = cnt.reload_handle();
+        let req = || "GET / HTTP/1.1\r\n\r\n".parse::<Request>().unwrap();
+
+        cnt.housekeeping();
+        assert_eq!(Some(ServerHeader::Default.value().unwrap().as_str()), cnt.dispatch(req()).get_header("Server"));
+
+        handle.reload();
+        cnt.housekeeping();
+        assert_eq!(Some("reloaded"), cnt.dispatch(req()).get_header("Server"));
+    }
+
+    #[test]
+    fn test_reload_hosts_and_cache_policy() {
+        let root = std::env::temp_dir().join("canteen-reload-mount");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.txt"), b"a").unwrap();
+
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/", &[Method::Get], hello);
+        cnt.mount_static("/files", &root, StaticOptions::new());
+        cnt.on_reload(|cfg| {
+            cfg.set_allowed_hosts(Some(&["Example.com"]))
+               .set_static_cache_control(Some("public, max-age=60"))
+               .set_max_body(Some(1));
+        });
+
+        let get = |cnt: &mut Canteen, path: &str, host: &str| {
+            cnt.dispatch(format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, host).parse().unwrap())
+        };
+
+        assert_eq!(200, get(&mut cnt, "/", "other.org").get_status());
+        assert_eq!(None, get(&mut cnt, "/files/a.txt", "example.com").get_header("Cache-Control"));
+
+        cnt.reload_handle().reload();
+        cnt.housekeeping();
+
+        assert_eq!(400, get(&mut cnt, "/", "other.org").get_status());
+        assert_eq!(200, get(&mut cnt, "/", "example.com:8080").get_status());
+        assert_eq!(Some("public, max-age=60"), get(&mut cnt, "/files/a.txt", "EXAMPLE.COM").get_header("Cache-Control"));
+        assert_eq!(None, get(&mut cnt, "/", "example.com").get_header("Cache-Control"));
+        assert_eq!(Some(1), cnt.body_limit());
+    }
+
+    #[test]
+    fn test_hsts_only_over_tls() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/", &[Method::Get], hello);
+        Arc::make_mut(&mut cnt.config).set_hsts(Some(Hsts::new(600)));
+
+        let req = || "GET / HTTP/1.1\r\n\r\n".parse::<Request>().unwrap();
+
+        assert_eq!(None, cnt.dispatch(req()).get_header("Strict-Transport-Security"));
+
+        let mut req = req();
+
+        req.secure = true;
+        assert_eq!(Some("max-age=600"), cnt.dispatch(req).get_header("Strict-Transport-Security"));
+    }
+
+    fn slow(_: &Request) -> Response {
+        std::thread::sleep(Duration::from_millis(300));
+        utils::make_response("done", "text/plain", 200)
+    }
+
+    fn fetch(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut conn = std::net::TcpStream::connect(addr).unwrap();
+        let mut out = String::new();
+
+        conn.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).as_bytes()).unwrap();
+        conn.read_to_string(&mut out).unwrap();
+
+        out
+    }
+
+    fn get(cnt: &mut Canteen, path: &str) -> Response {
+        cnt.dispatch(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).parse().unwrap())
+    }
+
+    #[test]
+    fn test_route_max_body() {
+        let mut cnt = Canteen::new();
+
+        cnt.set_max_body(8);
+        cnt.add_route("/small", &[Method::Post], hello);
+        cnt.add_route_with("/big", &[Method::Post], hello, route::RouteOptions::new().max_body(64));
+
+        let req = |path: &str| format!("POST {} HTTP/1.1\r\n\r\n0123456789", path).parse::<Request>().unwrap();
+
+        assert_eq!(413, cnt.dispatch(req("/small")).get_status());
+        assert_eq!(200, cnt.dispatch(req("/big")).get_status());
+    }
+
+    #[test]
+    fn test_oversized_body_not_read() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.set_max_body(8);
+        cnt.add_route("/small", &[Method::Post], hello);
+        cnt.add_route_with("/big", &[Method::Post], hello, route::RouteOptions::new().max_body(64));
+
+        let server = cnt.spawn();
+        let post = |path: &str, len: usize| {
+            let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+            let mut out = String::new();
+
+            // only the head is sent; a body that's too large isn't waited for
+            conn.write_all(format!("POST {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n", path, len).as_bytes()).unwrap();
+            conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let _ = conn.read_to_string(&mut out);
+            out
+        };
+
+        assert!(post("/small", 1 << 30).starts_with("HTTP/1.1 413"));
+        assert!(post("/big", 65).starts_with("HTTP/1.1 413"));
+
+        server.shutdown();
+    }
+
+    #[test]
+    fn test_route_deadlines() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.set_handler_timeout(Duration::from_millis(50));
+        cnt.add_route("/slow", &[Method::Get], slow);
+        cnt.add_route_with("/report", &[Method::Get], slow, route::RouteOptions::new().timeout(Duration::from_secs(5)));
+        cnt.add_route("/", &[Method::Get], hello);
+
+        let stats = cnt.stats_handle();
+        let server = cnt.spawn();
+        let addr = server.addr().unwrap();
+
+        assert!(fetch(addr, "/slow").starts_with("HTTP/1.1 504"));
+        assert!(fetch(addr, "/report").ends_with("done"));
+        assert!(fetch(addr, "/").ends_with("hello"));
+
+        let timeouts = |pattern: &str| stats.snapshot().into_iter().find(|s| s.pattern == pattern).unwrap().timeouts;
+
+        assert_eq!(1, timeouts("/slow"));
+        assert_eq!(0, timeouts("/report"));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_slow_warning() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/user/<int:id>", &[Method::Get], hello);
+
+        let job = cnt.route("GET /user/42 HTTP/1.1\r\n\r\n".parse().unwrap());
+        assert_eq!(None, job.slow_warning(Duration::from_secs(10)));
+
+        cnt.set_slow_threshold(Duration::from_millis(100));
+
+        let job = cnt.route("GET /user/42 HTTP/1.1\r\n\r\n".parse().unwrap());
+        assert_eq!(None, job.slow_warning(Duration::from_millis(100)));
+        assert_eq!(Some(String::from(r#"slow request: GET /user/42 (route /user/<int:id>) took 250ms, params [("id", "42")]"#)),
+                   job.slow_warning(Duration::from_millis(250)));
+    }
+
+    fn boom(_: &Request) -> Response {
+        panic!("boom");
+    }
+
+    #[test]
+    fn test_panicking_handler() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/boom", &[Method::Get], boom);
+
+        let res = cnt.dispatch("GET /boom HTTP/1.1\r\n\r\n".parse().unwrap());
+
+        assert_eq!(500, res.get_status());
+        assert_eq!(1, cnt.pool_health().panics);
+        assert_eq!(255, cnt.pool_health().workers);
+        assert!(!String::from_utf8_lossy(res.get_payload()).contains("Backtrace"));
+
+        cnt.enable_debug();
+
+        let res = cnt.dispatch("GET /boom HTTP/1.1\r\n\r\n".parse().unwrap());
+        let page = String::from_utf8_lossy(res.get_payload()).into_owned();
+
+        assert_eq!(500, res.get_status());
+        assert!(page.contains("<h2>boom</h2>"));
+        assert!(page.contains("<tr><th>Route</th><td>/boom</td></tr>"));
+    }
+
+    #[derive(serde_derive::Deserialize)]
+    #[allow(dead_code)]
+    struct Greeting {
+        message: String,
+    }
+
+    fn greeting(req: &Request) -> Response {
+        match req.path.as_str() {
+            "/greeting/good" => utils::json(&serde_json::json!({ "message": "hi" })),
+            _                => utils::json(&serde_json::json!({ "msg": "hi" })),
+        }
+    }
+
+    #[test]
+    fn test_response_contract() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route_with("/greeting/<which>", Method::Get, greeting, route::RouteOptions::new().response_type::<Greeting>());
+
+        assert_eq!(200, cnt.dispatch("GET /greeting/bad HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+
+        cnt.enable_debug();
+
+        assert_eq!(200, cnt.dispatch("GET /greeting/good HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+
+        let res = cnt.dispatch("GET /greeting/bad HTTP/1.1\r\n\r\n".parse().unwrap());
+        assert_eq!(500, res.get_status());
+        assert!(String::from_utf8_lossy(res.get_payload()).contains("missing field `message`"));
+    }
+
+    #[test]
+    fn test_client_read_growth() {
+        let mut config = Config::default();
+        config.set_read_buffer(4, 16);
+
+        let data: Vec<u8> = (0..40).collect();
+        let mut cl = Client::new(Box::new(MockTransport::new().incoming(&data).block()), Token(LISTENER_SLOTS), 0, &config, Instant::now());
+
+        assert!(cl.receive().unwrap());
+        assert_eq!(data, cl.i_buf);
+        assert_eq!(16, cl.chunk);
+
+        // tiny requests stay in a tiny buffer
+        let mut cl = Client::new(Box::new(MockTransport::new().incoming(b"GET").block()), Token(LISTENER_SLOTS), 0, &config, Instant::now());
+
+        assert!(cl.receive().unwrap());
+        assert_eq!(4, cl.chunk);
+        assert!(cl.i_buf.capacity() < 16);
+    }
+
+    #[test]
+    fn test_bind_dual_stack() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind_dual_stack(0);
+
+        let addrs = cnt.local_addrs();
+
+        assert!(addrs[0].is_ipv4());
+        assert_ne!(0, addrs[0].port());
+
+        if addrs.len() > 1 {
+            assert!(addrs[1].is_ipv6());
+            assert_eq!(addrs[0].port(), addrs[1].port());
+        }
+    }
+
+    #[test]
+    fn test_ephemeral_ports() {
+        let mut one = Canteen::new();
+        let mut two = Canteen::new();
+
+        assert_eq!(None, one.local_addr());
+
+        one.bind(("127.0.0.1", 0));
+        two.bind(("127.0.0.1", 0));
+
+        let addr = one.local_addr().unwrap();
+
+        assert_ne!(0, addr.port());
+        assert_ne!(addr, two.local_addr().unwrap());
+        assert_eq!(vec![addr], one.local_addrs());
+    }
+
+    #[test]
+    fn test_per_ip_limit() {
+        let mut cnt = Canteen::new();
+        let one = Some(IpAddr::from([10, 0, 0, 1]));
+        let two = Some(IpAddr::from([10, 0, 0, 2]));
+
+        cnt.set_max_connections_per_ip(2);
+
+        assert!(cnt.admit(one));
+        assert!(cnt.admit(one));
+        assert!(!cnt.admit(one));
+        assert!(cnt.admit(two));
+        assert!(cnt.admit(None));
+
+        cnt.release(one);
+        assert!(cnt.admit(one));
+
+        cnt.release(two);
+        assert!(cnt.per_ip.contains_key(&IpAddr::from([10, 0, 0, 1])));
+        assert!(!cnt.per_ip.contains_key(&IpAddr::from([10, 0, 0, 2])));
+
+        // with an allow list, a client must be known to be on it
+        cnt.filter_connections(ipfilter::IpFilter::new().deny("10.9.0.0/16"));
+        assert!(cnt.admit(None));
+        cnt.filter_connections(ipfilter::IpFilter::new().allow("10.0.0.0/8"));
+        assert!(!cnt.admit(None));
+    }
+
+    #[test]
+    fn test_log_requests() {
+        let mut cnt = Canteen::new();
+        let lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = lines.clone();
+        let format = accesslog::LogFormat::custom(|e: &accesslog::LogEntry| {
+            format!("{} {} {} {} {}", e.req.method.as_str(), e.req.path, e.status, e.size, e.latency >= Duration::from_millis(300))
+        });
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.log_requests(accesslog::AccessLog::new(move |line: &str| seen.lock().unwrap().push(String::from(line))).format(format));
+        cnt.add_route("/slow", &[Method::Get], slow);
+        cnt.add_route_with("/once", &[Method::Get], hello,
+                           route::RouteOptions::new().limit_requests(ratelimit::RateLimiter::new(1, Duration::from_secs(60))));
+
+        let server = cnt.spawn();
+        let addr = server.addr().unwrap();
+
+        fetch(addr, "/slow");
+        fetch(addr, "/once");
+        fetch(addr, "/once");
+        server.shutdown();
+
+        // requests turned away before a worker are logged too
+        assert_eq!(vec!["GET /slow 200 4 true", "GET /once 200 5 false", "GET /once 429 177 false"],
+                   *lines.lock().unwrap());
+    }
+
+    #[test]
+    fn test_filter_connections() {
+        let mut cnt = Canteen::new();
+
+        cnt.filter_connections(ipfilter::IpFilter::new().deny("10.0.0.2"));
+
+        assert!(cnt.admit(Some(IpAddr::from([10, 0, 0, 1]))));
+        assert!(!cnt.admit(Some(IpAddr::from([10, 0, 0, 2]))));
+        assert!(!cnt.per_ip.contains_key(&IpAddr::from([10, 0, 0, 2])));
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/", &[Method::Get], hello);
+        cnt.set_max_connections(1).set_workers(2);
+        assert_eq!(2, cnt.pool_health().workers);
+
+        let server = cnt.spawn();
+        let mut first = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+
+        first.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert!(read_response(&mut first).ends_with("hello"));
+
+        // the first connection is kept alive, so there's no room for another
+        let mut second = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut rest = Vec::new();
+
+        second.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let _ = second.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(0, second.read_to_end(&mut rest).unwrap_or(0));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    // read one response off a kept-alive connection.
+    fn read_response(conn: &mut std::net::TcpStream) -> String {
+        let mut buf = Vec::new();
+        let mut chunk = [0; 1024];
+
+        loop {
+            let head = String::from_utf8_lossy(&buf).to_string();
+
+            if let Some(end) = head.find("\r\n\r\n") {
+                let len = head.lines()
+                              .find_map(|l| l.strip_prefix("Content-Length: "))
+                              .map(|v| v.trim().parse::<usize>().unwrap())
+                              .unwrap_or(0);
+
+                if buf.len() >= end + 4 + len {
+                    return head;
+                }
+            }
+
+            match conn.read(&mut chunk).unwrap() {
+                0  => return String::from_utf8_lossy(&buf).to_string(),
+                sz => buf.extend_from_slice(&chunk[..sz]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_keep_alive_max_requests() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/", &[Method::Get], hello);
+        cnt.set_keep_alive(KeepAlive::new().max_requests(2));
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut rest = Vec::new();
+
+        conn.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let first = read_response(&mut conn);
+        assert!(first.starts_with("HTTP/1.1 200"));
+        assert!(first.contains("Connection: keep-alive\r\n"));
+        assert!(first.contains("Keep-Alive: timeout=5, max=1\r\n"));
+
+        conn.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let second = read_response(&mut conn);
+        assert!(second.starts_with("HTTP/1.1 200"));
+        assert!(second.contains("Connection: close\r\n"));
+        assert!(!second.contains("Keep-Alive"));
+
+        // and the server hangs up
+        assert_eq!(0, conn.read_to_end(&mut rest).unwrap());
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_keep_alive_idle_timeout() {
+        let clock = ManualClock::new();
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/", &[Method::Get], hello);
+        cnt.set_clock(clock.clone());
+        cnt.set_keep_alive(KeepAlive::new().idle_timeout(Duration::from_secs(10)));
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut rest = Vec::new();
+
+        conn.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert!(read_response(&mut conn).contains("Keep-Alive: timeout=10\r\n"));
+
+        clock.advance(Duration::from_secs(11));
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(0, conn.read_to_end(&mut rest).unwrap());
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_slow_clients_closed() {
+        let clock = ManualClock::new();
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/", &[Method::Get], hello);
+        cnt.set_clock(clock.clone());
+        cnt.set_read_timeouts(Duration::from_secs(5), Duration::from_secs(5));
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut rest = Vec::new();
+
+        // connected, but never sends a thing
+        std::thread::sleep(Duration::from_millis(100));
+        clock.advance(Duration::from_secs(6));
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(0, conn.read_to_end(&mut rest).unwrap());
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_keep_alive_disabled() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/", &[Method::Get], hello);
+        cnt.disable_keep_alive();
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut out = String::new();
+
+        conn.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        conn.read_to_string(&mut out).unwrap();
+        assert!(out.contains("Connection: close\r\n"));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_half_close() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/slow", &[Method::Get], slow);
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut out = String::new();
+
+        // the answer still arrives, and then the connection is closed
+        conn.write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        conn.shutdown(std::net::Shutdown::Write).unwrap();
+        conn.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.ends_with("done"));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_event_loop_hooks() {
+        use std::sync::Mutex;
+        use std::sync::atomic::AtomicUsize;
+
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (reader, mut writer) = mio::unix::pipe().unwrap();
+        let mut cnt = Canteen::new();
+
+        let (t, opened, closed, woken) = (ticks.clone(), events.clone(), events.clone(), events.clone());
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/", &[Method::Get], hello);
+        cnt.on_tick(move || { t.fetch_add(1, Ordering::SeqCst); })
+           .on_connection_open(move |c: &hooks::ConnectionInfo| opened.lock().unwrap().push(format!("open {}", c.peer.is_some())))
+           .on_connection_close(move |c: &hooks::ConnectionInfo| closed.lock().unwrap().push(format!("close {}", c.peer.is_some())))
+           .add_source(reader, EventSet::readable(), move |pipe: &mut mio::unix::PipeReader, _| {
+               let mut buf = [0u8; 16];
+               let n = pipe.read(&mut buf).unwrap_or(0);
+               woken.lock().unwrap().push(format!("wake {}", String::from_utf8_lossy(&buf[..n])));
+           });
+
+        let server = cnt.spawn();
+
+        assert!(fetch(server.addr().unwrap(), "/").ends_with("hello"));
+        writer.write_all(b"ping").unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+
+        assert!(ticks.load(Ordering::SeqCst) > 0);
+        assert_eq!(vec!["open true", "close true", "wake ping"], *events.lock().unwrap());
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    static ABORTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+    fn patient(req: &Request) -> Response {
+        for _ in 0..100 {
+            if req.is_aborted() {
+                ABORTED.store(true, Ordering::SeqCst);
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        utils::make_response("done", "text/plain", 200)
+    }
+
+    #[test]
+    fn test_client_abort() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/patient", &[Method::Get], patient);
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+
+        conn.write_all(b"GET /patient HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        // closing with a zero linger resets the connection
+        socket2::SockRef::from(&conn).set_linger(Some(Duration::ZERO)).unwrap();
+        drop(conn);
+
+        for _ in 0..50 {
+            if ABORTED.load(Ordering::SeqCst) {
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(ABORTED.load(Ordering::SeqCst));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_header_and_uri_limits() {
+        let mut cnt = builder::CanteenBuilder::new()
+            .bind("127.0.0.1:0")
+            .limits(config::Limits::strict().max_header(256).max_uri(32))
+            .build();
+
+        cnt.add_route("/", &[Method::Get], hello);
+
+        let server = cnt.spawn();
+        let addr = server.addr().unwrap();
+        let fetch = |raw: String| {
+            let mut conn = std::net::TcpStream::connect(addr).unwrap();
+            let mut out = String::new();
+
+            conn.write_all(raw.as_bytes()).unwrap();
+            conn.read_to_string(&mut out).unwrap();
+            out
+        };
+
+        let out = fetch(format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", "a".repeat(40)));
+        assert!(out.starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+
+        let out = fetch(format!("GET / HTTP/1.1\r\nHost: localhost\r\nX-Pad: {}\r\n\r\n", "a".repeat(300)));
+        assert!(out.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+
+        let out = fetch(String::from("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"));
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_accept_rate() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind_with(("127.0.0.1", 0), ListenerOptions::new().accept_rate(10, 1));
+        cnt.add_route("/", &[Method::Get], hello);
+
+        let server = cnt.spawn();
+        let addr = server.addr().unwrap();
+        let start = Instant::now();
+        let mut conns: Vec<std::net::TcpStream> = (0..3).map(|_| std::net::TcpStream::connect(addr).unwrap()).collect();
+
+        for conn in &mut conns {
+            let mut out = String::new();
+
+            conn.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+            conn.read_to_string(&mut out).unwrap();
+            assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+
+        // one connection straight away, then one every 100ms
+        assert!(start.elapsed() >= Duration::from_millis(180));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_accept_rate_drop() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind_with(("127.0.0.1", 0), ListenerOptions::new().accept_rate(1, 1).drop_excess_accepts(true));
+        cnt.add_route("/", &[Method::Get], hello);
+
+        let server = cnt.spawn();
+        let addr = server.addr().unwrap();
+        let mut first = std::net::TcpStream::connect(addr).unwrap();
+        let mut second = std::net::TcpStream::connect(addr).unwrap();
+        let mut out = String::new();
+
+        first.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        first.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        out.clear();
+        let _ = second.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        let _ = second.read_to_string(&mut out);
+        assert_eq!("", out);
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_fingerprinted_mount() {
+        let root = std::env::temp_dir().join("canteen-fingerprint-test");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("app.js"), "run();").unwrap();
+
+        let mut cnt = Canteen::new();
+        cnt.mount_static("/fp", &root, StaticOptions::new().fingerprint(true));
+
+        let url = utils::asset_url("app.js").unwrap();
+        assert!(url.starts_with("/fp/app.") && url.ends_with(".js"));
+
+        let res = cnt.dispatch(format!("GET {} HTTP/1.1\r\n\r\n", url).parse().unwrap());
+        assert_eq!(200, res.get_status());
+        assert_eq!(Some("public, max-age=31536000, immutable"), res.get_header("Cache-Control"));
+
+        let res = cnt.dispatch("GET /fp/app.js HTTP/1.1\r\n\r\n".parse().unwrap());
+        assert_eq!(200, res.get_status());
+        assert_eq!(None, res.get_header("Cache-Control"));
+    }
+
+    #[test]
+    fn test_route_method_sets() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/one", Method::Post, hello);
+        cnt.add_route("/all", Method::all(), hello);
+        cnt.add_route("/safe", Method::safe(), hello);
+
+        assert_eq!(200, cnt.dispatch("POST /one HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+        assert_eq!(200, cnt.dispatch("DELETE /all HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+        assert_eq!(404, cnt.dispatch("GET /none HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+
+        // the path matches, but not the method
+        let res = cnt.dispatch("GET /one HTTP/1.1\r\n\r\n".parse().unwrap());
+        assert_eq!(405, res.get_status());
+        assert_eq!(Some("POST"), res.get_header("Allow"));
+
+        let res = cnt.dispatch("PUT /safe HTTP/1.1\r\n\r\n".parse().unwrap());
+        assert_eq!(405, res.get_status());
+        assert_eq!(Some("GET, OPTIONS, PROPFIND"), res.get_header("Allow"));
+        assert_eq!(12, cnt.allowed("/all").len());
+    }
+
+    struct Tagged(&'static str);
+
+    impl Middleware for Tagged {
+        fn after(&self, _: &Request, res: &mut Response) {
+            let tags = res.get_header("X-Tags").map(|t| format!("{},{}", t, self.0)).unwrap_or_else(|| String::from(self.0));
+
+            res.set_header("X-Tags", &tags);
+        }
+    }
+
+    #[test]
+    fn test_route_precedence() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/u/<name>", Method::Get, |req: &Request| utils::text(req.get::<String>("name")));
+        cnt.add_route("/u/<int:id>", Method::Get, |req: &Request| utils::text(format!("#{}", req.get::<i32>("id"))));
+        cnt.add_route("/u/me", Method::Get, |_: &Request| utils::text("me"));
+
+        assert_eq!(b"me", get(&mut cnt, "/u/me").get_payload());
+        assert_eq!(b"#7", get(&mut cnt, "/u/7").get_payload());
+        assert_eq!(b"jo", get(&mut cnt, "/u/jo/").get_payload());
+    }
+
+    #[test]
+    fn test_route_query_string() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/search", Method::Get, |req: &Request| {
+            utils::text(String::from(req.get_query()["q"].as_str().unwrap_or("none")))
+        });
+        cnt.add_route("/users/<name>", Method::Get, |req: &Request| utils::text(req.get::<String>("name")));
+
+        // the second time round, the route comes from the cache
+        for _ in 0..2 {
+            let res = get(&mut cnt, "/search?q=rust");
+            assert_eq!(200, res.get_status());
+            assert_eq!(b"rust", res.get_payload());
+            assert_eq!(b"bob", get(&mut cnt, "/users/bob?x=1").get_payload());
+        }
+    }
+
+    #[test]
+    fn test_route_cache() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/u/<name>", Method::Get, |req: &Request| utils::text(req.get::<String>("name")));
+        assert_eq!(b"me", get(&mut cnt, "/u/me").get_payload());
+
+        // a route added later isn't hidden by what was cached before it
+        cnt.add_route("/u/me", Method::Get, |_: &Request| utils::text("mine"));
+        assert_eq!(b"mine", get(&mut cnt, "/u/me").get_payload());
+
+        cnt.set_route_cache_size(0);
+        assert_eq!(b"mine", get(&mut cnt, "/u/me").get_payload());
+        assert_eq!(b"jo", get(&mut cnt, "/u/jo").get_payload());
+        assert_eq!(b"jo", get(&mut cnt, "/u/jo").get_payload());
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let mut cnt = Canteen::new();
+        let everyone = |_: &Request| Some(String::from("everyone"));
+
+        cnt.limit_requests(ratelimit::RateLimiter::new(3, Duration::from_secs(60)).key_by(everyone));
+        cnt.add_route("/", Method::Get, hello);
+        cnt.add_route_with("/login", Method::Get, hello,
+                           route::RouteOptions::new().limit_requests(ratelimit::RateLimiter::new(1, Duration::from_secs(60)).key_by(everyone)));
+
+        assert_eq!(200, get(&mut cnt, "/login").get_status());
+
+        let res = get(&mut cnt, "/login");
+        assert_eq!(429, res.get_status());
+        assert_eq!(Some("60"), res.get_header("Retry-After"));
+
+        // the server-wide limit counts the first request to /login too
+        assert_eq!(200, get(&mut cnt, "/").get_status());
+        assert_eq!(200, get(&mut cnt, "/").get_status());
+        assert_eq!(Some("20"), get(&mut cnt, "/").get_header("Retry-After"));
+    }
+
+    #[test]
+    fn test_routes() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/b", &[Method::Post, Method::Get][..], utils::err_404);
+        cnt.add_route_with("/a/<int:id>", Method::Delete, utils::err_404, route::RouteOptions::new().name("a"));
+        cnt.add_route("/a/<int:id>", Method::Get, utils::err_404);
+
+        let table: Vec<_> = cnt.routes().collect();
+
+        assert_eq!(vec![("/a/<int:id>", vec![Method::Get, Method::Delete], Some("a")),
+                        ("/b", vec![Method::Get, Method::Post], None)], table);
+    }
+
+    #[test]
+    fn test_trailing_slash() {
+        let build = |slash| {
+            let mut cnt = Canteen::new();
+
+            cnt.set_trailing_slash(slash);
+            cnt.add_route("/dir/", Method::Get, |_: &Request| utils::text("dir"));
+            cnt.add_route("/file", Method::Get, |_: &Request| utils::text("file"));
+            cnt.add_route("/both", Method::Get, |_: &Request| utils::text("bare"));
+            cnt.add_route("/both/", Method::Get, |_: &Request| utils::text("slashed"));
+
+            cnt
+        };
+
+        let mut cnt = build(route::TrailingSlash::Ignore);
+        assert_eq!(200, get(&mut cnt, "/dir").get_status());
+        assert_eq!(200, get(&mut cnt, "/file/").get_status());
+        assert_eq!(b"slashed", get(&mut cnt, "/both/").get_payload());
+        assert_eq!(b"bare", get(&mut cnt, "/both").get_payload());
+
+        let mut cnt = build(route::TrailingSlash::Strict);
+        assert_eq!(404, get(&mut cnt, "/dir").get_status());
+        assert_eq!(404, get(&mut cnt, "/file/").get_status());
+        assert_eq!(200, get(&mut cnt, "/dir/").get_status());
+
+        let mut cnt = build(route::TrailingSlash::Redirect);
+        for _ in 0..2 {
+            let res = get(&mut cnt, "/dir");
+            assert_eq!(301, res.get_status());
+            assert_eq!(Some("http://localhost/dir/"), res.get_header("Location"));
+        }
+        assert_eq!(Some("http://localhost/file"), get(&mut cnt, "/file/").get_header("Location"));
+        assert_eq!(200, get(&mut cnt, "/both/").get_status());
+        assert_eq!(200, get(&mut cnt, "/file").get_status());
+    }
+
+    #[test]
+    fn test_route_groups() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_middleware(Tagged("server"));
+        cnt.add_route("/top", Method::Get, hello);
+
+        {
+            let mut api = cnt.group("/api/");
+
+            api.add_middleware(Tagged("api"))
+               .add_route("/users", Method::Get, hello)
+               .set_default(|_: &Request| utils::make_response("{}", "application/json", 404));
+
+            api.group("/v2")
+               .add_middleware(Tagged("v2"))
+               .add_route("/users", Method::Get, hello);
+        }
+
+        let res = get(&mut cnt, "/api/users");
+        assert_eq!(200, res.get_status());
+        assert_eq!(Some("api,server"), res.get_header("X-Tags"));
+
+        let res = get(&mut cnt, "/api/v2/users");
+        assert_eq!(200, res.get_status());
+        assert_eq!(Some("v2,api,server"), res.get_header("X-Tags"));
+
+        let res = get(&mut cnt, "/top");
+        assert_eq!(Some("server"), res.get_header("X-Tags"));
+
+        // unmatched paths under the group get its default, elsewhere the server's
+        let res = get(&mut cnt, "/api/nope");
+        assert_eq!(404, res.get_status());
+        assert_eq!(b"{}", res.get_payload());
+        assert_eq!(Some("api,server"), res.get_header("X-Tags"));
+        assert_ne!(b"{}", get(&mut cnt, "/apix").get_payload());
+        assert_eq!(405, cnt.dispatch("POST /api/users HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+    }
+
+    #[test]
+    fn test_route_middleware() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_middleware(Tagged("server"));
+        cnt.add_route_with("/one", Method::Get, hello, route::RouteOptions::new().with(Tagged("a")).with(Tagged("b")));
+        cnt.add_route("/two", Method::Get, hello);
+        cnt.group("/api")
+           .add_middleware(Tagged("api"))
+           .add_route_with("/three", Method::Get, hello, route::RouteOptions::new().with(Tagged("c")));
+
+        assert_eq!(Some("b,a,server"), get(&mut cnt, "/one").get_header("X-Tags"));
+        assert_eq!(Some("server"), get(&mut cnt, "/two").get_header("X-Tags"));
+        assert_eq!(Some("c,api,server"), get(&mut cnt, "/api/three").get_header("X-Tags"));
+    }
+
+    #[test]
+    fn test_closure_handlers() {
+        use std::sync::atomic::AtomicUsize;
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/count", &[Method::Get, Method::Post], move |_: &Request| {
+            utils::text(format!("{}", counter.fetch_add(1, Ordering::SeqCst) + 1))
+        });
+        cnt.set_default(|req: &Request| utils::make_response(format!("no {}", req.path), "text/plain", 404));
+
+        assert_eq!(b"1".to_vec(), cnt.dispatch("GET /count HTTP/1.1\r\n\r\n".parse().unwrap()).get_payload());
+        assert_eq!(b"2".to_vec(), cnt.dispatch("POST /count HTTP/1.1\r\n\r\n".parse().unwrap()).get_payload());
+        assert_eq!(b"no /x".to_vec(), cnt.dispatch("GET /x HTTP/1.1\r\n\r\n".parse().unwrap()).get_payload());
+        assert_eq!(2, hits.load(Ordering::SeqCst));
+    }
+
+    struct Counter(std::sync::atomic::AtomicUsize);
+
+    fn visit(req: &Request) -> Response {
+        match req.state::<Counter>() {
+            Some(counter) => utils::text(format!("{}", counter.0.fetch_add(1, Ordering::SeqCst) + 1)),
+            None          => utils::err_500(req),
+        }
+    }
+
+    #[test]
+    fn test_managed_state() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/visit", Method::Get, visit);
+        assert_eq!(500, cnt.dispatch("GET /visit HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+
+        cnt.manage(Counter(std::sync::atomic::AtomicUsize::new(0)));
+        cnt.manage(String::from("unrelated"));
+
+        assert_eq!(b"1".to_vec(), cnt.dispatch("GET /visit HTTP/1.1\r\n\r\n".parse().unwrap()).get_payload());
+        assert_eq!(b"2".to_vec(), cnt.dispatch("GET /visit HTTP/1.1\r\n\r\n".parse().unwrap()).get_payload());
+    }
+
+    fn halve(req: &Request) -> std::result::Result<Response, Error> {
+        let n: u32 = req.get("n");
+
+        match n % 2 {
+            0 => Ok(utils::text(format!("{}", n / 2))),
+            _ => Err(Error::bad_request("odd number")),
+        }
+    }
+
+    fn broken(_: &Request) -> std::result::Result<Response, Error> {
+        std::fs::read("/nonexistent/canteen")?;
+
+        Ok(utils::text("unreachable"))
+    }
+
+    #[test]
+    fn test_result_handlers() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/halve/<uint:n>", &[Method::Get], halve);
+        cnt.add_route("/broken", &[Method::Get], broken);
+
+        let res = cnt.dispatch("GET /halve/8 HTTP/1.1\r\n\r\n".parse().unwrap());
+        assert_eq!(b"4".to_vec(), res.get_payload());
+        assert_eq!(400, cnt.dispatch("GET /halve/7 HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+        assert_eq!(500, cnt.dispatch("GET /broken HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+
+        cnt.set_error_handler(|_: &Request, e: &Error| utils::make_response(e.message(), "text/plain", e.status()));
+
+        let res = cnt.dispatch("GET /halve/7 HTTP/1.1\r\n\r\n".parse().unwrap());
+        assert_eq!(b"odd number".to_vec(), res.get_payload());
+
+        // debug mode shows the error and where it came from instead
+        cnt.enable_debug();
+
+        let res = cnt.dispatch("GET /broken HTTP/1.1\r\n\r\n".parse().unwrap());
+        let page = String::from_utf8_lossy(res.get_payload()).into_owned();
+
+        assert_eq!(500, res.get_status());
+        assert!(page.contains("<tr><th>Route</th><td>/broken</td></tr>"));
+        assert!(page.contains("<h3>Caused by</h3>"));
+    }
+
+    #[test]
+    fn test_webdav_mount() {
+        let root = std::env::temp_dir().join("canteen-webdav-mount");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut cnt = Canteen::new();
+        cnt.mount_static("/ro", &root, StaticOptions::new());
+        cnt.mount_static("/dav", &root, StaticOptions::new().webdav(true));
+
+        let res = cnt.dispatch("PUT /dav/n.txt HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi".parse().unwrap());
+        assert_eq!(201, res.get_status());
+        assert_eq!(207, cnt.dispatch("PROPFIND /dav/ HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+        assert_eq!(200, cnt.dispatch("GET /ro/n.txt HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+        assert_eq!(405, cnt.dispatch("PUT /ro/n.txt HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+        assert!(cnt.allowed("/dav/n.txt").contains(&Method::Mkcol));
+        assert_eq!(vec![Method::Get], cnt.allowed("/ro/n.txt"));
+    }
+
+    fn mapped(_: &Request) -> Response {
+        unsafe { Response::from_mmap(std::env::temp_dir().join("canteen-mmap-serve.bin")) }.unwrap()
+    }
+
+    #[test]
+    fn test_body_in_pieces() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/echo", &[Method::Post], |req: &Request| utils::text(req.payload.clone()));
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut out = String::new();
+
+        // the head and the body arrive separately, and the body in parts
+        conn.write_all(b"POST /echo HTTP/1.1\r\nHost: local").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        conn.write_all(b"host\r\nConnection: close\r\nContent-Length: 11\r\n\r\n").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        conn.write_all(b"hello ").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        conn.write_all(b"world").unwrap();
+        conn.read_to_string(&mut out).unwrap();
+
+        assert!(out.starts_with("HTTP/1.1 200"));
+        assert!(out.ends_with("\r\n\r\nhello world"));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_pipelined_requests() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/echo", &[Method::Post], |req: &Request| utils::text(req.payload.clone()));
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut out = String::new();
+
+        conn.write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\n\r\none\
+                         POST /echo HTTP/1.1\r\nContent-Length: 3\r\n\r\ntwo\
+                         POST /echo HTTP/1.1\r\nConnection: close\r\nContent-Length: 5\r\n\r\nthree").unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        conn.read_to_string(&mut out).unwrap();
+
+        let bodies: Vec<&str> = out.split("HTTP/1.1 200").skip(1).map(|r| &r[r.find("\r\n\r\n").unwrap() + 4..]).collect();
+        assert_eq!(vec!["one", "two", "three"], bodies);
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_unclear_body_length() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/echo", &[Method::Post], |req: &Request| utils::text(req.payload.clone()));
+
+        let server = cnt.spawn();
+        let send = |raw: &[u8]| {
+            let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+            let mut out = String::new();
+
+            conn.write_all(raw).unwrap();
+            conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let _ = conn.read_to_string(&mut out);
+            out
+        };
+
+        assert!(send(b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n").starts_with("HTTP/1.1 400"));
+        assert!(send(b"POST /echo HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 4\r\n\r\nabcd").starts_with("HTTP/1.1 400"));
+        assert!(send(b"POST /echo HTTP/1.1\r\nContent-Length: +4\r\n\r\nabcd").starts_with("HTTP/1.1 400"));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_mmap_response() {
+        let data: Vec<u8> = (0..1 << 20).map(|i: u32| (i % 251) as u8).collect();
+        std::fs::write(std::env::temp_dir().join("canteen-mmap-serve.bin"), &data).unwrap();
+
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/big", Method::Get, mapped);
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut out = Vec::new();
+
+        conn.write_all(b"GET /big HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        conn.read_to_end(&mut out).unwrap();
+
+        let head = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert!(String::from_utf8_lossy(&out[..head]).contains("Content-Length: 1048576\r\n"));
+        assert_eq!(data, &out[head..]);
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    fn streamed(_: &Request) -> Response {
+        Response::stream(|out| {
+            for n in 0..50000 {
+                writeln!(out, "line {}", n)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    // reassemble a chunked body, checking its framing along the way.
+    fn dechunk(mut body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        loop {
+            let eol = body.windows(2).position(|w| w == b"\r\n").unwrap();
+            let len = usize::from_str_radix(std::str::from_utf8(&body[..eol]).unwrap(), 16).unwrap();
+
+            out.extend_from_slice(&body[eol + 2..eol + 2 + len]);
+            assert_eq!(b"\r\n", &body[eol + 2 + len..eol + 4 + len]);
+            body = &body[eol + 4 + len..];
+
+            if len == 0 {
+                assert!(body.is_empty());
+                return out;
+            }
+        }
+    }
+
+    #[test]
+    fn test_streamed_response() {
+        let expected: String = (0..50000).map(|n| format!("line {}\n", n)).collect();
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/export", &[Method::Get], streamed);
+
+        assert_eq!(expected.as_bytes(), cnt.dispatch("GET /export HTTP/1.1\r\n\r\n".parse().unwrap()).get_payload());
+
+        let server = cnt.spawn();
+        let addr = server.addr().unwrap();
+        let mut conn = std::net::TcpStream::connect(addr).unwrap();
+        let mut out = Vec::new();
+
+        conn.write_all(b"GET /export HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        conn.read_to_end(&mut out).unwrap();
+
+        let head = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let text = String::from_utf8_lossy(&out[..head]);
+        assert!(text.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!text.contains("Content-Length"));
+        assert_eq!(expected.as_bytes(), &dechunk(&out[head..])[..]);
+
+        // HTTP/1.0 clients get the whole body, with its length
+        let mut conn = std::net::TcpStream::connect(addr).unwrap();
+        let mut out = Vec::new();
+
+        conn.write_all(b"GET /export HTTP/1.0\r\n\r\n").unwrap();
+        conn.read_to_end(&mut out).unwrap();
+
+        let head = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert!(String::from_utf8_lossy(&out[..head]).contains(&format!("Content-Length: {}\r\n", expected.len())));
+        assert_eq!(expected.as_bytes(), &out[head..]);
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    fn which_route(req: &Request) -> Response {
+        match req.matched_route() {
+            Some(route) => utils::text(format!("{} {:?}", route.template(), route.name())),
+            None        => utils::text("none"),
+        }
+    }
+
+    #[test]
+    fn test_decoded_params() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route("/users/<name>/<path:rest>", Method::Get, |req: &Request| {
+            utils::text(format!("{}|{}|{}", req.get::<String>("name"), req.get::<String>("rest"), req.raw_param("rest").unwrap()))
+        });
+
+        let res = cnt.dispatch("GET /users/John%20Doe/a%2Fb/c%C3%A9 HTTP/1.1\r\n\r\n".parse().unwrap());
+        assert_eq!("John Doe|a/b/cé|a%2Fb/c%C3%A9", String::from_utf8_lossy(res.get_payload()));
+
+        assert_eq!(400, cnt.dispatch("GET /users/John%2/x HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+        assert_eq!(400, cnt.dispatch("GET /users/%FF/x HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+    }
+
+    #[test]
+    fn test_matched_route() {
+        let mut cnt = Canteen::new();
+
+        cnt.add_route_with("/user/<int:id>", &[Method::Get], which_route, route::RouteOptions::new().name("user"));
+        cnt.add_route("/plain", &[Method::Get], which_route);
+
+        // the second request for a path goes through the route cache
+        for _ in 0..2 {
+            let res = cnt.dispatch("GET /user/7 HTTP/1.1\r\n\r\n".parse().unwrap());
+            assert_eq!(b"/user/<int:id> Some(\"user\")", res.get_payload());
+        }
+
+        let res = cnt.dispatch("GET /plain HTTP/1.1\r\n\r\n".parse().unwrap());
+        assert_eq!(b"/plain None", res.get_payload());
+    }
+
+    fn hinted(req: &Request) -> Response {
+        req.send_early_hints(&["</app.css>; rel=preload; as=style"]);
+        thread::sleep(Duration::from_millis(50));
+
+        utils::make_response("<html></html>", "text/html", 200)
+    }
+
+    #[test]
+    fn test_early_hints() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/", &[Method::Get], hinted);
+
+        let server = cnt.spawn();
+        let addr = server.addr().unwrap();
+        let mut conn = std::net::TcpStream::connect(addr).unwrap();
+        let mut out = String::new();
+
+        conn.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        conn.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 103 Early Hints\r\nLink: </app.css>; rel=preload; as=style\r\n\r\nHTTP/1.1 200 OK\r\n"));
+
+        // HTTP/1.0 clients don't understand interim responses
+        let mut conn = std::net::TcpStream::connect(addr).unwrap();
+        let mut out = String::new();
+
+        conn.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        conn.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut cnt = Canteen::new();
+        let req = |path: &str| format!("TRACE {} HTTP/1.1\r\nHost: x\r\nCookie: a=b\r\n\r\n", path).parse::<Request>().unwrap();
+
+        cnt.add_route("/", &[Method::Get, Method::Post], hello);
+
+        let res = cnt.dispatch(req("/"));
+        assert_eq!(405, res.get_status());
+        assert_eq!(Some("GET, POST"), res.get_header("Allow"));
+        assert_eq!(404, cnt.dispatch(req("/nope")).get_status());
+
+        cnt.enable_trace();
+
+        let res = cnt.dispatch(req("/nope"));
+        assert_eq!(200, res.get_status());
+        assert_eq!("message/http", res.get_content_type());
+        assert_eq!(b"TRACE /nope HTTP/1.1\r\nHost: x\r\n\r\n", res.get_payload());
+    }
+
+    fn echo(req: &Request) -> Response {
+        utils::make_response(req.payload.clone(), "application/octet-stream", 200)
+    }
+
+    #[test]
+    fn test_binary_body() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/echo", &[Method::Post], echo);
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let body = [0x89, b'P', b'N', b'G', 0xff, 0x00, 0xfe, b'\r', b'\n'];
+        let mut raw = b"POST /echo HTTP/1.1\r\nConnection: close\r\nContent-Length: 9\r\n\r\n".to_vec();
+        let mut out = Vec::new();
+
+        raw.extend_from_slice(&body);
+        conn.write_all(&raw).unwrap();
+        conn.read_to_end(&mut out).unwrap();
+
+        assert!(out.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(out.ends_with(&body));
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_compressed_body() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+
+        let mut cnt = Canteen::new();
+        let post = |encoding: &str, body: &[u8]| {
+            let mut raw = format!("POST /echo HTTP/1.1\r\nContent-Encoding: {}\r\nContent-Length: {}\r\n\r\n",
+                                  encoding, body.len()).into_bytes();
+
+            raw.extend_from_slice(body);
+            parse::parse_request(&raw).unwrap()
+        };
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+
+        gz.write_all(&[b'a'; 4096]).unwrap();
+        let body = gz.finish().unwrap();
+
+        cnt.add_route("/echo", Method::Post, echo);
+
+        let res = cnt.dispatch(post("gzip", &body));
+        assert_eq!(200, res.get_status());
+        assert_eq!(&[b'a'; 4096][..], res.get_payload());
+
+        assert_eq!(400, cnt.dispatch(post("gzip", b"plain")).get_status());
+        assert_eq!(415, cnt.dispatch(post("br", b"plain")).get_status());
+
+        cnt.set_max_body(1024);
+        assert_eq!(413, cnt.dispatch(post("gzip", &body)).get_status());
+    }
+
+    #[cfg(feature = "h2")]
+    fn frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+        out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        out.extend_from_slice(&[kind, flags]);
+        out.extend_from_slice(&stream.to_be_bytes());
+        out.extend_from_slice(payload);
+    }
+
+    #[cfg(feature = "h2")]
+    #[test]
+    fn test_http2_prior_knowledge() {
+        let mut cnt = Canteen::new();
+
+        cnt.bind(("127.0.0.1", 0));
+        cnt.add_route("/", &[Method::Get], hello);
+        cnt.add_route("/echo", &[Method::Post], |req: &Request| utils::text(format!("{} {}", req.version(), req.payload.len())));
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut out = http2::PREFACE.to_vec();
+
+        frame(&mut out, 0x4, 0, 0, &[]);
+        frame(&mut out, 0x1, 0x5, 1, &hpack::encode(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "localhost")]));
+        frame(&mut out, 0x1, 0x4, 3, &hpack::encode(&[(":method", "POST"), (":scheme", "http"), (":path", "/echo"), (":authority", "localhost")]));
+        frame(&mut out, 0x0, 0x1, 3, &[0; 100]);
+        conn.write_all(&out).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // read frames until both streams have ended, keeping their bodies
+        let mut decoder = hpack::Decoder::new();
+        let mut bodies: HashMap<u32, Vec<u8>> = HashMap::new();
+        let mut ended = 0;
+
+        while ended < 2 {
+            let mut head = [0; 9];
+            conn.read_exact(&mut head).unwrap();
+
+            let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+            let stream = u32::from_be_bytes([head[5], head[6], head[7], head[8]]);
+            let mut payload = vec![0; len];
+            conn.read_exact(&mut payload).unwrap();
+
+            match head[3] {
+                0x0 => bodies.entry(stream).or_default().extend_from_slice(&payload),
+                0x1 => assert!(decoder.decode(&payload).unwrap().contains(&(String::from(":status"), String::from("200")))),
+                _   => {},
+            }
+
+            if head[3] <= 0x1 && head[4] & 0x1 != 0 {
+                ended += 1;
+            }
+        }
+
+        assert_eq!(b"hello".to_vec(), bodies[&1]);
+        assert_eq!(b"HTTP/2.0 100".to_vec(), bodies[&3]);
+
+        server.shutdown();
+        server.join().unwrap();
+    }
+
+    #[cfg(feature = "h2")]
+    #[test]
+    fn test_http2_prior_knowledge_redirect() {
+        let mut cnt = Canteen::new();
+
+        cnt.redirect_https(("127.0.0.1", 0), 8443, None);
+        cnt.add_route("/", &[Method::Get], hello);
+        Arc::make_mut(&mut cnt.config).set_max_uri(Some(32));
+
+        let server = cnt.spawn();
+        let mut conn = std::net::TcpStream::connect(server.addr().unwrap()).unwrap();
+        let mut out = http2::PREFACE.to_vec();
+
+        frame(&mut out, 0x4, 0, 0, &[]);
+        frame(&mut out, 0x1, 0x5, 1, &hpack::encode(&[(":method", "GET"), (":scheme", "http"), (":path", "/?a=1"), (":authority", "example.com")]));
+        frame(&mut out, 0x1, 0x5, 3, &hpack::encode(&[(":method", "GET"), (":scheme", "http"), (":path", "/xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx"), (":authority", "example.com")]));
+        conn.write_all(&out).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // the application is never served, only redirected to over TLS
+        let mut decoder = hpack::Decoder::new();
+        let mut heads: HashMap<u32, Vec<(String, String)>> = HashMap::new();
+
+        while heads.len() < 2 {
+            let mut head = [0; 9];
+            conn.read_exact(&mut head).unwrap();
+
+            let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+            let stream = u32::from_be_bytes([head[5], head[6], head[7], head[8]]);
+            let mut payload = vec![0; len];
+            conn.read_exact(&mut payload).unwrap();
+
+            if head[3] == 0x1 {
+                heads.insert(stream, decoder.decode(&payload).unwrap());
+            }
+        }
+
+        let field = |stream: u32, name: &str| heads[&stream].iter().find(|(n, _)| n == name).map(|(_, v)| v.clone());
+
+        assert_eq!(Some(String::from("301")), field(1, ":status"));
+        assert_eq!(Some(String::from("https://example.com:8443/?a=1")), field(1, "location"));
+        assert_eq!(Some(String::from("414")), field(3, ":status"));
+
+        server.shutdown();
+        server.join().unwrap();
     }
 }
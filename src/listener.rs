@@ -0,0 +1,214 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Per-listener configuration.
+
+use std::io;
+use std::net::{self, SocketAddr};
+use std::sync::Arc;
+use mio::tcp::TcpListener;
+use socket2::{Domain, Socket, Type};
+
+use crate::rewrite::Rewrite;
+use crate::throttle::TokenBucket;
+use crate::tls::TlsCerts;
+
+/// Options that apply to a single listening socket, passed to
+/// `Canteen::bind_with`.
+#[derive(Debug, Clone)]
+pub struct ListenerOptions {
+    pub(crate) rewrites:    Vec<Rewrite>,
+    pub(crate) nodelay:     bool,
+    pub(crate) recv_buffer: Option<usize>,
+    pub(crate) send_buffer: Option<usize>,
+    pub(crate) backlog:     i32,
+    pub(crate) v6_only:     Option<bool>,
+    pub(crate) accepts:     Option<(u64, u64)>,
+    pub(crate) shed:        bool,
+}
+
+impl Default for ListenerOptions {
+    fn default() -> Self {
+        ListenerOptions {
+            rewrites:    Vec::new(),
+            nodelay:     false,
+            recv_buffer: None,
+            send_buffer: None,
+            backlog:     1024,
+            v6_only:     None,
+            accepts:     None,
+            shed:        false,
+        }
+    }
+}
+
+impl ListenerOptions {
+    /// Create a set of listener options with the defaults.
+    pub fn new() -> ListenerOptions {
+        ListenerOptions::default()
+    }
+
+    /// Set `TCP_NODELAY` on accepted connections, turning off Nagle's
+    /// algorithm so small responses aren't held back.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::listener::ListenerOptions;
+    ///
+    /// let opts = ListenerOptions::new().nodelay(true);
+    /// ```
+    pub fn nodelay(mut self, nodelay: bool) -> ListenerOptions {
+        self.nodelay = nodelay;
+
+        self
+    }
+
+    /// Set the socket receive buffer size (`SO_RCVBUF`), in bytes, for the
+    /// listener and the connections it accepts.
+    pub fn recv_buffer(mut self, bytes: usize) -> ListenerOptions {
+        self.recv_buffer = Some(bytes);
+
+        self
+    }
+
+    /// Set the socket send buffer size (`SO_SNDBUF`), in bytes, for the
+    /// listener and the connections it accepts.
+    pub fn send_buffer(mut self, bytes: usize) -> ListenerOptions {
+        self.send_buffer = Some(bytes);
+
+        self
+    }
+
+    /// Set how many connections may wait to be accepted. Defaults to 1024.
+    pub fn backlog(mut self, backlog: i32) -> ListenerOptions {
+        self.backlog = backlog;
+
+        self
+    }
+
+    /// Set `IPV6_V6ONLY` on IPv6 listeners: `true` serves IPv6 only, `false`
+    /// also accepts IPv4 connections as IPv4-mapped addresses. By default
+    /// the operating system's setting is used.
+    pub fn v6_only(mut self, v6_only: bool) -> ListenerOptions {
+        self.v6_only = Some(v6_only);
+
+        self
+    }
+
+    /// Accept at most `per_sec` new connections a second, after an initial
+    /// `burst`. Connections over the rate wait in the backlog until they
+    /// can be accepted, unless `drop_excess_accepts` is set.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::listener::ListenerOptions;
+    ///
+    /// let opts = ListenerOptions::new().accept_rate(200, 50);
+    /// ```
+    pub fn accept_rate(mut self, per_sec: u64, burst: u64) -> ListenerOptions {
+        self.accepts = Some((per_sec, burst));
+
+        self
+    }
+
+    /// Close connections that arrive over the accept rate straight away,
+    /// rather than leaving them in the backlog.
+    pub fn drop_excess_accepts(mut self, drop: bool) -> ListenerOptions {
+        self.shed = drop;
+
+        self
+    }
+
+    /// Add a path rewrite rule, applied before routing. Rules run in the
+    /// order they were added.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::listener::ListenerOptions;
+    /// use canteen::rewrite::Rewrite;
+    ///
+    /// let opts = ListenerOptions::new().rewrite(Rewrite::strip_prefix("/app"));
+    /// ```
+    pub fn rewrite(mut self, rule: Rewrite) -> ListenerOptions {
+        self.rewrites.push(rule);
+
+        self
+    }
+}
+
+// create a listening socket with the given options. buffer sizes are set
+// before listening so accepted connections inherit them.
+pub(crate) fn bind(addr: &SocketAddr, opts: &ListenerOptions) -> io::Result<net::TcpListener> {
+    let sock = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+
+    #[cfg(unix)]
+    sock.set_reuse_address(true)?;
+
+    if let (true, Some(only)) = (addr.is_ipv6(), opts.v6_only) {
+        sock.set_only_v6(only)?;
+    }
+
+    if let Some(bytes) = opts.recv_buffer {
+        sock.set_recv_buffer_size(bytes)?;
+    }
+
+    if let Some(bytes) = opts.send_buffer {
+        sock.set_send_buffer_size(bytes)?;
+    }
+
+    sock.bind(&(*addr).into())?;
+    sock.listen(opts.backlog)?;
+
+    Ok(sock.into())
+}
+
+// what a listening socket does with the requests it receives.
+#[derive(Clone, Copy)]
+pub(crate) enum Mode {
+    Serve,
+    RedirectHttps { port: u16 },
+}
+
+pub(crate) struct Listener {
+    pub sock: TcpListener,
+    pub mode: Mode,
+    pub opts: ListenerOptions,
+    pub tls:  Option<Arc<TlsCerts>>,
+    pub rate: Option<TokenBucket>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use socket2::SockRef;
+
+    #[test]
+    fn test_bind_socket_options() {
+        let opts = ListenerOptions::new().recv_buffer(64 * 1024).send_buffer(32 * 1024).backlog(16);
+        let sock = bind(&"127.0.0.1:0".parse().unwrap(), &opts).unwrap();
+        let sref = SockRef::from(&sock);
+
+        // the kernel may round the sizes up
+        assert!(sref.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert!(sref.send_buffer_size().unwrap() >= 32 * 1024);
+        assert_ne!(0, sock.local_addr().unwrap().port());
+    }
+
+    #[test]
+    fn test_bind_v6_only() {
+        // not every machine has IPv6
+        let sock = match bind(&"[::1]:0".parse().unwrap(), &ListenerOptions::new().v6_only(true)) {
+            Ok(sock) => sock,
+            Err(_)   => return,
+        };
+
+        assert!(SockRef::from(&sock).only_v6().unwrap());
+    }
+}
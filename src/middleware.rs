@@ -0,0 +1,155 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Hooks that run around every handler.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::request::Request;
+use crate::response::Response;
+
+/// A middleware wraps request handling. Both hooks are optional.
+///
+/// `before` hooks run in the order the middleware were added. If one returns
+/// a response, the handler and any remaining `before` hooks are skipped.
+/// `after` hooks always run, in reverse order, on whichever response was
+/// produced.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Canteen, Request, Response};
+/// use canteen::middleware::Middleware;
+///
+/// struct PoweredBy;
+///
+/// impl Middleware for PoweredBy {
+///     fn after(&self, _: &Request, res: &mut Response) {
+///         res.set_header("X-Powered-By", "canteen");
+///     }
+/// }
+///
+/// let mut cnt = Canteen::new();
+/// cnt.add_middleware(PoweredBy);
+/// ```
+pub trait Middleware: Send + Sync {
+    /// Called before the handler, and may short-circuit it by returning a
+    /// response.
+    fn before(&self, _req: &mut Request) -> Option<Response> {
+        None
+    }
+
+    /// Called after the handler with the response it produced.
+    fn after(&self, _req: &Request, _res: &mut Response) {}
+}
+
+// middleware that run for only some routes, inside the server-wide chain.
+#[derive(Clone, Default)]
+pub(crate) struct Chain(pub(crate) Vec<Arc<dyn Middleware>>);
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Chain({})", self.0.len())
+    }
+}
+
+impl Chain {
+    // the server-wide chain `outer`, followed by these.
+    pub(crate) fn inside(&self, outer: &Arc<Vec<Arc<dyn Middleware>>>) -> Arc<Vec<Arc<dyn Middleware>>> {
+        match self.0.is_empty() {
+            true  => outer.clone(),
+            false => Arc::new(outer.iter().chain(&self.0).cloned().collect()),
+        }
+    }
+}
+
+/// Run `handler` wrapped in a chain of middleware.
+pub fn run<F>(chain: &[Arc<dyn Middleware>], req: &mut Request, handler: F) -> Response
+        where F: FnOnce(&Request) -> Response {
+    let mut ran = 0;
+    let mut early = None;
+
+    for mw in chain {
+        ran += 1;
+        early = mw.before(req);
+
+        if early.is_some() {
+            break;
+        }
+    }
+
+    let mut res = match early {
+        Some(res) => res,
+        None      => handler(req),
+    };
+
+    for mw in chain[..ran].iter().rev() {
+        mw.after(req, &mut res);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::utils;
+
+    struct Tracer {
+        name: &'static str,
+        log:  Arc<Mutex<Vec<String>>>,
+        stop: bool,
+    }
+
+    impl Middleware for Tracer {
+        fn before(&self, _: &mut Request) -> Option<Response> {
+            self.log.lock().unwrap().push(format!("before {}", self.name));
+
+            match self.stop {
+                true  => Some(utils::make_response("stopped", "text/plain", 403)),
+                false => None,
+            }
+        }
+
+        fn after(&self, _: &Request, _: &mut Response) {
+            self.log.lock().unwrap().push(format!("after {}", self.name));
+        }
+    }
+
+    fn tracer(name: &'static str, log: &Arc<Mutex<Vec<String>>>, stop: bool) -> Arc<dyn Middleware> {
+        Arc::new(Tracer { name, log: log.clone(), stop })
+    }
+
+    #[test]
+    fn test_middleware_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let chain = vec![tracer("a", &log, false), tracer("b", &log, false)];
+        let mut req = Request::new();
+
+        let res = run(&chain, &mut req, |_| {
+            log.lock().unwrap().push(String::from("handler"));
+            utils::make_response("ok", "text/plain", 200)
+        });
+
+        assert_eq!(200, res.get_status());
+        assert_eq!(vec!["before a", "before b", "handler", "after b", "after a"], *log.lock().unwrap());
+    }
+
+    #[test]
+    fn test_middleware_short_circuit() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let chain = vec![tracer("a", &log, true), tracer("b", &log, false)];
+        let mut req = Request::new();
+
+        let res = run(&chain, &mut req, |_| panic!("handler should not run"));
+
+        assert_eq!(403, res.get_status());
+        assert_eq!(vec!["before a", "after a"], *log.lock().unwrap());
+    }
+}
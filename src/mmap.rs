@@ -0,0 +1,152 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Read-only memory-mapped files, used by `Response::from_mmap` to send
+//! large files without copying them onto the heap. Where `mmap` isn't
+//! available the file is read into memory instead.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+/// A file mapped into memory, unmapped when dropped.
+pub struct MappedFile {
+    #[cfg(unix)]
+    ptr:  *mut libc::c_void,
+    #[cfg(not(unix))]
+    data: Vec<u8>,
+    len:  usize,
+}
+
+// the mapping is only ever read, so threads sharing it don't race with each
+// other. the file under it is another matter, which is why `open` is unsafe.
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Map the file at `path`.
+    ///
+    /// # Safety
+    ///
+    /// The mapping reads the file as it is on disk, not as it was when
+    /// mapped. The caller must make sure nothing else writes to or
+    /// truncates the file while the `MappedFile` is alive: a write changes
+    /// bytes behind a `&[u8]` that is meant to be immutable, and reading
+    /// past the end of a truncated file kills the process with `SIGBUS`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use canteen::mmap::MappedFile;
+    ///
+    /// // nothing modifies the videos while the server runs
+    /// let map = unsafe { MappedFile::open("/srv/www/video.mp4") }.unwrap();
+    ///
+    /// println!("{} bytes", map.len());
+    /// ```
+    #[cfg(unix)]
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedFile> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+
+        if len == 0 {
+            // there's nothing to map, and mmap refuses empty mappings
+            return Ok(MappedFile { ptr: std::ptr::null_mut(), len });
+        }
+
+        let ptr = libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0);
+
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(MappedFile { ptr, len })
+    }
+
+    /// Read the file at `path` into memory.
+    ///
+    /// # Safety
+    ///
+    /// Reading the file is always safe; this is `unsafe` to match the
+    /// mapped version.
+    #[cfg(not(unix))]
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedFile> {
+        let data = std::fs::read(path)?;
+
+        Ok(MappedFile { len: data.len(), data })
+    }
+
+    /// The size of the file, in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        match self.len {
+            0 => &[],
+            _ => unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) },
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            if self.len > 0 {
+                unsafe { libc::munmap(self.ptr, self.len); }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for MappedFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MappedFile({} bytes)", self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapped_file() {
+        let dir = std::env::temp_dir().join("canteen-mmap-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("data.bin"), b"\x00\x01mapped").unwrap();
+        std::fs::write(dir.join("empty.bin"), b"").unwrap();
+
+        let map = unsafe { MappedFile::open(dir.join("data.bin")) }.unwrap();
+        assert_eq!(b"\x00\x01mapped", &map[..]);
+
+        let empty = unsafe { MappedFile::open(dir.join("empty.bin")) }.unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(b"", &empty[..]);
+
+        assert!(unsafe { MappedFile::open(dir.join("missing.bin")) }.is_err());
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Pure entry points into request parsing and route matching.
+//!
+//! These do no I/O and never panic on bad input, which makes them suitable
+//! targets for fuzzing, e.g. with `cargo fuzz` (see the `fuzz/` directory).
+
+use std::collections::HashMap;
+
+use crate::request::{Method, Request, RequestError};
+use crate::route::Route;
+use crate::utils;
+
+// find the first occurrence of `needle` in `hay`.
+fn find(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    hay.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Parse a raw HTTP request. The request line and headers must be valid
+/// UTF-8; the body is kept as raw bytes.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::Method;
+/// use canteen::parse::parse_request;
+///
+/// let req = parse_request(b"POST /upload HTTP/1.1\r\nHost: x\r\n\r\n\xff\x00").unwrap();
+///
+/// assert_eq!(Method::Post, req.method);
+/// assert_eq!(vec![0xff, 0x00], req.payload);
+/// ```
+pub fn parse_request(bytes: &[u8]) -> Result<Request, RequestError> {
+    let (head, body) = match find(bytes, b"\r\n\r\n") {
+        Some(i) => (&bytes[..i], &bytes[i + 4..]),
+        None    => (bytes, &bytes[bytes.len()..]),
+    };
+
+    let head = std::str::from_utf8(head)
+        .map_err(|_| RequestError::ParseError(String::from("request head is not valid UTF-8")))?;
+    let mut lines = head.split("\r\n");
+    let mut req = Request::new();
+
+    let line = lines.next().unwrap_or("");
+    let mut ask = line.split(' ').filter(|s| !s.is_empty());
+
+    req.method = match ask.next() {
+        Some(m) => Method::from(m),
+        None    => return Err(RequestError::ParseError(String::from("empty request line"))),
+    };
+
+    req.path = match ask.next() {
+        Some(p) => String::from(p),
+        None    => return Err(RequestError::ParseError(format!("no request target in {:?}", line))),
+    };
+
+    if let Some(v) = ask.next() {
+        req.version = String::from(v);
+    }
+
+    for line in lines {
+        if let Some(i) = line.find(':') {
+            let (key, value) = line.split_at(i);
+
+            if !key.is_empty() {
+                req.headers.push((String::from(key), String::from(value[1..].trim())));
+            }
+        }
+    }
+
+    req.payload.extend_from_slice(body);
+
+    Ok(req)
+}
+
+/// The length of a request's head, up to and including the blank line that
+/// ends it, or `None` if it hasn't all arrived.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::parse::head_length;
+///
+/// assert_eq!(Some(18), head_length(b"GET / HTTP/1.1\r\n\r\nbody"));
+/// assert_eq!(None, head_length(b"GET / HTTP/1.1\r\nHost:"));
+/// ```
+pub fn head_length(bytes: &[u8]) -> Option<usize> {
+    find(bytes, b"\r\n\r\n").map(|i| i + 4)
+}
+
+/// The body length a request's head gives in its `Content-Length` header,
+/// or `None` if it has no body. A head whose length is unclear, because it
+/// gives more than one `Content-Length`, one that isn't a number, or any
+/// `Transfer-Encoding`, none of which are supported, is an error: guessing
+/// could read part of one request as the start of another.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::parse::content_length;
+///
+/// assert_eq!(Some(42), content_length(b"POST / HTTP/1.1\r\ncontent-length: 42\r\n\r\n").unwrap());
+/// assert_eq!(None, content_length(b"GET / HTTP/1.1\r\n\r\n").unwrap());
+/// assert!(content_length(b"POST / HTTP/1.1\r\nContent-Length: lots\r\n\r\n").is_err());
+/// assert!(content_length(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n").is_err());
+/// ```
+pub fn content_length(head: &[u8]) -> Result<Option<usize>, RequestError> {
+    let bad = |why: &str| Err(RequestError::ParseError(String::from(why)));
+    let mut length = None;
+
+    for line in head.split(|&b| b == b'\n').skip(1) {
+        let colon = match line.iter().position(|&b| b == b':') {
+            Some(colon) => colon,
+            None        => continue,
+        };
+        let (name, value) = line.split_at(colon);
+        let value = value[1..].trim_ascii();
+
+        if name.eq_ignore_ascii_case(b"transfer-encoding") {
+            return bad("Transfer-Encoding is not supported");
+        }
+
+        if name.eq_ignore_ascii_case(b"content-length") {
+            if length.is_some() {
+                return bad("more than one Content-Length");
+            }
+
+            if value.is_empty() || !value.iter().all(u8::is_ascii_digit) {
+                return bad("Content-Length is not a number");
+            }
+
+            length = match std::str::from_utf8(value).ok().and_then(|v| v.parse().ok()) {
+                Some(len) => Some(len),
+                None      => return bad("Content-Length is too large"),
+            };
+        }
+    }
+
+    Ok(length)
+}
+
+/// Match a path against a route pattern such as `/foo/<int:id>`, returning
+/// the extracted parameters if it matches.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::parse::match_route;
+///
+/// let params = match_route("/foo/<int:id>", b"/foo/42").unwrap();
+/// assert_eq!("42", params["id"]);
+///
+/// assert!(match_route("/foo/<int:id>", b"/foo/bar").is_none());
+/// ```
+pub fn match_route(pattern: &str, path: &[u8]) -> Option<HashMap<String, String>> {
+    let path = std::str::from_utf8(path).ok()?;
+    let route = Route::new(pattern, Method::Get, utils::err_404);
+    let mut req = Request::new();
+
+    req.method = Method::Get;
+    req.path = String::from(path);
+
+    match route.is_match(&req) {
+        true  => Some(route.parse(path)),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_request() {
+        let req = parse_request(b"GET /foo?a=1 HTTP/1.1\r\nHost: example.com\r\nX-Empty:\r\nX-Tight:val\r\n\r\n").unwrap();
+
+        assert_eq!(Method::Get, req.method);
+        assert_eq!("/foo?a=1", req.path);
+        assert_eq!(Some(String::from("example.com")), req.get_header("Host"));
+        assert_eq!(Some(String::from("")), req.get_header("X-Empty"));
+        assert_eq!(Some(String::from("val")), req.get_header("X-Tight"));
+        assert!(req.payload.is_empty());
+    }
+
+    #[test]
+    fn test_parse_request_errors() {
+        assert!(parse_request(b"").is_err());
+        assert!(parse_request(b"GET\r\n\r\n").is_err());
+        assert!(parse_request(b"GET /\xff HTTP/1.1\r\n\r\n").is_err());
+        assert!(parse_request(b"GET / HTTP/1.1").is_ok());
+    }
+
+    #[test]
+    fn test_match_route_escapes_literals() {
+        assert!(match_route("/a.b", b"/a.b").is_some());
+        assert!(match_route("/a.b", b"/aXb").is_none());
+        assert!(match_route("/a(b", b"/a(b").is_some());
+        assert!(match_route("/a", b"/\xff").is_none());
+    }
+
+    #[test]
+    fn test_corpus_does_not_panic() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/parse_request");
+        let mut seen = 0;
+
+        for entry in fs::read_dir(dir).unwrap() {
+            let data = fs::read(entry.unwrap().path()).unwrap();
+
+            let _ = parse_request(&data);
+            let _ = match_route("/api/<int:id>/<path:rest>", &data);
+            seen += 1;
+        }
+
+        assert!(seen > 0);
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Dropping root privileges once the listening sockets have been bound.
+
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Result};
+
+/// Switch the process to `user`, and to `group` if given (otherwise the
+/// user's primary group). Supplementary groups are reset to those `user` is
+/// a member of. This affects every thread in the process.
+///
+/// Once the switch is made, regaining root is attempted as a sanity check
+/// and treated as an error if it succeeds.
+pub fn drop_to(user: &str, group: Option<&str>) -> Result<()> {
+    let c_user = CString::new(user).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+
+    // these lookups aren't reentrant, but this runs before any handler does
+    let pw = unsafe { libc::getpwnam(c_user.as_ptr()) };
+
+    if pw.is_null() {
+        return Err(Error::new(ErrorKind::NotFound, format!("no such user: {}", user)));
+    }
+
+    let (uid, mut gid) = unsafe { ((*pw).pw_uid, (*pw).pw_gid) };
+
+    if let Some(name) = group {
+        let c_group = CString::new(name).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let gr = unsafe { libc::getgrnam(c_group.as_ptr()) };
+
+        if gr.is_null() {
+            return Err(Error::new(ErrorKind::NotFound, format!("no such group: {}", name)));
+        }
+
+        gid = unsafe { (*gr).gr_gid };
+    }
+
+    // groups first: once we're no longer root we can't change them
+    unsafe {
+        if libc::initgroups(c_user.as_ptr(), gid as _) != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        if libc::setgid(gid) != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        if libc::setuid(uid) != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        if uid != 0 && libc::setuid(0) == 0 {
+            return Err(Error::new(ErrorKind::PermissionDenied, "root privileges could be regained"));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_to_unknown_user() {
+        let err = drop_to("canteen-no-such-user", None).unwrap_err();
+
+        assert_eq!(ErrorKind::NotFound, err.kind());
+    }
+
+    #[test]
+    fn test_drop_to_bad_name() {
+        let err = drop_to("bad\0user", None).unwrap_err();
+
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+    }
+}
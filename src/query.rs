@@ -0,0 +1,435 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Query string and form parsing, including the PHP/Rails style of nesting:
+//! `tags[]=a&tags[]=b` makes a list, and `user[name]=x` a map.
+
+use std::collections::BTreeMap;
+
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::de::value::{Error, MapDeserializer, SeqDeserializer};
+use serde::forward_to_deserialize_any;
+
+/// A value from a query string or form.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryValue {
+    /// A plain value, e.g. `page=2`.
+    String(String),
+    /// The values of a key ending in `[]`, in order.
+    List(Vec<QueryValue>),
+    /// The values of keys like `user[name]`, by the name in brackets.
+    Map(BTreeMap<String, QueryValue>),
+}
+
+impl QueryValue {
+    /// The value, if it's a plain one.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            QueryValue::String(ref s) => Some(s),
+            _                         => None,
+        }
+    }
+
+    /// The values, if this is a list.
+    pub fn as_list(&self) -> Option<&[QueryValue]> {
+        match *self {
+            QueryValue::List(ref l) => Some(l),
+            _                       => None,
+        }
+    }
+
+    /// The entries, if this is a map.
+    pub fn as_map(&self) -> Option<&BTreeMap<String, QueryValue>> {
+        match *self {
+            QueryValue::Map(ref m) => Some(m),
+            _                      => None,
+        }
+    }
+
+    /// Look up an entry, if this is a map.
+    pub fn get(&self, key: &str) -> Option<&QueryValue> {
+        self.as_map().and_then(|m| m.get(key))
+    }
+}
+
+// decode `%XX` escapes and `+` as a space.
+fn decode(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+'                        => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = |b: u8| (b as char).to_digit(16);
+
+                match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                    (Some(hi), Some(lo)) => { out.push((hi * 16 + lo) as u8); i += 2; },
+                    _                    => out.push(b'%'),
+                }
+            },
+            b                           => out.push(b),
+        }
+
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// how deeply keys may nest; brackets past this are kept in the last key.
+const MAX_DEPTH: usize = 32;
+
+// split `user[name][]` into `["user", "name", ""]`.
+fn split_key(key: &str) -> Vec<String> {
+    let (base, mut rest) = match key.find('[') {
+        Some(i) if i > 0 && key.ends_with(']') => (&key[..i], &key[i..]),
+        _                                      => return vec![String::from(key)],
+    };
+    let mut keys = vec![String::from(base)];
+
+    while let Some(stripped) = rest.strip_prefix('[') {
+        if keys.len() == MAX_DEPTH {
+            keys.push(String::from(rest));
+            break;
+        }
+
+        match stripped.find(']') {
+            Some(end) => {
+                keys.push(String::from(&stripped[..end]));
+                rest = &stripped[end + 1..];
+            },
+            None      => break,
+        }
+    }
+
+    keys
+}
+
+fn insert(mut map: &mut BTreeMap<String, QueryValue>, mut keys: &[String], value: String) {
+    loop {
+        if keys.len() == 1 {
+            // a repeated plain key replaces the earlier value
+            map.insert(keys[0].clone(), QueryValue::String(value));
+            return;
+        }
+
+        let slot = map.entry(keys[0].clone()).or_insert_with(|| QueryValue::String(String::new()));
+
+        if keys[1].is_empty() {
+            if slot.as_list().is_none() {
+                *slot = QueryValue::List(Vec::new());
+            }
+
+            let list = match *slot {
+                QueryValue::List(ref mut list) => list,
+                _                              => return,
+            };
+
+            if keys.len() == 2 {
+                list.push(QueryValue::String(value));
+                return;
+            }
+
+            // `items[][name]=a&items[][price]=1` builds a list of maps, with
+            // a new map started whenever a key repeats
+            let fresh = match list.last() {
+                Some(QueryValue::Map(m)) => m.contains_key(&keys[2]),
+                _                        => true,
+            };
+
+            if fresh {
+                list.push(QueryValue::Map(BTreeMap::new()));
+            }
+
+            map = match list.last_mut() {
+                Some(QueryValue::Map(m)) => m,
+                _                        => return,
+            };
+            keys = &keys[2..];
+        } else {
+            if slot.as_map().is_none() {
+                *slot = QueryValue::Map(BTreeMap::new());
+            }
+
+            map = match *slot {
+                QueryValue::Map(ref mut m) => m,
+                _                          => return,
+            };
+            keys = &keys[1..];
+        }
+    }
+}
+
+/// Parse a query string, or an `application/x-www-form-urlencoded` body.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::query::{parse_query, QueryValue};
+///
+/// let q = parse_query("page=2&tags[]=a&tags[]=b&user[name]=Jo+Bloggs");
+///
+/// assert_eq!(Some("2"), q["page"].as_str());
+/// assert_eq!(2, q["tags"].as_list().unwrap().len());
+/// assert_eq!(Some("Jo Bloggs"), q["user"].get("name").and_then(QueryValue::as_str));
+/// ```
+pub fn parse_query(qs: &str) -> BTreeMap<String, QueryValue> {
+    let mut map = BTreeMap::new();
+
+    for pair in qs.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = match pair.find('=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None    => (pair, ""),
+        };
+        let keys = split_key(&decode(key));
+
+        if !keys[0].is_empty() {
+            insert(&mut map, &keys, decode(value));
+        }
+    }
+
+    map
+}
+
+/// Parse a query string, or an `application/x-www-form-urlencoded` body,
+/// into every value given for each key, in order. Keys are only decoded,
+/// not split into nested ones.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::query::parse_multi;
+///
+/// let q = parse_multi("tag=a&tag=b&q=red+shoes");
+///
+/// assert_eq!(vec!["a", "b"], q["tag"]);
+/// assert_eq!(vec!["red shoes"], q["q"]);
+/// ```
+pub fn parse_multi(qs: &str) -> BTreeMap<String, Vec<String>> {
+    let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for pair in qs.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let key = decode(key);
+
+        if !key.is_empty() {
+            map.entry(key).or_default().push(decode(value));
+        }
+    }
+
+    map
+}
+
+/// Parse a query string into any type that can be deserialized. Plain
+/// values are parsed as numbers or booleans where the type asks for one.
+///
+/// # Examples
+///
+/// ```rust
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate canteen;
+///
+/// use canteen::query::from_query;
+///
+/// #[derive(Deserialize)]
+/// struct Search {
+///     page: u32,
+///     tags: Vec<String>,
+/// }
+///
+/// # fn main() {
+/// let search: Search = from_query("page=2&tags[]=a&tags[]=b").unwrap();
+///
+/// assert_eq!(2, search.page);
+/// assert_eq!(vec!["a", "b"], search.tags);
+/// # }
+/// ```
+pub fn from_query<T: DeserializeOwned>(qs: &str) -> Result<T, Error> {
+    T::deserialize(QueryDeserializer(&QueryValue::Map(parse_query(qs))))
+}
+
+/// Deserializes a parsed query into other types, parsing plain values as
+/// numbers or booleans where needed. Made with `QueryValue`'s
+/// `IntoDeserializer` implementation.
+pub struct QueryDeserializer<'a>(&'a QueryValue);
+
+impl<'de, 'a> IntoDeserializer<'de, Error> for &'a QueryValue {
+    type Deserializer = QueryDeserializer<'a>;
+
+    fn into_deserializer(self) -> QueryDeserializer<'a> {
+        QueryDeserializer(self)
+    }
+}
+
+// plain values are strings, so parse them when a number or bool is wanted.
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                match *self.0 {
+                    QueryValue::String(ref s) => match s.parse() {
+                        Ok(v)  => visitor.$visit(v),
+                        Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(s), &visitor)),
+                    },
+                    _                         => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for QueryDeserializer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match *self.0 {
+            QueryValue::String(ref s) => visitor.visit_str(s),
+            QueryValue::List(ref l)   => visitor.visit_seq(SeqDeserializer::new(l.iter())),
+            QueryValue::Map(ref m)    => visitor.visit_map(MapDeserializer::new(m.iter().map(|(k, v)| (k.as_str(), v)))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8   => visit_i8,
+        deserialize_i16  => visit_i16,
+        deserialize_i32  => visit_i32,
+        deserialize_i64  => visit_i64,
+        deserialize_u8   => visit_u8,
+        deserialize_u16  => visit_u16,
+        deserialize_u32  => visit_u32,
+        deserialize_u64  => visit_u64,
+        deserialize_f32  => visit_f32,
+        deserialize_f64  => visit_f64
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match *self.0 {
+            // a single `tag=a` can stand in for a list of one
+            QueryValue::String(_) => visitor.visit_seq(SeqDeserializer::new(std::iter::once(self.0))),
+            _                     => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _: &'static str, _: &'static [&'static str],
+                                         visitor: V) -> Result<V::Value, Error> {
+        match *self.0 {
+            QueryValue::String(ref s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            _                         => self.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct tuple tuple_struct
+        map struct identifier ignored_any i128 u128
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(v: &str) -> QueryValue {
+        QueryValue::String(String::from(v))
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!("a b/c", decode("a+b%2Fc"));
+        assert_eq!("100%", decode("100%"));
+        assert_eq!("%zz", decode("%zz"));
+        assert_eq!("%é", decode("%é"));
+    }
+
+    #[test]
+    fn test_split_key() {
+        assert_eq!(vec!["a"], split_key("a"));
+        assert_eq!(vec!["a", ""], split_key("a[]"));
+        assert_eq!(vec!["user", "name", "first"], split_key("user[name][first]"));
+        assert_eq!(vec!["[x]"], split_key("[x]"));
+
+        let deep = format!("a{}", "[a]".repeat(30_000));
+        let keys = split_key(&deep);
+        assert_eq!(MAX_DEPTH + 1, keys.len());
+        assert!(keys[MAX_DEPTH].starts_with("[a][a]"));
+
+        // and parsing it doesn't run out of stack
+        let q = parse_query(&format!("{}=1", deep));
+        assert!(q["a"].get("a").is_some());
+    }
+
+    #[test]
+    fn test_parse_nested() {
+        let q = parse_query("a[]=1&a[]=2&user[name]=x&user[roles][]=admin&page=1&page=2&flag");
+
+        assert_eq!(QueryValue::List(vec![s("1"), s("2")]), q["a"]);
+        assert_eq!(Some(&s("x")), q["user"].get("name"));
+        assert_eq!(Some(&QueryValue::List(vec![s("admin")])), q["user"].get("roles"));
+        assert_eq!(s("2"), q["page"]);
+        assert_eq!(s(""), q["flag"]);
+    }
+
+    #[test]
+    fn test_parse_list_of_maps() {
+        let q = parse_query("items[][name]=a&items[][qty]=1&items[][name]=b");
+        let items = q["items"].as_list().unwrap();
+
+        assert_eq!(2, items.len());
+        assert_eq!(Some(&s("1")), items[0].get("qty"));
+        assert_eq!(Some(&s("b")), items[1].get("name"));
+        assert_eq!(None, items[1].get("qty"));
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct User {
+        name:  String,
+        admin: bool,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Search {
+        page:  u32,
+        ids:   Vec<i64>,
+        user:  User,
+        limit: Option<u8>,
+    }
+
+    #[test]
+    fn test_parse_multi() {
+        let q = parse_multi("a=1&&b&a=%32&=x&c[]=3");
+
+        assert_eq!(3, q.len());
+        assert_eq!(vec!["1", "2"], q["a"]);
+        assert_eq!(vec![""], q["b"]);
+        assert_eq!(vec!["3"], q["c[]"]);
+    }
+
+    #[test]
+    fn test_from_query() {
+        let found: Search = from_query("page=3&ids[]=1&ids[]=-2&user[name]=jo&user[admin]=true").unwrap();
+
+        assert_eq!(Search {
+            page:  3,
+            ids:   vec![1, -2],
+            user:  User { name: String::from("jo"), admin: true },
+            limit: None,
+        }, found);
+
+        assert!(from_query::<Search>("page=x&ids[]=1&user[name]=jo&user[admin]=true").is_err());
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! `Range` requests: answering with parts of a body, either as a single
+//! `206 Partial Content` response or, for several ranges, as
+//! `multipart/byteranges`.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::request::{Method, Request};
+use crate::response::Response;
+
+// more ranges than this in one request are ignored, and the whole body sent.
+const MAX_RANGES: usize = 32;
+
+/// What a `Range` header asks for from a body of a given length.
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+    /// No usable `Range` header: send the whole body.
+    Full,
+    /// The inclusive byte ranges to send, in order, with overlaps merged.
+    Partial(Vec<(u64, u64)>),
+    /// None of the ranges fall within the body.
+    Unsatisfiable,
+}
+
+/// Work out what a `Range` header asks for from a body of `len` bytes.
+/// Headers that can't be parsed are ignored, as RFC 9110 says.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::range::{self, Ranges};
+///
+/// assert_eq!(Ranges::Partial(vec![(0, 9), (90, 99)]), range::parse("bytes=0-9, -10", 100));
+/// assert_eq!(Ranges::Unsatisfiable, range::parse("bytes=200-", 100));
+/// assert_eq!(Ranges::Full, range::parse("lines=1-2", 100));
+/// ```
+pub fn parse(header: &str, len: u64) -> Ranges {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None        => return Ranges::Full,
+    };
+    let mut ranges = Vec::new();
+
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (first, last) = match spec.find('-') {
+            Some(i) => (spec[..i].trim(), spec[i + 1..].trim()),
+            None    => return Ranges::Full,
+        };
+
+        let range = match (first.parse::<u64>(), last.parse::<u64>()) {
+            // `-n` is the last n bytes
+            _ if first.is_empty() => match last.parse::<u64>() {
+                Ok(0)  => None,
+                Ok(n)  => Some((len.saturating_sub(n), len.saturating_sub(1))),
+                Err(_) => return Ranges::Full,
+            },
+            (Ok(a), _) if last.is_empty() => Some((a, len.saturating_sub(1))),
+            (Ok(a), Ok(b)) if a <= b      => Some((a, b.min(len.saturating_sub(1)))),
+            _                             => return Ranges::Full,
+        };
+
+        // ranges starting past the end can't be satisfied, but others might
+        if let Some((a, b)) = range {
+            if a < len {
+                ranges.push((a, b));
+            }
+        }
+    }
+
+    if ranges.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    ranges.sort();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+
+    for (a, b) in ranges {
+        match merged.last_mut() {
+            Some(prev) if a <= prev.1 + 1 => prev.1 = prev.1.max(b),
+            _                             => merged.push((a, b)),
+        }
+    }
+
+    Ranges::Partial(merged)
+}
+
+/// Cut a full `200 OK` response down to the ranges the request asks for.
+/// Other responses, and requests without a `Range` header, are left alone.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Request, Response};
+/// use canteen::{range, utils};
+///
+/// let req: Request = "GET /file HTTP/1.1\r\nRange: bytes=2-4\r\n\r\n".parse().unwrap();
+/// let res = range::apply(&req, utils::make_response("abcdefg", "text/plain", 200));
+///
+/// assert_eq!(206, res.get_status());
+/// assert_eq!(Some("bytes 2-4/7"), res.get_header("Content-Range"));
+/// assert_eq!(b"cde", res.get_payload());
+/// ```
+pub fn apply(req: &Request, mut res: Response) -> Response {
+    if req.method != Method::Get || res.get_status() != 200 {
+        return res;
+    }
+
+    let header = match req.get_header("Range") {
+        Some(header) => header,
+        None         => return res,
+    };
+    let len = res.get_payload().len() as u64;
+
+    match parse(&header, len) {
+        Ranges::Full             => res,
+        Ranges::Unsatisfiable    => {
+            res.set_status(416);
+            res.set_header("Content-Range", &format!("bytes */{}", len));
+            res.replace_payload(Vec::new());
+            res
+        },
+        Ranges::Partial(ranges)  => {
+            let body = match ranges.len() {
+                1 => {
+                    let (a, b) = ranges[0];
+
+                    res.set_header("Content-Range", &format!("bytes {}-{}/{}", a, b, len));
+                    res.get_payload()[a as usize..=b as usize].to_vec()
+                },
+                _ => {
+                    let boundary = format!("canteen-{:016x}", RandomState::new().build_hasher().finish());
+                    let ctype = String::from(res.get_content_type());
+                    let mut body = Vec::new();
+
+                    for (a, b) in ranges {
+                        body.extend_from_slice(format!("--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                                                       boundary, ctype, a, b, len).as_bytes());
+                        body.extend_from_slice(&res.get_payload()[a as usize..=b as usize]);
+                        body.extend_from_slice(b"\r\n");
+                    }
+
+                    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+                    res.set_content_type(&format!("multipart/byteranges; boundary={}", boundary));
+                    body
+                },
+            };
+
+            res.set_status(206);
+            res.replace_payload(body);
+            res
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Ranges::Partial(vec![(0, 0)]), parse("bytes=0-0", 10));
+        assert_eq!(Ranges::Partial(vec![(5, 9)]), parse("bytes=5-", 10));
+        assert_eq!(Ranges::Partial(vec![(0, 9)]), parse("bytes=-20", 10));
+        assert_eq!(Ranges::Partial(vec![(8, 9)]), parse("bytes=8-100", 10));
+        assert_eq!(Ranges::Partial(vec![(0, 5)]), parse("bytes=0-2, 3-4,2-5", 10));
+        assert_eq!(Ranges::Partial(vec![(1, 2)]), parse("bytes=1-2, 20-30", 10));
+        assert_eq!(Ranges::Unsatisfiable, parse("bytes=-0", 10));
+        assert_eq!(Ranges::Unsatisfiable, parse("bytes=0-", 0));
+        assert_eq!(Ranges::Full, parse("bytes=5-2", 10));
+        assert_eq!(Ranges::Full, parse("bytes=a-b", 10));
+        assert_eq!(Ranges::Full, parse("bytes=-", 10));
+        assert_eq!(Ranges::Full, parse(&format!("bytes={}", vec!["0-0"; 40].join(",")), 10));
+    }
+
+    #[test]
+    fn test_apply_multipart() {
+        let req: Request = "GET /f HTTP/1.1\r\nRange: bytes=0-1, 5-6\r\n\r\n".parse().unwrap();
+        let res = apply(&req, utils::make_response("abcdefgh", "text/plain", 200));
+        let ctype = String::from(res.get_content_type());
+        let boundary = ctype.strip_prefix("multipart/byteranges; boundary=").unwrap();
+
+        assert_eq!(206, res.get_status());
+        assert_eq!(None, res.get_header("Content-Range"));
+        assert_eq!(format!("--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/8\r\n\r\nab\r\n\
+                            --{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 5-6/8\r\n\r\nfg\r\n\
+                            --{b}--\r\n", b = boundary).into_bytes(), res.get_payload());
+    }
+
+    #[test]
+    fn test_apply_unsatisfiable() {
+        let req: Request = "GET /f HTTP/1.1\r\nRange: bytes=50-\r\n\r\n".parse().unwrap();
+        let res = apply(&req, utils::make_response("abc", "text/plain", 200));
+
+        assert_eq!(416, res.get_status());
+        assert_eq!(Some("bytes */3"), res.get_header("Content-Range"));
+        assert!(res.get_payload().is_empty());
+
+        let req: Request = "POST /f HTTP/1.1\r\nRange: bytes=0-0\r\n\r\n".parse().unwrap();
+        assert_eq!(200, apply(&req, utils::make_response("abc", "text/plain", 200)).get_status());
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Limits on how often a client may make requests. Limited requests are
+//! turned away with `429 Too Many Requests` as they're routed, before they
+//! reach a worker, so a flood of them can't tie up the pool.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::request::Request;
+use crate::throttle::TokenBucket;
+
+// how many clients are tracked at most. past that, those not heard from in a
+// while are forgotten, which at worst gives them a full bucket again.
+const MAX_KEYS: usize = 10_000;
+
+// picks what requests are counted against: a client, an API key, a user.
+type KeyFn = Arc<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+/// A token bucket per client, allowing `requests` every `per` on average,
+/// and bursts of up to `burst` requests at once. Clients are told by their
+/// IP address, or the /64 network for IPv6 ones since a single host is
+/// usually given a whole /64, unless `key_by` says otherwise.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use canteen::{Canteen, Method, Request};
+/// use canteen::ratelimit::RateLimiter;
+/// use canteen::route::RouteOptions;
+/// use canteen::utils;
+///
+/// let mut cnt = Canteen::new();
+///
+/// // ten requests a second from each address, anywhere
+/// cnt.limit_requests(RateLimiter::new(10, Duration::from_secs(1)).burst(20));
+///
+/// // and logging in is limited per account as well
+/// let login = RateLimiter::new(5, Duration::from_secs(60))
+///                         .key_by(|req: &Request| req.get_header("X-Account"));
+///
+/// cnt.add_route_with("/login", Method::Post, |_: &Request| utils::text("ok"),
+///                    RouteOptions::new().limit_requests(login));
+/// ```
+#[derive(Clone)]
+pub struct RateLimiter {
+    rate:    u64,
+    cost:    u64,
+    burst:   u64,
+    key:     KeyFn,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+// the buckets, in two generations: those used since the last turnover, and
+// those from before. when the first fills up the second is dropped and the
+// first takes its place, so keeping to `MAX_KEYS` never means a scan.
+#[derive(Default)]
+struct Buckets {
+    recent: HashMap<String, TokenBucket>,
+    older:  HashMap<String, TokenBucket>,
+}
+
+impl Buckets {
+    // the bucket for `key`, made with `new` if there's none.
+    fn get(&mut self, key: String, new: impl FnOnce() -> TokenBucket) -> &mut TokenBucket {
+        let bucket = match self.recent.remove(&key) {
+            Some(bucket) => bucket,
+            None         => {
+                if self.recent.len() >= MAX_KEYS / 2 {
+                    self.older = std::mem::take(&mut self.recent);
+                }
+
+                self.older.remove(&key).unwrap_or_else(new)
+            },
+        };
+
+        self.recent.entry(key).or_insert(bucket)
+    }
+}
+
+// the address requests are counted against by default.
+fn client_key(ip: IpAddr) -> String {
+    let v6 = match ip {
+        IpAddr::V4(v4) => return v4.to_string(),
+        IpAddr::V6(v6) => v6,
+    };
+
+    match v6.to_ipv4_mapped() {
+        Some(v4) => v4.to_string(),
+        None     => {
+            let s = v6.segments();
+
+            format!("{:x}:{:x}:{:x}:{:x}::/64", s[0], s[1], s[2], s[3])
+        },
+    }
+}
+
+impl RateLimiter {
+    /// Allow `requests` every `per`, with bursts of as many at once.
+    pub fn new(requests: u64, per: Duration) -> RateLimiter {
+        // a request costs `per` in milliseconds, and the bucket refills with
+        // a thousand tokens a second per request allowed
+        let cost = (per.as_millis() as u64).max(1);
+
+        RateLimiter {
+            rate:    requests.max(1).saturating_mul(1000),
+            cost,
+            burst:   requests.max(1),
+            key:     Arc::new(|req: &Request| req.peer.map(client_key)),
+            buckets: Arc::new(Mutex::new(Buckets::default())),
+        }
+    }
+
+    /// Allow bursts of up to `requests` at once, at least one.
+    pub fn burst(mut self, requests: u64) -> RateLimiter {
+        self.burst = requests.max(1);
+
+        self
+    }
+
+    /// Count requests against the key `key` returns for them, rather than
+    /// against the client's address. Requests it gives no key for aren't
+    /// limited.
+    pub fn key_by<F>(mut self, key: F) -> RateLimiter
+            where F: Fn(&Request) -> Option<String> + Send + Sync + 'static {
+        self.key = Arc::new(key);
+
+        self
+    }
+
+    /// Count `req` at `now`. Returns how long until it would have been
+    /// allowed, if it's over the limit.
+    pub fn check(&self, req: &Request, now: Instant) -> Result<(), Duration> {
+        let key = match (self.key)(req) {
+            Some(key) => key,
+            None      => return Ok(()),
+        };
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let (rate, cost, burst) = (self.rate, self.cost, self.burst.saturating_mul(self.cost));
+        let bucket = buckets.get(key, || TokenBucket::new(rate, burst, now));
+        let granted = bucket.take(cost as usize, now) as u64;
+
+        if granted == cost {
+            return Ok(());
+        }
+
+        bucket.give_back(granted as usize);
+
+        let short = (cost - granted) as u128;
+
+        Err(Duration::from_micros((short * 1_000_000).div_ceil(rate as u128) as u64))
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RateLimiter({} per {}ms, burst {})", self.rate / 1000, self.cost, self.burst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from(ip: &str) -> Request {
+        let mut req = Request::new();
+
+        req.peer = ip.parse().ok();
+        req
+    }
+
+    #[test]
+    fn test_limit_per_client() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(1));
+        let now = Instant::now();
+        let (a, b) = (from("10.0.0.1"), from("10.0.0.2"));
+
+        assert_eq!(Ok(()), limiter.check(&a, now));
+        assert_eq!(Ok(()), limiter.check(&a, now));
+        assert_eq!(Err(Duration::from_millis(500)), limiter.check(&a, now));
+        assert_eq!(Ok(()), limiter.check(&b, now));
+        assert_eq!(Ok(()), limiter.check(&a, now + Duration::from_millis(500)));
+
+        // requests with no key aren't counted
+        for _ in 0..10 {
+            assert_eq!(Ok(()), limiter.check(&Request::new(), now));
+        }
+    }
+
+    #[test]
+    fn test_ipv6_networks() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(1));
+        let now = Instant::now();
+
+        assert_eq!(Ok(()), limiter.check(&from("2001:db8:1:2::1"), now));
+        assert!(limiter.check(&from("2001:db8:1:2:ffff::9"), now).is_err());
+        assert_eq!(Ok(()), limiter.check(&from("2001:db8:1:3::1"), now));
+        assert_eq!("10.0.0.1", client_key("::ffff:10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_forgets_idle_clients() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60)).key_by(|req: &Request| req.get_header("X-Key"));
+        let now = Instant::now();
+        let keyed = |key: usize| {
+            let mut req = Request::new();
+
+            req.headers.push((String::from("x-key"), key.to_string()));
+            req
+        };
+
+        assert_eq!(Ok(()), limiter.check(&keyed(0), now));
+
+        for key in 1..3 * MAX_KEYS {
+            assert_eq!(Ok(()), limiter.check(&keyed(key), now));
+
+            // a client that keeps coming back is kept
+            if key % 1000 == 0 {
+                assert!(limiter.check(&keyed(0), now).is_err());
+            }
+        }
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.recent.len() + buckets.older.len() <= MAX_KEYS);
+    }
+
+    #[test]
+    fn test_slow_rates_and_bursts() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60)).burst(3).key_by(|_: &Request| Some(String::from("all")));
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(Ok(()), limiter.check(&Request::new(), now));
+        }
+
+        assert_eq!(Err(Duration::from_secs(60)), limiter.check(&Request::new(), now));
+        assert_eq!(Err(Duration::from_secs(30)), limiter.check(&Request::new(), now + Duration::from_secs(30)));
+    }
+}
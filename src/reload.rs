@@ -0,0 +1,111 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Reloading runtime settings while the server runs.
+//!
+//! A reload re-runs the function given to `Canteen::on_reload` against the
+//! current `Config` and re-reads TLS certificates. It can be requested with
+//! a `ReloadHandle` (e.g. from an admin route) or, on unix, with SIGHUP.
+//! Requests already being handled finish with the settings they started
+//! with.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::Config;
+
+// a function that updates the server-wide settings on reload.
+pub(crate) type ReloadFn = Box<dyn Fn(&mut Config) + Send>;
+
+// set by the signal handler, cleared by the event loop.
+static SIGHUP: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_sighup(_: libc::c_int) {
+    SIGHUP.store(true, Ordering::SeqCst);
+}
+
+// install a SIGHUP handler that flags a reload. SA_RESTART is left off so
+// the event loop wakes up to notice.
+#[cfg(unix)]
+pub(crate) fn watch_sighup() -> std::io::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+
+        action.sa_sigaction = on_sighup as *const () as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(libc::SIGHUP, &action, std::ptr::null_mut()) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// whether a SIGHUP has arrived since the last call.
+pub(crate) fn take_sighup() -> bool {
+    SIGHUP.swap(false, Ordering::SeqCst)
+}
+
+/// Requests a reload of a running server's settings. Clones share the same
+/// server. The reload happens on the event loop shortly after it is asked
+/// for.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::Canteen;
+///
+/// let mut cnt = Canteen::new();
+/// cnt.on_reload(|cfg| { cfg.set_rate_limit(Some(1024 * 1024)); });
+///
+/// let handle = cnt.reload_handle();
+/// handle.reload();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ReloadHandle {
+    pending: Arc<AtomicBool>,
+}
+
+impl ReloadHandle {
+    /// Ask the server to reload.
+    pub fn reload(&self) {
+        self.pending.store(true, Ordering::SeqCst);
+    }
+
+    // whether a reload has been asked for since the last call.
+    pub(crate) fn take(&self) -> bool {
+        self.pending.swap(false, Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_is_shared() {
+        let handle = ReloadHandle::default();
+        let other = handle.clone();
+
+        assert!(!handle.take());
+        other.reload();
+        assert!(handle.take());
+        assert!(!handle.take());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sighup_flags_reload() {
+        watch_sighup().unwrap();
+
+        unsafe { libc::raise(libc::SIGHUP) };
+        assert!(take_sighup());
+        assert!(!take_sighup());
+    }
+}
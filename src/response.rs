@@ -6,10 +6,22 @@
 // terms
 
 use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use mime_guess::MimeGuess;
 use chrono::Utc;
 use serde_json;
 use serde::Serialize;
 
+use crate::cookie::Cookie;
+use crate::mmap::MappedFile;
+use crate::stream::Streamer;
+use crate::request::Request;
+use crate::status::StatusCode;
+use crate::utils;
+
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// A trait that converts data from the handler function to a u8 slice.
@@ -44,105 +56,207 @@ impl ToOutput for Vec<u8> {
 /// This struct reprsents the response to an HTTP client.
 #[derive(Debug, Default)]
 pub struct Response {
-    status:     u16,
+    status:     StatusCode,
     cmsg:       String,
     ctype:      String,
     headers:    BTreeMap<String, String>,
+    cookies:    Vec<(String, String)>,
     payload:    Vec<u8>,
+    mapped:     Option<Arc<MappedFile>>,
+    stream:     Option<Streamer>,
 }
 
 impl Response {
     /// Create a new, empty Response.
     pub fn new() -> Response {
         let mut res = Response {
-            status:     200,
+            status:     StatusCode::OK,
             cmsg:       String::from("OK"),
             ctype:      String::from("text/plain"),
             headers:    BTreeMap::new(),
+            cookies:    Vec::new(),
             payload:    Vec::with_capacity(2048),
+            mapped:     None,
+            stream:     None,
         };
 
         let now = Utc::now().format("%a, %d %b %Y, %H:%M:%S %Z").to_string();
 
-        res.add_header("Connection", "close");
         res.add_header("Server", &format!("canteen/{}", VERSION));
         res.add_header("Date", &now);
 
         res
     }
 
+    /// Start building a response in one expression, from a `200 OK` with
+    /// an empty body.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Response;
+    ///
+    /// let res = Response::build()
+    ///     .status(201)
+    ///     .content_type("application/json")
+    ///     .header("Location", "/items/7")
+    ///     .body("{\"id\":7}")
+    ///     .finish();
+    ///
+    /// assert_eq!(201, res.get_status());
+    /// assert_eq!(Some("/items/7"), res.get_header("Location"));
+    /// ```
+    pub fn build() -> ResponseBuilder {
+        ResponseBuilder { res: Response::new() }
+    }
+
     /// Creates a Response with a JSON body
     ///
     /// # Examples
     ///
-    /// ```rust,ignore
+    /// ```rust
     /// use canteen::Response;
-    /// user serde::Serialize;
+    /// use serde_derive::Serialize;
     ///
     /// #[derive(Serialize)]
     /// struct Foo {
     ///     item: i32,
     /// }
     ///
-    /// let foo = Foo { item: 12345 };
-    /// let res = Response::as_json(&foo);
+    /// let res = Response::json(&Foo { item: 12345 });
+    ///
+    /// assert_eq!("application/json", res.get_content_type());
+    /// assert_eq!(b"{\"item\":12345}", res.get_payload());
     /// ```
-    pub fn as_json<T: Serialize>(data: &T) -> Response {
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` can't be serialized, e.g. a map with keys that
+    /// aren't strings. A panicking handler is answered with a 500.
+    pub fn json<T: Serialize + ?Sized>(data: &T) -> Response {
         let mut res = Response::new();
 
         res.set_content_type("application/json");
-        res.append(serde_json::to_string(data).unwrap());
+        res.append(serde_json::to_vec(data).expect("unable to serialize JSON response"));
+
+        res
+    }
+
+    /// Creates a Response with a JSON body. The same as `Response::json`.
+    pub fn as_json<T: Serialize>(data: &T) -> Response {
+        Response::json(data)
+    }
+
+    /// Creates a `200 OK` response whose body is written by `body` after
+    /// the head has been sent, and sent on to the client as it's written
+    /// with `Transfer-Encoding: chunked`. A write fails once the client has
+    /// gone, so `body` can stop early. Anything appended to the response
+    /// itself is ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Write;
+    /// use canteen::{Request, Response};
+    ///
+    /// fn export(_: &Request) -> Response {
+    ///     let mut res = Response::stream(|out| {
+    ///         for n in 0..100000 {
+    ///             writeln!(out, "{},{}", n, n * n)?;
+    ///         }
+    ///
+    ///         Ok(())
+    ///     });
+    ///
+    ///     res.set_content_type("text/csv");
+    ///     res
+    /// }
+    /// ```
+    pub fn stream<F>(body: F) -> Response
+        where F: FnOnce(&mut dyn io::Write) -> io::Result<()> + Send + 'static {
+        let mut res = Response::new();
+
+        res.stream = Some(Streamer(Box::new(body)));
+
+        res
+    }
+
+    /// Whether the body is written by a function given to
+    /// `Response::stream`, which hasn't been run yet.
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // take the body writer, leaving the response with the head to send.
+    pub(crate) fn take_stream(&mut self) -> Option<Streamer> {
+        self.stream.take()
+    }
+
+    // run the body writer into memory, for clients that can't take a
+    // chunked body. if it fails, the body is what it wrote until then.
+    pub(crate) fn buffer_stream(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let mut body = Vec::new();
+
+            if let Err(e) = (stream.0)(&mut body) {
+                log::debug!("streamed response ended early: {}", e);
+            }
+
+            self.replace_payload(body);
+        }
+    }
+
+    /// Creates a redirect to `location`, which is made absolute using the
+    /// request's scheme, host, and path. `status` should be one of the 3xx
+    /// codes.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Request, Response};
+    ///
+    /// let req: Request = "POST /login HTTP/1.1\r\nHost: example.com\r\n\r\n".parse().unwrap();
+    /// let res = Response::redirect(&req, "/home", 303);
+    ///
+    /// assert_eq!(Some("http://example.com/home"), res.get_header("Location"));
+    /// ```
+    pub fn redirect<S: Into<StatusCode>>(req: &Request, location: &str, status: S) -> Response {
+        let status = status.into();
+        let location = utils::absolute_url(req, location);
+        let reason = status.canonical_reason().unwrap_or("redirect").to_lowercase();
+        let body = format!("<a href=\"{}\">{}</a>", utils::escape_html(&location), reason);
+        let mut res = utils::make_response(body, "text/html", status);
+
+        res.add_header("Location", &location);
 
         res
     }
 
-    /// Gets the HTTP message for a given status.
-    fn get_http_message(status: u16) -> String {
-        let msg = match status {
-            100 => "Continue",
-            101 => "Switching Protocols",
-            200 => "OK",
-            201 => "Created",
-            202 => "Accepted",
-            203 => "Non-Authoritative Information",
-            204 => "No Content",
-            205 => "Reset Content",
-            206 => "Partial Content",
-            300 => "Multiple Choices",
-            301 => "Moved Permanently",
-            302 => "Found",
-            303 => "See Other",
-            304 => "Not Modified",
-            305 => "Use Proxy",
-            307 => "Temporary Redirect",
-            400 => "Bad Request",
-            401 => "Unauthorized",
-            402 => "Payment Required",
-            403 => "Forbidden",
-            404 => "Not Found",
-            405 => "Method Not Allowed",
-            406 => "Not Acceptable",
-            407 => "Proxy Authentication Required",
-            408 => "Request Time Out",
-            409 => "Conflict",
-            410 => "Gone",
-            411 => "Length Required",
-            412 => "Precondition Failed",
-            413 => "Request Entity Too Large",
-            414 => "Request-URI Too Large",
-            415 => "Unsupported Media Type",
-            416 => "Requested Range Not Satisfiable",
-            417 => "Expectation Failed",
-            500 => "Internal Server Error",
-            501 => "Not Implemented",
-            502 => "Bad Gateway",
-            503 => "Service Unavailable",
-            504 => "Gateway Time-out",
-            505 => "HTTP Version Not Supported",
-            _   => "OK",
+    /// Creates a `301 Moved Permanently` redirect to `new_path`, carrying
+    /// over the request's query string. If `new_path` has a query string of
+    /// its own, the request's parameters are appended to it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Request, Response};
+    ///
+    /// let req: Request = "GET /old/list?page=2 HTTP/1.1\r\nHost: example.com\r\n\r\n".parse().unwrap();
+    /// let res = Response::redirect_preserving_query(&req, "/new/list");
+    ///
+    /// assert_eq!(Some("http://example.com/new/list?page=2"), res.get_header("Location"));
+    /// ```
+    pub fn redirect_preserving_query(req: &Request, new_path: &str) -> Response {
+        let location = match req.path.find('?') {
+            Some(i) if i + 1 < req.path.len() => {
+                let sep = if new_path.contains('?') { '&' } else { '?' };
+
+                format!("{}{}{}", new_path, sep, &req.path[i + 1..])
+            },
+            _                                 => String::from(new_path),
         };
 
-        String::from(msg)
+        Response::redirect(req, &location, StatusCode::MOVED_PERMANENTLY)
     }
 
     /// Sets the response status for the HTTP response.
@@ -150,14 +264,122 @@ impl Response {
     /// # Examples
     ///
     /// ```rust
+    /// use canteen::{Response, StatusCode};
+    ///
+    /// let mut res = Response::new();
+    /// res.set_status(StatusCode::CREATED);
+    /// res.set_status(201);
+    /// ```
+    pub fn set_status<S: Into<StatusCode>>(&mut self, status: S) {
+        self.status = status.into();
+        self.cmsg = String::from(self.status.canonical_reason().unwrap_or(""));
+    }
+
+    /// Sets the response status along with a reason phrase of its own, for
+    /// codes that aren't registered or when the standard phrase won't do.
+    /// Control characters, which could end the status line early, are
+    /// replaced with spaces.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
     /// use canteen::Response;
     ///
     /// let mut res = Response::new();
-    /// res.set_status(200);
+    /// res.set_status_reason(599, "Network Connect Timeout Error");
+    ///
+    /// assert!(res.gen_output().starts_with(b"HTTP/1.1 599 Network Connect Timeout Error\r\n"));
+    /// ```
+    pub fn set_status_reason<S: Into<StatusCode>>(&mut self, status: S, reason: &str) {
+        self.status = status.into();
+        self.cmsg = reason.chars().map(|c| if c.is_control() && c != '\t' { ' ' } else { c }).collect();
+    }
+
+    /// Gets the reason phrase sent with the status.
+    pub fn get_reason(&self) -> &str {
+        &self.cmsg
+    }
+
+    /// Gets the response status.
+    pub fn get_status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Gets the Content-Type of the response.
+    pub fn get_content_type(&self) -> &str {
+        &self.ctype
+    }
+
+    /// Gets the value of a header set on the response.
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key).map(String::as_str)
+    }
+
+    /// Iterate over the headers set on the response, not including
+    /// `Content-Type` and `Content-Length`. Cookies come last, as one
+    /// `Set-Cookie` header each.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .chain(self.cookies.iter().map(|(_, v)| ("Set-Cookie", v.as_str())))
+    }
+
+    /// Creates a `200 OK` response whose body is the file at `path`,
+    /// mapped into memory rather than read, so large files aren't copied
+    /// onto the heap. The mapping lives until the response has been
+    /// written to the client. Adding to the body copies it into memory.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may write to or truncate the file until the response has
+    /// been sent; see `MappedFile::open`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use canteen::{Request, Response};
+    /// use canteen::utils;
+    ///
+    /// fn download(req: &Request) -> Response {
+    ///     // the dataset is only ever replaced by renaming a new file over it
+    ///     unsafe { Response::from_mmap("/srv/files/dataset.csv") }.unwrap_or_else(|_| utils::err_404(req))
+    /// }
     /// ```
-    pub fn set_status(&mut self, status: u16) {
-        self.status = status;
-        self.cmsg = Response::get_http_message(status);
+    pub unsafe fn from_mmap<P: AsRef<Path>>(path: P) -> io::Result<Response> {
+        let map = MappedFile::open(path.as_ref())?;
+        let mut res = Response::new();
+
+        match MimeGuess::from_path(path.as_ref()).first_raw() {
+            Some(ctype) => res.set_content_type(ctype),
+            None        => res.set_content_type("application/octet-stream"),
+        }
+
+        res.mapped = Some(Arc::new(map));
+
+        Ok(res)
+    }
+
+    /// Gets the body of the response.
+    pub fn get_payload(&self) -> &[u8] {
+        match self.mapped {
+            Some(ref map) => map,
+            None          => &self.payload,
+        }
+    }
+
+    // swap the body for another, e.g. part of it.
+    pub(crate) fn replace_payload(&mut self, payload: Vec<u8>) {
+        self.mapped = None;
+        self.payload = payload;
+    }
+
+    // the body, ready to be added to, copying a mapped file into memory.
+    fn body_mut(&mut self) -> &mut Vec<u8> {
+        if let Some(map) = self.mapped.take() {
+            self.payload = map.to_vec();
+        }
+
+        &mut self.payload
     }
 
     /// Sets the Content-Type header for the HTTP response.
@@ -190,6 +412,58 @@ impl Response {
         }
     }
 
+    /// Sets a header on the HTTP response, replacing any existing value.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Response;
+    ///
+    /// let mut res = Response::new();
+    /// res.set_header("Connection", "keep-alive");
+    /// ```
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        self.headers.insert(String::from(key), String::from(value));
+    }
+
+    /// Sets a cookie on the client, replacing any cookie of the same name
+    /// set on the response before.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Response;
+    /// use canteen::cookie::Cookie;
+    ///
+    /// let mut res = Response::new();
+    /// res.set_cookie(Cookie::new("theme", "dark").path("/"));
+    /// res.set_cookie(Cookie::new("lang", "en"));
+    ///
+    /// assert_eq!(2, res.headers().filter(|(k, _)| *k == "Set-Cookie").count());
+    /// ```
+    pub fn set_cookie(&mut self, cookie: Cookie) {
+        let value = cookie.to_string();
+
+        match self.cookies.iter_mut().find(|(name, _)| name == cookie.name()) {
+            Some(set) => set.1 = value,
+            None      => self.cookies.push((String::from(cookie.name()), value)),
+        }
+    }
+
+    /// Removes a header from the HTTP response.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Response;
+    ///
+    /// let mut res = Response::new();
+    /// res.remove_header("Server");
+    /// ```
+    pub fn remove_header(&mut self, key: &str) {
+        self.headers.remove(key);
+    }
+
     /// Appends data to the body of the HTTP response. The trait ToOutput must
     /// be implemented for the type passed.
     ///
@@ -203,29 +477,162 @@ impl Response {
     /// res.append(data);
     /// ```
     pub fn append<T: ToOutput>(&mut self, payload: T) {
-        self.payload.extend(payload.to_output().iter());
+        self.body_mut().extend(payload.to_output().iter());
     }
 
     /// Returns a byte array containing the full contents of the HTTP response,
     /// for use by the Canteen struct.
     pub fn gen_output(&self) -> Vec<u8> {
+        let (mut output, mapped) = self.gen_parts();
+
+        if let Some(map) = mapped {
+            output.extend_from_slice(&map);
+        }
+
+        output
+    }
+
+    // the response, or just its head if the body is a mapped file, which is
+    // returned alongside so it can be written without being copied.
+    pub(crate) fn gen_parts(&self) -> (Vec<u8>, Option<Arc<MappedFile>>) {
+        let body = self.get_payload();
         let mut output: Vec<u8> = Vec::with_capacity(self.payload.len() + 500);
         let mut inter = String::new();
 
         inter.push_str(&format!("HTTP/1.1 {} {}\r\n", self.status, self.cmsg));
 
-        for (key, value) in &self.headers {
+        for (key, value) in self.headers() {
             inter.push_str(&format!("{}: {}\r\n", key, value));
         }
 
         inter.push_str(&format!("Content-Type: {}\r\n", self.ctype));
-        inter.push_str(&format!("Content-Length: {}\r\n", self.payload.len()));
+
+        match self.stream {
+            Some(_) => inter.push_str("Transfer-Encoding: chunked\r\n"),
+            None    => inter.push_str(&format!("Content-Length: {}\r\n", body.len())),
+        }
+
         inter.push_str("\r\n");
 
         output.extend(inter.as_bytes());
-        output.extend(self.payload.iter());
 
-        output
+        if self.mapped.is_none() {
+            output.extend(body.iter());
+        }
+
+        (output, self.mapped.clone())
+    }
+}
+
+/// A response under construction, from `Response::build`.
+#[derive(Debug)]
+pub struct ResponseBuilder {
+    res: Response,
+}
+
+impl ResponseBuilder {
+    /// Set the status.
+    pub fn status<S: Into<StatusCode>>(mut self, status: S) -> ResponseBuilder {
+        self.res.set_status(status);
+
+        self
+    }
+
+    /// Set the `Content-Type`.
+    pub fn content_type(mut self, ctype: &str) -> ResponseBuilder {
+        self.res.set_content_type(ctype);
+
+        self
+    }
+
+    /// Set a header, replacing any value it already has.
+    pub fn header(mut self, key: &str, value: &str) -> ResponseBuilder {
+        self.res.set_header(key, value);
+
+        self
+    }
+
+    /// Set a cookie on the client.
+    pub fn cookie(mut self, cookie: Cookie) -> ResponseBuilder {
+        self.res.set_cookie(cookie);
+
+        self
+    }
+
+    /// Add to the body.
+    pub fn body<T: ToOutput>(mut self, payload: T) -> ResponseBuilder {
+        self.res.append(payload);
+
+        self
+    }
+
+    /// The finished response.
+    pub fn finish(self) -> Response {
+        self.res
+    }
+}
+
+impl From<ResponseBuilder> for Response {
+    fn from(builder: ResponseBuilder) -> Response {
+        builder.finish()
+    }
+}
+
+/// Writes go to the end of the body, so a response can be handed to
+/// anything that writes to an `io::Write`.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Write;
+/// use canteen::Response;
+///
+/// let mut res = Response::new();
+///
+/// for i in 0..3 {
+///     write!(res, "row {}\n", i).unwrap();
+/// }
+///
+/// serde_json::to_writer(&mut res, &[1, 2, 3]).unwrap();
+/// assert_eq!(b"row 0\nrow 1\nrow 2\n[1,2,3]", res.get_payload());
+/// ```
+impl io::Write for Response {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.body_mut().extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.body_mut().extend_from_slice(buf);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes go to the end of the body. Only import one of `fmt::Write` and
+/// `io::Write` where `write!` is used, or the call is ambiguous.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::fmt::Write;
+/// use canteen::Response;
+///
+/// let mut res = Response::new();
+///
+/// write!(res, "{} items", 3).unwrap();
+/// assert_eq!(b"3 items", res.get_payload());
+/// ```
+impl fmt::Write for Response {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.body_mut().extend_from_slice(s.as_bytes());
+
+        Ok(())
     }
 }
 
@@ -252,7 +659,68 @@ mod tests {
 
     #[test]
     fn test_response_http_message() {
-        assert_eq!("OK", Response::get_http_message(200));
+        let mut res = Response::new();
+
+        assert!(res.gen_output().starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+        res.set_status(StatusCode::NOT_FOUND);
+        assert!(res.gen_output().starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+
+        res.set_status(StatusCode::UNPROCESSABLE_CONTENT);
+        assert_eq!("Unprocessable Content", res.get_reason());
+
+        // unregistered codes go out with an empty phrase unless given one
+        res.set_status(499);
+        assert!(res.gen_output().starts_with(b"HTTP/1.1 499 \r\n"));
+
+        res.set_status_reason(499, "Client Closed\r\nX-Evil: 1");
+        assert_eq!(499, res.get_status());
+        assert!(res.gen_output().starts_with(b"HTTP/1.1 499 Client Closed  X-Evil: 1\r\n"));
+    }
+
+    #[test]
+    fn test_builder() {
+        let res = Response::build().status(StatusCode::ACCEPTED)
+                                   .header("X-Foo", "bar")
+                                   .header("X-Foo", "baz")
+                                   .body(vec![1, 2])
+                                   .body("3")
+                                   .finish();
+
+        assert_eq!(202, res.get_status());
+        assert_eq!("Accepted", res.get_reason());
+        assert_eq!(Some("baz"), res.get_header("X-Foo"));
+        assert_eq!(b"\x01\x023".to_vec(), res.get_payload());
+        assert_eq!("text/plain", Response::from(Response::build()).get_content_type());
+    }
+
+    #[test]
+    fn test_stream_head() {
+        let mut res = Response::stream(|out| out.write_all(b"streamed"));
+
+        assert!(res.is_streaming());
+
+        let head = String::from_utf8(res.gen_output()).unwrap();
+        assert!(head.ends_with("Transfer-Encoding: chunked\r\n\r\n"));
+        assert!(!head.contains("Content-Length"));
+
+        res.buffer_stream();
+        assert!(!res.is_streaming());
+        assert_eq!(b"streamed", res.get_payload());
+    }
+
+    #[test]
+    fn test_set_cookie() {
+        let mut res = Response::new();
+
+        res.set_cookie(Cookie::new("a", "1"));
+        res.set_cookie(Cookie::new("b", "2").http_only(true));
+        res.set_cookie(Cookie::removal("a"));
+
+        let out = String::from_utf8(res.gen_output()).unwrap();
+        assert!(out.contains("\r\nSet-Cookie: a=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT\r\n"));
+        assert!(out.contains("\r\nSet-Cookie: b=2; HttpOnly\r\n"));
+        assert_eq!(2, out.matches("Set-Cookie").count());
     }
 
     #[test]
@@ -285,4 +753,45 @@ mod tests {
 
         assert_eq!(ar, vc.to_output());
     }
+
+    #[test]
+    fn test_redirect_preserving_query() {
+        let req: Request = "GET /old?page=2&n=10 HTTP/1.1\r\nHost: example.com\r\n\r\n".parse().unwrap();
+
+        let res = Response::redirect_preserving_query(&req, "/new");
+        assert_eq!(301, res.get_status());
+        assert_eq!(Some("http://example.com/new?page=2&n=10"), res.get_header("Location"));
+
+        let res = Response::redirect_preserving_query(&req, "/new?sort=asc");
+        assert_eq!(Some("http://example.com/new?sort=asc&page=2&n=10"), res.get_header("Location"));
+
+        let req: Request = "GET /old? HTTP/1.1\r\nHost: example.com\r\n\r\n".parse().unwrap();
+        let res = Response::redirect_preserving_query(&req, "new");
+        assert_eq!(Some("http://example.com/new"), res.get_header("Location"));
+
+        // the query goes into the body's link as text, not markup
+        let req: Request = "GET /old?q=\"><script> HTTP/1.1\r\nHost: example.com\r\n\r\n".parse().unwrap();
+        let res = Response::redirect_preserving_query(&req, "/new");
+        assert_eq!(b"<a href=\"http://example.com/new?q=&quot;&gt;&lt;script&gt;\">moved permanently</a>".to_vec(), res.get_payload());
+    }
+
+    #[test]
+    fn test_from_mmap() {
+        let path = std::env::temp_dir().join("canteen-response-mmap.json");
+        std::fs::write(&path, b"[1, 2]").unwrap();
+
+        let mut res = unsafe { Response::from_mmap(&path) }.unwrap();
+        assert_eq!("application/json", res.get_content_type());
+        assert_eq!(b"[1, 2]", res.get_payload());
+        assert!(res.gen_output().ends_with(b"Content-Length: 6\r\n\r\n[1, 2]"));
+
+        let (head, mapped) = res.gen_parts();
+        assert!(head.ends_with(b"\r\n\r\n"));
+        assert_eq!(6, mapped.unwrap().len());
+
+        // adding to the body takes a copy
+        res.append(" ");
+        assert_eq!(b"[1, 2] ", res.get_payload());
+        assert!(res.gen_parts().1.is_none());
+    }
 }
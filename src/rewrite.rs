@@ -0,0 +1,133 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Path rewriting applied to requests before they are routed.
+
+use regex::Regex;
+
+/// A single rewrite rule. Rules only touch the path portion of the request
+/// target; any query string is carried over unchanged.
+#[derive(Debug, Clone)]
+pub enum Rewrite {
+    /// Remove a leading path prefix, e.g. `/app/foo` becomes `/foo`.
+    StripPrefix(String),
+    /// Prepend a path prefix, e.g. `/foo` becomes `/v1/foo`.
+    AddPrefix(String),
+    /// Replace the first match of a regex, using `$1`-style replacements.
+    Regex(Regex, String),
+}
+
+impl Rewrite {
+    /// Create a rule that strips `prefix` from matching paths. The prefix only
+    /// matches on whole path segments, so `/app` won't strip `/application`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::rewrite::Rewrite;
+    ///
+    /// let rw = Rewrite::strip_prefix("/app");
+    /// assert_eq!("/foo?a=1", rw.apply("/app/foo?a=1"));
+    /// ```
+    pub fn strip_prefix(prefix: &str) -> Rewrite {
+        Rewrite::StripPrefix(String::from(prefix.trim_end_matches('/')))
+    }
+
+    /// Create a rule that prepends `prefix` to every path.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::rewrite::Rewrite;
+    ///
+    /// let rw = Rewrite::add_prefix("/v1");
+    /// assert_eq!("/v1/foo", rw.apply("/foo"));
+    /// ```
+    pub fn add_prefix(prefix: &str) -> Rewrite {
+        Rewrite::AddPrefix(String::from(prefix.trim_end_matches('/')))
+    }
+
+    /// Create a rule that rewrites paths matching `pattern`. Panics if the
+    /// pattern is not a valid regex.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::rewrite::Rewrite;
+    ///
+    /// let rw = Rewrite::regex(r"^/users/(\d+)$", "/api/users/$1");
+    /// assert_eq!("/api/users/42", rw.apply("/users/42"));
+    /// ```
+    pub fn regex(pattern: &str, replacement: &str) -> Rewrite {
+        Rewrite::Regex(Regex::new(pattern).unwrap(), String::from(replacement))
+    }
+
+    /// Apply this rule to a request target, returning the rewritten target.
+    pub fn apply(&self, target: &str) -> String {
+        let (path, query) = match target.find('?') {
+            Some(i) => target.split_at(i),
+            None    => (target, ""),
+        };
+
+        let fixed = match *self {
+            Rewrite::StripPrefix(ref prefix) => {
+                match path.strip_prefix(prefix.as_str()) {
+                    Some("")                            => String::from("/"),
+                    Some(rest) if rest.starts_with('/') => String::from(rest),
+                    _                                   => String::from(path),
+                }
+            },
+            Rewrite::AddPrefix(ref prefix)   => format!("{}{}", prefix, path),
+            Rewrite::Regex(ref re, ref with) => re.replace(path, with.as_str()).into_owned(),
+        };
+
+        fixed + query
+    }
+}
+
+/// Apply a list of rules in order, each one seeing the output of the last.
+pub fn apply_all(rules: &[Rewrite], target: &str) -> String {
+    rules.iter().fold(String::from(target), |path, rule| rule.apply(&path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_prefix() {
+        let rw = Rewrite::strip_prefix("/app/");
+
+        assert_eq!("/foo/bar", rw.apply("/app/foo/bar"));
+        assert_eq!("/", rw.apply("/app"));
+        assert_eq!("/?x=1", rw.apply("/app?x=1"));
+        assert_eq!("/application", rw.apply("/application"));
+        assert_eq!("/other", rw.apply("/other"));
+    }
+
+    #[test]
+    fn test_add_prefix() {
+        let rw = Rewrite::add_prefix("/v1");
+
+        assert_eq!("/v1/foo?page=2", rw.apply("/foo?page=2"));
+    }
+
+    #[test]
+    fn test_regex_leaves_query() {
+        let rw = Rewrite::regex(r"^/old/(.*)$", "/new/$1");
+
+        assert_eq!("/new/thing?old=1", rw.apply("/old/thing?old=1"));
+        assert_eq!("/unrelated", rw.apply("/unrelated"));
+    }
+
+    #[test]
+    fn test_apply_all_chains() {
+        let rules = vec![Rewrite::strip_prefix("/app"), Rewrite::add_prefix("/api")];
+
+        assert_eq!("/api/users", apply_all(&rules, "/app/users"));
+    }
+}
@@ -8,20 +8,283 @@
 
 extern crate regex;
 
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use regex::Regex;
+use serde::de::DeserializeOwned;
 
+use crate::error::{Error, IntoResult};
+use crate::middleware::{Chain, Middleware};
+use crate::ratelimit::RateLimiter;
 use crate::request::*;
 use crate::response::*;
-
-// The various types of parameters that can be contained in a URI.
-#[derive(PartialEq, Eq, Hash, Debug)]
-enum ParamType {
+use crate::router::Segment;
+use crate::stats::RouteStats;
+use crate::utils;
+
+/// The various types of parameters that can be contained in a URI.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum ParamType {
+    /// `<int:name>`, a signed integer.
     Integer,
+    /// `<uint:name>`, an unsigned integer.
     Unsigned,
+    /// `<i64:name>`, a signed 64-bit integer, for IDs too large for `int`.
+    I64,
+    /// `<u64:name>`, an unsigned 64-bit integer.
+    U64,
+    /// `<name>` or `<str:name>`, a single path segment.
     String,
+    /// `<float:name>`, a decimal number.
     Float,
+    /// `<path:name>`, the rest of the path, slashes and all.
     Path,
+    /// `<uuid:name>`, a UUID in its hyphenated form, read as a `uuid::Uuid`.
+    /// Needs the `uuid` feature.
+    #[cfg(feature = "uuid")]
+    Uuid,
+    /// `<re"[a-z]{2,5}":name>`, a path segment matching the regular
+    /// expression given inline, which can't contain a `/`. Returned as a
+    /// `String`.
+    Pattern,
+    /// A type registered with `register_param`, by its name.
+    Custom(&'static str),
+}
+
+/// What happens when a request's path differs from a route's only by a
+/// trailing slash, set with `Canteen::set_trailing_slash`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TrailingSlash {
+    /// Answer both forms alike.
+    #[default]
+    Ignore,
+    /// Only answer the form the route was declared with.
+    Strict,
+    /// Answer the other form with a `301 Moved Permanently` to the one the
+    /// route was declared with.
+    Redirect,
+}
+
+/// A path parameter type of your own, declared in routes by its name, such
+/// as `<slug:title>`, and read with `Request::get`. Register it with
+/// `register_param` before adding routes that use it.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Canteen, Method, Request};
+/// use canteen::route::{self, FromParam};
+/// use canteen::utils;
+///
+/// struct Slug(String);
+///
+/// impl FromParam for Slug {
+///     const NAME: &'static str = "slug";
+///     const PATTERN: &'static str = "[a-z0-9]+(?:-[a-z0-9]+)*";
+///
+///     fn from_param(value: &str) -> Option<Slug> {
+///         Some(Slug(String::from(value)))
+///     }
+/// }
+///
+/// route::register_param::<Slug>();
+///
+/// let mut cnt = Canteen::new();
+/// cnt.add_route("/post/<slug:title>", Method::Get, |req: &Request| {
+///     let slug: Slug = req.get("title");
+///     utils::text(slug.0)
+/// });
+///
+/// assert_eq!(200, cnt.dispatch("GET /post/hello-world HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+/// assert_eq!(404, cnt.dispatch("GET /post/Hello_World HTTP/1.1\r\n\r\n".parse().unwrap()).get_status());
+/// ```
+pub trait FromParam: Sized {
+    /// The name routes declare the type by.
+    const NAME: &'static str;
+    /// A regular expression matching one value. Its groups must not
+    /// capture, so use `(?:...)`.
+    const PATTERN: &'static str;
+
+    /// Convert a value matched by `PATTERN`, or `None` if it can't be.
+    fn from_param(value: &str) -> Option<Self>;
+}
+
+impl<T: FromParam> FromUri for T {
+    fn from_uri(data: &str) -> T {
+        T::from_param(data).unwrap_or_else(|| panic!("matched {} parameter can't be converted", T::NAME))
+    }
+
+    fn try_from_uri(data: &str) -> Option<T> {
+        T::from_param(data)
+    }
+}
+
+// the registered parameter types, by name, and their patterns.
+static PARAMS: RwLock<Vec<(&'static str, &'static str)>> = RwLock::new(Vec::new());
+
+// the built-in parameter type called `name`. these can't be replaced.
+fn builtin(name: &str) -> Option<ParamType> {
+    match name {
+        "int"   => Some(ParamType::Integer),
+        "uint"  => Some(ParamType::Unsigned),
+        "i64"   => Some(ParamType::I64),
+        "u64"   => Some(ParamType::U64),
+        "float" => Some(ParamType::Float),
+        "path"  => Some(ParamType::Path),
+        "str"   => Some(ParamType::String),
+        #[cfg(feature = "uuid")]
+        "uuid"  => Some(ParamType::Uuid),
+        _       => None,
+    }
+}
+
+/// Register the path parameter type `T`, so routes added afterwards can
+/// declare parameters with its name. Registering a name again replaces the
+/// earlier type.
+///
+/// # Panics
+///
+/// Panics if `T`'s name is one of the built-in types, or isn't a word, or
+/// its pattern isn't a valid regular expression.
+pub fn register_param<T: FromParam>() {
+    if builtin(T::NAME).is_some() || T::NAME.is_empty() || !T::NAME.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        panic!("{:?} can't be used as the name of a parameter type", T::NAME);
+    }
+
+    if let Err(e) = Regex::new(T::PATTERN) {
+        panic!("bad pattern for parameter type {}: {}", T::NAME, e);
+    }
+
+    let mut params = PARAMS.write().unwrap();
+
+    params.retain(|&(name, _)| name != T::NAME);
+    params.push((T::NAME, T::PATTERN));
+}
+
+// the pattern for a registered parameter type.
+fn custom_param(name: &str) -> Option<(&'static str, &'static str)> {
+    PARAMS.read().unwrap().iter().find(|&&(n, _)| n == name).cloned()
+}
+
+/// What the route that matched a request was declared as, available to
+/// handlers and middleware through `Request::matched_route`.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Request, Response};
+/// use canteen::utils;
+///
+/// fn handler(req: &Request) -> Response {
+///     let template = req.matched_route().map(|r| r.template()).unwrap_or("unmatched");
+///
+///     utils::text(format!("served by {}", template))
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedRoute {
+    template: String,
+    name:     Option<String>,
+    method:   Method,
+    params:   Vec<(String, ParamType)>,
+    meta:     RouteMeta,
+}
+
+impl MatchedRoute {
+    /// The path the route was declared with, e.g. `/user/<int:id>`.
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// The name given with `RouteOptions::name`, if any.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The method the route was declared for.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// The route's parameters and their types, in the order they appear in
+    /// the path.
+    pub fn params(&self) -> &[(String, ParamType)] {
+        &self.params
+    }
+
+    /// The declared type of the parameter `name`.
+    pub fn param_type(&self, name: &str) -> Option<ParamType> {
+        self.params.iter().find(|(p, _)| p == name).map(|&(_, t)| t)
+    }
+
+    /// The tags given with `RouteOptions::tag`.
+    pub fn tags(&self) -> &[String] {
+        &self.meta.tags
+    }
+
+    /// Whether the route was given the tag `tag`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.meta.tags.iter().any(|t| t == tag)
+    }
+
+    /// The value of type `T` given with `RouteOptions::meta`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Request, Response};
+    /// use canteen::middleware::Middleware;
+    /// use canteen::utils;
+    ///
+    /// struct Scope(&'static str);
+    /// struct RequireScope;
+    ///
+    /// impl Middleware for RequireScope {
+    ///     fn before(&self, req: &mut Request) -> Option<Response> {
+    ///         let scope = req.matched_route().and_then(|r| r.meta::<Scope>())?;
+    ///
+    ///         match req.get_header("X-Scopes") {
+    ///             Some(ref have) if have.split(' ').any(|s| s == scope.0) => None,
+    ///             _                                                     => Some(utils::err_403(req)),
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn meta<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.meta.values.get(&TypeId::of::<T>()).and_then(|v| v.downcast_ref())
+    }
+}
+
+// checks a successful response body against what the route declared it
+// returns, in debug mode. the error lists what's wrong.
+pub(crate) struct ResponseContract(pub(crate) Box<CheckBody>);
+
+type CheckBody = dyn Fn(&[u8]) -> Result<(), Vec<String>> + Send + Sync;
+
+/// Tags and typed values attached to a route with `RouteOptions`.
+#[derive(Clone, Default)]
+pub struct RouteMeta {
+    tags:   Vec<String>,
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl fmt::Debug for RouteMeta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RouteMeta {{ tags: {:?}, values: {} }}", self.tags, self.values.len())
+    }
+}
+
+// values can't be compared, so metadata is equal if it has the same tags
+// and the same types of value.
+impl PartialEq for RouteMeta {
+    fn eq(&self, other: &RouteMeta) -> bool {
+        self.tags == other.tags
+            && self.values.len() == other.values.len()
+            && self.values.keys().all(|k| other.values.contains_key(k))
+    }
 }
 
 /// This struct represents a route definition. It is only necessary for
@@ -32,54 +295,278 @@ pub struct RouteDef {
     pub method:  Method,
 }
 
+/// Options for a single route, passed to `Canteen::add_route_with`. Options
+/// that aren't set fall back to the server-wide defaults.
+#[derive(Debug, Clone, Default)]
+pub struct RouteOptions {
+    pub(crate) timeout:  Option<Duration>,
+    pub(crate) max_body: Option<usize>,
+    pub(crate) name:     Option<String>,
+    pub(crate) meta:     RouteMeta,
+    pub(crate) chain:    Chain,
+    pub(crate) limiter:  Option<Arc<RateLimiter>>,
+}
+
+impl RouteOptions {
+    /// Create a set of route options with the defaults.
+    pub fn new() -> RouteOptions {
+        RouteOptions::default()
+    }
+
+    /// Set how long the handler may run before the client is sent a
+    /// `504 Gateway Timeout`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::route::RouteOptions;
+    ///
+    /// // reports are slow, give them a minute
+    /// let opts = RouteOptions::new().timeout(Duration::from_secs(60));
+    /// ```
+    pub fn timeout(mut self, timeout: Duration) -> RouteOptions {
+        self.timeout = Some(timeout);
+
+        self
+    }
+
+    /// Set the largest request body, in bytes, the route accepts. Larger
+    /// requests get a `413 Content Too Large` without the handler
+    /// being run.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::route::RouteOptions;
+    ///
+    /// let opts = RouteOptions::new().max_body(64 * 1024 * 1024);
+    /// ```
+    pub fn max_body(mut self, bytes: usize) -> RouteOptions {
+        self.max_body = Some(bytes);
+
+        self
+    }
+
+    /// Name the route, so middleware and logging can refer to it by
+    /// something steadier than its path, and `Canteen::url_for` can build
+    /// links to it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::route::RouteOptions;
+    ///
+    /// let opts = RouteOptions::new().name("user_detail");
+    /// ```
+    pub fn name(mut self, name: &str) -> RouteOptions {
+        self.name = Some(String::from(name));
+
+        self
+    }
+
+    /// Tag the route, for middleware to check with `MatchedRoute::has_tag`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::route::RouteOptions;
+    ///
+    /// let opts = RouteOptions::new().tag("admin").tag("audited");
+    /// ```
+    pub fn tag(mut self, tag: &str) -> RouteOptions {
+        self.meta.tags.push(String::from(tag));
+
+        self
+    }
+
+    /// Attach a typed value to the route, such as the scopes it requires or
+    /// a summary for generated docs, for middleware to read with
+    /// `MatchedRoute::meta`. A later value of the same type replaces an
+    /// earlier one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::route::RouteOptions;
+    ///
+    /// struct RateClass(&'static str);
+    ///
+    /// let opts = RouteOptions::new().meta(RateClass("expensive"));
+    /// ```
+    pub fn meta<T: Any + Send + Sync>(mut self, value: T) -> RouteOptions {
+        self.meta.values.insert(TypeId::of::<T>(), Arc::new(value));
+
+        self
+    }
+
+    /// Limit how often each client may call the route, on top of any
+    /// server-wide limit set with `Canteen::limit_requests`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use canteen::ratelimit::RateLimiter;
+    /// use canteen::route::RouteOptions;
+    ///
+    /// let opts = RouteOptions::new().limit_requests(RateLimiter::new(5, Duration::from_secs(60)));
+    /// ```
+    pub fn limit_requests(mut self, limiter: RateLimiter) -> RouteOptions {
+        self.limiter = Some(Arc::new(limiter));
+
+        self
+    }
+
+    /// Run a middleware around this route's handler only. It runs inside
+    /// the server-wide middleware and those of any group the route is in,
+    /// in the order added.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::{Canteen, Method, Request, Response};
+    /// use canteen::middleware::Middleware;
+    /// use canteen::route::RouteOptions;
+    /// use canteen::utils;
+    ///
+    /// struct AuthRequired;
+    ///
+    /// impl Middleware for AuthRequired {
+    ///     fn before(&self, req: &mut Request) -> Option<Response> {
+    ///         match req.get_header("Authorization") {
+    ///             Some(_) => None,
+    ///             None    => Some(utils::err_403(req)),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut cnt = Canteen::new();
+    /// cnt.add_route_with("/admin", Method::Get, |_: &Request| utils::text("hi"),
+    ///                    RouteOptions::new().with(AuthRequired));
+    /// ```
+    pub fn with<M: Middleware + 'static>(mut self, mw: M) -> RouteOptions {
+        self.chain.0.push(Arc::new(mw));
+
+        self
+    }
+
+    /// Declare that the route's successful responses are JSON that
+    /// deserializes as `T`. In debug mode, see `Canteen::enable_debug`,
+    /// responses that don't are logged and replaced with a `500`, so
+    /// contract drift shows up in development and CI.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use serde_derive::Deserialize;
+    /// use canteen::route::RouteOptions;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct User {
+    ///     id:   u32,
+    ///     name: String,
+    /// }
+    ///
+    /// let opts = RouteOptions::new().response_type::<User>();
+    /// ```
+    pub fn response_type<T: DeserializeOwned + 'static>(self) -> RouteOptions {
+        self.meta(ResponseContract(Box::new(|body| {
+            serde_json::from_slice::<T>(body).map(|_| ()).map_err(|e| vec![e.to_string()])
+        })))
+    }
+}
+
+/// A request handler: a function, or a closure that may capture state such
+/// as a database pool. Handlers returning a plain `Response` are wrapped to
+/// fit.
+pub type HandlerFn = Arc<dyn Fn(&Request) -> Result<Response, Error> + Send + Sync>;
+
 /// This struct defines a route or endpoint.
 pub struct Route {
     matcher:     Regex,
     method:      Method,
     params:      HashMap<String, ParamType>,
-    pub handler: fn(&Request) -> Response,
+    segments:    Vec<Segment>,
+    pub handler: HandlerFn,
+    pub stats:   Arc<RouteStats>,
+    pub opts:    RouteOptions,
+    pub info:    Arc<MatchedRoute>,
 }
 
 impl Route {
     /// Create a new Route. This function is called by the Canteen struct.
-    pub fn new(path: &str, method: Method, handler: fn(&Request) -> Response) -> Route {
-        let re = Regex::new(r"^<(?:(int|uint|str|float|path):)?([\w_][a-zA-Z0-9_]*)>$").unwrap();
+    pub fn new<H, R>(path: &str, method: Method, handler: H) -> Route
+        where H: Fn(&Request) -> R + Send + Sync + 'static,
+              R: IntoResult {
+        Route::shared(path, method, Arc::new(move |req: &Request| handler(req).into_result()))
+    }
+
+    // create a Route with a handler that may also serve other routes.
+    //
+    // panics if a parameter is declared with a type that isn't registered.
+    pub(crate) fn shared(path: &str, method: Method, handler: HandlerFn) -> Route {
+        let re = Regex::new(r#"^<(?:(\w+):|re"(.+)":)?([\w_][a-zA-Z0-9_]*)>$"#).unwrap();
         let parts: Vec<&str> = path.split('/').filter(|&s| s != "").collect();
         let mut matcher: String = String::from(r"^");
         let mut params: HashMap<String, ParamType> = HashMap::new();
+        let mut declared: Vec<(String, ParamType)> = Vec::new();
+        let mut segments: Vec<Segment> = Vec::new();
 
         for part in parts {
             let chunk: String = if re.is_match(part) {
                 let caps = re.captures(part).unwrap();
-                let param = caps.get(2).unwrap().as_str();
+                let param = caps.get(3).unwrap().as_str();
+                let inline = caps.get(2).map(|m| m.as_str());
                 let ptype: ParamType = match caps.get(1) {
-                    Some(x)     => {
-                        match x.as_str() {
-                            "int"   => ParamType::Integer,
-                            "uint"  => ParamType::Unsigned,
-                            "float" => ParamType::Float,
-                            "path"  => ParamType::Path,
-                            "str"   => ParamType::String,
-                            _       => ParamType::String,
-
+                    Some(x)                  => {
+                        match (builtin(x.as_str()), custom_param(x.as_str())) {
+                            (Some(ptype), _)        => ptype,
+                            (None, Some((name, _))) => ParamType::Custom(name),
+                            (None, None)            => panic!("unknown parameter type {:?} in route {}", x.as_str(), path),
                         }
                     }
-                    None        => ParamType::String,
+                    None if inline.is_some() => ParamType::Pattern,
+                    None                     => ParamType::String,
                 };
 
                 let mstr: String = match ptype {
                     ParamType::String   => String::from(r"(?:[^/])+"),
                     ParamType::Integer  => String::from(r"-*[0-9]+"),
                     ParamType::Unsigned => String::from(r"[0-9]+"),
+                    ParamType::I64      => String::from(r"-?[0-9]+"),
+                    ParamType::U64      => String::from(r"[0-9]+"),
                     ParamType::Float    => String::from(r"-*[0-9]*[.]?[0-9]+"),
                     ParamType::Path     => String::from(r".+"),
+                    #[cfg(feature = "uuid")]
+                    ParamType::Uuid     => String::from(r"[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}"),
+                    ParamType::Pattern  => {
+                        let inline = inline.unwrap_or_default();
+
+                        if let Err(e) = Regex::new(inline) {
+                            panic!("bad pattern for parameter {} in route {}: {}", param, path, e);
+                        }
+
+                        format!("(?:{})", inline)
+                    },
+                    ParamType::Custom(name) => {
+                        format!("(?:{})", custom_param(name).map(|(_, pattern)| pattern).unwrap_or_default())
+                    },
                 };
 
+                declared.push((String::from(param), ptype));
                 params.insert(String::from(param), ptype);
+                segments.push(match ptype {
+                    ParamType::String => Segment::Param(None),
+                    ParamType::Path   => Segment::Path,
+                    _                 => Segment::Param(Some(mstr.clone())),
+                });
 
                 format!("/(?P<{}>{})", &param, &mstr)
             } else {
-                String::from("/") + part
+                segments.push(Segment::Static(String::from(part)));
+
+                String::from("/") + &regex::escape(part)
             };
 
             matcher.push_str(&chunk);
@@ -91,16 +578,113 @@ impl Route {
         Route {
             matcher: Regex::new(&matcher).unwrap(),
             params,
+            segments,
             method,
             handler,
+            stats:   Arc::new(RouteStats::new(path, method)),
+            opts:    RouteOptions::default(),
+            info:    Arc::new(MatchedRoute {
+                template: String::from(path),
+                name:     None,
+                method,
+                params:   declared,
+                meta:     RouteMeta::default(),
+            }),
+        }
+    }
+
+    /// Apply a route's options, including its name and metadata.
+    pub fn set_options(&mut self, opts: RouteOptions) {
+        let info = Arc::make_mut(&mut self.info);
+
+        info.name = opts.name.clone();
+        info.meta = opts.meta.clone();
+        self.opts = opts;
+    }
+
+    /// Whether `path` ends with a slash exactly when the route's pattern
+    /// does. Any query string is ignored.
+    pub fn slash_matches(&self, path: &str) -> bool {
+        let path = path.split('?').next().unwrap_or("");
+
+        path.ends_with('/') == self.info.template.ends_with('/')
+    }
+
+    /// `path` with its trailing slash added or removed to match the
+    /// route's pattern, without any query string.
+    pub fn canonical(&self, path: &str) -> String {
+        let path = path.split('?').next().unwrap_or("").trim_end_matches('/');
+
+        match self.info.template.ends_with('/') {
+            true  => format!("{}/", path),
+            false => String::from(path),
         }
     }
 
+    // the route's path, segment by segment, for the router.
+    pub(crate) fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    // the parameters found by the router, named.
+    pub(crate) fn bind(&self, values: &[&str]) -> HashMap<String, String> {
+        self.info.params.iter().zip(values).map(|((name, _), value)| (name.clone(), String::from(*value))).collect()
+    }
+
     /// Check if this Route matches a given URI.
     pub fn is_match(&self, req: &Request) -> bool {
         self.matcher.is_match(&req.path) && self.method == req.method
     }
 
+    /// Check if this Route's path matches a given URI, whatever the method.
+    pub fn is_path_match(&self, path: &str) -> bool {
+        self.matcher.is_match(path)
+    }
+
+    /// Build the path for this route with the parameters `params`, the
+    /// reverse of `parse`. Values are percent-encoded, keeping the slashes
+    /// in `path` parameters. Returns `None` if a parameter is missing, or a
+    /// value doesn't fit its type.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::Method;
+    /// use canteen::route::Route;
+    /// use canteen::utils;
+    ///
+    /// let route = Route::new("/user/<int:id>/<name>", Method::Get, utils::err_404);
+    ///
+    /// assert_eq!(Some(String::from("/user/7/Jo%20Ann")), route.url(&[("id", "7"), ("name", "Jo Ann")]));
+    /// assert_eq!(None, route.url(&[("id", "seven"), ("name", "jo")]));
+    /// ```
+    pub fn url(&self, params: &[(&str, &str)]) -> Option<String> {
+        let mut parts = Vec::new();
+
+        for part in self.info.template.split('/') {
+            let name = match part.strip_prefix('<').and_then(|p| p.strip_suffix('>')) {
+                Some(inner) => inner.rsplit(':').next().unwrap_or(inner),
+                None        => {
+                    parts.push(String::from(part));
+                    continue;
+                },
+            };
+            let value = params.iter().find(|&&(k, _)| k == name)?.1;
+
+            parts.push(match self.params.get(name) {
+                Some(ParamType::Path) => value.split('/').map(utils::encode_segment).collect::<Vec<_>>().join("/"),
+                _                     => utils::encode_segment(value),
+            });
+        }
+
+        let url = parts.join("/");
+
+        match self.matcher.is_match(&url) {
+            true  => Some(url),
+            false => None,
+        }
+    }
+
     /// Parse and extract the variables from a URI based on this Route's definition.
     pub fn parse(&self, path: &str) -> HashMap<String, String> {
         let mut params: HashMap<String, String> = HashMap::new();
@@ -142,6 +726,116 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_route_url() {
+        let route = Route::new("/files/<path:rest>/", Method::Get, utils::err_404);
+
+        assert_eq!(Some(String::from("/files/a%20b/c.txt/")), route.url(&[("rest", "a b/c.txt")]));
+        assert_eq!(None, route.url(&[]));
+        assert_eq!(Some(String::from("/static")), Route::new("/static", Method::Get, utils::err_404).url(&[]));
+
+        let route = Route::new("/n/<uint:n>/<float:x>", Method::Get, utils::err_404);
+
+        assert_eq!(Some(String::from("/n/3/0.5")), route.url(&[("x", "0.5"), ("n", "3"), ("extra", "1")]));
+        assert_eq!(None, route.url(&[("n", "-3"), ("x", "0.5")]));
+    }
+
+    struct Hex(u32);
+
+    impl FromParam for Hex {
+        const NAME: &'static str = "hex";
+        const PATTERN: &'static str = "[0-9a-f]+";
+
+        fn from_param(value: &str) -> Option<Hex> {
+            u32::from_str_radix(value, 16).ok().map(Hex)
+        }
+    }
+
+    #[test]
+    fn test_custom_param() {
+        register_param::<Hex>();
+
+        let route = Route::new("/color/<hex:rgb>", Method::Get, utils::err_404);
+        let mut req = Request::new();
+
+        assert_eq!(Some(ParamType::Custom("hex")), route.info.param_type("rgb"));
+        assert!(route.is_path_match("/color/ff8000"));
+        assert!(!route.is_path_match("/color/orange"));
+        assert_eq!(Some(String::from("/color/ff")), route.url(&[("rgb", "ff")]));
+
+        req.params = route.parse("/color/ff8000");
+        let hex: Hex = req.get("rgb");
+        assert_eq!(0xff8000, hex.0);
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn test_uuid_param() {
+        let route = Route::new("/order/<uuid:id>", Method::Get, utils::err_404);
+        let mut req = Request::new();
+
+        assert!(route.is_path_match("/order/67E55044-10b1-426f-9247-bb680e5fe0c8"));
+        assert!(!route.is_path_match("/order/67e5504410b1426f9247bb680e5fe0c8"));
+        assert!(!route.is_path_match("/order/67e55044-10b1-426f-9247-bb680e5fe0c"));
+
+        req.params = route.parse("/order/67e55044-10b1-426f-9247-bb680e5fe0c8");
+        let id: uuid::Uuid = req.get("id");
+        assert_eq!(uuid::uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8"), id);
+    }
+
+    #[test]
+    fn test_64_bit_params() {
+        let route = Route::new("/row/<i64:id>/<u64:ver>", Method::Get, utils::err_404);
+        let mut req = Request::new();
+
+        assert!(!route.is_path_match("/row/1/-2"));
+        req.params = route.parse("/row/-9007199254740993/18446744073709551615");
+
+        let id: i64 = req.get("id");
+        let ver: u64 = req.get("ver");
+        assert_eq!(-9007199254740993, id);
+        assert_eq!(u64::MAX, ver);
+    }
+
+    #[test]
+    fn test_inline_pattern() {
+        let route = Route::new(r#"/lang/<re"[a-z]{2}(-[A-Z]{2})?":code>/<re"v\d+":version>"#, Method::Get, utils::err_404);
+
+        assert_eq!(Some(ParamType::Pattern), route.info.param_type("code"));
+        assert!(route.is_path_match("/lang/en-GB/v2"));
+        assert!(!route.is_path_match("/lang/english/v2"));
+        assert!(!route.is_path_match("/lang/en/2"));
+        assert_eq!("en-GB", route.parse("/lang/en-GB/v2")["code"]);
+        assert_eq!(Some(String::from("/lang/fr/v10")), route.url(&[("code", "fr"), ("version", "v10")]));
+        assert_eq!(None, route.url(&[("code", "french"), ("version", "v10")]));
+    }
+
+    #[test]
+    #[should_panic(expected = "bad pattern")]
+    fn test_bad_inline_pattern() {
+        Route::new(r#"/lang/<re"[a-z":code>"#, Method::Get, utils::err_404);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown parameter type")]
+    fn test_unknown_param_type() {
+        Route::new("/color/<colour:rgb>", Method::Get, utils::err_404);
+    }
+
+    #[test]
+    fn test_trailing_slash() {
+        let bare = Route::new("/a/<b>", Method::Get, utils::err_404);
+        let slashed = Route::new("/a/<b>/", Method::Get, utils::err_404);
+        let root = Route::new("/", Method::Get, utils::err_404);
+
+        assert!(bare.slash_matches("/a/x?y=/"));
+        assert!(!bare.slash_matches("/a/x/"));
+        assert!(slashed.slash_matches("/a/x/?y"));
+        assert!(root.slash_matches("/"));
+        assert_eq!("/a/x", bare.canonical("/a/x/?y=1"));
+        assert_eq!("/a/x/", slashed.canonical("/a/x"));
+    }
+
     #[test]
     fn test_route_match_simple() {
         let route = Route::new("/api/v1/foo/<foo_stuff>", Method::Get, utils::err_404);
@@ -212,4 +906,35 @@ mod tests {
         assert_eq!(42, foo_id);
         assert_eq!(1234, bar_id);
     }
+
+    #[test]
+    fn test_route_info() {
+        let mut route = Route::new("/user/<int:id>/files/<path:rest>", Method::Get, utils::err_404);
+
+        assert_eq!("/user/<int:id>/files/<path:rest>", route.info.template());
+        assert_eq!(None, route.info.name());
+        assert_eq!(vec![(String::from("id"), ParamType::Integer), (String::from("rest"), ParamType::Path)],
+                   route.info.params());
+        assert_eq!(Some(ParamType::Path), route.info.param_type("rest"));
+        assert_eq!(None, route.info.param_type("name"));
+
+        route.set_options(RouteOptions::new().name("user_files"));
+        assert_eq!(Some("user_files"), route.info.name());
+    }
+
+    #[test]
+    fn test_route_meta() {
+        #[derive(Debug, PartialEq)]
+        struct Scopes(Vec<&'static str>);
+
+        let mut route = Route::new("/admin", Method::Post, utils::err_404);
+
+        route.set_options(RouteOptions::new().tag("admin").meta(7u32).meta(Scopes(vec!["write"])).meta(8u32));
+
+        assert!(route.info.has_tag("admin"));
+        assert!(!route.info.has_tag("public"));
+        assert_eq!(Some(&Scopes(vec!["write"])), route.info.meta::<Scopes>());
+        assert_eq!(Some(&8u32), route.info.meta::<u32>());
+        assert_eq!(None, route.info.meta::<String>());
+    }
 }
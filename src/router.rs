@@ -0,0 +1,343 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! The route table's lookup structure: a tree keyed by path segment, so
+//! finding the routes for a path costs about the same however many routes
+//! there are. Static segments are looked up directly, and only parameters
+//! with a constrained type run a regular expression, against a single
+//! segment.
+
+use std::collections::{BTreeMap, HashMap};
+use regex::Regex;
+
+use crate::route::RouteDef;
+
+// one segment of a route's path, between slashes.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Segment {
+    // matched exactly.
+    Static(String),
+    // a parameter matching any segment, or only those matching a pattern.
+    Param(Option<String>),
+    // a `path` parameter, matching one or more whole segments.
+    Path,
+}
+
+// a route that matched a path, with its parameters' values in the order
+// they appear.
+#[derive(Debug)]
+pub(crate) struct Match<'r, 'p> {
+    pub(crate) def:    &'r RouteDef,
+    pub(crate) values: Vec<&'p str>,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    params:  Vec<(Option<String>, Option<Regex>, Node)>,
+    path:    Option<Box<Node>>,
+    routes:  Vec<RouteDef>,
+}
+
+impl Node {
+    fn insert(&mut self, segments: &[Segment], def: RouteDef) {
+        let (first, rest) = match segments.split_first() {
+            Some(split) => split,
+            None        => return self.routes.push(def),
+        };
+
+        let child = match *first {
+            Segment::Static(ref text)   => self.statics.entry(text.clone()).or_default(),
+            Segment::Param(ref pattern) => {
+                let at = match self.params.iter().position(|(p, _, _)| p == pattern) {
+                    Some(at) => at,
+                    None     => {
+                        let re = pattern.as_ref().map(|p| Regex::new(&format!("^(?:{})$", p)).unwrap());
+                        // constrained parameters go before those matching anything
+                        let at = match re {
+                            Some(_) => self.params.iter().position(|(p, _, _)| p.is_none()).unwrap_or(self.params.len()),
+                            None    => self.params.len(),
+                        };
+
+                        self.params.insert(at, (pattern.clone(), re, Node::default()));
+                        at
+                    },
+                };
+
+                &mut self.params[at].2
+            },
+            Segment::Path               => self.path.get_or_insert_with(Box::default),
+        };
+
+        child.insert(rest, def);
+    }
+
+    // collects the routes under this node matching `spans` of `path`, most
+    // specific first: static segments, then parameters with a pattern in
+    // the order they were added, then any others, then `path` parameters,
+    // shortest first so that routes with more segments after them come
+    // before those without.
+    fn lookup<'r, 'p>(&'r self, path: &'p str, spans: &[(usize, usize)], slash: bool,
+                      values: &mut Vec<&'p str>, found: &mut Vec<Match<'r, 'p>>) {
+        let (&(start, end), rest) = match spans.split_first() {
+            Some(split) => split,
+            None        => {
+                for def in &self.routes {
+                    found.push(Match { def, values: values.clone() });
+                }
+
+                return;
+            },
+        };
+        let segment = &path[start..end];
+
+        if let Some(child) = self.statics.get(segment) {
+            child.lookup(path, rest, slash, values, found);
+        }
+
+        for (_, re, child) in &self.params {
+            let fits = match *re {
+                Some(ref re) => re.is_match(segment),
+                None         => !segment.is_empty(),
+            };
+
+            if fits {
+                values.push(segment);
+                child.lookup(path, rest, slash, values, found);
+                values.pop();
+            }
+        }
+
+        if let Some(ref child) = self.path {
+            for taken in 1..=spans.len() {
+                // a path parameter that runs to the end keeps the final slash
+                let last = match taken == spans.len() && slash {
+                    true  => spans[taken - 1].1 + 1,
+                    false => spans[taken - 1].1,
+                };
+
+                if last > start {
+                    values.push(&path[start..last]);
+                    child.lookup(path, &spans[taken..], slash, values, found);
+                    values.pop();
+                }
+            }
+        }
+    }
+}
+
+// the routes, arranged for lookup by path.
+#[derive(Debug, Default)]
+pub(crate) struct Router {
+    root: Node,
+}
+
+impl Router {
+    pub(crate) fn new() -> Router {
+        Router::default()
+    }
+
+    // adds the route `def`, made up of `segments`.
+    pub(crate) fn insert(&mut self, segments: &[Segment], def: RouteDef) {
+        self.root.insert(segments, def);
+    }
+
+    // the routes whose paths match `path`, whatever their method, most
+    // specific first. a path matches with or without a trailing slash, and
+    // any query string is left out.
+    pub(crate) fn lookup<'r, 'p>(&'r self, path: &'p str) -> Vec<Match<'r, 'p>> {
+        let path = path.split('?').next().unwrap_or("");
+        let mut found = Vec::new();
+        let mut spans = Vec::new();
+        let mut slash = false;
+
+        if path.len() > 1 {
+            let inner = match path.strip_prefix('/') {
+                Some(inner) => inner,
+                None        => return found,
+            };
+            let inner = match inner.strip_suffix('/') {
+                Some(inner) => {
+                    slash = true;
+                    inner
+                },
+                None        => inner,
+            };
+            let mut start = 1;
+
+            for segment in inner.split('/') {
+                spans.push((start, start + segment.len()));
+                start += segment.len() + 1;
+            }
+        } else if !path.is_empty() && path != "/" {
+            return found;
+        }
+
+        self.root.lookup(path, &spans, slash, &mut Vec::new(), &mut found);
+
+        found
+    }
+}
+
+// the routes found for recent request paths, so repeat requests skip the
+// lookup. it holds at most `capacity` entries, dropping the least recently
+// used to make room; a capacity of zero turns it off.
+#[derive(Debug)]
+pub(crate) struct RouteCache {
+    capacity: usize,
+    tick:     u64,
+    entries:  HashMap<RouteDef, (RouteDef, u64)>,
+    order:    BTreeMap<u64, RouteDef>,
+}
+
+impl RouteCache {
+    pub(crate) fn new(capacity: usize) -> RouteCache {
+        RouteCache {
+            capacity,
+            tick:    0,
+            entries: HashMap::new(),
+            order:   BTreeMap::new(),
+        }
+    }
+
+    // the route cached for `key`, which becomes the most recently used.
+    pub(crate) fn get(&mut self, key: &RouteDef) -> Option<&RouteDef> {
+        let (_, used) = self.entries.get_mut(key)?;
+
+        self.tick += 1;
+        self.order.remove(used);
+        self.order.insert(self.tick, key.clone());
+        *used = self.tick;
+
+        self.entries.get(key).map(|(def, _)| def)
+    }
+
+    pub(crate) fn insert(&mut self, key: RouteDef, def: RouteDef) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.tick += 1;
+
+        if let Some((_, used)) = self.entries.insert(key.clone(), (def, self.tick)) {
+            self.order.remove(&used);
+        }
+
+        self.order.insert(self.tick, key);
+        self.trim();
+    }
+
+    // change the capacity, dropping entries that no longer fit.
+    pub(crate) fn resize(&mut self, capacity: usize) {
+        if capacity != self.capacity {
+            self.capacity = capacity;
+            self.trim();
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    fn trim(&mut self) {
+        while self.entries.len() > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(&oldest) => oldest,
+                None          => break,
+            };
+
+            if let Some(key) = self.order.remove(&oldest) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Method;
+
+    fn def(path: &str) -> RouteDef {
+        RouteDef { pathdef: String::from(path), method: Method::Get }
+    }
+
+    fn matches(router: &Router, path: &str) -> Vec<(String, Vec<String>)> {
+        router.lookup(path)
+              .into_iter()
+              .map(|m| (m.def.pathdef.clone(), m.values.iter().map(|v| String::from(*v)).collect()))
+              .collect()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut router = Router::new();
+        let digits = Some(String::from("[0-9]+"));
+
+        router.insert(&[], def("/"));
+        router.insert(&[Segment::Static(String::from("u")), Segment::Param(None)], def("/u/<name>"));
+        router.insert(&[Segment::Static(String::from("u")), Segment::Param(digits)], def("/u/<int:id>"));
+        router.insert(&[Segment::Static(String::from("u")), Segment::Static(String::from("me"))], def("/u/me"));
+        router.insert(&[Segment::Static(String::from("f")), Segment::Path, Segment::Static(String::from("raw"))], def("/f/<path:p>/raw"));
+        router.insert(&[Segment::Static(String::from("f")), Segment::Path], def("/f/<path:p>"));
+
+        assert_eq!(vec![(String::from("/"), vec![])], matches(&router, "/"));
+        assert_eq!(vec![(String::from("/"), vec![])], matches(&router, ""));
+        assert!(matches(&router, "//").is_empty());
+        assert!(matches(&router, "u/me").is_empty());
+
+        let found = matches(&router, "/u/me/");
+        assert_eq!(vec!["/u/me", "/u/<name>"], found.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>());
+
+        let found = matches(&router, "/u/42");
+        assert_eq!((String::from("/u/<int:id>"), vec![String::from("42")]), found[0]);
+        assert_eq!((String::from("/u/<name>"), vec![String::from("42")]), found[1]);
+
+        let found = matches(&router, "/f/a/b/raw/");
+        assert_eq!((String::from("/f/<path:p>/raw"), vec![String::from("a/b")]), found[0]);
+        assert_eq!((String::from("/f/<path:p>"), vec![String::from("a/b/raw/")]), found[1]);
+
+        assert!(matches(&router, "/u/").is_empty());
+        assert!(matches(&router, "/u//").is_empty());
+    }
+
+    #[test]
+    fn test_lookup_query() {
+        let mut router = Router::new();
+
+        router.insert(&[Segment::Static(String::from("search"))], def("/search"));
+        router.insert(&[Segment::Static(String::from("u")), Segment::Param(None)], def("/u/<name>"));
+
+        assert_eq!(vec![(String::from("/search"), vec![])], matches(&router, "/search?q=rust"));
+        assert_eq!(vec![(String::from("/search"), vec![])], matches(&router, "/search/?q=a/b"));
+        assert_eq!(vec![(String::from("/u/<name>"), vec![String::from("bob")])], matches(&router, "/u/bob?x=1"));
+        assert_eq!(1, matches(&router, "/u/bob?").len());
+    }
+
+    #[test]
+    fn test_route_cache() {
+        let mut cache = RouteCache::new(2);
+
+        cache.insert(def("/a"), def("/<x>"));
+        cache.insert(def("/b"), def("/<x>"));
+        assert!(cache.get(&def("/a")).is_some());
+
+        cache.insert(def("/c"), def("/<x>"));
+        assert_eq!(2, cache.entries.len());
+        assert!(cache.get(&def("/b")).is_none());
+        assert_eq!(Some(&def("/<x>")), cache.get(&def("/a")));
+
+        cache.resize(1);
+        assert_eq!(1, cache.entries.len());
+        assert!(cache.get(&def("/a")).is_some());
+
+        cache.resize(0);
+        cache.insert(def("/d"), def("/<x>"));
+        assert_eq!(0, cache.entries.len());
+    }
+}
@@ -0,0 +1,516 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Validation of JSON request bodies against a JSON Schema. The commonly
+//! used keywords are supported: `type`, `enum`, `const`, `properties`,
+//! `required`, `additionalProperties`, `items`, the length, size and range
+//! limits, `pattern`, `allOf`/`anyOf`/`oneOf`/`not`, and local `$ref`s into
+//! `$defs` or `definitions`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use regex::Regex;
+use serde_derive::Serialize;
+use serde_json::{Map, Value};
+
+use crate::middleware::Middleware;
+use crate::request::Request;
+use crate::response::Response;
+use crate::route::{ResponseContract, RouteOptions};
+
+/// Why a schema couldn't be loaded.
+#[derive(Debug)]
+pub enum SchemaError {
+    /// The file couldn't be read.
+    Io(io::Error),
+    /// The file isn't JSON.
+    Json(serde_json::Error),
+    /// The schema uses a keyword wrongly, e.g. a `pattern` that isn't a
+    /// valid regex.
+    Invalid(String),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SchemaError::Io(ref e)      => write!(f, "unable to read schema: {}", e),
+            SchemaError::Json(ref e)    => write!(f, "schema isn't valid JSON: {}", e),
+            SchemaError::Invalid(ref m) => write!(f, "invalid schema: {}", m),
+        }
+    }
+}
+
+impl Error for SchemaError {}
+
+/// A place where a value doesn't match its schema.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationError {
+    /// A JSON pointer to the value, e.g. `/items/2/name`; empty for the
+    /// whole document.
+    pub path:    String,
+    /// What's wrong with it.
+    pub message: String,
+}
+
+/// A JSON Schema, checked and ready to validate against.
+///
+/// # Examples
+///
+/// ```rust
+/// use serde_json::json;
+/// use canteen::schema::Schema;
+///
+/// let schema = Schema::from_value(json!({
+///     "type": "object",
+///     "required": ["name"],
+///     "properties": { "name": { "type": "string", "minLength": 1 } },
+/// })).unwrap();
+///
+/// assert!(schema.validate(&json!({ "name": "jo" })).is_ok());
+/// assert_eq!("/name", schema.validate(&json!({ "name": "" })).unwrap_err()[0].path);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Schema {
+    root:     Value,
+    patterns: HashMap<String, Regex>,
+}
+
+impl Schema {
+    /// Load a schema from a JSON value.
+    pub fn from_value(root: Value) -> Result<Schema, SchemaError> {
+        let mut patterns = HashMap::new();
+
+        compile(&root, &mut patterns)?;
+
+        Ok(Schema { root, patterns })
+    }
+
+    /// Load a schema from a file, usually once at startup.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Schema, SchemaError> {
+        std::fs::read_to_string(path).map_err(SchemaError::Io)?.parse()
+    }
+
+    /// Check `value` against the schema, returning every mismatch.
+    pub fn validate(&self, value: &Value) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+
+        self.check(&self.root, value, "", &mut errors, 0);
+
+        match errors.is_empty() {
+            true  => Ok(()),
+            false => Err(errors),
+        }
+    }
+
+    // follow a local `$ref` like `#/$defs/user`.
+    fn resolve(&self, reference: &str) -> Option<&Value> {
+        let pointer = reference.strip_prefix('#')?;
+
+        match pointer.is_empty() {
+            true  => Some(&self.root),
+            false => self.root.pointer(pointer),
+        }
+    }
+
+    fn check(&self, schema: &Value, value: &Value, path: &str, errors: &mut Vec<ValidationError>, depth: usize) {
+        let fail = |errors: &mut Vec<ValidationError>, message: String| {
+            errors.push(ValidationError { path: String::from(path), message })
+        };
+
+        let schema = match schema {
+            Value::Bool(true)    => return,
+            Value::Bool(false)   => return fail(errors, String::from("no value is allowed here")),
+            Value::Object(map)   => map,
+            _                    => return,
+        };
+
+        if let Some(Value::String(reference)) = schema.get("$ref") {
+            match self.resolve(reference) {
+                // a schema referring to itself forever would never finish
+                Some(target) if depth < 64 => self.check(target, value, path, errors, depth + 1),
+                _                          => fail(errors, format!("unresolvable reference {}", reference)),
+            }
+            return;
+        }
+
+        if let Some(types) = schema.get("type") {
+            let names: Vec<&str> = match types {
+                Value::String(t) => vec![t.as_str()],
+                Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+                _                => Vec::new(),
+            };
+
+            if !names.iter().any(|t| is_type(value, t)) {
+                return fail(errors, format!("expected {}, found {}", names.join(" or "), type_name(value)));
+            }
+        }
+
+        if let Some(Value::Array(options)) = schema.get("enum") {
+            if !options.contains(value) {
+                fail(errors, String::from("value isn't one of the allowed values"));
+            }
+        }
+
+        if let Some(expected) = schema.get("const") {
+            if expected != value {
+                fail(errors, format!("expected {}", expected));
+            }
+        }
+
+        match value {
+            Value::String(s)  => self.check_string(schema, s, &mut |m| fail(errors, m)),
+            Value::Number(n)  => check_number(schema, n.as_f64().unwrap_or(0.0), &mut |m| fail(errors, m)),
+            Value::Array(a)   => {
+                check_size(schema, a.len(), "minItems", "maxItems", "items", &mut |m| fail(errors, m));
+
+                if let Some(items) = schema.get("items") {
+                    for (i, item) in a.iter().enumerate() {
+                        self.check(items, item, &format!("{}/{}", path, i), errors, depth);
+                    }
+                }
+            },
+            Value::Object(o)  => self.check_object(schema, o, path, errors, depth),
+            _                 => {},
+        }
+
+        if let Some(Value::Array(subs)) = schema.get("allOf") {
+            for sub in subs {
+                self.check(sub, value, path, errors, depth);
+            }
+        }
+
+        // how many branches of `anyOf` or `oneOf` the value matches
+        let matching = |key: &str| schema.get(key).and_then(Value::as_array).map(|subs| {
+            subs.iter().filter(|sub| {
+                let mut found = Vec::new();
+
+                self.check(sub, value, path, &mut found, depth);
+                found.is_empty()
+            }).count()
+        });
+
+        if matching("anyOf") == Some(0) {
+            fail(errors, String::from("value matches none of anyOf"));
+        }
+
+        if matching("oneOf").map(|n| n != 1).unwrap_or(false) {
+            fail(errors, String::from("value must match exactly one of oneOf"));
+        }
+
+        if let Some(not) = schema.get("not") {
+            let mut found = Vec::new();
+
+            self.check(not, value, path, &mut found, depth);
+
+            if found.is_empty() {
+                fail(errors, String::from("value matches a schema it must not"));
+            }
+        }
+    }
+
+    fn check_string<F: FnMut(String)>(&self, schema: &Map<String, Value>, s: &str, fail: &mut F) {
+        check_size(schema, s.chars().count(), "minLength", "maxLength", "characters", fail);
+
+        if let Some(Value::String(pattern)) = schema.get("pattern") {
+            if let Some(re) = self.patterns.get(pattern) {
+                if !re.is_match(s) {
+                    fail(format!("doesn't match the pattern {}", pattern));
+                }
+            }
+        }
+    }
+
+    fn check_object(&self, schema: &Map<String, Value>, o: &Map<String, Value>, path: &str,
+                    errors: &mut Vec<ValidationError>, depth: usize) {
+        let props = schema.get("properties").and_then(Value::as_object);
+
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !o.contains_key(name) {
+                    errors.push(ValidationError {
+                        path:    format!("{}/{}", path, escape(name)),
+                        message: String::from("is required"),
+                    });
+                }
+            }
+        }
+
+        check_size(schema, o.len(), "minProperties", "maxProperties", "properties", &mut |message| {
+            errors.push(ValidationError { path: String::from(path), message })
+        });
+
+        for (name, item) in o {
+            let at = format!("{}/{}", path, escape(name));
+
+            match (props.and_then(|p| p.get(name)), schema.get("additionalProperties")) {
+                (Some(sub), _)                   => self.check(sub, item, &at, errors, depth),
+                (None, Some(Value::Bool(false))) => errors.push(ValidationError {
+                    path:    at,
+                    message: String::from("isn't an allowed property"),
+                }),
+                (None, Some(extra))              => self.check(extra, item, &at, errors, depth),
+                (None, None)                     => {},
+            }
+        }
+    }
+}
+
+/// Load a schema from a JSON string.
+impl std::str::FromStr for Schema {
+    type Err = SchemaError;
+
+    fn from_str(text: &str) -> Result<Schema, SchemaError> {
+        Schema::from_value(serde_json::from_str(text).map_err(SchemaError::Json)?)
+    }
+}
+
+// compile every `pattern` in the schema up front, so a bad one is found at
+// startup rather than on the first request.
+fn compile(schema: &Value, patterns: &mut HashMap<String, Regex>) -> Result<(), SchemaError> {
+    match schema {
+        Value::Object(map) => {
+            if let Some(pattern) = map.get("pattern") {
+                let pattern = pattern.as_str().ok_or_else(|| SchemaError::Invalid(String::from("pattern must be a string")))?;
+                let re = Regex::new(pattern).map_err(|e| SchemaError::Invalid(format!("bad pattern {:?}: {}", pattern, e)))?;
+
+                patterns.insert(String::from(pattern), re);
+            }
+
+            map.values().try_for_each(|v| compile(v, patterns))
+        },
+        Value::Array(items) => items.iter().try_for_each(|v| compile(v, patterns)),
+        _                   => Ok(()),
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null"    => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string"  => value.is_string(),
+        "number"  => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().map(|f| f.fract() == 0.0).unwrap_or(false),
+        "array"   => value.is_array(),
+        "object"  => value.is_object(),
+        _         => false,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null      => "null",
+        Value::Bool(_)   => "boolean",
+        Value::String(_) => "string",
+        Value::Number(_) => "number",
+        Value::Array(_)  => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+// escape a property name for use in a JSON pointer.
+fn escape(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+fn check_size<F: FnMut(String)>(schema: &Map<String, Value>, len: usize, min: &str, max: &str, what: &str, fail: &mut F) {
+    if let Some(n) = schema.get(min).and_then(Value::as_u64) {
+        if (len as u64) < n {
+            fail(format!("must have at least {} {}", n, what));
+        }
+    }
+
+    if let Some(n) = schema.get(max).and_then(Value::as_u64) {
+        if len as u64 > n {
+            fail(format!("must have at most {} {}", n, what));
+        }
+    }
+}
+
+fn check_number<F: FnMut(String)>(schema: &Map<String, Value>, n: f64, fail: &mut F) {
+    let limit = |key: &str| schema.get(key).and_then(Value::as_f64);
+
+    if let Some(min) = limit("minimum").filter(|&min| n < min) {
+        fail(format!("must be at least {}", min));
+    }
+    if let Some(max) = limit("maximum").filter(|&max| n > max) {
+        fail(format!("must be at most {}", max));
+    }
+    if let Some(min) = limit("exclusiveMinimum").filter(|&min| n <= min) {
+        fail(format!("must be more than {}", min));
+    }
+    if let Some(max) = limit("exclusiveMaximum").filter(|&max| n >= max) {
+        fail(format!("must be less than {}", max));
+    }
+}
+
+// the schema a route's request bodies must match, kept in its metadata.
+struct RequestSchema(Arc<Schema>);
+
+impl RouteOptions {
+    /// Require the route's request bodies to be JSON matching `schema`,
+    /// when the `ValidateJson` middleware is in use.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::route::RouteOptions;
+    /// use canteen::schema::Schema;
+    ///
+    /// let schema: Schema = r#"{ "type": "object", "required": ["email"] }"#.parse().unwrap();
+    /// let opts = RouteOptions::new().request_schema(schema);
+    /// ```
+    pub fn request_schema(self, schema: Schema) -> RouteOptions {
+        self.meta(RequestSchema(Arc::new(schema)))
+    }
+
+    /// Declare that the route's successful responses are JSON matching
+    /// `schema`, checked in debug mode as `RouteOptions::response_type` is.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::route::RouteOptions;
+    /// use canteen::schema::Schema;
+    ///
+    /// let schema: Schema = r#"{ "type": "array", "items": { "type": "string" } }"#.parse().unwrap();
+    /// let opts = RouteOptions::new().response_schema(schema);
+    /// ```
+    pub fn response_schema(self, schema: Schema) -> RouteOptions {
+        self.meta(ResponseContract(Box::new(move |body| {
+            let value = serde_json::from_slice::<Value>(body).map_err(|e| vec![format!("body isn't JSON: {}", e)])?;
+
+            schema.validate(&value).map_err(|errors| {
+                errors.into_iter().map(|e| format!("{}: {}", if e.path.is_empty() { "/" } else { &e.path }, e.message)).collect()
+            })
+        })))
+    }
+}
+
+/// Validates JSON request bodies against the schemas routes declare with
+/// `RouteOptions::request_schema`. Bodies that aren't JSON, or don't match,
+/// get a `400 Bad Request` listing what's wrong, and the handler isn't run.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Canteen, Method};
+/// use canteen::route::RouteOptions;
+/// use canteen::schema::{Schema, ValidateJson};
+/// use canteen::utils;
+///
+/// let schema: Schema = r#"{ "type": "object", "required": ["name"] }"#.parse().unwrap();
+/// let mut cnt = Canteen::new();
+///
+/// cnt.add_middleware(ValidateJson);
+/// cnt.add_route_with("/users", Method::Post, utils::err_404, RouteOptions::new().request_schema(schema));
+///
+/// let res = cnt.dispatch("POST /users HTTP/1.1\r\n\r\n{}".parse().unwrap());
+/// assert_eq!(400, res.get_status());
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidateJson;
+
+impl ValidateJson {
+    /// The `400 Bad Request` sent for a body with `errors`.
+    pub fn rejection(errors: &[ValidationError]) -> Response {
+        #[derive(Serialize)]
+        struct Body<'a> {
+            error:  &'a str,
+            errors: &'a [ValidationError],
+        }
+
+        let mut res = Response::as_json(&Body { error: "request body doesn't match the schema", errors });
+
+        res.set_status(400);
+        res
+    }
+}
+
+impl Middleware for ValidateJson {
+    fn before(&self, req: &mut Request) -> Option<Response> {
+        let schema = &req.matched_route()?.meta::<RequestSchema>()?.0;
+
+        let errors = match serde_json::from_slice::<Value>(&req.payload) {
+            Ok(body) => schema.validate(&body).err()?,
+            Err(e)   => vec![ValidationError { path: String::new(), message: format!("body isn't JSON: {}", e) }],
+        };
+
+        Some(ValidateJson::rejection(&errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn paths(schema: &Schema, value: Value) -> Vec<String> {
+        match schema.validate(&value) {
+            Ok(())      => Vec::new(),
+            Err(errors) => errors.into_iter().map(|e| e.path).collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = Schema::from_value(json!({
+            "$defs": { "tag": { "type": "string", "pattern": "^[a-z]+$" } },
+            "type": "object",
+            "required": ["id", "tags"],
+            "additionalProperties": false,
+            "properties": {
+                "id":    { "type": "integer", "minimum": 1 },
+                "tags":  { "type": "array", "items": { "$ref": "#/$defs/tag" }, "maxItems": 3 },
+                "kind":  { "enum": ["a", "b"] },
+                "note":  { "type": ["string", "null"], "maxLength": 4 },
+                "a/b":   { "const": true },
+            },
+        })).unwrap();
+
+        assert_eq!(Vec::<String>::new(), paths(&schema, json!({ "id": 3, "tags": ["x"], "note": null })));
+        assert_eq!(vec!["/tags", "/id"], paths(&schema, json!({ "id": 0.5 })));
+        assert_eq!(vec!["/id", "/tags/1"], paths(&schema, json!({ "id": 0, "tags": ["ok", "NO"] })));
+        assert_eq!(vec!["/a~1b", "/kind", "/note", "/x"],
+                   paths(&schema, json!({ "id": 1, "tags": [], "kind": "c", "note": "toolong", "a/b": false, "x": 1 })));
+        assert_eq!(vec![""], paths(&schema, json!([])));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let schema = Schema::from_value(json!({
+            "anyOf": [{ "type": "string" }, { "type": "integer" }],
+            "oneOf": [{ "minimum": 0 }, { "type": "string" }, { "maximum": 10 }],
+            "not": { "const": 5 },
+        })).unwrap();
+
+        assert!(schema.validate(&json!(20)).is_ok());
+        assert!(schema.validate(&json!(3)).is_err());
+        assert!(schema.validate(&json!(5)).is_err());
+        assert!(schema.validate(&json!(true)).is_err());
+    }
+
+    #[test]
+    fn test_bad_schema() {
+        assert!(matches!("{".parse::<Schema>(), Err(SchemaError::Json(_))));
+        assert!(matches!(Schema::from_value(json!({ "pattern": "(" })), Err(SchemaError::Invalid(_))));
+        assert!(matches!(Schema::from_file("/nonexistent/schema.json"), Err(SchemaError::Io(_))));
+    }
+
+    #[test]
+    fn test_rejection() {
+        let res = ValidateJson::rejection(&[ValidationError { path: String::from("/a"), message: String::from("bad") }]);
+        let body: Value = serde_json::from_slice(res.get_payload()).unwrap();
+
+        assert_eq!(400, res.get_status());
+        assert_eq!(json!([{ "path": "/a", "message": "bad" }]), body["errors"]);
+    }
+}
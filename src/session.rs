@@ -0,0 +1,335 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Sessions kept on the client in a signed cookie, so they need no storage
+//! on the server. The cookie can be read by the client but not changed
+//! without the signature failing, so keep secrets out of it.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use ring::hmac;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::cookie::{Cookie, SameSite};
+use crate::middleware::Middleware;
+use crate::request::Request;
+use crate::response::Response;
+use crate::utils;
+
+/// The values kept for a client between requests, read and changed with
+/// `Request::session`.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::session::Session;
+///
+/// let mut session = Session::new();
+/// session.insert("user_id", 42);
+///
+/// assert_eq!(Some(42), session.get::<u32>("user_id"));
+/// assert_eq!(None, session.get::<String>("user_id"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    values:  BTreeMap<String, Value>,
+    changed: bool,
+}
+
+impl Session {
+    /// Create an empty session.
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// The value stored as `key`, if there is one and it reads as a `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        self.values.get(key).and_then(|v| serde_json::from_value(v.clone()).ok())
+    }
+
+    /// Store `value` as `key`, replacing what was there.
+    pub fn insert<V: Into<Value>>(&mut self, key: &str, value: V) {
+        self.values.insert(String::from(key), value.into());
+        self.changed = true;
+    }
+
+    /// Take the value stored as `key` out of the session.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let old = self.values.remove(key);
+
+        self.changed |= old.is_some();
+
+        old
+    }
+
+    /// Whether a value is stored as `key`.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    /// Empty the session, as when logging out. The client's cookie is
+    /// removed.
+    pub fn clear(&mut self) {
+        self.changed |= !self.values.is_empty();
+        self.values.clear();
+    }
+
+    /// The number of values stored.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Whether nothing is stored.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Whether the session has changed since the request arrived, and so
+    /// will be sent back to the client.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+// the session, in the request's extensions, where handlers can change it
+// through a shared reference.
+pub(crate) struct SessionCell(pub(crate) Mutex<Session>);
+
+// what the cookie holds, before signing.
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exp:    Option<u64>,
+    values: BTreeMap<String, Value>,
+}
+
+/// Middleware that loads each request's session from a cookie signed with
+/// HMAC-SHA256, and sends it back when the handler changes it. A cookie
+/// that's been tampered with, or has expired, is ignored and the request
+/// starts with an empty session.
+///
+/// The cookie is `HttpOnly` and `SameSite=Lax`, for the whole site.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Canteen, Method, Request, Response};
+/// use canteen::session::Sessions;
+/// use canteen::utils;
+///
+/// fn visit(req: &Request) -> Response {
+///     let mut session = req.session().unwrap();
+///     let visits = session.get::<u64>("visits").unwrap_or(0) + 1;
+///
+///     session.insert("visits", visits);
+///     utils::text(format!("visit number {}", visits))
+/// }
+///
+/// let mut cnt = Canteen::new();
+/// cnt.add_middleware(Sessions::new(b"a secret of at least thirty-two bytes")
+///                        .secure(true));
+/// cnt.add_route("/", Method::Get, visit);
+///
+/// let res = cnt.dispatch("GET / HTTP/1.1\r\n\r\n".parse().unwrap());
+/// assert!(res.headers().any(|(name, value)| name == "Set-Cookie" && value.starts_with("session=")));
+/// ```
+#[derive(Clone)]
+pub struct Sessions {
+    key:     hmac::Key,
+    name:    String,
+    max_age: Option<Duration>,
+    secure:  bool,
+}
+
+impl Sessions {
+    /// Sign session cookies with `secret`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `secret` is shorter than 32 bytes.
+    pub fn new(secret: &[u8]) -> Sessions {
+        if secret.len() < 32 {
+            panic!("session secret must be at least 32 bytes, not {}", secret.len());
+        }
+
+        Sessions {
+            key:     hmac::Key::new(hmac::HMAC_SHA256, secret),
+            name:    String::from("session"),
+            max_age: None,
+            secure:  false,
+        }
+    }
+
+    /// Name the cookie `name`, rather than `session`.
+    pub fn cookie_name(mut self, name: &str) -> Sessions {
+        self.name = String::from(name);
+
+        self
+    }
+
+    /// Keep sessions for `age` after they last changed. By default they
+    /// last until the browser is closed.
+    pub fn max_age(mut self, age: Duration) -> Sessions {
+        self.max_age = Some(age);
+
+        self
+    }
+
+    /// Only send the cookie over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Sessions {
+        self.secure = secure;
+
+        self
+    }
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+
+    /// The cookie value holding `session`.
+    pub fn encode(&self, session: &Session) -> String {
+        let stored = Stored {
+            exp:    self.max_age.map(|age| Sessions::now() + age.as_secs()),
+            values: session.values.clone(),
+        };
+        let payload = utils::base64_encode(&serde_json::to_vec(&stored).unwrap_or_default());
+        let tag = hmac::sign(&self.key, payload.as_bytes());
+
+        format!("{}.{}", payload, utils::base64_encode(tag.as_ref()))
+    }
+
+    /// The session held in the cookie value `value`, or `None` if it isn't
+    /// signed with this secret or has expired.
+    pub fn decode(&self, value: &str) -> Option<Session> {
+        let (payload, tag) = value.rsplit_once('.')?;
+
+        hmac::verify(&self.key, payload.as_bytes(), &utils::base64_decode(tag)?).ok()?;
+
+        let stored: Stored = serde_json::from_slice(&utils::base64_decode(payload)?).ok()?;
+
+        match stored.exp {
+            Some(exp) if exp <= Sessions::now() => None,
+            _                                   => Some(Session { values: stored.values, changed: false }),
+        }
+    }
+
+    fn cookie(&self, value: &str) -> Cookie {
+        let cookie = Cookie::new(&self.name, value).path("/").http_only(true).same_site(SameSite::Lax).secure(self.secure);
+
+        match self.max_age {
+            Some(age) => cookie.max_age(age),
+            None      => cookie,
+        }
+    }
+}
+
+impl Middleware for Sessions {
+    fn before(&self, req: &mut Request) -> Option<Response> {
+        let session = req.cookie(&self.name).and_then(|value| self.decode(&value)).unwrap_or_default();
+
+        req.extensions_mut().insert(SessionCell(Mutex::new(session)));
+
+        None
+    }
+
+    fn after(&self, req: &Request, res: &mut Response) {
+        let session = match req.session() {
+            Some(session) if session.changed => session,
+            _                                => return,
+        };
+
+        match session.is_empty() {
+            true  => res.set_cookie(Cookie::removal(&self.name).path("/")),
+            false => res.set_cookie(self.cookie(&self.encode(&session))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Canteen, Method};
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_round_trip() {
+        let sessions = Sessions::new(SECRET);
+        let mut session = Session::new();
+
+        session.insert("user", "alice");
+        session.insert("roles", vec!["admin"]);
+
+        let value = sessions.encode(&session);
+        let decoded = sessions.decode(&value).unwrap();
+
+        assert_eq!(Some(String::from("alice")), decoded.get("user"));
+        assert_eq!(Some(vec![String::from("admin")]), decoded.get("roles"));
+        assert!(!decoded.is_changed());
+
+        // a changed payload or a different secret fails the signature
+        let (payload, tag) = value.rsplit_once('.').unwrap();
+        let forged = utils::base64_encode(br#"{"values":{"user":"mallory"}}"#);
+
+        assert!(sessions.decode(&format!("{}.{}", forged, tag)).is_none());
+        assert!(sessions.decode(payload).is_none());
+        assert!(Sessions::new(b"another secret, also 32 bytes long").decode(&value).is_none());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let sessions = Sessions::new(SECRET).max_age(Duration::from_secs(0));
+        let mut session = Session::new();
+
+        session.insert("user", "alice");
+        assert!(sessions.decode(&sessions.encode(&session)).is_none());
+    }
+
+    #[test]
+    fn test_sessions_middleware() {
+        let mut cnt = Canteen::new();
+        let get = |cnt: &mut Canteen, path: &str, cookie: &str| {
+            cnt.dispatch(format!("GET {} HTTP/1.1\r\nCookie: {}\r\n\r\n", path, cookie).parse().unwrap())
+        };
+        let set_cookie = |res: &Response| res.headers().find(|&(name, _)| name == "Set-Cookie").map(|(_, v)| String::from(v));
+
+        cnt.add_middleware(Sessions::new(SECRET).cookie_name("sid"));
+        cnt.add_route("/login", Method::Get, |req: &Request| {
+            req.session().unwrap().insert("user", "alice");
+            utils::text("hi")
+        });
+        cnt.add_route("/me", Method::Get, |req: &Request| {
+            utils::text(req.session().unwrap().get::<String>("user").unwrap_or_default())
+        });
+        cnt.add_route("/logout", Method::Get, |req: &Request| {
+            req.session().unwrap().clear();
+            utils::text("bye")
+        });
+
+        let res = get(&mut cnt, "/login", "");
+        let set = set_cookie(&res).unwrap();
+        let cookie = set.split(';').next().unwrap();
+
+        assert!(set.contains("HttpOnly"));
+
+        let res = get(&mut cnt, "/me", cookie);
+        assert_eq!(b"alice", res.get_payload());
+        assert_eq!(None, set_cookie(&res));
+        assert_eq!(b"", get(&mut cnt, "/me", "sid=bogus.c2ln").get_payload());
+        assert!(set_cookie(&get(&mut cnt, "/logout", cookie)).unwrap().starts_with("sid=; Max-Age=0"));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 32 bytes")]
+    fn test_short_secret() {
+        Sessions::new(b"hunter2");
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Application state shared by every handler, such as a connection pool or
+//! the application's own configuration.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// The values given to `Canteen::manage`, at most one of each type. Handlers
+/// reach them with `Request::state`.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::state::State;
+///
+/// let mut state = State::new();
+/// state.insert(String::from("db://localhost"));
+///
+/// assert_eq!(Some(&String::from("db://localhost")), state.get::<String>());
+/// assert_eq!(None, state.get::<u32>());
+/// ```
+#[derive(Clone, Default)]
+pub struct State {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl State {
+    /// Create an empty container.
+    pub fn new() -> State {
+        State::default()
+    }
+
+    /// Store `value`, replacing any value of the same type.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.map.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// The stored value of type `T`.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>()).and_then(|v| v.downcast_ref())
+    }
+
+    /// The number of values stored.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether nothing is stored.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "State({} values)", self.map.len())
+    }
+}
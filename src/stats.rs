@@ -0,0 +1,347 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Per-route request statistics.
+//!
+//! Every route gets its own set of atomic counters when it is added, so
+//! recording a request never takes a lock. Latencies are kept in a histogram
+//! with power-of-two microsecond buckets, which is what the reported
+//! percentiles are estimated from.
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::request::Method;
+
+const BUCKETS: usize = 40;
+
+/// The live counters for a single route.
+#[derive(Debug)]
+pub struct RouteStats {
+    pattern:   String,
+    method:    Method,
+    requests:  AtomicU64,
+    errors:    AtomicU64,
+    timeouts:  AtomicU64,
+    bytes_out: AtomicU64,
+    latency:   [AtomicU64; BUCKETS],
+}
+
+impl RouteStats {
+    /// Create an empty set of counters for a route.
+    pub fn new(pattern: &str, method: Method) -> RouteStats {
+        RouteStats {
+            pattern:   String::from(pattern),
+            method,
+            requests:  AtomicU64::new(0),
+            errors:    AtomicU64::new(0),
+            timeouts:  AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            latency:   std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    /// The route pattern the counters are for.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Record a completed request. Any 5xx status counts as an error.
+    pub fn record(&self, status: u16, elapsed: Duration, bytes: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+
+        if status >= 500 {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.latency[bucket(elapsed)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the body of a streamed response, which is written after the
+    /// request has been counted by `record`.
+    pub fn record_bytes(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Record a request whose handler missed its deadline. The request is
+    /// counted separately, by `record`, once the handler finishes.
+    pub fn record_timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take a point-in-time copy of the counters.
+    pub fn snapshot(&self) -> RouteSnapshot {
+        let counts: Vec<u64> = self.latency.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+        let requests = self.requests.load(Ordering::Relaxed);
+        let errors = self.errors.load(Ordering::Relaxed);
+
+        RouteSnapshot {
+            pattern:    self.pattern.clone(),
+            method:     self.method,
+            requests,
+            errors,
+            error_rate: if requests == 0 { 0.0 } else { errors as f64 / requests as f64 },
+            timeouts:   self.timeouts.load(Ordering::Relaxed),
+            p50:        percentile(&counts, 0.50),
+            p99:        percentile(&counts, 0.99),
+            bytes_out:  self.bytes_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A point-in-time view of a route's statistics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteSnapshot {
+    pub pattern:    String,
+    pub method:     Method,
+    pub requests:   u64,
+    pub errors:     u64,
+    pub error_rate: f64,
+    pub timeouts:   u64,
+    pub p50:        Duration,
+    pub p99:        Duration,
+    pub bytes_out:  u64,
+}
+
+/// The live counters for the worker pool.
+#[derive(Debug)]
+pub struct PoolStats {
+    workers:  AtomicU64,
+    queued:   AtomicU64,
+    active:   AtomicU64,
+    panics:   AtomicU64,
+    worst:    AtomicU64,
+    wait:     [AtomicU64; BUCKETS],
+}
+
+impl PoolStats {
+    /// Create an empty set of counters for a pool of `workers` threads.
+    pub fn new(workers: usize) -> PoolStats {
+        PoolStats {
+            workers:  AtomicU64::new(workers as u64),
+            queued:   AtomicU64::new(0),
+            active:   AtomicU64::new(0),
+            panics:   AtomicU64::new(0),
+            worst:    AtomicU64::new(0),
+            wait:     std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    /// Record a job being queued for the pool.
+    pub fn enqueue(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a worker picking up a job that waited `wait` in the queue.
+    pub fn start(&self, wait: Duration) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.worst.fetch_max(wait.as_micros() as u64, Ordering::Relaxed);
+        self.wait[bucket(wait)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a worker finishing a job.
+    pub fn finish(&self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Record a handler panicking.
+    pub fn record_panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of jobs waiting for a worker, e.g. for deciding when to
+    /// shed load.
+    pub fn queue_depth(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// The longest queue wait since the last call.
+    pub fn take_worst_wait(&self) -> Duration {
+        Duration::from_micros(self.worst.swap(0, Ordering::Relaxed))
+    }
+
+    /// Take a point-in-time copy of the counters.
+    pub fn snapshot(&self) -> PoolSnapshot {
+        let counts: Vec<u64> = self.wait.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+
+        PoolSnapshot {
+            workers:  self.workers.load(Ordering::Relaxed),
+            active:   self.active.load(Ordering::Relaxed),
+            queued:   self.queued.load(Ordering::Relaxed),
+            panics:   self.panics.load(Ordering::Relaxed),
+            wait_p50: percentile(&counts, 0.50),
+            wait_p99: percentile(&counts, 0.99),
+        }
+    }
+}
+
+impl Default for PoolStats {
+    fn default() -> Self {
+        PoolStats::new(0)
+    }
+}
+
+/// A point-in-time view of the worker pool's health.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolSnapshot {
+    pub workers:  u64,
+    pub active:   u64,
+    pub queued:   u64,
+    pub panics:   u64,
+    pub wait_p50: Duration,
+    pub wait_p99: Duration,
+}
+
+/// A shareable handle onto the statistics for every route on a server.
+/// Cloning it is cheap, so it can be handed to an admin handler or another
+/// thread while the server runs.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    routes: Arc<Mutex<Vec<Arc<RouteStats>>>>,
+    pool:   Arc<PoolStats>,
+}
+
+impl Stats {
+    /// Create an empty registry.
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    /// Add a route's counters to the registry.
+    pub fn add(&self, rs: Arc<RouteStats>) {
+        self.routes.lock().unwrap().push(rs);
+    }
+
+    /// Take a snapshot of every registered route.
+    pub fn snapshot(&self) -> Vec<RouteSnapshot> {
+        self.routes.lock().unwrap().iter().map(|rs| rs.snapshot()).collect()
+    }
+
+    /// Take a snapshot of the worker pool's health.
+    pub fn pool(&self) -> PoolSnapshot {
+        self.pool.snapshot()
+    }
+
+    // the pool's live counters.
+    pub(crate) fn pool_stats(&self) -> Arc<PoolStats> {
+        self.pool.clone()
+    }
+
+    // start counting a pool of a different size.
+    pub(crate) fn set_workers(&self, workers: usize) {
+        self.pool.workers.store(workers as u64, Ordering::Relaxed);
+    }
+}
+
+// the histogram bucket for a latency: bucket n holds [2^n, 2^(n+1)) µs.
+fn bucket(elapsed: Duration) -> usize {
+    let micros = elapsed.as_micros().max(1) as u64;
+
+    ((63 - micros.leading_zeros()) as usize).min(BUCKETS - 1)
+}
+
+// estimate a percentile as the upper bound of the bucket it falls into.
+fn percentile(counts: &[u64], pct: f64) -> Duration {
+    let total: u64 = counts.iter().sum();
+
+    if total == 0 {
+        return Duration::from_micros(0);
+    }
+
+    let rank = ((total as f64) * pct).ceil().max(1.0) as u64;
+    let mut seen = 0;
+
+    for (i, count) in counts.iter().enumerate() {
+        seen += count;
+
+        if seen >= rank {
+            return Duration::from_micros(1 << (i + 1));
+        }
+    }
+
+    Duration::from_micros(1 << BUCKETS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(0, bucket(Duration::from_micros(0)));
+        assert_eq!(0, bucket(Duration::from_micros(1)));
+        assert_eq!(1, bucket(Duration::from_micros(3)));
+        assert_eq!(10, bucket(Duration::from_micros(1024)));
+        assert_eq!(BUCKETS - 1, bucket(Duration::from_secs(1 << 30)));
+    }
+
+    #[test]
+    fn test_route_stats_snapshot() {
+        let rs = RouteStats::new("/foo/<int:id>", Method::Get);
+
+        for _ in 0..98 {
+            rs.record(200, Duration::from_micros(100), 10);
+        }
+
+        rs.record(500, Duration::from_millis(50), 5);
+        rs.record(503, Duration::from_millis(50), 5);
+        rs.record_timeout();
+
+        let snap = rs.snapshot();
+
+        assert_eq!(100, snap.requests);
+        assert_eq!(2, snap.errors);
+        assert_eq!(0.02, snap.error_rate);
+        assert_eq!(1, snap.timeouts);
+        assert_eq!(990, snap.bytes_out);
+        assert_eq!(Duration::from_micros(128), snap.p50);
+        assert_eq!(Duration::from_micros(65536), snap.p99);
+    }
+
+    #[test]
+    fn test_stats_registry() {
+        let stats = Stats::new();
+        let rs = Arc::new(RouteStats::new("/", Method::Get));
+
+        stats.add(rs.clone());
+        rs.record(404, Duration::from_micros(10), 1);
+
+        let snap = stats.clone().snapshot();
+
+        assert_eq!(1, snap.len());
+        assert_eq!("/", snap[0].pattern);
+        assert_eq!(0, snap[0].errors);
+        assert_eq!(1, snap[0].requests);
+    }
+
+    #[test]
+    fn test_pool_stats() {
+        let pool = PoolStats::new(4);
+
+        pool.enqueue();
+        pool.enqueue();
+        assert_eq!(2, pool.queue_depth());
+
+        pool.start(Duration::from_micros(100));
+        pool.start(Duration::from_millis(3));
+        pool.finish();
+        pool.record_panic();
+
+        let snap = pool.snapshot();
+
+        assert_eq!(4, snap.workers);
+        assert_eq!(0, snap.queued);
+        assert_eq!(1, snap.active);
+        assert_eq!(1, snap.panics);
+        assert_eq!(Duration::from_micros(128), snap.wait_p50);
+        assert_eq!(Duration::from_millis(3), pool.take_worst_wait());
+        assert_eq!(Duration::from_micros(0), pool.take_worst_wait());
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! HTTP response status codes.
+
+use std::error::Error;
+use std::fmt;
+
+/// An HTTP response status code. Plain integers convert into it, so
+/// functions that take an `Into<StatusCode>` accept either.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::StatusCode;
+///
+/// assert_eq!(404, StatusCode::NOT_FOUND.as_u16());
+/// assert_eq!(Some("Not Found"), StatusCode::NOT_FOUND.canonical_reason());
+/// assert!(StatusCode::NOT_FOUND.is_client_error());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StatusCode(u16);
+
+/// The error returned for numbers that can't be status codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidStatusCode(u16);
+
+impl fmt::Display for InvalidStatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid status code {}, must be within 100-599", self.0)
+    }
+}
+
+impl Error for InvalidStatusCode {}
+
+impl StatusCode {
+    /// `100 Continue`
+    pub const CONTINUE: StatusCode = StatusCode(100);
+    /// `101 Switching Protocols`
+    pub const SWITCHING_PROTOCOLS: StatusCode = StatusCode(101);
+    /// `102 Processing`
+    pub const PROCESSING: StatusCode = StatusCode(102);
+    /// `103 Early Hints`
+    pub const EARLY_HINTS: StatusCode = StatusCode(103);
+    /// `200 OK`
+    pub const OK: StatusCode = StatusCode(200);
+    /// `201 Created`
+    pub const CREATED: StatusCode = StatusCode(201);
+    /// `202 Accepted`
+    pub const ACCEPTED: StatusCode = StatusCode(202);
+    /// `203 Non-Authoritative Information`
+    pub const NON_AUTHORITATIVE_INFORMATION: StatusCode = StatusCode(203);
+    /// `204 No Content`
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    /// `205 Reset Content`
+    pub const RESET_CONTENT: StatusCode = StatusCode(205);
+    /// `206 Partial Content`
+    pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
+    /// `207 Multi-Status`
+    pub const MULTI_STATUS: StatusCode = StatusCode(207);
+    /// `208 Already Reported`
+    pub const ALREADY_REPORTED: StatusCode = StatusCode(208);
+    /// `226 IM Used`
+    pub const IM_USED: StatusCode = StatusCode(226);
+    /// `300 Multiple Choices`
+    pub const MULTIPLE_CHOICES: StatusCode = StatusCode(300);
+    /// `301 Moved Permanently`
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    /// `302 Found`
+    pub const FOUND: StatusCode = StatusCode(302);
+    /// `303 See Other`
+    pub const SEE_OTHER: StatusCode = StatusCode(303);
+    /// `304 Not Modified`
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    /// `305 Use Proxy`
+    pub const USE_PROXY: StatusCode = StatusCode(305);
+    /// `307 Temporary Redirect`
+    pub const TEMPORARY_REDIRECT: StatusCode = StatusCode(307);
+    /// `308 Permanent Redirect`
+    pub const PERMANENT_REDIRECT: StatusCode = StatusCode(308);
+    /// `400 Bad Request`
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    /// `401 Unauthorized`
+    pub const UNAUTHORIZED: StatusCode = StatusCode(401);
+    /// `402 Payment Required`
+    pub const PAYMENT_REQUIRED: StatusCode = StatusCode(402);
+    /// `403 Forbidden`
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    /// `404 Not Found`
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    /// `405 Method Not Allowed`
+    pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    /// `406 Not Acceptable`
+    pub const NOT_ACCEPTABLE: StatusCode = StatusCode(406);
+    /// `407 Proxy Authentication Required`
+    pub const PROXY_AUTHENTICATION_REQUIRED: StatusCode = StatusCode(407);
+    /// `408 Request Timeout`
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
+    /// `409 Conflict`
+    pub const CONFLICT: StatusCode = StatusCode(409);
+    /// `410 Gone`
+    pub const GONE: StatusCode = StatusCode(410);
+    /// `411 Length Required`
+    pub const LENGTH_REQUIRED: StatusCode = StatusCode(411);
+    /// `412 Precondition Failed`
+    pub const PRECONDITION_FAILED: StatusCode = StatusCode(412);
+    /// `413 Content Too Large`
+    pub const PAYLOAD_TOO_LARGE: StatusCode = StatusCode(413);
+    /// `414 URI Too Long`
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
+    /// `415 Unsupported Media Type`
+    pub const UNSUPPORTED_MEDIA_TYPE: StatusCode = StatusCode(415);
+    /// `416 Range Not Satisfiable`
+    pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    /// `417 Expectation Failed`
+    pub const EXPECTATION_FAILED: StatusCode = StatusCode(417);
+    /// `418 I'm a teapot`
+    pub const IM_A_TEAPOT: StatusCode = StatusCode(418);
+    /// `421 Misdirected Request`
+    pub const MISDIRECTED_REQUEST: StatusCode = StatusCode(421);
+    /// `422 Unprocessable Content`
+    pub const UNPROCESSABLE_CONTENT: StatusCode = StatusCode(422);
+    /// `423 Locked`
+    pub const LOCKED: StatusCode = StatusCode(423);
+    /// `424 Failed Dependency`
+    pub const FAILED_DEPENDENCY: StatusCode = StatusCode(424);
+    /// `425 Too Early`
+    pub const TOO_EARLY: StatusCode = StatusCode(425);
+    /// `426 Upgrade Required`
+    pub const UPGRADE_REQUIRED: StatusCode = StatusCode(426);
+    /// `428 Precondition Required`
+    pub const PRECONDITION_REQUIRED: StatusCode = StatusCode(428);
+    /// `429 Too Many Requests`
+    pub const TOO_MANY_REQUESTS: StatusCode = StatusCode(429);
+    /// `431 Request Header Fields Too Large`
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
+    /// `451 Unavailable For Legal Reasons`
+    pub const UNAVAILABLE_FOR_LEGAL_REASONS: StatusCode = StatusCode(451);
+    /// `500 Internal Server Error`
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    /// `501 Not Implemented`
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
+    /// `502 Bad Gateway`
+    pub const BAD_GATEWAY: StatusCode = StatusCode(502);
+    /// `503 Service Unavailable`
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+    /// `504 Gateway Timeout`
+    pub const GATEWAY_TIMEOUT: StatusCode = StatusCode(504);
+    /// `505 HTTP Version Not Supported`
+    pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
+    /// `506 Variant Also Negotiates`
+    pub const VARIANT_ALSO_NEGOTIATES: StatusCode = StatusCode(506);
+    /// `507 Insufficient Storage`
+    pub const INSUFFICIENT_STORAGE: StatusCode = StatusCode(507);
+    /// `508 Loop Detected`
+    pub const LOOP_DETECTED: StatusCode = StatusCode(508);
+    /// `510 Not Extended`
+    pub const NOT_EXTENDED: StatusCode = StatusCode(510);
+    /// `511 Network Authentication Required`
+    pub const NETWORK_AUTHENTICATION_REQUIRED: StatusCode = StatusCode(511);
+
+    /// Make a status code from a number, which must be within 100-599.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canteen::StatusCode;
+    ///
+    /// assert_eq!(Ok(StatusCode::IM_A_TEAPOT), StatusCode::from_u16(418));
+    /// assert!(StatusCode::from_u16(42).is_err());
+    /// ```
+    pub fn from_u16(code: u16) -> Result<StatusCode, InvalidStatusCode> {
+        match code {
+            100..=599 => Ok(StatusCode(code)),
+            _         => Err(InvalidStatusCode(code)),
+        }
+    }
+
+    /// The numeric code, e.g. `404`.
+    pub fn as_u16(self) -> u16 {
+        self.0
+    }
+
+    /// The reason phrase registered with IANA for the code, if it has one.
+    pub fn canonical_reason(self) -> Option<&'static str> {
+        let reason = match self.0 {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            102 => "Processing",
+            103 => "Early Hints",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            203 => "Non-Authoritative Information",
+            204 => "No Content",
+            205 => "Reset Content",
+            206 => "Partial Content",
+            207 => "Multi-Status",
+            208 => "Already Reported",
+            226 => "IM Used",
+            300 => "Multiple Choices",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            305 => "Use Proxy",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            402 => "Payment Required",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            407 => "Proxy Authentication Required",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            410 => "Gone",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Content Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            418 => "I'm a teapot",
+            421 => "Misdirected Request",
+            422 => "Unprocessable Content",
+            423 => "Locked",
+            424 => "Failed Dependency",
+            425 => "Too Early",
+            426 => "Upgrade Required",
+            428 => "Precondition Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            451 => "Unavailable For Legal Reasons",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            506 => "Variant Also Negotiates",
+            507 => "Insufficient Storage",
+            508 => "Loop Detected",
+            510 => "Not Extended",
+            511 => "Network Authentication Required",
+            _   => return None,
+        };
+
+        Some(reason)
+    }
+
+    /// Whether this is a 1xx code.
+    pub fn is_informational(self) -> bool {
+        (100..200).contains(&self.0)
+    }
+
+    /// Whether this is a 2xx code.
+    pub fn is_success(self) -> bool {
+        (200..300).contains(&self.0)
+    }
+
+    /// Whether this is a 3xx code.
+    pub fn is_redirection(self) -> bool {
+        (300..400).contains(&self.0)
+    }
+
+    /// Whether this is a 4xx code.
+    pub fn is_client_error(self) -> bool {
+        (400..500).contains(&self.0)
+    }
+
+    /// Whether this is a 5xx code.
+    pub fn is_server_error(self) -> bool {
+        (500..600).contains(&self.0)
+    }
+}
+
+impl Default for StatusCode {
+    fn default() -> Self {
+        StatusCode::OK
+    }
+}
+
+/// Numbers outside 100-599 become `500 Internal Server Error`, so a bad
+/// code in a handler can't take down the worker or put a broken status
+/// line on the wire; use `StatusCode::from_u16` to tell them apart.
+impl From<u16> for StatusCode {
+    fn from(code: u16) -> StatusCode {
+        StatusCode::from_u16(code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+impl From<StatusCode> for u16 {
+    fn from(code: StatusCode) -> u16 {
+        code.0
+    }
+}
+
+impl PartialEq<u16> for StatusCode {
+    fn eq(&self, other: &u16) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<StatusCode> for u16 {
+    fn eq(&self, other: &StatusCode) -> bool {
+        *self == other.0
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification() {
+        assert!(StatusCode::CONTINUE.is_informational());
+        assert!(StatusCode::NO_CONTENT.is_success());
+        assert!(StatusCode::FOUND.is_redirection());
+        assert!(StatusCode::GONE.is_client_error());
+        assert!(StatusCode::BAD_GATEWAY.is_server_error());
+        assert!(!StatusCode::OK.is_server_error());
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(Ok(StatusCode::OK), StatusCode::from_u16(200));
+        assert_eq!(Err(InvalidStatusCode(99)), StatusCode::from_u16(99));
+        assert_eq!(Err(InvalidStatusCode(600)), StatusCode::from_u16(600));
+        assert_eq!(None, StatusCode::from_u16(599).unwrap().canonical_reason());
+        assert_eq!(Some("Content Too Large"), StatusCode::PAYLOAD_TOO_LARGE.canonical_reason());
+        assert_eq!(Some("Network Authentication Required"), StatusCode::from(511).canonical_reason());
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, StatusCode::from(1000));
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, StatusCode::from(0));
+    }
+
+    #[test]
+    fn test_compare_with_integers() {
+        assert_eq!(StatusCode::NOT_FOUND, 404);
+        assert_eq!(404, StatusCode::NOT_FOUND);
+        assert_eq!(404u16, u16::from(StatusCode::NOT_FOUND));
+    }
+}
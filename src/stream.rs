@@ -0,0 +1,189 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Streamed response bodies, made with `Response::stream`.
+//!
+//! The body is written on the worker after the head has gone out, and sent
+//! to the client with `Transfer-Encoding: chunked` as it's produced. If the
+//! client reads more slowly than the body is written, the writer waits, so
+//! no more than a bounded amount is ever buffered for the connection.
+//! HTTP/1.0 and HTTP/2 clients get the whole body at once instead.
+
+use std::fmt;
+use std::io::{self, ErrorKind, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use mio::{Sender, Token};
+
+use crate::Message;
+
+// how much is gathered before it's sent on as a chunk.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+// how much may wait to be written to the client before the writer waits.
+const MAX_BACKLOG: usize = 256 * 1024;
+
+// how often a waiting writer checks whether the client has gone.
+const WAIT: Duration = Duration::from_millis(100);
+
+/// Frame `data` as one chunk of a chunked body. An empty chunk ends the
+/// body.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::stream;
+///
+/// assert_eq!(b"5\r\nhello\r\n".to_vec(), stream::chunk(b"hello"));
+/// assert_eq!(b"0\r\n\r\n".to_vec(), stream::chunk(b""));
+/// ```
+pub fn chunk(data: &[u8]) -> Vec<u8> {
+    let mut out = format!("{:x}\r\n", data.len()).into_bytes();
+
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+
+    out
+}
+
+// writes a streamed body.
+pub(crate) type StreamFn = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+// a response's body writer, until it's run.
+pub(crate) struct Streamer(pub(crate) StreamFn);
+
+impl fmt::Debug for Streamer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Streamer")
+    }
+}
+
+/// How much of a streamed body is waiting to be written to the client,
+/// shared between the worker writing it and the event loop sending it.
+#[derive(Debug, Default)]
+pub(crate) struct Backlog {
+    queued: Mutex<usize>,
+    room:   Condvar,
+}
+
+impl Backlog {
+    // the event loop has `queued` bytes left to write.
+    pub(crate) fn set(&self, queued: usize) {
+        *self.queued.lock().unwrap() = queued;
+        self.room.notify_all();
+    }
+
+    // wait until there's room for `len` more bytes, or the client goes.
+    fn reserve(&self, len: usize, gone: &AtomicBool) -> io::Result<()> {
+        let mut queued = self.queued.lock().unwrap();
+
+        while *queued > 0 && *queued + len > MAX_BACKLOG {
+            if gone.load(Ordering::SeqCst) {
+                return Err(ErrorKind::BrokenPipe.into());
+            }
+
+            queued = self.room.wait_timeout(queued, WAIT).unwrap().0;
+        }
+
+        *queued += len;
+
+        Ok(())
+    }
+}
+
+// sends what the body writer writes to the event loop, a chunk at a time.
+pub(crate) struct ChunkWriter {
+    tx:      Sender<Message>,
+    token:   Token,
+    id:      u64,
+    gone:    Arc<AtomicBool>,
+    backlog: Arc<Backlog>,
+    buf:     Vec<u8>,
+    sent:    usize,
+}
+
+impl ChunkWriter {
+    pub(crate) fn new(tx: Sender<Message>, token: Token, id: u64, gone: Arc<AtomicBool>,
+                      backlog: Arc<Backlog>) -> ChunkWriter {
+        ChunkWriter { tx, token, id, gone, backlog, buf: Vec::new(), sent: 0 }
+    }
+
+    // run the body writer, then end the body. returns how much was written.
+    pub(crate) fn run(mut self, stream: Streamer) -> usize {
+        if let Err(e) = (stream.0)(&mut self).and_then(|_| self.flush()) {
+            // the head is out, so all that can be done is to stop short
+            log::debug!("streamed response ended early: {}", e);
+        }
+
+        let _ = self.tx.send(Message::End(self.token, self.id));
+
+        self.sent
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.gone.load(Ordering::SeqCst) {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+
+        self.buf.extend_from_slice(data);
+
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let out = chunk(&self.buf);
+
+        self.backlog.reserve(out.len(), &self.gone)?;
+        self.sent += self.buf.len();
+        self.buf.clear();
+        self.tx.send(Message::Chunk(self.token, self.id, out)).map_err(|_| io::Error::from(ErrorKind::BrokenPipe))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backlog() {
+        let backlog = Arc::new(Backlog::default());
+        let gone = Arc::new(AtomicBool::new(false));
+
+        // the first reservation always fits, however large
+        backlog.reserve(MAX_BACKLOG * 2, &gone).unwrap();
+
+        let waiter = {
+            let backlog = backlog.clone();
+            let gone = gone.clone();
+
+            std::thread::spawn(move || backlog.reserve(10, &gone))
+        };
+
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+
+        backlog.set(0);
+        waiter.join().unwrap().unwrap();
+
+        // a client that goes away releases the writer
+        backlog.set(MAX_BACKLOG);
+        gone.store(true, Ordering::SeqCst);
+        assert_eq!(ErrorKind::BrokenPipe, backlog.reserve(10, &gone).unwrap_err().kind());
+    }
+}
@@ -0,0 +1,323 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! Recording real traffic and replaying it in tests.
+//!
+//! A `Recorder` is a middleware that writes every request and the response
+//! it got to its own JSON file. A `Replayer` loads those files back and runs
+//! each request through a `Canteen`'s router, reporting any response that
+//! no longer matches what was recorded.
+
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use chrono::Utc;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::Canteen;
+use crate::middleware::Middleware;
+use crate::request::{Method, Request};
+use crate::response::Response;
+
+/// A message body, kept as text when it is valid UTF-8 and hex otherwise.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Body {
+    pub data:   String,
+    #[serde(default)]
+    pub binary: bool,
+}
+
+impl Body {
+    fn from_bytes(bytes: &[u8]) -> Body {
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(data) => Body { data, binary: false },
+            Err(_)   => Body { data: bytes.iter().map(|b| format!("{:02x}", b)).collect(), binary: true },
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        if !self.binary {
+            return self.data.as_bytes().to_vec();
+        }
+
+        (0..self.data.len() / 2)
+            .filter_map(|i| u8::from_str_radix(&self.data[i * 2..i * 2 + 2], 16).ok())
+            .collect()
+    }
+}
+
+/// A recorded request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method:  String,
+    pub path:    String,
+    pub headers: Vec<(String, String)>,
+    pub body:    Body,
+}
+
+impl RecordedRequest {
+    /// Rebuild the request that was recorded.
+    pub fn to_request(&self) -> Request {
+        let mut req = Request::new();
+
+        req.method = Method::from(self.method.as_str());
+        req.path = self.path.clone();
+        req.payload = self.body.to_bytes();
+
+        for (key, value) in &self.headers {
+            req.headers.push((key.clone(), value.clone()));
+        }
+
+        req
+    }
+}
+
+/// A recorded response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status:       u16,
+    pub content_type: String,
+    pub headers:      Vec<(String, String)>,
+    pub body:         Body,
+}
+
+/// A request and the response it got.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub request:  RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+impl Exchange {
+    /// Capture a request and its response.
+    pub fn new(req: &Request, res: &Response) -> Exchange {
+        let req_headers: Vec<(String, String)> = req.headers()
+            .map(|(k, v)| (String::from(k), String::from(v)))
+            .collect();
+
+        Exchange {
+            request: RecordedRequest {
+                method:  String::from(req.method.as_str()),
+                path:    req.path.clone(),
+                headers: req_headers,
+                body:    Body::from_bytes(&req.payload),
+            },
+            response: RecordedResponse {
+                status:       res.get_status().as_u16(),
+                content_type: String::from(res.get_content_type()),
+                headers:      res.headers().map(|(k, v)| (String::from(k), String::from(v))).collect(),
+                body:         Body::from_bytes(res.get_payload()),
+            },
+        }
+    }
+}
+
+/// A middleware that writes every request/response pair to a JSON file in a
+/// directory.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use canteen::Canteen;
+/// use canteen::testing::Recorder;
+///
+/// let mut cnt = Canteen::new();
+/// cnt.add_middleware(Recorder::new("fixtures/traffic").unwrap());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    dir: PathBuf,
+    seq: Arc<AtomicUsize>,
+}
+
+impl Recorder {
+    /// Create a recorder writing to `dir`, creating it if needed.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Recorder> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        Ok(Recorder {
+            dir: PathBuf::from(dir.as_ref()),
+            seq: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Write an exchange to a new file in the recorder's directory.
+    pub fn record(&self, exchange: &Exchange) -> Result<PathBuf> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{}-{:06}.json", Utc::now().timestamp_millis(), seq));
+        let data = serde_json::to_string_pretty(exchange).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        fs::write(&path, data)?;
+
+        Ok(path)
+    }
+}
+
+impl Middleware for Recorder {
+    fn after(&self, req: &Request, res: &mut Response) {
+        if let Err(e) = self.record(&Exchange::new(req, res)) {
+            log::warn!("unable to record exchange for {}: {}", req.path, e);
+        }
+    }
+}
+
+/// A difference between a recorded response and a replayed one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub name:     String,
+    pub field:    &'static str,
+    pub expected: String,
+    pub actual:   String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} differs, expected {:?} but got {:?}", self.name, self.field, self.expected, self.actual)
+    }
+}
+
+/// Replays recorded exchanges through a server's router.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use canteen::Canteen;
+/// use canteen::testing::Replayer;
+///
+/// let mut cnt = Canteen::new();
+/// // ... add routes ...
+///
+/// let mismatches = Replayer::from_dir("fixtures/traffic").unwrap().run(&mut cnt);
+/// assert!(mismatches.is_empty(), "{:?}", mismatches);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Replayer {
+    exchanges: Vec<(String, Exchange)>,
+}
+
+impl Replayer {
+    /// Load every `.json` exchange in `dir`, in file name order.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Replayer> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map(|e| e == "json").unwrap_or(false))
+            .collect();
+        let mut exchanges = Vec::with_capacity(paths.len());
+
+        paths.sort();
+
+        for path in paths {
+            let data = fs::read_to_string(&path)?;
+            let exchange = serde_json::from_str(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+            exchanges.push((path.display().to_string(), exchange));
+        }
+
+        Ok(Replayer { exchanges })
+    }
+
+    /// Add an exchange to be replayed.
+    pub fn add(&mut self, name: &str, exchange: Exchange) -> &mut Replayer {
+        self.exchanges.push((String::from(name), exchange));
+
+        self
+    }
+
+    /// The loaded exchanges and their names.
+    pub fn exchanges(&self) -> &[(String, Exchange)] {
+        &self.exchanges
+    }
+
+    /// Replay every exchange through `cnt`, comparing the status, content
+    /// type, and body of each response to what was recorded. Recorded text
+    /// content types are given the server's default charset first, as the
+    /// server gives it to responses after the middleware has seen them.
+    pub fn run(&self, cnt: &mut Canteen) -> Vec<Mismatch> {
+        let mut found = Vec::new();
+
+        for (name, exchange) in &self.exchanges {
+            let res = cnt.dispatch(exchange.request.to_request());
+            let expected = &exchange.response;
+            let checks = [
+                ("status", expected.status.to_string(), res.get_status().to_string()),
+                ("content type", cnt.config.content_type(&expected.content_type), String::from(res.get_content_type())),
+                ("body", expected.body.data.clone(), Body::from_bytes(res.get_payload()).data),
+            ];
+
+            for (field, want, got) in checks.iter() {
+                if want != got {
+                    found.push(Mismatch {
+                        name:     name.clone(),
+                        field,
+                        expected: want.clone(),
+                        actual:   got.clone(),
+                    });
+                }
+            }
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    fn hello(_: &Request) -> Response {
+        utils::make_response("hello", "text/plain", 200)
+    }
+
+    fn goodbye(_: &Request) -> Response {
+        utils::make_response("goodbye", "text/plain", 200)
+    }
+
+    #[test]
+    fn test_body_roundtrip() {
+        let text = Body::from_bytes(b"abc");
+        let bin = Body::from_bytes(&[0x00, 0xff, 0x10]);
+
+        assert!(!text.binary);
+        assert_eq!("00ff10", bin.data);
+        assert_eq!(vec![0x00, 0xff, 0x10], bin.to_bytes());
+        assert_eq!(b"abc".to_vec(), text.to_bytes());
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let dir = std::env::temp_dir().join(format!("canteen-replay-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let rec = Recorder::new(&dir).unwrap();
+        let req: Request = "GET /hello HTTP/1.1\r\nHost: example.com\r\n\r\n".parse().unwrap();
+        let mut res = hello(&req);
+
+        rec.after(&req, &mut res);
+
+        let replayer = Replayer::from_dir(&dir).unwrap();
+        assert_eq!(1, replayer.exchanges().len());
+        assert_eq!("/hello", replayer.exchanges()[0].1.request.path);
+
+        let mut cnt = Canteen::new();
+        cnt.add_route("/hello", &[Method::Get], hello);
+        assert!(replayer.run(&mut cnt).is_empty());
+
+        let mut cnt = Canteen::new();
+        cnt.add_route("/hello", &[Method::Get], goodbye);
+        let found = replayer.run(&mut cnt);
+
+        assert_eq!(1, found.len());
+        assert_eq!("body", found[0].field);
+        assert_eq!("goodbye", found[0].actual);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! A token bucket used to cap the rate at which bytes are written to a
+//! connection.
+
+use std::time::{Duration, Instant};
+
+/// A token bucket holding up to `burst` bytes' worth of tokens, refilled at
+/// `rate` bytes per second.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate:   u64,
+    burst:  u64,
+    tokens: u64,
+    last:   Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket. A `burst` of zero is bumped up to one byte so the
+    /// bucket can always make progress.
+    pub fn new(rate: u64, burst: u64, now: Instant) -> TokenBucket {
+        let burst = burst.max(1);
+
+        TokenBucket {
+            rate:   rate.max(1),
+            burst,
+            tokens: burst,
+            last:   now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last);
+        let fresh = (elapsed.as_micros() * u128::from(self.rate) / 1_000_000) as u64;
+
+        if fresh > 0 {
+            self.tokens = self.burst.min(self.tokens.saturating_add(fresh));
+            // only advance by the time that was actually converted to tokens,
+            // so slow trickles of time aren't rounded away
+            self.last += Duration::from_micros((u128::from(fresh) * 1_000_000 / u128::from(self.rate)) as u64);
+        }
+
+        if self.tokens == self.burst {
+            self.last = now;
+        }
+    }
+
+    /// Take up to `want` tokens, returning how many were granted.
+    pub fn take(&mut self, want: usize, now: Instant) -> usize {
+        self.refill(now);
+
+        let granted = self.tokens.min(want as u64);
+        self.tokens -= granted;
+
+        granted as usize
+    }
+
+    /// Return tokens that were taken but not used.
+    pub fn give_back(&mut self, unused: usize) {
+        self.tokens = self.burst.min(self.tokens + unused as u64);
+    }
+
+    /// How long until at least one token is available. Returns `None` if
+    /// tokens are available right now.
+    pub fn delay(&mut self, now: Instant) -> Option<Duration> {
+        self.refill(now);
+
+        if self.tokens > 0 {
+            return None;
+        }
+
+        let elapsed = now.saturating_duration_since(self.last);
+        let needed = Duration::from_micros(1_000_000 / self.rate + 1);
+
+        Some(needed.checked_sub(elapsed).unwrap_or_else(|| Duration::from_millis(1)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_starts_full() {
+        let now = Instant::now();
+        let mut tb = TokenBucket::new(1000, 500, now);
+
+        assert_eq!(500, tb.take(2000, now));
+        assert_eq!(0, tb.take(1, now));
+    }
+
+    #[test]
+    fn test_bucket_refills_at_rate() {
+        let now = Instant::now();
+        let mut tb = TokenBucket::new(1000, 1000, now);
+
+        tb.take(1000, now);
+        assert_eq!(100, tb.take(1000, now + Duration::from_millis(100)));
+        assert_eq!(1000, tb.take(5000, now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_bucket_refills_at_high_rate() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(10 * 3600);
+        let mut tb = TokenBucket::new(1_000_000_000, u64::MAX, now);
+
+        tb.take(usize::MAX, now);
+        assert_eq!(36_000_000_000_000, tb.take(usize::MAX, later));
+        assert_eq!(0, tb.take(1, later));
+    }
+
+    #[test]
+    fn test_bucket_give_back() {
+        let now = Instant::now();
+        let mut tb = TokenBucket::new(10, 10, now);
+
+        tb.take(10, now);
+        tb.give_back(4);
+        assert_eq!(4, tb.take(10, now));
+    }
+
+    #[test]
+    fn test_bucket_delay() {
+        let now = Instant::now();
+        let mut tb = TokenBucket::new(100, 100, now);
+
+        assert_eq!(None, tb.delay(now));
+        tb.take(100, now);
+
+        let wait = tb.delay(now).unwrap();
+        assert!(wait > Duration::from_millis(5) && wait <= Duration::from_millis(11));
+        assert_eq!(None, tb.delay(now + Duration::from_millis(20)));
+    }
+}
@@ -0,0 +1,380 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! TLS listeners, backed by rustls.
+//!
+//! Certificates are read from PEM files when a listener is bound and re-read
+//! whenever the files change, so a renewed certificate takes effect without
+//! a restart. Connections that are already open keep the certificate they
+//! were accepted with; only new connections see the new one.
+
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use mio::{EventLoop, EventSet, PollOpt, Token};
+use mio::tcp::TcpStream;
+use rustls::{ServerConfig, ServerConnection};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use rustls_pki_types::pem::PemObject;
+
+use crate::Canteen;
+use crate::transport::Transport;
+
+// how often the certificate files are checked for changes.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+fn invalid<E: ToString>(path: &Path, e: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e.to_string()))
+}
+
+// build a server configuration from a PEM certificate chain and private key.
+fn load(cert: &Path, key: &Path) -> io::Result<Arc<ServerConfig>> {
+    let chain = CertificateDer::pem_slice_iter(&fs::read(cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(cert, e))?;
+    let pkey = PrivateKeyDer::from_pem_slice(&fs::read(key)?).map_err(|e| invalid(key, e))?;
+
+    if chain.is_empty() {
+        return Err(invalid(cert, "no certificates found"));
+    }
+
+    #[allow(unused_mut)]
+    let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|b| b.with_no_client_auth().with_single_cert(chain, pkey))
+        .map_err(|e| invalid(cert, e))?;
+
+    #[cfg(feature = "h2")]
+    {
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    }
+
+    Ok(Arc::new(config))
+}
+
+fn mtimes(cert: &Path, key: &Path) -> Option<(SystemTime, SystemTime)> {
+    let cert = fs::metadata(cert).and_then(|m| m.modified()).ok()?;
+    let key = fs::metadata(key).and_then(|m| m.modified()).ok()?;
+
+    Some((cert, key))
+}
+
+// when the files were last checked, and what they looked like then.
+struct Stamp {
+    checked: Instant,
+    mtimes:  Option<(SystemTime, SystemTime)>,
+}
+
+// the certificate and key behind a TLS listener.
+pub(crate) struct TlsCerts {
+    cert:   PathBuf,
+    key:    PathBuf,
+    config: RwLock<Arc<ServerConfig>>,
+    stamp:  Mutex<Stamp>,
+}
+
+impl TlsCerts {
+    pub fn new(cert: &Path, key: &Path, now: Instant) -> io::Result<TlsCerts> {
+        let stamp = Stamp { checked: now, mtimes: mtimes(cert, key) };
+
+        Ok(TlsCerts {
+            config: RwLock::new(load(cert, key)?),
+            cert:   PathBuf::from(cert),
+            key:    PathBuf::from(key),
+            stamp:  Mutex::new(stamp),
+        })
+    }
+
+    // re-read the files, keeping the current configuration if they're bad.
+    pub fn reload(&self) -> io::Result<()> {
+        let config = load(&self.cert, &self.key)?;
+
+        *self.config.write().unwrap() = config;
+
+        Ok(())
+    }
+
+    // reload if the files have changed since they were last looked at.
+    pub fn refresh(&self, now: Instant) {
+        let mut stamp = self.stamp.lock().unwrap();
+
+        if now.saturating_duration_since(stamp.checked) < CHECK_INTERVAL {
+            return;
+        }
+
+        let current = mtimes(&self.cert, &self.key);
+        stamp.checked = now;
+
+        if current.is_none() || current == stamp.mtimes {
+            return;
+        }
+
+        match self.reload() {
+            Ok(())  => {
+                log::info!("reloaded TLS certificate from {}", self.cert.display());
+                stamp.mtimes = current;
+            },
+            Err(e)  => log::warn!("unable to reload TLS certificate, keeping the old one: {}", e),
+        }
+    }
+
+    pub fn accept(&self, sock: TcpStream) -> io::Result<TlsStream> {
+        let config = self.config.read().unwrap().clone();
+        let conn = ServerConnection::new(config).map_err(io::Error::other)?;
+
+        Ok(TlsStream { sock, conn })
+    }
+}
+
+/// Reloads the certificates of every TLS listener on demand, e.g. from a
+/// signal handler or an admin route. Listeners also pick up changed files on
+/// their own, within a second of the next connection.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use canteen::Canteen;
+///
+/// let mut cnt = Canteen::new();
+/// cnt.bind_tls(("0.0.0.0", 443), "cert.pem", "key.pem");
+///
+/// let reloader = cnt.tls_reloader();
+/// // ... later, once the certificate has been renewed ...
+/// reloader.reload().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct TlsReloader {
+    certs: Vec<Arc<TlsCerts>>,
+}
+
+impl TlsReloader {
+    pub(crate) fn new(certs: Vec<Arc<TlsCerts>>) -> TlsReloader {
+        TlsReloader { certs }
+    }
+
+    /// Re-read every listener's certificate and key. A listener whose files
+    /// can't be loaded keeps serving its current certificate, and the first
+    /// such error is returned.
+    pub fn reload(&self) -> io::Result<()> {
+        let mut result = Ok(());
+
+        for certs in &self.certs {
+            if let Err(e) = certs.reload() {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// An accepted connection on a TLS listener.
+pub(crate) struct TlsStream {
+    sock: TcpStream,
+    conn: ServerConnection,
+}
+
+impl TlsStream {
+    // write out as much pending ciphertext as the socket will take.
+    fn flush_tls(&mut self) -> io::Result<()> {
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.sock)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.conn.reader().read(buf) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {},
+                other                                           => return other,
+            }
+
+            // no plaintext buffered, pull in more records
+            if self.conn.read_tls(&mut self.sock)? == 0 {
+                return Ok(0);
+            }
+
+            if let Err(e) = self.conn.process_new_packets() {
+                // try to let the peer know why we're hanging up
+                let _ = self.flush_tls();
+                return Err(io::Error::new(ErrorKind::InvalidData, e));
+            }
+
+            match self.flush_tls() {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {},
+                other                                           => other?,
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // don't buffer more while earlier records are still waiting to go out
+        self.flush_tls()?;
+
+        let size = self.conn.writer().write(buf)?;
+
+        match self.flush_tls() {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(size),
+            Err(e)                                          => Err(e),
+            Ok(())                                          => Ok(size),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_tls()
+    }
+}
+
+impl Transport for TlsStream {
+    fn register(&self, evl: &mut EventLoop<Canteen>, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+        evl.register(&self.sock, token, interest, opts)
+    }
+
+    fn reregister(&self, evl: &mut EventLoop<Canteen>, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+        evl.reregister(&self.sock, token, interest, opts)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.peer_addr()
+    }
+
+    fn protocol(&self) -> Option<&[u8]> {
+        self.conn.alpn_protocol()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::net::TcpStream as StdStream;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+    use rustls_pki_types::ServerName;
+
+    use crate::{Method, Request, Response};
+    use crate::utils;
+
+    fn data(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/tls").join(name)
+    }
+
+    fn hello(_: &Request) -> Response {
+        utils::make_response("hello", "text/plain", 200)
+    }
+
+    // connect to the server, returning the response and the certificate the
+    // server presented.
+    fn fetch(addr: SocketAddr) -> (String, Vec<u8>) {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(data("ca.pem")).unwrap()).unwrap();
+
+        let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions().unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let conn = ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+        let mut tls = StreamOwned::new(conn, StdStream::connect(addr).unwrap());
+        let mut out = Vec::new();
+
+        tls.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+        // the server closes without a close_notify, which rustls reports
+        match tls.read_to_end(&mut out) {
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {},
+            other                                              => { other.unwrap(); },
+        }
+
+        let cert = tls.conn.peer_certificates().unwrap()[0].to_vec();
+
+        (String::from_utf8(out).unwrap(), cert)
+    }
+
+    #[test]
+    fn test_load_rejects_bad_files() {
+        assert!(load(&data("cert1.pem"), &data("key1.pem")).is_ok());
+        assert!(load(&data("key1.pem"), &data("key1.pem")).is_err());
+        assert!(load(&data("cert1.pem"), &data("missing.pem")).is_err());
+    }
+
+    #[test]
+    fn test_tls_listener_and_reload() {
+        let dir = std::env::temp_dir().join(format!("canteen-tls-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy(data("cert1.pem"), dir.join("cert.pem")).unwrap();
+        fs::copy(data("key1.pem"), dir.join("key.pem")).unwrap();
+
+        let mut cnt = Canteen::new();
+        cnt.bind_tls(("127.0.0.1", 0), dir.join("cert.pem"), dir.join("key.pem"));
+        cnt.add_route("/", &[Method::Get], hello);
+
+        let reloader = cnt.tls_reloader();
+        let server = cnt.spawn();
+        let addr = server.addr().unwrap();
+
+        let (res, first) = fetch(addr);
+        assert!(res.starts_with("HTTP/1.1 200"));
+        assert!(res.ends_with("hello"));
+
+        // a broken renewal keeps the old certificate
+        fs::write(dir.join("cert.pem"), "garbage").unwrap();
+        assert!(reloader.reload().is_err());
+        assert_eq!(first, fetch(addr).1);
+
+        fs::copy(data("cert2.pem"), dir.join("cert.pem")).unwrap();
+        fs::copy(data("key2.pem"), dir.join("key.pem")).unwrap();
+        reloader.reload().unwrap();
+
+        let (res, second) = fetch(addr);
+        assert!(res.ends_with("hello"));
+        assert_ne!(first, second);
+
+        server.shutdown();
+        server.join().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_refresh_picks_up_changed_files() {
+        let dir = std::env::temp_dir().join(format!("canteen-tls-refresh-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy(data("cert1.pem"), dir.join("cert.pem")).unwrap();
+        fs::copy(data("key1.pem"), dir.join("key.pem")).unwrap();
+
+        let now = Instant::now();
+        let certs = TlsCerts::new(&dir.join("cert.pem"), &dir.join("key.pem"), now).unwrap();
+        let before = certs.config.read().unwrap().clone();
+
+        // make sure the modification time moves even on coarse filesystems
+        let later = SystemTime::now() + Duration::from_secs(5);
+        fs::copy(data("cert2.pem"), dir.join("cert.pem")).unwrap();
+        fs::copy(data("key2.pem"), dir.join("key.pem")).unwrap();
+        fs::File::options().write(true).open(dir.join("cert.pem")).unwrap().set_modified(later).unwrap();
+
+        // too soon to look again
+        certs.refresh(now);
+        assert!(Arc::ptr_eq(&before, &certs.config.read().unwrap()));
+
+        certs.refresh(now + CHECK_INTERVAL);
+        assert!(!Arc::ptr_eq(&before, &certs.config.read().unwrap()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright (c) 2016
+// Jeff Nettleton
+//
+// Licensed under the MIT license (http://opensource.org/licenses/MIT). This
+// file may not be copied, modified, or distributed except according to those
+// terms
+
+//! The connection types the event loop can drive.
+
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use mio::{EventLoop, EventSet, PollOpt, Token};
+use mio::tcp::TcpStream;
+
+use crate::Canteen;
+
+/// A byte stream the event loop can read requests from and write responses
+/// to. Reads and writes are non-blocking, reporting `WouldBlock` when they
+/// can't make progress.
+pub trait Transport: Read + Write + Send {
+    /// Register interest in the transport's readiness with the event loop.
+    fn register(&self, evl: &mut EventLoop<Canteen>, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()>;
+
+    /// Update a previous registration.
+    fn reregister(&self, evl: &mut EventLoop<Canteen>, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()>;
+
+    /// The address of the remote end.
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// The application protocol negotiated for the connection, e.g. with
+    /// ALPN, if any.
+    fn protocol(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl Transport for TcpStream {
+    fn register(&self, evl: &mut EventLoop<Canteen>, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+        evl.register(self, token, interest, opts)
+    }
+
+    fn reregister(&self, evl: &mut EventLoop<Canteen>, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+        evl.reregister(self, token, interest, opts)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+}
+
+/// One scripted outcome of a read or write on a `MockTransport`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Hand back these bytes from a read.
+    Data(Vec<u8>),
+    /// Accept up to this many bytes of a write.
+    Accept(usize),
+    /// Fail with `WouldBlock`.
+    Block,
+    /// Report the end of the stream.
+    Eof,
+    /// Fail with an error of this kind.
+    Fail(ErrorKind),
+}
+
+/// An in-memory transport whose reads and writes follow a script, so
+/// partial reads, slow writes, and resets can be tested deterministically.
+///
+/// Once the read script runs out, reads block; once the write script runs
+/// out, writes are accepted in full.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{ErrorKind, Read};
+/// use canteen::transport::MockTransport;
+///
+/// let mut mock = MockTransport::new().incoming(b"GET / HTTP/1.1\r\n").block().reset();
+/// let mut buf = [0u8; 64];
+///
+/// assert_eq!(16, mock.read(&mut buf).unwrap());
+/// assert_eq!(ErrorKind::WouldBlock, mock.read(&mut buf).unwrap_err().kind());
+/// assert_eq!(ErrorKind::ConnectionReset, mock.read(&mut buf).unwrap_err().kind());
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockTransport {
+    reads:   VecDeque<Step>,
+    writes:  VecDeque<Step>,
+    written: Arc<Mutex<Vec<u8>>>,
+    peer:    SocketAddr,
+}
+
+impl MockTransport {
+    /// Create a mock with empty scripts.
+    pub fn new() -> MockTransport {
+        MockTransport {
+            reads:   VecDeque::new(),
+            writes:  VecDeque::new(),
+            written: Arc::new(Mutex::new(Vec::new())),
+            peer:    SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 40000),
+        }
+    }
+
+    /// Queue bytes to be returned by a read.
+    pub fn incoming(mut self, data: &[u8]) -> MockTransport {
+        self.reads.push_back(Step::Data(data.to_vec()));
+
+        self
+    }
+
+    /// Queue a read that would block.
+    pub fn block(mut self) -> MockTransport {
+        self.reads.push_back(Step::Block);
+
+        self
+    }
+
+    /// Queue the end of the stream.
+    pub fn eof(mut self) -> MockTransport {
+        self.reads.push_back(Step::Eof);
+
+        self
+    }
+
+    /// Queue a connection reset on read.
+    pub fn reset(mut self) -> MockTransport {
+        self.reads.push_back(Step::Fail(ErrorKind::ConnectionReset));
+
+        self
+    }
+
+    /// Queue the outcome of the next write.
+    pub fn write_step(mut self, step: Step) -> MockTransport {
+        self.writes.push_back(step);
+
+        self
+    }
+
+    /// Set the address reported as the remote end.
+    pub fn peer(mut self, addr: SocketAddr) -> MockTransport {
+        self.peer = addr;
+
+        self
+    }
+
+    /// A handle onto everything written so far.
+    pub fn output(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.written.clone()
+    }
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        MockTransport::new()
+    }
+}
+
+impl Read for MockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.reads.pop_front() {
+            Some(Step::Data(mut data)) => {
+                let n = data.len().min(buf.len());
+
+                buf[..n].copy_from_slice(&data[..n]);
+
+                if n < data.len() {
+                    // whatever didn't fit comes back on the next read
+                    self.reads.push_front(Step::Data(data.split_off(n)));
+                }
+
+                Ok(n)
+            },
+            Some(Step::Eof)            => Ok(0),
+            Some(Step::Fail(kind))     => Err(io::Error::new(kind, "scripted failure")),
+            Some(_) | None             => Err(io::Error::new(ErrorKind::WouldBlock, "scripted block")),
+        }
+    }
+}
+
+impl Write for MockTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = match self.writes.pop_front() {
+            Some(Step::Accept(n))  => n.min(buf.len()),
+            Some(Step::Block)      => return Err(io::Error::new(ErrorKind::WouldBlock, "scripted block")),
+            Some(Step::Fail(kind)) => return Err(io::Error::new(kind, "scripted failure")),
+            Some(Step::Eof)        => 0,
+            Some(Step::Data(_)) |
+            None                   => buf.len(),
+        };
+
+        self.written.lock().unwrap().extend_from_slice(&buf[..n]);
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MockTransport {
+    fn register(&self, _: &mut EventLoop<Canteen>, _: Token, _: EventSet, _: PollOpt) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reregister(&self, _: &mut EventLoop<Canteen>, _: Token, _: EventSet, _: PollOpt) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_read_splits_data() {
+        let mut mock = MockTransport::new().incoming(b"abcdef").eof();
+        let mut buf = [0u8; 4];
+
+        assert_eq!(4, mock.read(&mut buf).unwrap());
+        assert_eq!(b"abcd", &buf);
+        assert_eq!(2, mock.read(&mut buf).unwrap());
+        assert_eq!(0, mock.read(&mut buf).unwrap());
+        assert_eq!(ErrorKind::WouldBlock, mock.read(&mut buf).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_mock_write_script() {
+        let mut mock = MockTransport::new().write_step(Step::Accept(2)).write_step(Step::Block);
+        let out = mock.output();
+
+        assert_eq!(2, mock.write(b"hello").unwrap());
+        assert_eq!(ErrorKind::WouldBlock, mock.write(b"llo").unwrap_err().kind());
+        assert_eq!(3, mock.write(b"llo").unwrap());
+        assert_eq!(b"hello".to_vec(), *out.lock().unwrap());
+    }
+}
@@ -6,14 +6,14 @@
 // terms
 
 use std::env;
-use std::fs::File;
-use std::path::PathBuf;
-use std::io::prelude::*;
 use chrono::{Utc, DateTime, TimeZone};
-use mime_guess::MimeGuess;
-use std::time::{UNIX_EPOCH, SystemTime};
-use crate::response::{ToOutput, Response};
-use crate::request::Request;
+use std::time::{Duration, UNIX_EPOCH, SystemTime};
+use crate::assets;
+use crate::files;
+use serde::Serialize;
+use crate::response::Response;
+use crate::request::{Method, Request};
+use crate::status::StatusCode;
 
 /// Convenience method for creating a response from the basic components
 /// required (a request body, content type, and response status).
@@ -28,16 +28,89 @@ use crate::request::Request;
 ///     utils::make_response("Hello, world!", "text/plain", 200)
 /// }
 /// ```
-pub fn make_response<T: ToOutput>(body: T, c_type: &str, status: u16) -> Response {
+pub fn make_response<B: Into<Vec<u8>>, S: Into<StatusCode>>(body: B, c_type: &str, status: S) -> Response {
     let mut res = Response::new();
 
-    res.set_status(status);
+    res.set_status(status.into().as_u16());
     res.set_content_type(c_type);
-    res.append(body);
+    res.append(body.into());
 
     res
 }
 
+/// Shorthand for a `200 OK` plain text response.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Request, Response};
+/// use canteen::utils;
+///
+/// fn handler(req: &Request) -> Response {
+///     let to_dbl: i32 = req.get("to_dbl");
+///
+///     utils::text(format!("{}", to_dbl * 2))
+/// }
+/// ```
+pub fn text<B: Into<Vec<u8>>>(body: B) -> Response {
+    make_response(body, "text/plain; charset=utf-8", 200)
+}
+
+/// Shorthand for a `200 OK` HTML response.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Request, Response};
+/// use canteen::utils;
+///
+/// fn handler(_: &Request) -> Response {
+///     utils::html("<h1>Hello, world!</h1>")
+/// }
+/// ```
+pub fn html<B: Into<Vec<u8>>>(body: B) -> Response {
+    make_response(body, "text/html; charset=utf-8", 200)
+}
+
+/// Shorthand for a `200 OK` response with `data` serialized as JSON.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::{Request, Response};
+/// use canteen::utils;
+///
+/// fn handler(_: &Request) -> Response {
+///     utils::json(&vec![1, 2, 3])
+/// }
+/// ```
+pub fn json<T: Serialize>(data: &T) -> Response {
+    Response::as_json(data)
+}
+
+/// The URL of the fingerprinted copy of `name`, a path relative to the root
+/// of a static mount made with `StaticOptions::fingerprint`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use canteen::{Canteen, Request, Response};
+/// use canteen::files::StaticOptions;
+/// use canteen::utils;
+///
+/// fn index(_: &Request) -> Response {
+///     let css = utils::asset_url("css/app.css").unwrap_or_default();
+///
+///     utils::html(format!("<link rel=\"stylesheet\" href=\"{}\">", css))
+/// }
+///
+/// let mut cnt = Canteen::new();
+/// cnt.mount_static("/static", "public", StaticOptions::new().fingerprint(true));
+/// ```
+pub fn asset_url(name: &str) -> Option<String> {
+    assets::lookup(name)
+}
+
 /// Converts std::time::SystemTime to chrono::DateTime<Utc>
 ///
 /// Code from: https://users.rust-lang.org/t/convert-std-time-systemtime-to-chrono-datetime-datetime/7684/4
@@ -59,6 +132,69 @@ pub fn _conv_systemtime(t: SystemTime) -> DateTime<Utc> {
     Utc.timestamp(sec, nsec)
 }
 
+/// Percent-encode `segment` for use as one segment of a URL path, leaving
+/// only the unreserved characters as they are.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::utils;
+///
+/// assert_eq!("caf%C3%A9%20menu.pdf", utils::encode_segment("café menu.pdf"));
+/// assert_eq!("a%2Fb", utils::encode_segment("a/b"));
+/// ```
+pub fn encode_segment(segment: &str) -> String {
+    let mut out = String::new();
+
+    for b in segment.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            _                                                                  => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    out
+}
+
+/// Decode the percent-escapes in `text`, e.g. a path segment. Returns
+/// `None` if an escape isn't two hex digits, or the result isn't UTF-8.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::utils;
+///
+/// assert_eq!(Some(String::from("John Doe/é")), utils::percent_decode("John%20Doe%2F%C3%A9"));
+/// assert_eq!(None, utils::percent_decode("100%"));
+/// assert_eq!(None, utils::percent_decode("%FF"));
+/// ```
+pub fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            },
+            b    => {
+                out.push(b);
+                i += 1;
+            },
+        }
+    }
+
+    String::from_utf8(out).ok()
+}
+
 /// Replace the URI escape codes with their ASCII equivalents.
 pub fn replace_escape(path: &str) -> String {
     let mut fixed = String::from(path);
@@ -111,10 +247,47 @@ pub fn replace_escape(path: &str) -> String {
     fixed
 }
 
-fn err_body(message: &str, path: &str) -> String {
+// make `text` safe to put in HTML or XML, as text or a quoted attribute.
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub(crate) fn err_body(message: &str, path: &str) -> String {
     format!("<html><head>\
              <style>body {{ font-family: helvetica, sans-serif; }} p {{ font-size: 14 }}</style>\
-             </head><body><h3>Your request failed</h3><p>{}: {}</p></body></html>", message, path)
+             </head><body><h3>Your request failed</h3><p>{}: {}</p></body></html>", escape_html(message), escape_html(path))
+}
+
+/// Default handler function for HTTP 400 errors.
+pub fn err_400(req: &Request) -> Response {
+    make_response(err_body("bad request", &req.path), "text/html", 400)
+}
+
+/// Default handler function for HTTP 401 errors, asking for credentials
+/// with the `WWW-Authenticate` challenge `challenge`.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::Request;
+/// use canteen::utils;
+///
+/// let req: Request = "GET /admin HTTP/1.1\r\n\r\n".parse().unwrap();
+/// let res = utils::err_401(&req, "Basic realm=\"admin\"");
+///
+/// assert_eq!(401, res.get_status());
+/// assert_eq!(Some("Basic realm=\"admin\""), res.get_header("WWW-Authenticate"));
+/// ```
+pub fn err_401(req: &Request, challenge: &str) -> Response {
+    let mut res = make_response(err_body("unauthorized", &req.path), "text/html", 401);
+
+    res.add_header("WWW-Authenticate", challenge);
+
+    res
 }
 
 /// Default handler function for HTTP 403 errors.
@@ -137,6 +310,55 @@ pub fn err_404_json(message: &str) -> Response {
     make_response(format!("{{ message: 'not found: {}' }}", message), "application/json", 404)
 }
 
+/// Default handler function for HTTP 405 errors, listing the methods the
+/// resource does allow in the `Allow` header.
+pub fn err_405(req: &Request, allow: &[Method]) -> Response {
+    let mut names: Vec<&str> = allow.iter().map(Method::as_str).collect();
+    let mut res = make_response(err_body("method not allowed", &req.path), "text/html", 405);
+
+    names.sort();
+    names.dedup();
+    res.add_header("Allow", &names.join(", "));
+
+    res
+}
+
+/// Default handler function for HTTP 412 errors.
+pub fn err_412(req: &Request) -> Response {
+    make_response(err_body("precondition failed", &req.path), "text/html", 412)
+}
+
+/// Default handler function for HTTP 413 errors.
+pub fn err_413(req: &Request) -> Response {
+    make_response(err_body("request entity too large", &req.path), "text/html", 413)
+}
+
+/// Default handler function for HTTP 414 errors.
+pub fn err_414(req: &Request) -> Response {
+    make_response(err_body("request target too long", &req.path), "text/html", 414)
+}
+
+/// Default handler function for HTTP 415 errors.
+pub fn err_415(req: &Request) -> Response {
+    make_response(err_body("unsupported media type", &req.path), "text/html", 415)
+}
+
+/// Default handler function for HTTP 429 errors, telling the client to try
+/// again after `wait`, rounded up to whole seconds.
+pub fn err_429(req: &Request, wait: Duration) -> Response {
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    let mut res = make_response(err_body("too many requests", &req.path), "text/html", 429);
+
+    res.add_header("Retry-After", &secs.max(1).to_string());
+
+    res
+}
+
+/// Default handler function for HTTP 431 errors.
+pub fn err_431(req: &Request) -> Response {
+    make_response(err_body("request header fields too large", &req.path), "text/html", 431)
+}
+
 /// Default handler function for HTTP 500 errors.
 pub fn err_500(req: &Request) -> Response {
     make_response(err_body("internal server error", &req.path), "text/html", 500)
@@ -147,71 +369,193 @@ pub fn err_500_json(message: &str) -> Response {
     make_response(format!("{{ message: 'internal server error: {}' }}", message), "application/json", 500)
 }
 
-/// Handler that sends static files relative to the current working directory.
-pub fn static_file(req: &Request) -> Response {
-    let mut res = Response::new();
+/// Default handler function for HTTP 504 errors.
+pub fn err_504(req: &Request) -> Response {
+    make_response(err_body("gateway timeout", &req.path), "text/html", 504)
+}
 
-    let cwd = env::current_dir().unwrap();
-    let clean = replace_escape(&req.path);
-    let mut fpath = PathBuf::from(&cwd);
-    let mut fbuf: Vec<u8> = Vec::new();
-
-    for chunk in clean.split('/') {
-        if chunk == "" || chunk == "." || chunk == ".." {
-            /* bzzzzt */
-            continue;
-        }
+/// Builds a 301 redirect to the HTTPS equivalent of the request, preserving
+/// the path and query string. The host is taken from the request's `Host`
+/// header; `port` is omitted from the new location when it is 443.
+pub fn https_redirect(req: &Request, port: u16) -> Response {
+    let host = match req.get_header("Host") {
+        Some(h) => h,
+        None    => return err_400(req),
+    };
+
+    // drop any port from the host, taking care not to mangle IPv6 literals
+    let name = match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _                                   => &host[..],
+    };
 
-        fpath.push(&chunk);
+    let location = match port {
+        443 => format!("https://{}{}", name, req.path),
+        _   => format!("https://{}:{}{}", name, port, req.path),
+    };
+
+    let mut res = make_response(format!("<a href=\"{}\">moved permanently</a>", escape_html(&location)), "text/html", 301);
+    res.add_header("Location", &location);
+
+    res
+}
+
+/// Answers a TRACE request by echoing it back as a `message/http` body, as
+/// described in RFC 7231. Headers likely to hold credentials are left out.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::Request;
+/// use canteen::utils;
+///
+/// let req: Request = "TRACE /foo HTTP/1.1\r\nHost: x\r\nCookie: id=1\r\n\r\n".parse().unwrap();
+/// let res = utils::trace_echo(&req);
+///
+/// assert_eq!(b"TRACE /foo HTTP/1.1\r\nHost: x\r\n\r\n", res.get_payload());
+/// ```
+pub fn trace_echo(req: &Request) -> Response {
+    const SENSITIVE: [&str; 3] = ["authorization", "proxy-authorization", "cookie"];
+    let headers = req.headers().filter(|(k, _)| !SENSITIVE.contains(&k.to_ascii_lowercase().as_str()));
+    let mut body = format!("{} {} {}\r\n", req.method.as_str(), req.path, req.version());
+
+    for (key, value) in headers {
+        body.push_str(&format!("{}: {}\r\n", key, value));
     }
+    body.push_str("\r\n");
 
-    let file = File::open(&fpath);
+    make_response(body, "message/http", 200)
+}
 
-    match file {
-        Ok(mut f)   => {
-            let last = match f.metadata() {
-                Err(_)  => Utc::now(),
-                Ok(md)  => {
-                    match md.modified() {
-                        Err(_)  => Utc::now(), // should never happen...
-                        Ok(st)  => _conv_systemtime(st),
-                    }
-                }
-            };
-
-            if let Some(hdr) = req.get_header("If-Modified-Since") {
-                if let Ok(dt_utc) = Utc.datetime_from_str(&hdr, "%a, %d %b %Y, %H:%M:%S UTC") {
-                    if dt_utc >= last {
-                        // it hasn't been modified, return a 304
-                        res.set_status(304);
-                        return res;
-                    }
-                }
-            }
+/// Resolves `location` against the request's scheme, `Host` header, and
+/// path, so that it can be used as an absolute URL. Locations that already
+/// have a scheme are returned as they are, and so are relative ones if the
+/// request has no `Host`.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::Request;
+/// use canteen::utils;
+///
+/// let req: Request = "GET /docs/intro HTTP/1.1\r\nHost: example.com\r\n\r\n".parse().unwrap();
+///
+/// assert_eq!("http://example.com/login", utils::absolute_url(&req, "/login"));
+/// assert_eq!("http://example.com/docs/setup", utils::absolute_url(&req, "setup"));
+/// assert_eq!("http://example.com/about", utils::absolute_url(&req, "../about"));
+/// ```
+pub fn absolute_url(req: &Request, location: &str) -> String {
+    if location.contains("://") {
+        return String::from(location);
+    }
+
+    let host = match req.get_header("Host") {
+        Some(h) => h,
+        None    => return String::from(location),
+    };
+
+    if let Some(rest) = location.strip_prefix("//") {
+        return format!("{}://{}", req.scheme(), rest);
+    }
 
-            match f.read_to_end(&mut fbuf) {
-                Ok(_)   => {
-                    res.add_header("Last-Modified", &last.format("%a, %d %b %Y, %H:%M:%S %Z").to_string());
-                    res.set_status(200);
-
-                    match MimeGuess::from_path(&fpath).first_raw() {
-                        Some(ftype) => res.set_content_type(ftype),
-                        None        => res.set_content_type("text/plain"),
-                    };
-
-                    res.append(fbuf);
-                },
-                Err(_)  => {
-                    return err_500(&req);
-                },
+    // split off the query so it's not mistaken for part of the path
+    let (path, query) = match location.find('?') {
+        Some(i) => location.split_at(i),
+        None    => (location, ""),
+    };
+
+    let joined = if path.starts_with('/') {
+        String::from(path)
+    } else {
+        let current = req.path.split('?').next().unwrap_or("/");
+        let dir = &current[..current.rfind('/').map(|i| i + 1).unwrap_or(0)];
+
+        format!("{}{}", dir, path)
+    };
+
+    // drop `.` segments, and `..` along with the segment before it
+    let mut parts: Vec<&str> = Vec::new();
+    let segments: Vec<&str> = joined.split('/').skip(1).collect();
+    for (i, seg) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+
+        match *seg {
+            "."  => { if last { parts.push(""); } },
+            ".." => { parts.pop(); if last { parts.push(""); } },
+            _    => parts.push(seg),
+        }
+    }
+
+    format!("{}://{}/{}{}", req.scheme(), host, parts.join("/"), query)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `data` as standard, padded base64.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::utils;
+///
+/// assert_eq!("aGk/Pz4=", utils::base64_encode(b"hi??>"));
+/// ```
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (u32::from(b) << (16 - 8 * i)));
+
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true  => out.push(BASE64[(n >> (18 - 6 * i)) as usize & 63] as char),
+                false => out.push('='),
             }
-        },
-        Err(_)      => {
-            return err_404(&req);
         }
     }
 
-    res
+    out
+}
+
+/// Decode standard base64, with or without padding. Returns `None` if
+/// `text` isn't base64.
+///
+/// # Examples
+///
+/// ```rust
+/// use canteen::utils;
+///
+/// assert_eq!(Some(b"hi??>".to_vec()), utils::base64_decode("aGk/Pz4="));
+/// assert_eq!(None, utils::base64_decode("a*"));
+/// ```
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+
+    for c in text.bytes() {
+        acc = (acc << 6) | BASE64.iter().position(|&b| b == c)? as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+
+    // a lone character can't encode a whole byte
+    match bits {
+        6 => None,
+        _ => Some(out),
+    }
+}
+
+/// Handler that sends static files relative to the current working directory.
+pub fn static_file(req: &Request) -> Response {
+    let cwd = env::current_dir().unwrap();
+
+    files::serve(req, &cwd, &req.path)
 }
 
 #[cfg(test)]
@@ -226,8 +570,99 @@ mod tests {
         assert_eq!("abcdefghijklmnopqrstuvwxyz", replace_escape(&path));
     }
 
+    #[test]
+    fn test_https_redirect() {
+        let req: Request = "GET /foo/bar?page=2 HTTP/1.1\r\nHost: example.com:8080\r\n\r\n".parse().unwrap();
+        let out = String::from_utf8(https_redirect(&req, 443).gen_output()).unwrap();
+
+        assert!(out.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(out.contains("Location: https://example.com/foo/bar?page=2\r\n"));
+        assert!(!out.contains("Strict-Transport-Security"));
+    }
+
+    #[test]
+    fn test_https_redirect_port() {
+        let req: Request = "GET / HTTP/1.1\r\nHost: [::1]\r\n\r\n".parse().unwrap();
+        let out = String::from_utf8(https_redirect(&req, 8443).gen_output()).unwrap();
+
+        assert!(out.contains("Location: https://[::1]:8443/\r\n"));
+    }
+
+    #[test]
+    fn test_https_redirect_no_host() {
+        let req: Request = "GET / HTTP/1.1\r\n\r\n".parse().unwrap();
+        let out = String::from_utf8(https_redirect(&req, 443).gen_output()).unwrap();
+
+        assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_base64() {
+        for data in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar", &[0xff, 0x00, 0xfe]] {
+            assert_eq!(Some(data.to_vec()), base64_decode(&base64_encode(data)));
+        }
+
+        assert_eq!("Zm9vYg==", base64_encode(b"foob"));
+        assert_eq!(Some(b"foob".to_vec()), base64_decode("Zm9vYg"));
+        assert_eq!(None, base64_decode("Zm9vY"));
+    }
+
     #[test]
     fn test_conv_systemtime() {
         assert_eq!(_conv_systemtime(UNIX_EPOCH), Utc.timestamp(0, 0));
     }
+
+    #[test]
+    fn test_err_405_allow() {
+        let req: Request = "TRACE /foo HTTP/1.1\r\n\r\n".parse().unwrap();
+        let out = String::from_utf8(err_405(&req, &[Method::Post, Method::Get, Method::Post]).gen_output()).unwrap();
+
+        assert!(out.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(out.contains("Allow: GET, POST\r\n"));
+    }
+
+    #[test]
+    fn test_trace_echo() {
+        let req: Request = "TRACE /a?b=1 HTTP/1.1\r\nX-One: 1\r\nAuthorization: Basic eA==\r\nHost: h\r\n\r\n".parse().unwrap();
+        let res = trace_echo(&req);
+
+        assert_eq!(200, res.get_status());
+        assert_eq!(b"TRACE /a?b=1 HTTP/1.1\r\nX-One: 1\r\nHost: h\r\n\r\n", res.get_payload());
+    }
+
+    #[test]
+    fn test_absolute_url() {
+        let mut req: Request = "GET /a/b/c?x=1 HTTP/1.1\r\nHost: example.com:8080\r\n\r\n".parse().unwrap();
+
+        assert_eq!("http://example.com:8080/a/b/d", absolute_url(&req, "d"));
+        assert_eq!("http://example.com:8080/a/d?y=2", absolute_url(&req, "../d?y=2"));
+        assert_eq!("http://example.com:8080/a/b/", absolute_url(&req, "."));
+        assert_eq!("http://example.com:8080/", absolute_url(&req, "/../.."));
+        assert_eq!("https://other.org/", absolute_url(&req, "https://other.org/"));
+
+        req.secure = true;
+        assert_eq!("https://cdn.example.com/x", absolute_url(&req, "//cdn.example.com/x"));
+        assert_eq!("https://example.com:8080/x", absolute_url(&req, "/x"));
+
+        let req: Request = "GET /a HTTP/1.1\r\n\r\n".parse().unwrap();
+        assert_eq!("/b", absolute_url(&req, "/b"));
+    }
+
+    #[test]
+    fn test_shorthands() {
+        let res = text(String::from("hi"));
+        assert_eq!(200, res.get_status());
+        assert_eq!("text/plain; charset=utf-8", res.get_content_type());
+        assert_eq!(b"hi", res.get_payload());
+
+        assert_eq!("text/html; charset=utf-8", html("<p>").get_content_type());
+
+        let res = json(&vec![1, 2]);
+        assert_eq!("application/json", res.get_content_type());
+        assert_eq!(b"[1,2]", res.get_payload());
+
+        let res = make_response(vec![0xffu8, 0x00], "application/octet-stream", StatusCode::from(201));
+        assert_eq!(201, res.get_status());
+        assert_eq!(&[0xff, 0x00], res.get_payload());
+    }
 }